@@ -3,7 +3,9 @@
 use std::collections::HashMap;
 use std::iter::once;
 use std::borrow::Cow;
-use crate::{Font, Glyph, Value, Context, State, type1, type2, IResultExt, R, VMetrics, HMetrics, GlyphId, Name};
+use std::ops::Range;
+use std::sync::{Arc, RwLock};
+use crate::{Font, Glyph, Value, Context, State, TryIndex, type1, type2, IResultExt, R, VMetrics, HMetrics, GlyphId, Name, GlyphCountReport, GlyphHints, FontError, GlyphError, warn_invalid};
 use nom::{
     number::complete::{be_u8, be_u16, be_i16, be_u24, be_u32, be_i32},
     bytes::complete::{take},
@@ -13,13 +15,14 @@ use nom::{
     error::{make_error, ErrorKind},
     Err::*,
 };
+use crate::opentype::os2::weight_class_for_name;
 use pdf_encoding::{Encoding};
 use pathfinder_content::outline::{Outline};
 use pathfinder_geometry::{vector::Vector2F, transform2d::Transform2F, rect::RectF};use tuple::TupleElements;
 
 #[derive(Clone)]
 pub struct CffFont {
-    glyphs: Vec<Glyph>,
+    charstrings: Charstrings,
     font_matrix: Transform2F,
     codepoint_map: [u16; 256],  // codepoint -> glyph index
     name_map: HashMap<String, u16>,
@@ -27,22 +30,73 @@ pub struct CffFont {
     bbox: Option<RectF>,
     vmetrics: Option<VMetrics>,
     name: Name,
+    // cid -> gid of CID-keyed fonts
+    cid_map: Option<HashMap<u16, u16>>,
+    ros: Option<Ros>,
+    weight: Option<u16>,
+    italic: bool,
+}
+
+/// Registry, ordering and supplement of the character collection of a CID-keyed font
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ros {
+    pub registry: String,
+    pub ordering: String,
+    pub supplement: i32,
 }
 
 impl CffFont {
     pub fn parse(data: &[u8], idx: u32) -> Self {
-        read_cff(data).get().slot(idx).parse_font()
+        read_cff(data).get().slot(idx).parse_font().unwrap_or_else(|e| panic!("{}", e))
+    }
+    /// Like `parse`, but returns an error if the header, the indices, the dicts, the charset or the encoding
+    /// of the font are broken.
+    ///
+    /// Charstrings are only interpreted when their glyph is used, see `ParseOptions::check_glyphs`.
+    pub fn try_parse(data: &[u8], idx: u32) -> Result<Self, FontError> {
+        match data.first() {
+            Some(1) => {}
+            Some(major) => return Err(FontError::Parse(format!("unsupported CFF version {}", major))),
+            None => return Err(FontError::Truncated)
+        }
+        let cff = read_cff(data).map_err(|_| FontError::Truncated)?.1;
+        cff.try_slot(idx)?.parse_font()
+    }
+    /// The character collection, if this is a CID-keyed font
+    pub fn ros(&self) -> Option<&Ros> {
+        self.ros.as_ref()
     }
 }
 impl Font for CffFont {
     fn num_glyphs(&self) -> u32 {
-        self.glyphs.len() as u32
+        self.charstrings.len() as u32
     }
     fn font_matrix(&self) -> Transform2F {
         self.font_matrix
     }
     fn glyph(&self, id: GlyphId) -> Option<Glyph> {
-        self.glyphs.get(id.0 as usize).cloned()
+        warn_invalid(self.try_glyph(id))
+    }
+    fn try_glyph(&self, id: GlyphId) -> Result<Glyph, GlyphError> {
+        self.charstrings.try_get(id.0).map(|(path, advance, lsb, _)| Glyph {
+            metrics: HMetrics { advance, lsb },
+            path
+        })
+    }
+    fn advance(&self, gid: GlyphId) -> Option<f32> {
+        self.charstrings.get(gid.0).map(|(_, advance, _, _)| advance)
+    }
+    fn glyph_hints(&self, gid: GlyphId) -> Option<GlyphHints> {
+        self.charstrings.get(gid.0).map(|(_, _, _, hints)| hints)
+    }
+    fn cff_glyph_segments(&self, gid: GlyphId) -> Option<Vec<CffSegment>> {
+        self.charstrings.get(gid.0).map(|(path, _, _, _)| outline_segments(&path))
+    }
+    fn glyph_count_report(&self) -> GlyphCountReport {
+        GlyphCountReport {
+            cff: Some(self.charstrings.len() as u32),
+            .. GlyphCountReport::default()
+        }
     }
     fn gid_for_codepoint(&self, codepoint: u32) -> Option<GlyphId> {
         match self.codepoint_map.get(codepoint as usize) {
@@ -56,12 +110,24 @@ impl Font for CffFont {
             Some(&gid) => Some(GlyphId(gid as u32))
         }
     }
+    fn gid_for_cid(&self, cid: u32) -> Option<GlyphId> {
+        if cid > 0xFFFF {
+            return None;
+        }
+        self.cid_map.as_ref()?.get(&(cid as u16)).map(|&gid| GlyphId(gid as u32))
+    }
     fn encoding(&self) -> Option<Encoding> {
         self.encoding
     }
     fn get_notdef_gid(&self) -> GlyphId {
         GlyphId(0)
     }
+    fn weight(&self) -> Option<u16> {
+        self.weight
+    }
+    fn is_italic(&self) -> bool {
+        self.italic
+    }
     fn bbox(&self) -> Option<RectF> {
         self.bbox
     }
@@ -73,23 +139,61 @@ impl Font for CffFont {
     }
 }
 
+/// A path operation of a charstring, in absolute glyph coordinates.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CffSegment {
+    MoveTo(Vector2F),
+    LineTo(Vector2F),
+    CurveTo(Vector2F, Vector2F, Vector2F),
+    ClosePath,
+}
+
+/// The operations that drew `outline`. The interpreter only ever pushes endpoints and cubic curves.
+pub fn outline_segments(outline: &Outline) -> Vec<CffSegment> {
+    let mut segments = vec![];
+    for contour in outline.contours() {
+        let len = contour.len();
+        if len == 0 {
+            continue;
+        }
+        segments.push(CffSegment::MoveTo(contour.position_of(0)));
+        let mut i = 1;
+        while i < len {
+            if !contour.point_is_endpoint(i) && i + 2 < len {
+                segments.push(CffSegment::CurveTo(contour.position_of(i), contour.position_of(i + 1), contour.position_of(i + 2)));
+                i += 3;
+            } else {
+                segments.push(CffSegment::LineTo(contour.position_of(i)));
+                i += 1;
+            }
+        }
+        if contour.is_closed() {
+            segments.push(CffSegment::ClosePath);
+        }
+    }
+    segments
+}
+
 pub fn read_cff(data: &[u8]) -> R<Cff> {
     let i = data;
     let (i, major) = be_u8(i)?;
-    assert_eq!(major, 1);
+    if major != 1 {
+        return Err(nom::Err::Failure(make_error(data, ErrorKind::Verify)));
+    }
     let (i, _minor) = be_u8(i)?;
     
     let (i, hdrSize) = be_u8(i)?;
     let (i, _offSize) = be_u8(i)?;
-    let (i, _) = take(hdrSize - 4)(i)?;
+    let (i, _) = take(hdrSize.saturating_sub(4))(i)?;
     
-    let (i, _name_index) = index(i)?;
+    let (i, name_index) = index(i)?;
     let (i, dict_index) = index(i)?;
     let (i, string_index) = index(i)?;
     let (i, subroutines) = index(i)?;
     
     Ok((i, Cff {
         data,
+        name_index,
         dict_index,
         string_index,
         subroutines
@@ -109,6 +213,7 @@ type Index<'a> = Vec<&'a [u8]>;
 
 pub struct Cff<'a> {
     data: &'a [u8],
+    name_index: Index<'a>,
     dict_index: Index<'a>,
     string_index:Index<'a>,
     subroutines: Index<'a>,
@@ -116,148 +221,268 @@ pub struct Cff<'a> {
 pub struct CffSlot<'a> {
     cff: Cff<'a>,
     top_dict: Dict,
-    private_dict: Dict,
+    // (private dict, local subroutines) of each font dict. Only CID-keyed fonts have more than one.
+    private_dicts: Vec<(Dict, Index<'a>)>,
+    // font dict index of each glyph (CID-keyed fonts only)
+    fd_select: Option<Vec<u8>>,
     char_strings: Index<'a>,
-    subrs: Index<'a>,
-    num_glyphs: usize
+    num_glyphs: usize,
+    // the entry of the name index
+    font_name: Option<&'a [u8]>,
+    top_dict_data: &'a [u8],
 }
     
 impl<'a> Cff<'a> {
     pub fn slot(self, idx: u32) -> CffSlot<'a> {
-        let data = self.dict_index.get(idx as usize).expect("font not found");
-        let top_dict = dict(data).unwrap().1;
+        match self.try_slot(idx) {
+            Ok(slot) => slot,
+            Err(e) => panic!("{}", e)
+        }
+    }
+    /// The font `idx` of the CFF data, or an error if its dicts or indices are missing or out of bounds.
+    pub fn try_slot(self, idx: u32) -> Result<CffSlot<'a>, FontError> {
+        let invalid = |msg: &str| FontError::Parse(msg.into());
+        let data = self.dict_index.get(idx as usize).cloned().ok_or_else(|| invalid("font not found"))?;
+        let top_dict = dict(data).map_err(|_| invalid("invalid top dict"))?.1;
         info!("top dict: {:?}", top_dict);
         
-        let private_dict_entry = top_dict.get(&Operator::Private)
-            .expect("no private dict entry");
+        let offset = top_dict.get(&Operator::CharStrings).and_then(|arr| arr.first()?.to_uint())
+            .ok_or_else(|| invalid("no CharStrings entry"))? as usize;
+        let char_strings = self.data.get(offset ..).and_then(|data| index(data).ok())
+            .ok_or(FontError::Truncated)?.1;
         
-        let private_dict_size = private_dict_entry[0].to_int() as usize;
-        let private_dict_offset = private_dict_entry[1].to_int() as usize;
-        let private_dict_data = &self.data[private_dict_offset .. private_dict_offset + private_dict_size];
-        let private_dict = dict(private_dict_data).get();
-        info!("private dict: {:?}", private_dict);
+        // num glyphs includes glyph 0 (.notdef)
+        let num_glyphs = char_strings.len() as usize;
         
-        let offset = top_dict[&Operator::CharStrings][0].to_int() as usize;
-        let char_strings = index(self.data.get(offset ..).unwrap()).get();
+        // CID-keyed fonts have their private dicts in the font dicts of the FDArray
+        let private_dicts = match top_dict.get(&Operator::FDArray).and_then(|arr| arr.first()) {
+            Some(fd_array_offset) => {
+                let fd_array = fd_array_offset.to_uint().and_then(|offset| self.data.get(offset as usize ..)).and_then(|data| index(data).ok())
+                    .ok_or(FontError::Truncated)?.1;
+                let private_dicts = fd_array.iter().map(|&data| {
+                    let font_dict = dict(data).map_err(|_| invalid("invalid font dict"))?.1;
+                    self.private_dict(&font_dict)
+                }).collect::<Result<Vec<_>, _>>()?;
+                if private_dicts.is_empty() {
+                    return Err(invalid("empty FDArray"));
+                }
+                private_dicts
+            }
+            None => vec![self.private_dict(&top_dict)?]
+        };
         
-        let subrs = private_dict.get(&Operator::Subrs).map(|arr| {
-            let private_subroutines_offset = arr[0].to_int() as usize;
-            index(&self.data[(private_dict_offset + private_subroutines_offset) as usize ..]).get()
-        }).unwrap_or_default();
+        let fd_select = match top_dict.get(&Operator::FDSelect).and_then(|arr| arr.first()) {
+            Some(offset) => {
+                let fd_select = offset.to_uint().and_then(|offset| self.data.get(offset as usize ..)).and_then(|data| fd_select(data, num_glyphs).ok())
+                    .ok_or(FontError::Truncated)?.1;
+                if fd_select.iter().any(|&fd| fd as usize >= private_dicts.len()) {
+                    return Err(invalid("FDSelect refers to a missing font dict"));
+                }
+                Some(fd_select)
+            }
+            None => None
+        };
         
-        // num glyphs includes glyph 0 (.notdef)
-        let num_glyphs = char_strings.len() as usize;
+        let font_name = self.name_index.get(idx as usize).cloned();
         
-        CffSlot {
+        Ok(CffSlot {
             cff: self,
             top_dict,
-            private_dict,
+            private_dicts,
+            fd_select,
             char_strings,
-            subrs,
-            num_glyphs
-        }
+            num_glyphs,
+            font_name,
+            top_dict_data: data,
+        })
+    }
+    // the private dict of the top dict or a font dict, and its local subroutines
+    fn private_dict(&self, font_dict: &Dict) -> Result<(Dict, Index<'a>), FontError> {
+        let invalid = |msg: &str| FontError::Parse(msg.into());
+        let private_dict_entry = font_dict.get(&Operator::Private)
+            .filter(|entry| entry.len() >= 2)
+            .ok_or_else(|| invalid("no private dict entry"))?;
+        
+        let (private_dict_size, private_dict_offset) = match (private_dict_entry[0].to_uint(), private_dict_entry[1].to_uint()) {
+            (Some(size), Some(offset)) => (size as usize, offset as usize),
+            _ => return Err(invalid("invalid private dict entry"))
+        };
+        let data: &'a [u8] = self.data;
+        let private_dict_data = data.get(private_dict_offset .. private_dict_offset + private_dict_size)
+            .ok_or(FontError::Truncated)?;
+        let private_dict = dict(private_dict_data).map_err(|_| invalid("invalid private dict"))?.1;
+        info!("private dict: {:?}", private_dict);
+        
+        let subrs = match private_dict.get(&Operator::Subrs).and_then(|arr| arr.first()) {
+            Some(private_subroutines_offset) => {
+                let offset = private_subroutines_offset.to_uint().map(|offset| private_dict_offset + offset as usize);
+                offset.and_then(|offset| data.get(offset ..)).and_then(|data| index(data).ok())
+                    .ok_or(FontError::Truncated)?.1
+            }
+            None => Index::default()
+        };
+        Ok((private_dict, subrs))
     }
 }
 impl<'a> CffSlot<'a> {
     pub fn font_matrix(&self) -> Transform2F {
         self.top_dict.get(&Operator::FontMatrix)
-            .map(|arr| {
-                let (a, b, c, d, e, f) = TupleElements::from_iter(arr.iter().map(|&v| v.to_float())).unwrap();
-                Transform2F::row_major(a, b, e, c, d, f)
-            })
+            .and_then(|arr| TupleElements::from_iter(arr.iter().map(|&v| v.to_float())))
+            .map(|(a, b, c, d, e, f)| Transform2F::row_major(a, b, e, c, d, f))
             .unwrap_or(Transform2F::from_scale(Vector2F::splat(0.001)))
     }
     pub fn bbox(&self) -> Option<RectF> {
         self.top_dict.get(&Operator::FontBBox)
-            .map(|arr| {
-                let (a, b, c, d) = TupleElements::from_iter(arr.iter().map(|&v| v.to_float())).unwrap();
-                RectF::from_points(Vector2F::new(a, b), Vector2F::new(c, d))
-            })
-    }
-    // -> (outline, width, lsb)
-    pub fn outlines(&self) -> impl Iterator<Item=(Outline, f32, f32)> + '_ {
-        let n = self.top_dict.get(&Operator::CharstringType).map(|v| v[0].to_int()).unwrap_or(2);
-        let char_string_type = match n {
-            1 => CharstringType::Type1,
-            2 => CharstringType::Type2,
-            _ => panic!("invalid charstring type")
-        };
-        
-        let subr_bias = match char_string_type {
-            CharstringType::Type2 => bias(self.subrs.len()),
-            CharstringType::Type1 => 0
-        };
-        let global_subr_bias = match char_string_type {
-            CharstringType::Type2 => bias(self.cff.subroutines.len() as usize),
+            .and_then(|arr| TupleElements::from_iter(arr.iter().map(|&v| v.to_float())))
+            .map(|(a, b, c, d)| RectF::from_points(Vector2F::new(a, b), Vector2F::new(c, d)))
+    }
+    fn charstring_type(&self) -> Result<CharstringType, FontError> {
+        match self.top_dict.get(&Operator::CharstringType).map(|v| v.first().and_then(|v| v.to_int())).unwrap_or(Some(2)) {
+            Some(1) => Ok(CharstringType::Type1),
+            Some(2) => Ok(CharstringType::Type2),
+            _ => Err(FontError::Parse("invalid charstring type".into()))
+        }
+    }
+    /// The charstrings of the font, to be interpreted glyph by glyph. This copies the CFF data.
+    ///
+    /// Returns an error if the charstring type or the charset is invalid.
+    pub fn charstrings(&self) -> Result<Charstrings, FontError> {
+        let data = self.cff.data;
+        let ranges = |index: &Index| index.iter().map(|item| range_in(data, item)).collect::<Vec<_>>();
+        let char_string_type = self.charstring_type()?;
+        let bias_of = |num| match char_string_type {
+            CharstringType::Type2 => bias(num),
             CharstringType::Type1 => 0
         };
-
-        let context = Context {
-            subr_bias,
-            subrs: self.subrs.as_slice(),
-            global_subrs: self.cff.subroutines.as_slice(),
-            global_subr_bias
-        };
-        
-        let default_width = self.private_dict.get(&Operator::DefaultWidthX).map(|a| a[0].to_float()).unwrap_or(0.);
-        let nominal_width = self.private_dict.get(&Operator::NominalWidthX).map(|a| a[0].to_float()).unwrap_or(0.);
-        
-        // build glyphs
-        let mut state = State::new();
-        self.char_strings.iter().enumerate().map(move |(id, data)| {
-            trace!("charstring for glyph {}", id);
-            match char_string_type {
-                CharstringType::Type1 => {
-                    type1::charstring(data, &context, &mut state).expect("faild to parse charstring");
-                },
-                CharstringType::Type2 => {
-                    type2::charstring(data, &context, &mut state).expect("faild to parse charstring");
-                }
+        let fonts = self.private_dicts.iter().map(|(private_dict, subrs)| {
+            let default_width = private_dict.get(&Operator::DefaultWidthX).and_then(|a| a.first()).map(|a| a.to_float()).unwrap_or(0.);
+            let nominal_width = private_dict.get(&Operator::NominalWidthX).and_then(|a| a.first()).map(|a| a.to_float()).unwrap_or(0.);
+            (ranges(subrs), bias_of(subrs.len()), default_width, nominal_width)
+        }).collect();
+        let standard_glyphs = match self.is_cid_keyed() {
+            true => vec![],
+            false => {
+                let gids: HashMap<SID, u32> = self.charset()?.iter().enumerate().map(|(idx, &sid)| (sid, idx as u32 + 1)).collect();
+                STANDARD_ENCODING.iter().map(|&sid| gids.get(&sid).cloned().filter(|_| sid != 0)).collect()
             }
-            trace!("glyph {} {:?} {:?}", id, state.char_width, state.delta_width);
-            let width = match (state.char_width, state.delta_width) {
-                (Some(w), None) => w,
-                (None, None) => default_width,
-                (None, Some(delta)) => delta + nominal_width,
-                (Some(_), Some(_)) => panic!("BUG: both char_width and delta_width set")
-            };
-            let lsb = state.lsb.unwrap_or_default();
-            let path = state.take_path();
-            state.clear();
-            (path, width, lsb)
+        };
+        Ok(Charstrings {
+            data: Arc::from(data),
+            char_string_type,
+            char_strings: ranges(&self.char_strings),
+            global_subrs: ranges(&self.cff.subroutines),
+            global_subr_bias: bias_of(self.cff.subroutines.len()),
+            fonts,
+            fd_select: self.fd_select.clone(),
+            standard_glyphs,
+            cache: Arc::default(),
         })
     }
-    fn parse_font(&self) -> CffFont {
-        let glyph_name = |sid: SID|
-            STANDARD_STRINGS.get(sid as usize).cloned().unwrap_or_else(||
-                ::std::str::from_utf8(self.cff.string_index.get(sid as usize - STANDARD_STRINGS.len()).expect("no such string")).expect("Invalid glyph name")
-            );
-        let charset_offset = self.top_dict.get(&Operator::Charset).map(|v| v[0].to_int() as usize).unwrap_or(0);
-        let sids: Cow<[SID]> = match charset_offset {
+    /// (outline, advance, lsb, hints) of each glyph, `None` for broken charstrings
+    pub fn outlines(&self) -> Result<impl Iterator<Item=Option<(Outline, f32, f32, GlyphHints)>> + '_, FontError> {
+        let charstrings = self.charstrings()?;
+        Ok((0 .. self.num_glyphs as u32).map(move |gid| warn_invalid(charstrings.interpret(gid))))
+    }
+    pub fn num_glyphs(&self) -> usize {
+        self.num_glyphs
+    }
+    // the string with the given SID
+    fn string(&self, sid: usize) -> Option<String> {
+        let bytes = match STANDARD_STRINGS.get(sid) {
+            Some(s) => s.as_bytes(),
+            None => self.cff.string_index.get(sid - STANDARD_STRINGS.len())?
+        };
+        Some(String::from_utf8_lossy(bytes).into_owned())
+    }
+    // a string of the top dict, given by its SID
+    fn top_dict_string(&self, op: Operator) -> Option<String> {
+        let sid = self.top_dict.get(&op)?.get(0)?.to_uint()? as usize;
+        self.string(sid)
+    }
+    /// The weight class of the Weight entry (like "Bold")
+    pub fn weight(&self) -> Option<u16> {
+        weight_class_for_name(&self.top_dict_string(Operator::Weight)?)
+    }
+    /// The ItalicAngle entry, in degrees counterclockwise from the vertical
+    pub fn italic_angle(&self) -> f32 {
+        self.top_dict.get(&Operator::ItalicAngle).and_then(|arr| arr.first()).map(|v| v.to_float()).unwrap_or(0.)
+    }
+    /// The character collection of CID-keyed fonts
+    pub fn ros(&self) -> Option<Ros> {
+        match self.top_dict.get(&Operator::ROS)?.as_slice() {
+            &[registry, ordering, supplement] => Some(Ros {
+                registry: self.string(registry.to_uint()? as usize)?,
+                ordering: self.string(ordering.to_uint()? as usize)?,
+                supplement: supplement.to_int()?,
+            }),
+            _ => None
+        }
+    }
+    /// Whether glyphs are identified by CIDs instead of names
+    pub fn is_cid_keyed(&self) -> bool {
+        self.top_dict.contains_key(&Operator::ROS)
+    }
+    // the SID (or CID) of each glyph after .notdef
+    fn charset(&self) -> Result<Cow<[SID]>, FontError> {
+        let charset_offset = self.top_dict.get(&Operator::Charset).and_then(|v| v.first()?.to_uint()).unwrap_or(0) as usize;
+        Ok(match charset_offset {
             0 => ISO_ADOBE_CHARSET[..].into(),
             1 => EXPERT_CHARSET[..].into(),
             2 => EXPERT_SUBSET_CHARSET[..].into(),
             offset => {
-                let charset = charset(self.cff.data.get(offset ..).unwrap(), self.num_glyphs).get();
+                let data = self.cff.data.get(offset ..).ok_or(FontError::Truncated)?;
+                let charset = charset(data, self.num_glyphs).map_err(|_| FontError::Parse("invalid charset".into()))?.1;
                 
                 // index = gid - 1 -> sid
                 match charset {
                     Charset::Continous(sids) => sids,
                     Charset::Ranges(ranges) => ranges.into_iter()
-                        .flat_map(|(sid, num)| (sid .. sid + num + 1))
+                        .flat_map(|(sid, num)| (sid ..= sid.saturating_add(num)))
                         .collect(),
                 }.into()
             }
+        })
+    }
+    /// cid -> gid, if the font is CID-keyed, or an error if its charset is broken
+    pub fn cid_map(&self) -> Result<Option<HashMap<u16, u16>>, FontError> {
+        if !self.is_cid_keyed() {
+            return Ok(None);
+        }
+        Ok(Some(once(0).chain(self.charset()?.iter().cloned()).take(self.num_glyphs).enumerate()
+            .map(|(gid, cid)| (cid, gid as u16))
+            .collect()))
+    }
+    /// The PostScript name (from the name index), and the full and family name of the top dict
+    pub fn name(&self) -> Name {
+        Name {
+            postscript_name: self.font_name.map(|name| String::from_utf8_lossy(name).into_owned()),
+            full_name: self.top_dict_string(Operator::FullName),
+            family: self.top_dict_string(Operator::FamilyName),
+            .. Name::default()
+        }
+    }
+    fn parse_font(&self) -> Result<CffFont, FontError> {
+        let glyph_name = |sid: SID| match STANDARD_STRINGS.get(sid as usize) {
+            Some(&name) => Ok(name),
+            None => self.cff.string_index.get(sid as usize - STANDARD_STRINGS.len())
+                .and_then(|name| ::std::str::from_utf8(name).ok())
+                .ok_or_else(|| FontError::Parse(format!("invalid glyph name {}", sid)))
+        };
+        let cid_map = self.cid_map()?;
+        // CID-keyed fonts have neither glyph names nor an encoding
+        let sids: Cow<[SID]> = match cid_map {
+            Some(_) => Cow::Borrowed(&[]),
+            None => self.charset()?
         };
         
         // sid -> gid
         let sid_map: HashMap<SID, u16> = once(0).chain(sids.iter().cloned()).enumerate()
             .map(|(gid, sid)| (sid as u16, gid as u16))
-            .inspect(|&(sid, gid)| debug!("sid {} ({}) -> gid {}", sid, glyph_name(sid), gid))
+            .inspect(|&(sid, gid)| debug!("sid {} ({:?}) -> gid {}", sid, glyph_name(sid).ok(), gid))
             .collect();
         let name_map: HashMap<_, _> = once(0).chain(sids.iter().cloned()).enumerate()
-            .map(|(gid, sid)| (glyph_name(sid).to_owned(), gid as u16))
-            .collect();
+            .map(|(gid, sid)| Ok((glyph_name(sid)?.to_owned(), gid as u16)))
+            .collect::<Result<_, FontError>>()?;
     
         let build_default = |encoding: &[SID; 256]| -> [u16; 256] {
             let mut cmap = [0u16; 256];
@@ -269,56 +494,55 @@ impl<'a> CffSlot<'a> {
             cmap
         };
         
-        let (cmap, encoding) = match self.top_dict.get(&Operator::Encoding).map(|a| a[0].to_int()) {
+        let (cmap, encoding) = match self.top_dict.get(&Operator::Encoding).and_then(|a| a.first()?.to_int()) {
+            _ if cid_map.is_some() => ([0; 256], None),
             None | Some(0)
                 => (build_default(&STANDARD_ENCODING), Some(Encoding::AdobeStandard)),
             Some(1)
                 => (build_default(&EXPERT_ENCODING), Some(Encoding::AdobeExpert)),
             Some(offset) => {
                 let mut cmap = [0u16; 256];
-                let (codepoints, supplement) = parse_encoding(self.cff.data.get(offset as _ ..).unwrap()).get();
+                let data = self.cff.data.get(offset as usize ..).ok_or(FontError::Truncated)?;
+                let (codepoints, supplement) = parse_encoding(data).map_err(|_| FontError::Parse("invalid encoding".into()))?.1;
                 // encodings start at gid 1
                 match codepoints {
                     GlyphEncoding::Continous(codepoints) => codepoints.iter()
                         .enumerate().for_each(|(gid, &codepoint)| cmap[codepoint as usize] = gid as u16 + 1),
                     GlyphEncoding::Ranges(ranges) => ranges.iter()
-                        .flat_map(|&(first, left)| (first ..).take(left as usize + 1))
-                        .enumerate().for_each(|(gid, codepoint)| cmap[codepoint as usize] = gid as u16 + 1),
+                        .flat_map(|&(first, left)| (first as usize ..= (first as usize + left as usize).min(255)))
+                        .enumerate().for_each(|(gid, codepoint)| cmap[codepoint] = gid as u16 + 1),
+                }
+                for &(codepoint, sid) in supplement.iter() {
+                    match sid_map.get(&sid) {
+                        Some(&gid) => cmap[codepoint as usize] = gid,
+                        None => warn!("encoding supplement: no glyph for SID {}", sid)
+                    }
                 }
-                supplement.iter()
-                    .for_each(|&(codepoint, sid)| cmap[codepoint as usize] = sid_map[&sid]);
                     
                 (cmap, None)
             }
         };
         debug!("cmap:");
         for (i, &gid) in cmap.iter().enumerate() {
-            if gid != 0 {
-                let sid = sids[gid as usize - 1];
-                debug!("{} -> gid={}, sid={}, name={}", i, gid, sid, glyph_name(sid));
+            if let Some(&sid) = sids.get((gid as usize).wrapping_sub(1)) {
+                debug!("{} -> gid={}, sid={}, name={:?}", i, gid, sid, glyph_name(sid).ok());
             }
         }
         
-        let glyphs = self.outlines().map(|(outline, width, lsb)| {
-            Glyph {
-                metrics: HMetrics {
-                    advance: width,
-                    lsb
-                },
-                path: outline
-            }
-        }).collect();
-        
-        CffFont {
-            glyphs,
+        Ok(CffFont {
+            charstrings: self.charstrings()?,
             font_matrix: self.font_matrix(),
             codepoint_map: cmap,
             name_map,
             encoding,
             bbox: self.bbox(),
             vmetrics: None,
-            name: Name::default(),
-        }
+            name: self.name(),
+            cid_map,
+            ros: self.ros(),
+            weight: self.weight(),
+            italic: self.italic_angle() != 0.,
+        })
     }
 }
 
@@ -335,21 +559,145 @@ fn dict(mut input: &[u8]) -> R<HashMap<Operator, Vec<Value>>> {
     Ok((input, map))
 }
 
+#[derive(Copy, Clone)]
 enum CharstringType {
     Type1,
     Type2
 }
 
+// the position of `part` in `data`, which contains it
+fn range_in(data: &[u8], part: &[u8]) -> Range<usize> {
+    let start = part.as_ptr() as usize - data.as_ptr() as usize;
+    start .. start + part.len()
+}
+
+// the items of an index, given by their position in `data`
+struct IndexRanges<'a> {
+    data: &'a [u8],
+    items: &'a [Range<usize>],
+}
+impl<'a> TryIndex for IndexRanges<'a> {
+    #[inline]
+    fn try_index(&self, idx: usize) -> Option<&[u8]> {
+        self.data.get(self.items.get(idx)?.clone())
+    }
+}
+
+/// The charstrings of a CFF font, interpreted when a glyph is first asked for.
+///
+/// Holds its own copy of the CFF data. Clones share it, and the interpreted glyphs.
+#[derive(Clone)]
+pub struct Charstrings {
+    data: Arc<[u8]>,
+    char_string_type: CharstringType,
+    char_strings: Vec<Range<usize>>,
+    global_subrs: Vec<Range<usize>>,
+    global_subr_bias: i32,
+    // (local subroutines, their bias, default width, nominal width) of each font dict
+    fonts: Vec<(Vec<Range<usize>>, i32, f32, f32)>,
+    // font dict of each glyph (CID-keyed fonts only)
+    fd_select: Option<Vec<u8>>,
+    // glyph of each code of the standard encoding, for accented characters (not in CID-keyed fonts)
+    standard_glyphs: Vec<Option<u32>>,
+    // gid -> (outline, advance, lsb, hints)
+    cache: Arc<RwLock<HashMap<u32, (Outline, f32, f32, GlyphHints)>>>,
+}
+impl Charstrings {
+    /// The number of glyphs, including .notdef
+    pub fn len(&self) -> usize {
+        self.char_strings.len()
+    }
+    /// (outline, advance, lsb, hints) of glyph `gid`, interpreted when it is first used.
+    ///
+    /// Returns `None` if there is no such glyph or its charstring is broken.
+    pub fn get(&self, gid: u32) -> Option<(Outline, f32, f32, GlyphHints)> {
+        warn_invalid(self.try_get(gid))
+    }
+    /// Like `get`, but tells a missing glyph from a broken one.
+    pub fn try_get(&self, gid: u32) -> Result<(Outline, f32, f32, GlyphHints), GlyphError> {
+        if let Some(glyph) = self.cache.read().unwrap().get(&gid) {
+            return Ok(glyph.clone());
+        }
+        let glyph = self.interpret(gid)?;
+        self.cache.write().unwrap().insert(gid, glyph.clone());
+        Ok(glyph)
+    }
+    /// The base and accent glyph of an accented character (seac), which are part of its outline.
+    pub fn components(&self, gid: u32) -> Option<(u32, u32)> {
+        let (_, base, accent) = self.run(gid).ok()?.4?;
+        Some((self.standard_glyph(base)?, self.standard_glyph(accent)?))
+    }
+    fn standard_glyph(&self, code: u8) -> Option<u32> {
+        self.standard_glyphs.get(code as usize).cloned().flatten()
+    }
+    fn interpret(&self, gid: u32) -> Result<(Outline, f32, f32, GlyphHints), GlyphError> {
+        let (mut path, width, lsb, hints, seac) = self.run(gid)?;
+        if let Some((offset, base, accent)) = seac {
+            // base and accent are not accented themselves
+            for &(code, offset) in [(base, Vector2F::default()), (accent, offset)].iter() {
+                let component = self.standard_glyph(code).and_then(|gid| warn_invalid(self.run(gid)));
+                match component {
+                    Some((mut component, ..)) => {
+                        component.transform(&Transform2F::from_translation(offset));
+                        path.push_outline(component);
+                    }
+                    None => warn!("seac of glyph {}: no glyph for code {}", gid, code)
+                }
+            }
+        }
+        Ok((path, width, lsb, hints))
+    }
+    // (outline, advance, lsb, hints, seac) of the charstring alone
+    fn run(&self, gid: u32) -> Result<(Outline, f32, f32, GlyphHints, Option<(Vector2F, u8, u8)>), GlyphError> {
+        let invalid = |msg: &str| GlyphError::Invalid(GlyphId(gid), msg.into());
+        let range = self.char_strings.get(gid as usize).ok_or(GlyphError::Missing(GlyphId(gid)))?;
+        let data = self.data.get(range.clone()).ok_or_else(|| invalid("charstring out of bounds"))?;
+        let fd = match self.fd_select {
+            Some(ref fd_select) => *fd_select.get(gid as usize).ok_or_else(|| invalid("no font dict"))? as usize,
+            None => 0
+        };
+        let (ref subrs, subr_bias, default_width, nominal_width) = *self.fonts.get(fd).ok_or_else(|| invalid("no font dict"))?;
+        let context = Context {
+            subr_bias,
+            subrs: IndexRanges { data: &self.data, items: subrs },
+            global_subrs: IndexRanges { data: &self.data, items: &self.global_subrs },
+            global_subr_bias: self.global_subr_bias,
+        };
+
+        trace!("charstring for glyph {}", gid);
+        let mut state = State::new();
+        let result = match self.char_string_type {
+            CharstringType::Type1 => type1::charstring(data, &context, &mut state),
+            CharstringType::Type2 => type2::charstring(data, &context, &mut state)
+        };
+        if result.is_err() {
+            return Err(invalid("broken charstring"));
+        }
+        trace!("glyph {} {:?} {:?}", gid, state.char_width, state.delta_width);
+        let width = match (state.char_width, state.delta_width) {
+            (Some(w), None) => w,
+            (None, None) => default_width,
+            (None, Some(delta)) => delta + nominal_width,
+            (Some(_), Some(_)) => return Err(invalid("both an absolute and a relative width"))
+        };
+        let lsb = state.lsb.unwrap_or_default();
+        let path = state.take_path();
+        Ok((path, width, lsb, state.hints.clone(), state.seac))
+    }
+}
+
     
 fn index(i: &[u8]) -> R<Vec<&[u8]>> {
     let (i, n) = map(be_u16, |n| n as usize)(i)?;
     debug!("n={}", n);
     if n != 0 {
         let (i, offSize) = be_u8(i)?;
-        let (i, offsets) = count(map(offset(offSize), |o| o - 1), n+1)(i)?;
+        // offsets start at 1. a zero offset wraps around and fails below
+        let (i, offsets) = count(map(offset(offSize), |o| o.wrapping_sub(1)), n+1)(i)?;
         let (i, data) = take(offsets[n])(i)?;
         
-        let items = offsets.windows(2).map(|w| data.get(w[0] as usize .. w[1] as usize).unwrap()).collect();
+        let items = offsets.windows(2).map(|w| data.get(w[0] as usize .. w[1] as usize)).collect::<Option<_>>()
+            .ok_or_else(|| Failure(make_error(i, ErrorKind::Verify)))?;
         Ok((i, items))
     } else {
         Ok((i, vec![]))
@@ -368,11 +716,12 @@ fn offset(size: u8) -> impl Fn(&[u8]) -> R<u32> {
 
 fn float(data: &[u8]) -> R<f32> {
     let mut pos = 0;
-    let mut next_nibble = || -> u8 {
-        let nibble = (data[pos/2] >> (4 * (1 - (pos & 1)) as u8)) & 0xf;
+    let mut next_nibble = || -> Option<u8> {
+        let nibble = (data.get(pos/2)? >> (4 * (1 - (pos & 1)) as u8)) & 0xf;
         pos += 1;
-        nibble
+        Some(nibble)
     };
+    let invalid = || Err(Failure(make_error(data, ErrorKind::Float)));
     
     let mut is_negaive = false;
     let mut num_digits = 0;
@@ -380,27 +729,27 @@ fn float(data: &[u8]) -> R<f32> {
     let mut p: i32 = 0;
     let mut power_negative = false;
     let mut decimal_point = None;
-    loop {
+    'number: loop {
         match next_nibble() {
-            d @ 0 ..= 9 => {
-                n = 10 * n + d as i32;
+            Some(d @ 0 ..= 9) => {
+                n = n.saturating_mul(10).saturating_add(d as i32);
                 num_digits += 1;
             }
-            0xa => decimal_point = Some(num_digits),
-            b @ 0xb | b @ 0xc  => { // positive 10^x
+            Some(0xa) => decimal_point = Some(num_digits),
+            Some(b @ 0xb) | Some(b @ 0xc)  => { // positive 10^x
                 power_negative = b == 0xc;
                 loop {
                     match next_nibble() {
-                        d @ 0 ..= 9 => p = 10 * p + d as i32,
-                        0xf => break,
-                        _ => panic!("invalid float")
+                        Some(d @ 0 ..= 9) => p = p.saturating_mul(10).saturating_add(d as i32),
+                        Some(0xf) => break 'number,
+                        _ => return invalid()
                     }
                 }
             },
-            0xd => panic!("reserved"),
-            0xe => is_negaive = true,
-            0xf => break,
-            _ => unreachable!()
+            Some(0xe) => is_negaive = true,
+            Some(0xf) => break,
+            // 0xd is reserved
+            _ => return invalid()
         }
     }
     
@@ -416,7 +765,7 @@ fn float(data: &[u8]) -> R<f32> {
         if power_negative {
             p *= -1;
         }
-        power += p;
+        power = power.saturating_add(p);
     }
     if power != 0 {
         value *= 10.0f32.powi(power);
@@ -429,15 +778,15 @@ fn value(input: &[u8]) -> R<Value> {
     let (i, b0) = be_u8(input)?;
     
     match b0 {
-        22 ..= 27 => panic!("reserved"),
+        22 ..= 27 => Err(Failure(make_error(input, ErrorKind::Switch))),
         28 => map(be_i16, |n| n.into())(i),
         29 => map(be_i32, |n| n.into())(i),
         30 => map(float, |f| f.into())(i),
-        31 => panic!("reserved"),
+        31 => Err(Failure(make_error(input, ErrorKind::Switch))),
         b0 @ 32 ..= 246 => Ok((i, (b0 as i32 - 139).into())),
         b0 @ 247 ..= 250 => map(be_u8, |b1| ((b0 as i32 - 247) * 256 + b1 as i32 + 108).into())(i),
         b0 @ 251 ..= 254 => map(be_u8, |b1| (-(b0 as i32 - 251) * 256 - b1 as i32 - 108).into())(i),
-        255 => panic!("reserved"),
+        255 => Err(Failure(make_error(input, ErrorKind::Switch))),
         _ => Err(Error(make_error(input, ErrorKind::TooLarge))) 
     }
 }
@@ -477,6 +826,8 @@ enum Operator {
     CIDCount,
     UIDBase,
     FDArray,
+    FDSelect,
+    FontName,
     
     BlueValues,
     OtherBlues,
@@ -547,6 +898,8 @@ fn operator(input: &[u8]) -> R<Operator> {
                 34 => (i, CIDCount),
                 35 => (i, UIDBase),
                 36 => (i, FDArray),
+                37 => (i, FDSelect),
+                38 => (i, FontName),
                 _ => return Err(nom::Err::Failure(make_error(input, ErrorKind::TooLarge)))
             }
         }
@@ -586,7 +939,7 @@ fn ranges<'a, F>(count_parser: F, num_glyphs: usize) -> impl Fn(&'a [u8]) -> R<'
             total += n as usize + 1;
             input = i;
             
-            if total >= num_glyphs - 1 {
+            if total >= num_glyphs.saturating_sub(1) {
                 break;
             }
         }
@@ -598,7 +951,7 @@ fn charset(i: &[u8], num_glyphs: usize) -> R<Charset> {
     
     match format {
         0 => {
-            map(count(be_u16, num_glyphs as usize - 1), |a| Charset::Continous(a))(i)
+            map(count(be_u16, num_glyphs.saturating_sub(1)), |a| Charset::Continous(a))(i)
         },
         1 => {
             map(ranges(map(be_u8, |n| n as u16), num_glyphs), |r| Charset::Ranges(r))(i)
@@ -606,7 +959,222 @@ fn charset(i: &[u8], num_glyphs: usize) -> R<Charset> {
         2 => {
             map(ranges(be_u16, num_glyphs), |r| Charset::Ranges(r))(i)
         },
-        _ => panic!("invalid charset format")
+        _ => Err(Failure(make_error(i, ErrorKind::Switch)))
+    }
+}
+
+// the font dict index of each glyph
+fn fd_select(i: &[u8], num_glyphs: usize) -> R<Vec<u8>> {
+    let (i, format) = be_u8(i)?;
+    match format {
+        0 => count(be_u8, num_glyphs)(i),
+        3 => {
+            let (i, num_ranges) = be_u16(i)?;
+            let (i, ranges) = count(tuple((be_u16, be_u8)), num_ranges as usize)(i)?;
+            let (i, sentinel) = be_u16(i)?;
+            let mut fds = vec![0; num_glyphs];
+            let ends = ranges.iter().skip(1).map(|&(first, _)| first).chain(once(sentinel));
+            for (&(first, fd), end) in ranges.iter().zip(ends) {
+                for gid in first as usize .. (end as usize).min(num_glyphs) {
+                    fds[gid] = fd;
+                }
+            }
+            Ok((i, fds))
+        }
+        _ => Err(Failure(make_error(i, ErrorKind::Switch)))
+    }
+}
+
+// the entries of a dict as (operator, encoded operands). Two byte operators are 12 << 8 | op.
+fn raw_dict(data: &[u8]) -> Option<Vec<(u16, &[u8])>> {
+    let mut entries = vec![];
+    let (mut start, mut pos) = (0, 0);
+    while pos < data.len() {
+        pos += match data[pos] {
+            12 => {
+                entries.push((12 << 8 | *data.get(pos + 1)? as u16, &data[start .. pos]));
+                start = pos + 2;
+                2
+            }
+            op @ 0 ..= 21 => {
+                entries.push((op as u16, &data[start .. pos]));
+                start = pos + 1;
+                1
+            }
+            28 => 3,
+            29 => 5,
+            // nibbles up to the 0xf nibble
+            30 => 2 + data.get(pos + 1 ..)?.iter().position(|&b| b >> 4 == 0xf || b & 0xf == 0xf)?,
+            32 ..= 246 => 1,
+            247 ..= 254 => 2,
+            _ => return None
+        };
+    }
+    if pos > data.len() {
+        return None;
+    }
+    Some(entries)
+}
+
+fn push_operator(data: &mut Vec<u8>, op: u16) {
+    if op > 0xFF {
+        data.push(12);
+    }
+    data.push(op as u8);
+}
+
+fn write_dict<'a>(entries: impl IntoIterator<Item=(u16, &'a [u8])>) -> Vec<u8> {
+    let mut data = vec![];
+    for (op, operands) in entries {
+        data.extend_from_slice(operands);
+        push_operator(&mut data, op);
+    }
+    data
+}
+
+// append an entry with integers of fixed size, so that offsets can be written before they are known
+fn push_offsets(data: &mut Vec<u8>, op: u16, offsets: &[usize]) {
+    for &n in offsets {
+        data.push(29);
+        data.extend_from_slice(&(n as u32).to_be_bytes());
+    }
+    push_operator(data, op);
+}
+
+fn write_index(items: &[&[u8]]) -> Vec<u8> {
+    let mut data = (items.len() as u16).to_be_bytes().to_vec();
+    if items.is_empty() {
+        return data;
+    }
+    let end = items.iter().map(|item| item.len()).sum::<usize>() + 1;
+    let off_size = match end {
+        0 ..= 0xFF => 1,
+        0x100 ..= 0xFFFF => 2,
+        0x10000 ..= 0xFFFFFF => 3,
+        _ => 4
+    };
+    data.push(off_size as u8);
+    let mut offset = 1;
+    for len in once(0).chain(items.iter().map(|item| item.len())) {
+        offset += len;
+        data.extend_from_slice(&(offset as u32).to_be_bytes()[4 - off_size ..]);
+    }
+    for item in items {
+        data.extend_from_slice(item);
+    }
+    data
+}
+
+impl<'a> CffSlot<'a> {
+    /// The CFF data of a font with only the glyphs `gids`, renumbered in this order. `gids` has to start with .notdef.
+    ///
+    /// The charset and FDSelect are rewritten, the encoding is dropped (OpenType fonts use their `cmap`)
+    /// and all subroutines are kept.
+    pub fn subset(&self, gids: &[u32]) -> Result<Vec<u8>, FontError> {
+        let invalid = |msg: &str| FontError::Parse(msg.into());
+        let raw = |data: &'a [u8]| raw_dict(data).ok_or_else(|| invalid("invalid dict"));
+        let data = self.cff.data;
+
+        let char_strings = gids.iter()
+            .map(|&gid| self.char_strings.get(gid as usize).cloned().ok_or_else(|| invalid("glyph not found")))
+            .collect::<Result<Vec<_>, _>>()?;
+        let char_strings = write_index(&char_strings);
+        // format 0: the SID (or CID) of every glyph but .notdef
+        let charset = self.charset()?;
+        let mut charset_data = vec![0];
+        for &gid in gids.iter().skip(1) {
+            let sid = charset.get(gid as usize - 1).cloned().unwrap_or(0);
+            charset_data.extend_from_slice(&sid.to_be_bytes());
+        }
+        let fd_select = self.fd_select.as_ref().map(|fd_select| {
+            once(0).chain(gids.iter().map(|&gid| fd_select[gid as usize])).collect::<Vec<u8>>()
+        });
+
+        // the dicts that have a private dict: each font dict of the FDArray, or the top dict
+        let font_dicts = match self.top_dict.get(&Operator::FDArray) {
+            Some(offset) => offset.first().and_then(|offset| offset.to_uint()).and_then(|offset| data.get(offset as usize ..)).and_then(|data| index(data).ok())
+                .ok_or(FontError::Truncated)?.1,
+            None => vec![self.top_dict_data]
+        };
+        // (private dict, local subroutines right after it)
+        let mut privates = Vec::with_capacity(font_dicts.len());
+        for (&font_dict, &(_, ref subrs)) in font_dicts.iter().zip(&self.private_dicts) {
+            let entry = dict(font_dict).map_err(|_| invalid("invalid font dict"))?.1.remove(&Operator::Private)
+                .filter(|entry| entry.len() >= 2)
+                .ok_or_else(|| invalid("no private dict entry"))?;
+            let (size, offset) = match (entry[0].to_uint(), entry[1].to_uint()) {
+                (Some(size), Some(offset)) => (size as usize, offset as usize),
+                _ => return Err(invalid("invalid private dict entry"))
+            };
+            let private_data = data.get(offset .. offset + size).ok_or(FontError::Truncated)?;
+            let mut private = write_dict(raw(private_data)?.into_iter().filter(|&(op, _)| op != 19));
+            let subrs = match subrs.len() {
+                0 => vec![],
+                _ => {
+                    let size = private.len() + 6;
+                    push_offsets(&mut private, 19, &[size]);
+                    write_index(subrs)
+                }
+            };
+            privates.push((private, subrs));
+        }
+
+        let mut top_dict = write_dict(raw(self.top_dict_data)?.into_iter()
+            .filter(|&(op, _)| !matches!(op, 15 ..= 18 | 0x0C24 | 0x0C25)));
+        // charset, CharStrings and Private or FDArray and FDSelect
+        let top_dict_len = top_dict.len() + if fd_select.is_some() { 26 } else { 23 };
+
+        let name_index = write_index(&[self.font_name.unwrap_or_default()]);
+        let string_index = write_index(&self.cff.string_index);
+        let global_subrs = write_index(&self.cff.subroutines);
+        let mut offset = 4 + name_index.len() + write_index(&[&vec![0; top_dict_len][..]]).len()
+            + string_index.len() + global_subrs.len();
+        let mut next = |len: usize| {
+            offset += len;
+            offset - len
+        };
+        let charset_offset = next(charset_data.len());
+        let fd_select_offset = next(fd_select.as_ref().map(|fds| fds.len()).unwrap_or(0));
+        let char_strings_offset = next(char_strings.len());
+        let private_offsets: Vec<usize> = privates.iter().map(|(private, subrs)| next(private.len() + subrs.len())).collect();
+        let fd_array_offset = next(0);
+
+        push_offsets(&mut top_dict, 15, &[charset_offset]);
+        push_offsets(&mut top_dict, 17, &[char_strings_offset]);
+        let fd_array = match fd_select {
+            Some(_) => {
+                push_offsets(&mut top_dict, 0x0C24, &[fd_array_offset]);
+                push_offsets(&mut top_dict, 0x0C25, &[fd_select_offset]);
+                let font_dicts = font_dicts.iter().zip(&privates).zip(&private_offsets)
+                    .map(|((&font_dict, (private, _)), &offset)| {
+                        let mut font_dict = write_dict(raw(font_dict)?.into_iter().filter(|&(op, _)| op != 18));
+                        push_offsets(&mut font_dict, 18, &[private.len(), offset]);
+                        Ok(font_dict)
+                    })
+                    .collect::<Result<Vec<_>, FontError>>()?;
+                write_index(&font_dicts.iter().map(|d| d.as_slice()).collect::<Vec<_>>())
+            }
+            None => {
+                push_offsets(&mut top_dict, 18, &[privates[0].0.len(), private_offsets[0]]);
+                vec![]
+            }
+        };
+        debug_assert_eq!(top_dict.len(), top_dict_len);
+
+        let mut out = vec![1, 0, 4, 4];
+        out.extend(name_index);
+        out.extend(write_index(&[&top_dict[..]]));
+        out.extend(string_index);
+        out.extend(global_subrs);
+        out.extend(charset_data);
+        out.extend(fd_select.unwrap_or_default());
+        out.extend(char_strings);
+        for (private, subrs) in privates {
+            out.extend(private);
+            out.extend(subrs);
+        }
+        out.extend(fd_array);
+        Ok(out)
     }
 }
 
@@ -628,7 +1196,7 @@ fn parse_encoding(i: &[u8]) -> R<(GlyphEncoding, Vec<(u8, SID)>)> {
             let (i, num) = be_u8(i)?;
             map(count(tuple((be_u8, be_u8)), num as usize), |a| GlyphEncoding::Ranges(a))(i)?
         }
-        _ => panic!("invalid charset format")
+        _ => return Err(Failure(make_error(i, ErrorKind::Switch)))
     };
     if format & 0x80 != 0 {
         let (i, n) = be_u8(i)?;
@@ -1043,3 +1611,122 @@ static ISO_ADOBE_CHARSET: [SID; 228] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 1
 static EXPERT_CHARSET: [SID; 165] = [1, 229, 230, 231, 232, 233, 234, 235, 236, 237, 238, 13, 14, 15, 99, 239, 240, 241, 242, 243, 244, 245, 246, 247, 248, 27, 28, 249, 250, 251, 252, 253, 254, 255, 256, 257, 258, 259, 260, 261, 262, 263, 264, 265, 266, 109, 110, 267, 268, 269, 270, 271, 272, 273, 274, 275, 276, 277, 278, 279, 280, 281, 282, 283, 284, 285, 286, 287, 288, 289, 290, 291, 292, 293, 294, 295, 296, 297, 298, 299, 300, 301, 302, 303, 304, 305, 306, 307, 308, 309, 310, 311, 312, 313, 314, 315, 316, 317, 318, 158, 155, 163, 319, 320, 321, 322, 323, 324, 325, 326, 150, 164, 169, 327, 328, 329, 330, 331, 332, 333, 334, 335, 336, 337, 338, 339, 340, 341, 342, 343, 344, 345, 346, 347, 348, 349, 350, 351, 352, 353, 354, 355, 356, 357, 358, 359, 360, 361, 362, 363, 364, 365, 366, 367, 368, 369, 370, 371, 372, 373, 374, 375, 376, 377, 378];
 
 static EXPERT_SUBSET_CHARSET: [SID; 86] =[1, 231, 232, 235, 236, 237, 238, 13, 14, 15, 99, 239, 240, 241, 242, 243, 244, 245, 246, 247, 248, 27, 28, 249, 250, 251, 253, 254, 255, 256, 257, 258, 259, 260, 261, 262, 263, 264, 265, 266, 109, 110, 267, 268, 269, 270, 272, 300, 301, 302, 305, 314, 315, 158, 155, 163, 320, 321, 322, 323, 324, 325, 326, 150, 164, 169, 327, 328, 329, 330, 331, 332, 333, 334, 335, 336, 337, 338, 339, 340, 341, 342, 343, 344, 345, 346];
+
+#[test]
+fn test_cid_keyed() {
+    let data = [
+        1, 0, 4, 1, // header
+        0, 1, 1, 1, 2, b'A', // name index
+        0, 1, 1, 1, 34, // top dict index
+        248, 27, 248, 28, 139, 12, 30, // ROS: Adobe Identity 0
+        29, 0, 0, 0, 69, 15, // charset
+        29, 0, 0, 0, 85, 17, // CharStrings
+        29, 0, 0, 0, 97, 12, 36, // FDArray
+        29, 0, 0, 0, 74, 12, 37, // FDSelect
+        0, 2, 1, 1, 6, 14, b'A', b'd', b'o', b'b', b'e', b'I', b'd', b'e', b'n', b't', b'i', b't', b'y', // string index
+        0, 0, // global subroutines
+        0, 0, 10, 0, 20, // charset: cids 10 and 20
+        3, 0, 2, 0, 0, 0, 0, 2, 1, 0, 3, // FDSelect: glyphs 0 and 1 use font dict 0, glyph 2 font dict 1
+        0, 3, 1, 1, 2, 3, 6, 14, 14, 32, 10, 14, // CharStrings: endchar, endchar, 0 callsubr endchar
+        0, 2, 1, 1, 8, 15, // FDArray
+        142, 29, 0, 0, 0, 117, 18, // Private of 3 bytes at 117
+        144, 29, 0, 0, 0, 120, 18, // Private of 5 bytes at 120
+        248, 136, 20, // defaultWidthX 500
+        250, 124, 20, 144, 19, // defaultWidthX 1000, Subrs
+        0, 1, 1, 1, 14, // local subroutines of font dict 1
+        239, 239, 21, 248, 136, 6, 248, 136, 7, 252, 136, 6, 11,
+    ];
+    let font = CffFont::parse(&data, 0);
+    // nothing is interpreted until a glyph is asked for
+    assert!(font.charstrings.cache.read().unwrap().is_empty());
+    assert_eq!(font.ros(), Some(&Ros { registry: "Adobe".into(), ordering: "Identity".into(), supplement: 0 }));
+    assert_eq!(font.gid_for_cid(0), Some(GlyphId(0)));
+    assert_eq!(font.gid_for_cid(20), Some(GlyphId(2)));
+    assert_eq!(font.gid_for_cid(11), None);
+    assert_eq!(font.gid_for_cid(0x10014), None);
+    assert_eq!(font.glyph(GlyphId(1)).unwrap().metrics.advance, 500.);
+
+    let glyph = font.glyph(GlyphId(2)).unwrap();
+    assert_eq!(glyph.metrics.advance, 1000.);
+    assert_eq!(glyph.path.bounds(), RectF::from_points(Vector2F::new(100., 100.), Vector2F::new(600., 600.)));
+    assert_eq!(font.advance(GlyphId(2)), Some(1000.));
+    assert_eq!(font.charstrings.cache.read().unwrap().len(), 2);
+}
+
+#[test]
+fn test_seac() {
+    let data = [
+        1, 0, 4, 1, // header
+        0, 1, 1, 1, 2, b'A', // name index
+        0, 1, 1, 1, 16, // top dict index
+        29, 0, 0, 0, 34, 15, 29, 0, 0, 0, 41, 17, 139, 139, 18, // charset, CharStrings, empty Private
+        0, 0, // string index
+        0, 0, // global subroutines
+        0, 0, 34, 0, 125, 0, 171, // charset: A acute Aacute
+        0, 4, 1, 1, 2, 14, 25, 31, // CharStrings index
+        14, // .notdef
+        139, 139, 21, 248, 36, 6, 251, 92, 247, 192, 5, 14, // 0 0 rmoveto 400 hlineto -200 300 rlineto endchar
+        247, 42, 248, 36, 21, 239, 6, 139, 239, 5, 14, // 150 400 rmoveto 100 hlineto 0 100 rlineto endchar
+        189, 239, 204, 247, 86, 14, // 50 100 65 194 endchar: A with acute moved by (50, 100)
+    ];
+    let font = CffFont::parse(&data, 0);
+    assert_eq!(font.charstrings.components(3), Some((1, 2)));
+    assert_eq!(font.charstrings.components(1), None);
+
+    let glyph = font.glyph(GlyphId(3)).unwrap();
+    assert_eq!(glyph.path.contours().len(), 2);
+    assert_eq!(glyph.path.bounds(), RectF::from_points(Vector2F::new(0., 0.), Vector2F::new(400., 600.)));
+}
+
+#[test]
+fn test_broken_charstring() {
+    let data = [
+        1, 0, 4, 1, // header
+        0, 1, 1, 1, 2, b'A', // name index
+        0, 1, 1, 1, 16, // top dict index
+        29, 0, 0, 0, 34, 15, 29, 0, 0, 0, 41, 17, 139, 139, 18, // charset, CharStrings, empty Private
+        0, 0, // string index
+        0, 0, // global subroutines
+        0, 0, 34, 0, 125, 0, 171, // charset: A acute Aacute
+        0, 4, 1, 1, 2, 14, 25, 31, // CharStrings index
+        14, // .notdef
+        139, 139, 21, 248, 36, 6, 251, 92, 247, 192, 5, 14, // 0 0 rmoveto 400 hlineto -200 300 rlineto endchar
+        247, 42, 248, 36, 21, 239, 6, 139, 239, 5, 14, // 150 400 rmoveto 100 hlineto 0 100 rlineto endchar
+        21, 14, 14, 14, 14, 14, // rmoveto without operands
+    ];
+    // broken glyphs are only an error when they are checked
+    let options = crate::ParseOptions { check_glyphs: true, .. crate::ParseOptions::default() };
+    assert!(matches!(crate::try_parse_with_options(&data, &options), Err(FontError::Parse(_))));
+
+    // the other glyphs can still be used
+    let font = CffFont::try_parse(&data, 0).unwrap();
+    assert!(font.glyph(GlyphId(1)).is_some());
+    assert!(font.glyph(GlyphId(3)).is_none());
+    assert_eq!(font.charstrings.components(3), None);
+
+    assert!(matches!(font.try_glyph(GlyphId(3)), Err(GlyphError::Invalid(GlyphId(3), _))));
+    assert!(matches!(font.try_glyph(GlyphId(4)), Err(GlyphError::Missing(GlyphId(4)))));
+    let glyphs = font.parse_all_glyphs();
+    assert_eq!(glyphs.len(), 4);
+    assert!(glyphs[.. 3].iter().all(|glyph| glyph.is_ok()));
+    assert!(matches!(glyphs[3], Err(GlyphError::Invalid(GlyphId(3), _))));
+}
+
+#[test]
+fn test_invalid_charset() {
+    let data = [
+        1, 0, 4, 1, // header
+        0, 1, 1, 1, 2, b'A', // name index
+        0, 1, 1, 1, 16, // top dict index
+        29, 0, 0, 0, 34, 15, 29, 0, 0, 0, 41, 17, 139, 139, 18, // charset, CharStrings, empty Private
+        0, 0, // string index
+        0, 0, // global subroutines
+        3, 0, 34, 0, 125, 0, 171, // charset of the unknown format 3
+        0, 4, 1, 1, 2, 3, 4, 5, // CharStrings index
+        14, 14, 14, 14,
+    ];
+    assert!(CffFont::try_parse(&data, 0).is_err());
+    let slot = read_cff(&data).unwrap().1.try_slot(0).unwrap();
+    assert!(slot.charstrings().is_err());
+    assert!(slot.subset(&[0, 1]).is_err());
+}
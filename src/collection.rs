@@ -0,0 +1,113 @@
+use crate::{Font, FontError, ParseOptions, OpenTypeFont, R};
+use crate::opentype::parse_tables_at;
+use nom::{
+    number::complete::{be_u16, be_u32},
+    bytes::complete::take,
+    multi::count,
+};
+
+/// A TrueType or OpenType font collection (`ttcf`).
+///
+/// The fonts share the data of the collection, so tables used by several of them are not copied.
+pub struct Collection<'a> {
+    data: &'a [u8],
+    offsets: Vec<u32>,
+}
+impl<'a> Collection<'a> {
+    /// Read the header of a collection.
+    pub fn parse(data: &'a [u8]) -> Result<Self, FontError> {
+        if !data.starts_with(b"ttcf") {
+            return Err(FontError::UnknownMagic(magic(data)));
+        }
+        let (_, offsets) = parse_header(data).map_err(|_| FontError::Truncated)?;
+        Ok(Collection { data, offsets })
+    }
+    pub fn num_fonts(&self) -> u32 {
+        self.offsets.len() as u32
+    }
+    /// The font at `index`, or None if there is no such font or it is broken.
+    pub fn get(&self, index: u32) -> Option<Box<dyn Font + Send + Sync + 'static>> {
+        self.get_with_options(index, &ParseOptions::default())
+    }
+    /// The font at `index` read with `options`, or None if there is no such font, it is broken
+    /// or it exceeds the limits of `options`. See `try_get` for the error.
+    pub fn get_with_options(&self, index: u32, options: &ParseOptions) -> Option<Box<dyn Font + Send + Sync + 'static>> {
+        self.try_get(index, options).ok()
+    }
+    /// Like `get_with_options`, but returns the error.
+    pub fn try_get(&self, index: u32, options: &ParseOptions) -> Result<Box<dyn Font + Send + Sync + 'static>, FontError> {
+        let &offset = self.offsets.get(index as usize).ok_or(FontError::Truncated)?;
+        let (_, tables) = parse_tables_at(self.data, offset as usize).map_err(|_| FontError::Truncated)?;
+        Ok(Box::new(OpenTypeFont::try_from_tables(tables, options)?) as _)
+    }
+}
+
+// offsets of the table directories
+fn parse_header(data: &[u8]) -> R<Vec<u32>> {
+    let (i, _tag) = take(4usize)(data)?;
+    let (i, _major) = be_u16(i)?;
+    let (i, _minor) = be_u16(i)?;
+    let (i, num_fonts) = be_u32(i)?;
+    count(be_u32, num_fonts as usize)(i)
+}
+
+fn magic(data: &[u8]) -> [u8; 4] {
+    let mut magic = [0; 4];
+    for (m, &b) in magic.iter_mut().zip(data) {
+        *m = b;
+    }
+    magic
+}
+
+/// Read the header of a font collection. See `Collection`.
+pub fn parse_collection(data: &[u8]) -> Result<Collection, FontError> {
+    Collection::parse(data)
+}
+
+/// The font at `index` of `data`, or None if there is no such font or it is broken.
+///
+/// For a collection this is the face at `index`; any other font is its only face at index 0.
+pub fn parse_index(data: &[u8], index: u32) -> Option<Box<dyn Font + Send + Sync + 'static>> {
+    if data.starts_with(b"ttcf") {
+        Collection::parse(data).ok()?.get(index)
+    } else if index == 0 {
+        crate::try_parse(data).ok()
+    } else {
+        None
+    }
+}
+
+#[test]
+fn test_collection() {
+    use crate::GlyphId;
+    use crate::opentype::test_otto_font;
+
+    // two faces sharing the same table directory (and tables) after a 20 byte header
+    let mut font = test_otto_font();
+    let num_tables = u16::from_be_bytes([font[4], font[5]]) as usize;
+    for n in 0 .. num_tables {
+        let pos = 12 + 16 * n + 8;
+        let offset = u32::from_be_bytes([font[pos], font[pos + 1], font[pos + 2], font[pos + 3]]);
+        font[pos .. pos + 4].copy_from_slice(&(offset + 20).to_be_bytes());
+    }
+    let mut data = b"ttcf".to_vec();
+    data.extend_from_slice(&[0, 1, 0, 0,  0, 0, 0, 2,  0, 0, 0, 20,  0, 0, 0, 20]);
+    data.extend_from_slice(&font);
+
+    let collection = parse_collection(&data).unwrap();
+    assert_eq!(collection.num_fonts(), 2);
+    for index in 0 .. 2 {
+        let font = collection.get(index).unwrap();
+        assert_eq!(font.glyph(GlyphId(1)).unwrap().metrics.advance, 600.);
+    }
+    assert!(collection.get(2).is_none());
+    let options = ParseOptions { max_glyphs: Some(1), .. ParseOptions::default() };
+    assert!(collection.get_with_options(0, &options).is_none());
+    assert!(matches!(collection.try_get(0, &options), Err(FontError::LimitExceeded(_))));
+    assert_eq!(parse_index(&data, 1).unwrap().num_glyphs(), 2);
+    assert!(parse_index(&data, 2).is_none());
+    assert!(parse_index(&test_otto_font(), 0).is_some());
+    assert!(parse_index(&test_otto_font(), 1).is_none());
+    assert!(parse_index(b"\x00\x01\x00\x00\x00", 0).is_none());
+    assert!(matches!(parse_collection(b"ttcf\0\x01"), Err(FontError::Truncated)));
+}
@@ -0,0 +1,422 @@
+use std::collections::HashMap;
+use crate::{Font, Glyph, HMetrics};
+use pathfinder_content::{
+    outline::{Outline, Contour, ContourIterFlags},
+    segment::Segment,
+};
+use pathfinder_geometry::{
+    line_segment::LineSegment2F,
+    vector::Vector2F,
+    transform2d::Transform2F,
+};
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn lerp_line(a: LineSegment2F, b: LineSegment2F, t: f32) -> LineSegment2F {
+    LineSegment2F::new(a.from().lerp(b.from(), t), a.to().lerp(b.to(), t))
+}
+
+fn lerp_segment(a: &Segment, b: &Segment, t: f32) -> Option<Segment> {
+    if a.kind != b.kind || a.flags != b.flags {
+        return None;
+    }
+    Some(Segment {
+        baseline: lerp_line(a.baseline, b.baseline, t),
+        ctrl: lerp_line(a.ctrl, b.ctrl, t),
+        kind: a.kind,
+        flags: a.flags
+    })
+}
+
+/// Linear interpolation between two outlines with the same structure.
+///
+/// Both outlines need the same number of contours, and each pair of contours
+/// the same sequence of segment kinds. Returns None otherwise.
+pub fn morph_outlines(from: &Outline, to: &Outline, t: f32) -> Option<Outline> {
+    if from.contours().len() != to.contours().len() {
+        return None;
+    }
+
+    let mut segments = vec![];
+    for (a, b) in from.contours().iter().zip(to.contours()) {
+        let a: Vec<Segment> = a.iter(ContourIterFlags::empty()).collect();
+        let b: Vec<Segment> = b.iter(ContourIterFlags::empty()).collect();
+        if a.len() != b.len() {
+            return None;
+        }
+        for (a, b) in a.iter().zip(b.iter()) {
+            segments.push(lerp_segment(a, b, t)?);
+        }
+    }
+
+    Some(Outline::from_segments(segments.into_iter()))
+}
+
+/// Interpolates outline and metrics of two glyphs. See `morph_outlines`.
+pub fn morph_glyphs(from: &Glyph, to: &Glyph, t: f32) -> Option<Glyph> {
+    Some(Glyph {
+        path: morph_outlines(&from.path, &to.path, t)?,
+        metrics: HMetrics {
+            lsb: lerp(from.metrics.lsb, to.metrics.lsb, t),
+            advance: lerp(from.metrics.advance, to.metrics.advance, t),
+        }
+    })
+}
+
+/// Thicken the outline by `strength` (in glyph units) on each side.
+///
+/// The outline is moved right by `strength`, so the left side bearing stays the same,
+/// and the advance grows by twice the strength.
+pub fn embolden(glyph: &mut Glyph, strength: f32) {
+    glyph.path.dilate(Vector2F::splat(strength));
+    glyph.path.transform(&Transform2F::from_translation(Vector2F::new(strength, 0.0)));
+    glyph.metrics.advance += 2.0 * strength;
+}
+
+#[test]
+fn test_morph() {
+    use pathfinder_geometry::rect::RectF;
+    use pathfinder_content::outline::Contour;
+
+    let square = |size: f32| {
+        let mut outline = Outline::new();
+        outline.push_contour(Contour::from_rect(RectF::new(Vector2F::default(), Vector2F::splat(size))));
+        outline
+    };
+    let small = square(10.);
+    let large = square(30.);
+
+    let mid = morph_outlines(&small, &large, 0.5).unwrap();
+    assert_eq!(mid.bounds(), RectF::new(Vector2F::default(), Vector2F::splat(20.)));
+
+    let mut two = square(10.);
+    two.push_outline(square(5.));
+    assert!(morph_outlines(&small, &two, 0.5).is_none());
+}
+
+/// Approximate a segment by a polyline, including both end points.
+fn flatten(segment: &Segment) -> Vec<Vector2F> {
+    const STEPS: usize = 16;
+    if segment.is_line() {
+        return vec![segment.baseline.from(), segment.baseline.to()];
+    }
+    let cubic = segment.to_cubic();
+    (0 ..= STEPS).map(|k| cubic.as_cubic_segment().sample(k as f32 / STEPS as f32)).collect()
+}
+
+/// The filled area of the outline.
+///
+/// The signed areas of all contours are summed, so holes (wound the other way) are subtracted.
+pub fn outline_area(outline: &Outline) -> f32 {
+    let mut area = 0.0;
+    for contour in outline.contours() {
+        for segment in contour.iter(ContourIterFlags::empty()) {
+            for pair in flatten(&segment).windows(2) {
+                area += pair[0].x() * pair[1].y() - pair[1].x() * pair[0].y();
+            }
+        }
+    }
+    (0.5 * area).abs()
+}
+
+/// The total length of all contours of the outline.
+pub fn outline_perimeter(outline: &Outline) -> f32 {
+    outline.contours().iter()
+        .flat_map(|contour| contour.iter(ContourIterFlags::empty()))
+        .map(|segment| flatten(&segment).windows(2).map(|pair| (pair[1] - pair[0]).length()).sum::<f32>())
+        .sum()
+}
+
+#[test]
+fn test_area_and_perimeter() {
+    use pathfinder_geometry::rect::RectF;
+    use pathfinder_content::outline::Contour;
+
+    let mut outline = Outline::new();
+    outline.push_contour(Contour::from_rect(RectF::new(Vector2F::default(), Vector2F::splat(30.))));
+    let mut hole = Contour::new();
+    for &(x, y) in &[(10., 10.), (10., 20.), (20., 20.), (20., 10.)] {
+        hole.push_endpoint(Vector2F::new(x, y));
+    }
+    hole.close();
+    outline.push_contour(hole);
+
+    assert_eq!(outline_area(&outline), 800.);
+    assert_eq!(outline_perimeter(&outline), 160.);
+}
+
+// the contour as a closed polyline
+fn polygon(contour: &Contour) -> Vec<Vector2F> {
+    contour.iter(ContourIterFlags::empty()).flat_map(|segment| flatten(&segment)).collect()
+}
+
+fn polygon_area(points: &[Vector2F]) -> f32 {
+    let n = points.len();
+    0.5 * (0 .. n).map(|k| {
+        let (a, b) = (points[k], points[(k + 1) % n]);
+        a.x() * b.y() - b.x() * a.y()
+    }).sum::<f32>()
+}
+
+// even-odd test
+fn polygon_contains(points: &[Vector2F], p: Vector2F) -> bool {
+    let n = points.len();
+    let mut inside = false;
+    for k in 0 .. n {
+        let (a, b) = (points[k], points[(k + 1) % n]);
+        if (a.y() > p.y()) != (b.y() > p.y()) {
+            let x = a.x() + (p.y() - a.y()) / (b.y() - a.y()) * (b.x() - a.x());
+            if p.x() < x {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+/// The contours of the outline, each outer contour followed by the holes directly inside it.
+///
+/// A contour is inside another if its first point is and it is smaller. Contours inside an odd number
+/// of others are holes. The outer contours keep their original order.
+pub fn sorted_contours(outline: &Outline) -> Vec<Contour> {
+    let contours = outline.contours();
+    let n = contours.len();
+    let polygons: Vec<_> = contours.iter().map(polygon).collect();
+    let areas: Vec<f32> = polygons.iter().map(|p| polygon_area(p).abs()).collect();
+
+    // the smallest contour around each contour
+    let parents: Vec<Option<usize>> = (0 .. n).map(|i| {
+        if contours[i].is_empty() {
+            return None;
+        }
+        let p = contours[i].position_of(0);
+        (0 .. n)
+            .filter(|&j| areas[j] > areas[i] && polygon_contains(&polygons[j], p))
+            .min_by(|&a, &b| areas[a].partial_cmp(&areas[b]).unwrap())
+    }).collect();
+    // parents are strictly larger, so this ends
+    let depth = |mut i: usize| {
+        let mut depth = 0;
+        while let Some(parent) = parents[i] {
+            depth += 1;
+            i = parent;
+        }
+        depth
+    };
+
+    let mut sorted = Vec::with_capacity(n);
+    for outer in (0 .. n).filter(|&i| depth(i) % 2 == 0) {
+        sorted.push(contours[outer].clone());
+        for hole in (0 .. n).filter(|&j| parents[j] == Some(outer) && depth(j) % 2 == 1) {
+            sorted.push(contours[hole].clone());
+        }
+    }
+    sorted
+}
+
+#[test]
+fn test_sorted_contours() {
+    use pathfinder_geometry::rect::RectF;
+
+    let square = |x: f32, size: f32| Contour::from_rect(RectF::new(Vector2F::splat(x), Vector2F::splat(size)));
+    let mut outline = Outline::new();
+    outline.push_contour(square(12., 6.)); // island in the hole
+    outline.push_contour(square(10., 10.)); // hole
+    outline.push_contour(square(50., 10.)); // separate square
+    outline.push_contour(square(0., 30.)); // outer
+
+    let sorted: Vec<_> = sorted_contours(&outline).iter().map(|c| c.bounds().origin().x()).collect();
+    assert_eq!(sorted, vec![12., 50., 0., 10.]);
+}
+
+// the contour as a closed polyline without repeated points
+fn simple_polygon(contour: &Contour) -> Vec<Vector2F> {
+    let mut points = polygon(contour);
+    points.dedup();
+    while points.len() > 1 && points.first() == points.last() {
+        points.pop();
+    }
+    points
+}
+
+fn cross(a: Vector2F, b: Vector2F) -> f32 {
+    a.x() * b.y() - a.y() * b.x()
+}
+
+// nonzero winding number of the edges around `p`
+fn winding(edges: &[(Vector2F, Vector2F)], p: Vector2F) -> i32 {
+    let mut winding = 0;
+    for &(a, b) in edges {
+        let side = cross(b - a, p - a);
+        if a.y() <= p.y() && b.y() > p.y() && side > 0.0 {
+            winding += 1;
+        } else if b.y() <= p.y() && a.y() > p.y() && side < 0.0 {
+            winding -= 1;
+        }
+    }
+    winding
+}
+
+/// The union of all contours of the outline under the nonzero fill rule, without self-intersections
+/// or overlapping contours.
+///
+/// Curves are flattened, so the result only has line segments. Filled regions are on the left of
+/// each contour, so outer contours run counter-clockwise (with y pointing up) and holes clockwise.
+/// Edges that coincide over a length (rather than crossing) are not split.
+pub fn union_outline(outline: &Outline) -> Outline {
+    const EPSILON: f32 = 1e-5;
+
+    let edges: Vec<(Vector2F, Vector2F)> = outline.contours().iter()
+        .map(simple_polygon)
+        .filter(|points| points.len() > 2)
+        .flat_map(|points| {
+            let n = points.len();
+            (0 .. n).map(move |k| (points[k], points[(k + 1) % n]))
+        })
+        .collect();
+
+    // intersections on each edge with their position along it.
+    // Both edges get the very same point, so the pieces can be joined by exact comparison.
+    let mut splits: Vec<Vec<(f32, Vector2F)>> = vec![vec![]; edges.len()];
+    for i in 0 .. edges.len() {
+        let (p, r) = (edges[i].0, edges[i].1 - edges[i].0);
+        for j in i + 1 .. edges.len() {
+            let (q, s) = (edges[j].0, edges[j].1 - edges[j].0);
+            let denom = cross(r, s);
+            if denom.abs() < 1e-9 {
+                continue;
+            }
+            let t = cross(q - p, s) / denom;
+            let u = cross(q - p, r) / denom;
+            if t < -EPSILON || t > 1.0 + EPSILON || u < -EPSILON || u > 1.0 + EPSILON {
+                continue;
+            }
+            let t_inner = t > EPSILON && t < 1.0 - EPSILON;
+            let u_inner = u > EPSILON && u < 1.0 - EPSILON;
+            let point = match (t_inner, u_inner) {
+                (false, _) if t < 0.5 => edges[i].0,
+                (false, _) => edges[i].1,
+                (true, false) if u < 0.5 => edges[j].0,
+                (true, false) => edges[j].1,
+                (true, true) => p + r * t,
+            };
+            if t_inner {
+                splits[i].push((t, point));
+            }
+            if u_inner {
+                splits[j].push((u, point));
+            }
+        }
+    }
+
+    // keep the pieces that separate filled from unfilled area, directed so the filled side is on the left
+    let mut pieces = vec![];
+    for (&(a, b), mut points) in edges.iter().zip(splits) {
+        points.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap());
+        let points: Vec<Vector2F> = Some(a).into_iter()
+            .chain(points.into_iter().map(|(_, point)| point))
+            .chain(Some(b))
+            .collect();
+        for pair in points.windows(2) {
+            let (from, to) = (pair[0], pair[1]);
+            let d = to - from;
+            if from == to {
+                continue;
+            }
+            let offset = Vector2F::new(-d.y(), d.x()) * (1e-3 / d.length()).min(1e-2);
+            let middle = (from + to) * 0.5;
+            let left = winding(&edges, middle + offset) != 0;
+            let right = winding(&edges, middle - offset) != 0;
+            match (left, right) {
+                (true, false) => pieces.push((from, to)),
+                (false, true) => pieces.push((to, from)),
+                _ => {}
+            }
+        }
+    }
+
+    // join the pieces into closed contours. Where several pieces start at the same point, take the first one
+    // clockwise from the way back, which keeps to the filled area on the left of the incoming piece.
+    let key = |p: Vector2F| (p.x().to_bits(), p.y().to_bits());
+    let mut starting_at: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+    for (n, &(from, _)) in pieces.iter().enumerate() {
+        starting_at.entry(key(from)).or_default().push(n);
+    }
+    let clockwise_angle = |back: Vector2F, d: Vector2F| {
+        let angle = -cross(back, d).atan2(back.dot(d));
+        if angle <= 0.0 { angle + 2.0 * std::f32::consts::PI } else { angle }
+    };
+    let mut used = vec![false; pieces.len()];
+    let mut result = Outline::new();
+    for first in 0 .. pieces.len() {
+        if used[first] {
+            continue;
+        }
+        let mut contour = Contour::new();
+        let mut current = first;
+        loop {
+            used[current] = true;
+            let (from, to) = pieces[current];
+            contour.push_endpoint(from);
+            if key(to) == key(pieces[first].0) {
+                break;
+            }
+            let back = from - to;
+            let next = starting_at.get(&key(to)).and_then(|candidates| candidates.iter()
+                .filter(|&&n| !used[n])
+                .min_by(|&&a, &&b| {
+                    let angle_a = clockwise_angle(back, pieces[a].1 - pieces[a].0);
+                    let angle_b = clockwise_angle(back, pieces[b].1 - pieces[b].0);
+                    angle_a.partial_cmp(&angle_b).unwrap()
+                })
+            );
+            match next {
+                Some(&n) => current = n,
+                None => break
+            }
+        }
+        if contour.len() > 2 {
+            contour.close();
+            result.push_contour(contour);
+        }
+    }
+    result
+}
+
+#[test]
+fn test_union_outline() {
+    use pathfinder_geometry::rect::RectF;
+
+    // two overlapping squares
+    let mut outline = Outline::new();
+    outline.push_contour(Contour::from_rect(RectF::new(Vector2F::splat(0.), Vector2F::splat(20.))));
+    outline.push_contour(Contour::from_rect(RectF::new(Vector2F::splat(10.), Vector2F::splat(20.))));
+    let union = union_outline(&outline);
+    assert_eq!(union.contours().len(), 1);
+    assert!((outline_area(&union) - 700.).abs() < 1e-3);
+    assert!((outline_perimeter(&union) - 120.).abs() < 1e-3);
+
+    // a bow tie crossing itself: two triangles
+    let mut bow_tie = Contour::new();
+    for &(x, y) in &[(0., 0.), (10., 10.), (10., 0.), (0., 10.)] {
+        bow_tie.push_endpoint(Vector2F::new(x, y));
+    }
+    bow_tie.close();
+    let mut outline = Outline::new();
+    outline.push_contour(bow_tie);
+    let union = union_outline(&outline);
+    assert_eq!(union.contours().len(), 2);
+    assert!((outline_area(&union) - 50.).abs() < 1e-3);
+}
+
+/// Top of the ink of the glyph for `c` (in glyph space), or None if the font has no visible glyph for it.
+pub fn ink_top<F: Font + ?Sized>(font: &F, c: char) -> Option<f32> {
+    let gid = font.gid_for_unicode_codepoint(c as u32)?;
+    let glyph = font.glyph(gid)?;
+    if glyph.path.contours().is_empty() {
+        return None;
+    }
+    Some(glyph.path.bounds().max_y())
+}
@@ -0,0 +1,79 @@
+use pdf_encoding::glyphname_to_unicode;
+use crate::{Font, GlyphId};
+
+fn hex_codepoint(digits: &str) -> Option<u32> {
+    // the Adobe Glyph List specification only allows uppercase hex digits
+    if !digits.bytes().all(|b| b.is_ascii_digit() || (b'A' ..= b'F').contains(&b)) {
+        return None;
+    }
+    let codepoint = u32::from_str_radix(digits, 16).ok()?;
+    match codepoint {
+        0xD800 ..= 0xDFFF => None,
+        c if c > 0x10FFFF => None,
+        c => Some(c)
+    }
+}
+
+/// The unicode codepoint a glyph name stands for.
+///
+/// Understands the names of the Adobe Glyph List as well as `uniXXXX` and `uXXXX` to `uXXXXXX`.
+/// Names of ligatures and variants (with a `.suffix`) have no single codepoint and return `None`.
+pub fn glyph_name_to_codepoint(name: &str) -> Option<u32> {
+    if let Some(unicode) = glyphname_to_unicode(name) {
+        let mut chars = unicode.chars();
+        return match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c as u32),
+            _ => None
+        };
+    }
+    if name.starts_with("uni") && name.len() == 7 {
+        return hex_codepoint(&name[3 ..]);
+    }
+    if name.starts_with('u') && (5 ..= 7).contains(&name.len()) {
+        return hex_codepoint(&name[1 ..]);
+    }
+    None
+}
+
+/// Parse a codepoint written as `U+XXXX`, `0xXX`, a decimal number or a single character.
+pub fn parse_codepoint(spec: &str) -> Option<u32> {
+    let mut chars = spec.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(c as u32);
+    }
+    let codepoint = if spec.starts_with("U+") || spec.starts_with("u+") || spec.starts_with("0x") || spec.starts_with("0X") {
+        u32::from_str_radix(&spec[2 ..], 16).ok()?
+    } else if spec.bytes().all(|b| b.is_ascii_digit()) {
+        spec.parse().ok()?
+    } else {
+        return None;
+    };
+    std::char::from_u32(codepoint).map(|c| c as u32)
+}
+
+/// Look up a glyph by name through its unicode codepoint and the character map of the font.
+pub fn gid_for_name_via_unicode<F: Font + ?Sized>(font: &F, name: &str) -> Option<GlyphId> {
+    font.gid_for_unicode_codepoint(glyph_name_to_codepoint(name)?)
+}
+
+#[test]
+fn test_parse_codepoint() {
+    assert_eq!(parse_codepoint("U+0041"), Some(0x41));
+    assert_eq!(parse_codepoint("0x41"), Some(0x41));
+    assert_eq!(parse_codepoint("65"), Some(65));
+    assert_eq!(parse_codepoint("A"), Some(0x41));
+    assert_eq!(parse_codepoint("5"), Some(0x35));
+    assert_eq!(parse_codepoint("U+D800"), None);
+    assert_eq!(parse_codepoint("Aacute"), None);
+}
+
+#[test]
+fn test_glyph_name_to_codepoint() {
+    assert_eq!(glyph_name_to_codepoint("A"), Some(0x41));
+    assert_eq!(glyph_name_to_codepoint("Aacute"), Some(0xC1));
+    assert_eq!(glyph_name_to_codepoint("uni20AC"), Some(0x20AC));
+    assert_eq!(glyph_name_to_codepoint("u1F600"), Some(0x1F600));
+    assert_eq!(glyph_name_to_codepoint("uni20ac"), None);
+    assert_eq!(glyph_name_to_codepoint("uniD800"), None);
+    assert_eq!(glyph_name_to_codepoint("no_such_glyph"), None);
+}
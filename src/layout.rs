@@ -0,0 +1,913 @@
+use std::ops::Range;
+use crate::{Font, GlyphId, LineMetrics};
+use crate::opentype::{Tag, shape::{ShapeStep, ShapeStage}};
+use pathfinder_color::ColorU;
+use pathfinder_content::outline::{Outline, Contour};
+use pathfinder_geometry::{vector::Vector2F, transform2d::Transform2F, rect::RectF};
+
+/// A glyph and its placement, as produced by a shaper.
+///
+/// All values are in glyph space (usually font units).
+#[derive(Clone, Debug)]
+pub struct PositionedGlyph {
+    pub gid: GlyphId,
+
+    /// byte range of the source text this glyph represents.
+    /// glyphs formed from several characters (ligatures) cover all of them.
+    pub cluster: Range<usize>,
+
+    /// displacement from the current pen position
+    pub offset: Vector2F,
+
+    /// how far the pen moves after this glyph
+    pub advance: Vector2F,
+}
+
+/// Writing direction of a run of text.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Direction {
+    LeftToRight,
+    RightToLeft,
+}
+
+// pairs of characters with the Bidi_Mirrored property that mirror each other (from BidiMirroring.txt)
+static MIRROR_PAIRS: &[(char, char)] = &[
+    ('(', ')'), ('<', '>'), ('[', ']'), ('{', '}'), ('«', '»'),
+    ('\u{2039}', '\u{203A}'), ('\u{2045}', '\u{2046}'), ('\u{207D}', '\u{207E}'), ('\u{208D}', '\u{208E}'),
+    ('\u{2208}', '\u{220B}'), ('\u{2209}', '\u{220C}'), ('\u{220A}', '\u{220D}'), ('\u{223C}', '\u{223D}'),
+    ('\u{2264}', '\u{2265}'), ('\u{2266}', '\u{2267}'), ('\u{226A}', '\u{226B}'), ('\u{226E}', '\u{226F}'),
+    ('\u{2270}', '\u{2271}'), ('\u{2272}', '\u{2273}'), ('\u{2276}', '\u{2277}'), ('\u{227A}', '\u{227B}'),
+    ('\u{227C}', '\u{227D}'), ('\u{2282}', '\u{2283}'), ('\u{2286}', '\u{2287}'), ('\u{228F}', '\u{2290}'),
+    ('\u{2291}', '\u{2292}'), ('\u{22A2}', '\u{22A3}'), ('\u{22B2}', '\u{22B3}'), ('\u{22B4}', '\u{22B5}'),
+    ('\u{2308}', '\u{2309}'), ('\u{230A}', '\u{230B}'), ('\u{2329}', '\u{232A}'),
+    ('\u{27E6}', '\u{27E7}'), ('\u{27E8}', '\u{27E9}'), ('\u{27EA}', '\u{27EB}'),
+    ('\u{2983}', '\u{2984}'), ('\u{2985}', '\u{2986}'),
+    ('\u{3008}', '\u{3009}'), ('\u{300A}', '\u{300B}'), ('\u{300C}', '\u{300D}'), ('\u{300E}', '\u{300F}'),
+    ('\u{3010}', '\u{3011}'), ('\u{3014}', '\u{3015}'), ('\u{3016}', '\u{3017}'), ('\u{3018}', '\u{3019}'),
+    ('\u{301A}', '\u{301B}'),
+    ('\u{FE59}', '\u{FE5A}'), ('\u{FE5B}', '\u{FE5C}'), ('\u{FE5D}', '\u{FE5E}'), ('\u{FE64}', '\u{FE65}'),
+    ('\u{FF08}', '\u{FF09}'), ('\u{FF1C}', '\u{FF1E}'), ('\u{FF3B}', '\u{FF3D}'), ('\u{FF5B}', '\u{FF5D}'),
+    ('\u{FF5F}', '\u{FF60}'), ('\u{FF62}', '\u{FF63}'),
+];
+
+/// The mirrored form of `c` (Unicode Bidi_Mirroring_Glyph), if it has one.
+pub fn bidi_mirror(c: char) -> Option<char> {
+    MIRROR_PAIRS.iter().find_map(|&(a, b)| {
+        if c == a {
+            Some(b)
+        } else if c == b {
+            Some(a)
+        } else {
+            None
+        }
+    })
+}
+
+/// Map a single-direction run of text to glyphs, in visual order (left to right).
+///
+/// In right-to-left runs, characters with a mirrored form (like brackets) are replaced by it
+/// before the character map lookup, unless the font lacks the mirrored glyph.
+/// No shaping is done besides kerning, cursive and mark attachment. Characters without a glyph use the `.notdef` glyph.
+pub fn layout_run<F: Font + ?Sized>(font: &F, text: &str, direction: Direction) -> Vec<PositionedGlyph> {
+    shape_run(font, text, direction, &[])
+}
+
+// layout_run, with the substitutions and single adjustments of `features`
+fn shape_run<F: Font + ?Sized>(font: &F, text: &str, direction: Direction, features: &[Tag]) -> Vec<PositionedGlyph> {
+    shape_traced(font, text, direction, features, None)
+}
+
+// the buffer with the plain advance of each glyph
+fn unpositioned<F: Font + ?Sized>(font: &F, gids: &[(GlyphId, Range<usize>)]) -> Vec<PositionedGlyph> {
+    gids.iter().map(|&(gid, ref cluster)| PositionedGlyph {
+        gid,
+        cluster: cluster.clone(),
+        offset: Vector2F::default(),
+        advance: Vector2F::new(font.positioned_advance(gid, &[]), 0.0),
+    }).collect()
+}
+
+// shape_run, adding the glyph buffer after each step to `trace`
+pub(crate) fn shape_traced<F: Font + ?Sized>(font: &F, text: &str, direction: Direction, features: &[Tag], mut trace: Option<&mut Vec<ShapeStage>>) -> Vec<PositionedGlyph> {
+    let mut gids: Vec<(GlyphId, Range<usize>)> = text.char_indices().map(|(pos, c)| {
+        let mirrored = match direction {
+            Direction::RightToLeft => bidi_mirror(c).and_then(|m| font.gid_for_unicode_codepoint(m as u32)),
+            Direction::LeftToRight => None
+        };
+        let gid = mirrored
+            .or_else(|| font.gid_for_unicode_codepoint(c as u32))
+            .unwrap_or_else(|| font.get_notdef_gid());
+        (gid, pos .. pos + c.len_utf8())
+    }).collect();
+    if let Some(ref mut stages) = trace {
+        stages.push(ShapeStage { step: ShapeStep::Cmap, fired: !gids.is_empty(), glyphs: unpositioned(font, &gids) });
+    }
+    if !features.is_empty() {
+        font.trace_substitutions(&mut gids, features, &mut |feature, lookup, fired, gids| {
+            if let Some(ref mut stages) = trace {
+                stages.push(ShapeStage { step: ShapeStep::Substitution { feature, lookup }, fired, glyphs: unpositioned(font, gids) });
+            }
+        });
+    }
+    if direction == Direction::RightToLeft {
+        gids.reverse();
+    }
+
+    let mut glyphs: Vec<PositionedGlyph> = gids.into_iter().map(|(gid, cluster)| PositionedGlyph {
+        gid,
+        cluster,
+        offset: Vector2F::default(),
+        advance: Vector2F::default(),
+    }).collect();
+    font.position_glyphs(&mut glyphs, features, &mut |step, fired, glyphs| {
+        if let Some(ref mut stages) = trace {
+            stages.push(ShapeStage { step, fired, glyphs: glyphs.to_vec() });
+        }
+    });
+
+    attach_step(&mut glyphs, ShapeStep::CursiveAttachment, &mut trace, |glyphs| attach_cursive(font, glyphs, direction));
+    attach_step(&mut glyphs, ShapeStep::MarkAttachment, &mut trace, |glyphs| attach_marks(font, glyphs, direction));
+    glyphs
+}
+
+// apply an attachment step, adding the glyphs after it to `trace`
+fn attach_step(glyphs: &mut [PositionedGlyph], step: ShapeStep, trace: &mut Option<&mut Vec<ShapeStage>>, attach: impl FnOnce(&mut [PositionedGlyph])) {
+    match trace {
+        Some(stages) => {
+            let before: Vec<_> = glyphs.iter().map(|g| (g.offset, g.advance)).collect();
+            attach(glyphs);
+            let fired = glyphs.iter().zip(&before).any(|(g, &(offset, advance))| g.offset != offset || g.advance != advance);
+            stages.push(ShapeStage { step, fired, glyphs: glyphs.to_vec() });
+        }
+        None => attach(glyphs)
+    }
+}
+
+// Place each mark on the glyph it (logically) follows, using mark-to-base or mark-to-mark attachment.
+// If the preceding glyph is an attached mark that does not take the mark, the glyph it is attached to is tried next.
+// Attached marks do not advance the pen.
+fn attach_marks<F: Font + ?Sized>(font: &F, glyphs: &mut [PositionedGlyph], direction: Direction) {
+    let n = glyphs.len();
+    let visual = |k: usize| match direction {
+        Direction::LeftToRight => k,
+        Direction::RightToLeft => n - 1 - k,
+    };
+    let mut attached = vec![false; n];
+    for k in 1 .. n {
+        let mark = visual(k);
+        let mut b = k;
+        let target = loop {
+            if b == 0 {
+                break None;
+            }
+            b -= 1;
+            let base = visual(b);
+            if let Some(pos) = font.mark_attachment(glyphs[base].gid, glyphs[mark].gid) {
+                break Some((base, pos));
+            }
+            if !attached[b] {
+                break None;
+            }
+        };
+        if let Some((base, pos)) = target {
+            glyphs[mark].advance = Vector2F::default();
+            // pen position of the base minus that of the mark
+            let (first, last) = (base.min(mark), base.max(mark));
+            let between = glyphs[first .. last].iter().fold(Vector2F::default(), |sum, g| sum + g.advance);
+            let pen = if base < mark { -between } else { between };
+            glyphs[mark].offset = glyphs[base].offset + pos + pen;
+            attached[k] = true;
+        }
+    }
+}
+
+// Join the exit anchor of each glyph to the entry anchor of the logically following one.
+// The glyph on the left advances up to the joint, and one of the two is moved vertically:
+// the following glyph, or the preceding one if the lookup is flagged right to left.
+fn attach_cursive<F: Font + ?Sized>(font: &F, glyphs: &mut [PositionedGlyph], direction: Direction) {
+    let n = glyphs.len();
+    let visual = |k: usize| match direction {
+        Direction::LeftToRight => k,
+        Direction::RightToLeft => n - 1 - k,
+    };
+    // (moved glyph, the glyph it hangs off, vertical distance)
+    let mut forward = vec![];
+    let mut backward = vec![];
+    for k in 1 .. n {
+        let (prev, next) = (visual(k - 1), visual(k));
+        let (exit, entry, right_to_left) = match (font.cursive_anchors(glyphs[prev].gid), font.cursive_anchors(glyphs[next].gid)) {
+            (Some(a), Some(b)) => match (a.exit, b.entry) {
+                (Some(exit), Some(entry)) => (exit, entry, b.right_to_left),
+                _ => continue
+            },
+            _ => continue
+        };
+        let (left, x) = match direction {
+            Direction::LeftToRight => (prev, exit.0 as f32 - entry.0 as f32),
+            Direction::RightToLeft => (next, entry.0 as f32 - exit.0 as f32),
+        };
+        glyphs[left].advance = Vector2F::new(x, glyphs[left].advance.y());
+        if right_to_left {
+            backward.push((prev, next, entry.1 as f32 - exit.1 as f32));
+        } else {
+            forward.push((next, prev, exit.1 as f32 - entry.1 as f32));
+        }
+    }
+    // chains are resolved from the glyph that stays in place
+    for &(moved, parent, dy) in forward.iter().chain(backward.iter().rev()) {
+        let y = glyphs[parent].offset.y() + dy;
+        glyphs[moved].offset = Vector2F::new(glyphs[moved].offset.x(), y);
+    }
+}
+
+/// Shapes runs of text with a list of OpenType features.
+///
+/// The `GSUB` lookups of the features are applied to the glyphs (in logical order) and their `GPOS` single adjustments
+/// to the advances. Kerning, cursive and mark attachment are done as in `layout_run`.
+/// There is no script or language selection and no contextual substitution.
+pub struct Shaper<'a, F: Font + ?Sized> {
+    font: &'a F,
+    features: Vec<Tag>,
+}
+impl<'a, F: Font + ?Sized> Shaper<'a, F> {
+    /// The features used by `new`: glyph composition, localized forms and standard ligatures.
+    pub const DEFAULT_FEATURES: [Tag; 4] = [Tag(*b"ccmp"), Tag(*b"locl"), Tag(*b"rlig"), Tag(*b"liga")];
+
+    /// A shaper with the `DEFAULT_FEATURES`.
+    pub fn new(font: &'a F) -> Self {
+        Shaper::with_features(font, &Self::DEFAULT_FEATURES)
+    }
+    pub fn with_features(font: &'a F, features: &[Tag]) -> Self {
+        Shaper { font, features: features.to_vec() }
+    }
+    pub fn features(&self) -> &[Tag] {
+        &self.features
+    }
+    /// Shape a single-direction run of text. The glyphs are in visual order, like those of `layout_run`.
+    pub fn shape(&self, text: &str, direction: Direction) -> Vec<PositionedGlyph> {
+        shape_run(self.font, text, direction, &self.features)
+    }
+    /// Shape and draw a line of left-to-right text, like `draw_text`.
+    pub fn draw_text(&self, size: f32, text: &str) -> Outline {
+        draw_glyphs(self.font, size, &self.shape(text, Direction::LeftToRight))
+    }
+}
+
+/// Lay out left-to-right text. See `layout_run`.
+pub fn layout<F: Font + ?Sized>(font: &F, text: &str) -> Vec<PositionedGlyph> {
+    layout_run(font, text, Direction::LeftToRight)
+}
+
+/// Lay out and draw a line of left-to-right text, like `draw_glyphs(font, size, &layout(font, text))`.
+pub fn draw_text<F: Font + ?Sized>(font: &F, size: f32, text: &str) -> Outline {
+    draw_glyphs(font, size, &layout(font, text))
+}
+
+/// Like `draw_text`, but adds the text to an existing outline, with the baseline starting at `origin`.
+///
+/// `origin` is in the space of the output (scaled to `size`).
+pub fn draw_text_into<F: Font + ?Sized>(font: &F, size: f32, text: &str, outline: &mut Outline, origin: Vector2F) {
+    let mut text_outline = draw_text(font, size, text);
+    text_outline.transform(&Transform2F::from_translation(origin));
+    outline.push_outline(text_outline);
+}
+
+/// A laid out line of text that can be updated while it is edited.
+pub struct TextLayout<'a, F: Font + ?Sized> {
+    font: &'a F,
+    direction: Direction,
+    text: String,
+    glyphs: Vec<PositionedGlyph>,
+}
+impl<'a, F: Font + ?Sized> TextLayout<'a, F> {
+    /// Lay out `text` like `layout_run` does.
+    pub fn new(font: &'a F, text: &str, direction: Direction) -> Self {
+        TextLayout {
+            font,
+            direction,
+            text: text.into(),
+            glyphs: layout_run(font, text, direction),
+        }
+    }
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+    /// The glyphs in visual order.
+    pub fn glyphs(&self) -> &[PositionedGlyph] {
+        &self.glyphs
+    }
+
+    /// Replace the text from `byte_index` on by `new_text`.
+    ///
+    /// In left-to-right text the glyphs before the change are kept, except for the last one,
+    /// which may kern with (or form a cluster with) the changed text. Right-to-left text is laid out again as a whole.
+    ///
+    /// Panics if `byte_index` is not on a character boundary.
+    pub fn relayout_from(&mut self, byte_index: usize, new_text: &str) {
+        self.text.truncate(byte_index);
+        self.text.push_str(new_text);
+
+        if self.direction == Direction::RightToLeft {
+            self.glyphs = layout_run(self.font, &self.text, self.direction);
+            return;
+        }
+
+        let keep = self.glyphs.iter()
+            .take_while(|g| g.cluster.end <= byte_index)
+            .count()
+            .saturating_sub(1);
+        let restart = self.glyphs.get(keep).map(|g| g.cluster.start).unwrap_or(0);
+        self.glyphs.truncate(keep);
+
+        let tail = layout_run(self.font, &self.text[restart ..], Direction::LeftToRight);
+        self.glyphs.extend(tail.into_iter().map(|mut g| {
+            g.cluster = g.cluster.start + restart .. g.cluster.end + restart;
+            g
+        }));
+    }
+}
+
+/// Horizontal placement of the lines of a `Paragraph`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Alignment {
+    Left,
+    Center,
+    Right,
+}
+
+/// A line of a `Paragraph`.
+#[derive(Clone, Debug)]
+pub struct TextLine {
+    /// The glyphs of the line, laid out like `layout_run` does. Their clusters refer to the whole text.
+    pub glyphs: Vec<PositionedGlyph>,
+    /// Pen position of the first glyph (in glyph space). The first baseline is at y = 0, the following ones below it.
+    pub origin: Vector2F,
+    /// Sum of the advances (in glyph space), without the spaces the line was broken at
+    pub width: f32,
+}
+
+/// Lays out left-to-right text in several lines.
+///
+/// Lines end at '\n' and, with a `max_width`, are broken greedily at spaces. Words wider than the maximum
+/// get a line of their own. Consecutive baselines are `line_spacing` times the line height apart: ascent minus descent
+/// plus line gap of the font's `vmetrics`, or one em without them.
+pub struct Paragraph<'a, F: Font + ?Sized> {
+    font: &'a F,
+    size: f32,
+    max_width: Option<f32>,
+    alignment: Alignment,
+    line_spacing: f32,
+}
+impl<'a, F: Font + ?Sized> Paragraph<'a, F> {
+    /// Left aligned lines that are only broken at '\n', with the line height of the font.
+    ///
+    /// `size` is the number of output units per em, as for `draw_text`.
+    pub fn new(font: &'a F, size: f32) -> Self {
+        Paragraph { font, size, max_width: None, alignment: Alignment::Left, line_spacing: 1.0 }
+    }
+    /// Break lines that would be wider than `width` (in output units).
+    pub fn max_width(mut self, width: f32) -> Self {
+        self.max_width = Some(width);
+        self
+    }
+    /// Align the lines within `max_width`, or within the widest line if there is no maximum.
+    pub fn alignment(mut self, alignment: Alignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
+    /// Multiply the line height by `factor`.
+    pub fn line_spacing(mut self, factor: f32) -> Self {
+        self.line_spacing = factor;
+        self
+    }
+
+    // output units -> glyph space, horizontally
+    fn glyph_units(&self, x: f32) -> f32 {
+        x / (self.size * self.font.font_matrix().matrix.m11())
+    }
+    fn line_height(&self) -> f32 {
+        let (descent, ascent) = line_extent(self.font);
+        let line_gap = self.font.vmetrics().map(|vmetrics| vmetrics.line_gap).unwrap_or(0.0);
+        (ascent - descent + line_gap) * self.line_spacing
+    }
+
+    /// Lay out `text` into lines.
+    pub fn lines(&self, text: &str) -> Vec<TextLine> {
+        let max_width = self.max_width.map(|width| self.glyph_units(width));
+        let mut lines = vec![];
+        let mut start = 0;
+        for line in text.split('\n') {
+            let mut glyphs = layout_run(self.font, line, Direction::LeftToRight);
+            for g in glyphs.iter_mut() {
+                g.cluster = g.cluster.start + start .. g.cluster.end + start;
+            }
+            match max_width {
+                Some(max_width) => {
+                    let (mut first, mut x, mut space) = (0, 0.0, None);
+                    for i in 0 .. glyphs.len() {
+                        x += glyphs[i].advance.x();
+                        if is_space_in(text, &glyphs[i]) {
+                            space = Some(i);
+                        } else if x > max_width {
+                            if let Some(s) = space.take() {
+                                lines.push(glyphs[first .. s].to_vec());
+                                first = s + 1;
+                                x = glyphs[first ..= i].iter().map(|g| g.advance.x()).sum();
+                            }
+                        }
+                    }
+                    lines.push(glyphs[first ..].to_vec());
+                }
+                None => lines.push(glyphs)
+            }
+            start += line.len() + 1;
+        }
+
+        let widths: Vec<f32> = lines.iter().map(|glyphs| {
+            let end = glyphs.len() - glyphs.iter().rev().take_while(|g| is_space_in(text, g)).count();
+            glyphs[.. end].iter().map(|g| g.advance.x()).sum()
+        }).collect();
+        let box_width = max_width.unwrap_or_else(|| widths.iter().cloned().fold(0.0, f32::max));
+        let line_height = self.line_height();
+        lines.into_iter().zip(widths).enumerate().map(|(n, (glyphs, width))| {
+            let x = match self.alignment {
+                Alignment::Left => 0.0,
+                Alignment::Center => (box_width - width) / 2.0,
+                Alignment::Right => box_width - width,
+            };
+            TextLine { glyphs, origin: Vector2F::new(x, -(n as f32) * line_height), width }
+        }).collect()
+    }
+
+    /// Lay out and draw `text`, scaled to `size` like `draw_text`.
+    pub fn draw(&self, text: &str) -> Outline {
+        let mut outline = Outline::new();
+        for line in self.lines(text) {
+            place_glyphs(self.font, self.size, line.origin, &line.glyphs, &mut outline);
+        }
+        outline
+    }
+
+    /// The outline of each glyph of `text`, placed and scaled like `draw` would, for callers that rasterize glyphs
+    /// themselves. Glyphs without an outline are left out.
+    pub fn glyph_outlines(&self, text: &str) -> Vec<(PositionedGlyph, Outline)> {
+        let mut outlines = vec![];
+        for line in self.lines(text) {
+            let mut pen = line.origin;
+            for g in line.glyphs {
+                let mut outline = Outline::new();
+                pen = place_glyphs(self.font, self.size, pen, std::slice::from_ref(&g), &mut outline);
+                if !outline.contours().is_empty() {
+                    outlines.push((g, outline));
+                }
+            }
+        }
+        outlines
+    }
+}
+
+// whether the glyph stands for a single space of `text`
+fn is_space_in(text: &str, g: &PositionedGlyph) -> bool {
+    &text[g.cluster.clone()] == " "
+}
+
+/// Place the outlines of already positioned glyphs, starting at the origin.
+///
+/// No character mapping or shaping is done. The result is scaled to `size` units per em (y pointing up).
+/// Glyphs that are missing in the font are skipped, but still advance the pen.
+pub fn draw_glyphs<F: Font + ?Sized>(font: &F, size: f32, glyphs: &[PositionedGlyph]) -> Outline {
+    let mut outline = Outline::new();
+    place_glyphs(font, size, Vector2F::default(), glyphs, &mut outline);
+    outline
+}
+
+// add the glyphs to `outline`, starting at `pen` (in glyph space). returns the final pen position.
+fn place_glyphs<F: Font + ?Sized>(font: &F, size: f32, mut pen: Vector2F, glyphs: &[PositionedGlyph], outline: &mut Outline) -> Vector2F {
+    let scale = Transform2F::from_scale(Vector2F::splat(size)) * font.font_matrix();
+    for g in glyphs {
+        if let Some(glyph) = font.glyph(g.gid) {
+            let mut path = glyph.path;
+            path.transform(&(scale * Transform2F::from_translation(pen + g.offset)));
+            outline.push_outline(path);
+        }
+        pen = pen + g.advance;
+    }
+    pen
+}
+
+/// The cell of each glyph for hit testing, together with its source range.
+///
+/// Cells span the advance of the glyph horizontally and descent to ascent vertically, so adjacent
+/// glyphs tile without gaps. They are in the same space as the outlines of `draw_glyphs`.
+pub fn glyph_cells<F: Font + ?Sized>(font: &F, size: f32, glyphs: &[PositionedGlyph]) -> Vec<(Range<usize>, RectF)> {
+    let scale = Transform2F::from_scale(Vector2F::splat(size)) * font.font_matrix();
+    let (descent, ascent) = line_extent(font);
+    let mut pen = Vector2F::default();
+    glyphs.iter().map(|g| {
+        let cell = RectF::from_points(
+            Vector2F::new(pen.x(), pen.y() + descent),
+            Vector2F::new(pen.x() + g.advance.x(), pen.y() + ascent)
+        );
+        pen = pen + g.advance;
+        (g.cluster.clone(), scale * cell)
+    }).collect()
+}
+
+// descent and ascent of a line in glyph space
+fn line_extent<F: Font + ?Sized>(font: &F) -> (f32, f32) {
+    match font.vmetrics() {
+        Some(vmetrics) => (vmetrics.descent, vmetrics.ascent),
+        // one em above the baseline
+        None => (0.0, (font.font_matrix().inverse() * Vector2F::new(0.0, 1.0)).y())
+    }
+}
+
+/// The background rectangle of a line of glyphs, for highlighting or selection.
+///
+/// It spans the advance of the glyphs horizontally and the descent to the ascent of the font vertically,
+/// so it matches the line height rather than the ink. Fill it before the outline of `draw_glyphs`.
+pub fn draw_line_background<F: Font + ?Sized>(font: &F, size: f32, glyphs: &[PositionedGlyph]) -> Outline {
+    let scale = Transform2F::from_scale(Vector2F::splat(size)) * font.font_matrix();
+    let (descent, ascent) = line_extent(font);
+    let width: f32 = glyphs.iter().map(|g| g.advance.x()).sum();
+
+    let mut outline = Outline::new();
+    outline.push_contour(Contour::from_rect(scale * RectF::from_points(
+        Vector2F::new(0.0, descent),
+        Vector2F::new(width, ascent)
+    )));
+    outline
+}
+
+/// Draw several runs of left-to-right text, each with its own style, as one line.
+///
+/// The text is laid out as a whole, so kerning also applies across run boundaries.
+/// Returns one outline per run (placed like `draw_glyphs` would), together with the style of the run.
+pub fn draw_styled_runs<F: Font + ?Sized, S: Clone>(font: &F, size: f32, runs: &[(String, S)]) -> Vec<(Outline, S)> {
+    let text: String = runs.iter().map(|(text, _)| text.as_str()).collect();
+    let glyphs = layout_run(font, &text, Direction::LeftToRight);
+
+    let mut result = Vec::with_capacity(runs.len());
+    let mut pen = Vector2F::default();
+    let (mut run_start, mut glyph_start) = (0, 0);
+    for (run_text, style) in runs {
+        let run_end = run_start + run_text.len();
+        let glyph_end = glyph_start + glyphs[glyph_start ..].iter()
+            .take_while(|g| g.cluster.start < run_end)
+            .count();
+
+        let mut outline = Outline::new();
+        pen = place_glyphs(font, size, pen, &glyphs[glyph_start .. glyph_end], &mut outline);
+        result.push((outline, style.clone()));
+
+        run_start = run_end;
+        glyph_start = glyph_end;
+    }
+    result
+}
+
+/// The layers of the color glyph `gid`, scaled like `draw_glyphs` would, bottom first.
+///
+/// Layers using the text color get `foreground`. Paint them in order with source-over blending (see `blend_over`).
+/// Returns None if the glyph has no color layers.
+pub fn draw_color_glyph<F: Font + ?Sized>(font: &F, size: f32, gid: GlyphId, palette: usize, foreground: ColorU) -> Option<Vec<(Outline, ColorU)>> {
+    let scale = Transform2F::from_scale(Vector2F::splat(size)) * font.font_matrix();
+    let layers = font.color_glyph_layers(gid, palette)?;
+    Some(layers.into_iter().filter_map(|(layer_gid, color)| {
+        let mut path = font.glyph(layer_gid)?.path;
+        path.transform(&scale);
+        Some((path, color.unwrap_or(foreground)))
+    }).collect())
+}
+
+/// Paint `src` over `dst` (source-over compositing of non-premultiplied colors).
+pub fn blend_over(dst: ColorU, src: ColorU) -> ColorU {
+    let src_a = src.a as f32 / 255.;
+    let dst_a = dst.a as f32 / 255.;
+    let a = src_a + dst_a * (1. - src_a);
+    if a == 0. {
+        return ColorU { r: 0, g: 0, b: 0, a: 0 };
+    }
+    let channel = |s: u8, d: u8| {
+        let c = (s as f32 * src_a + d as f32 * dst_a * (1. - src_a)) / a;
+        c.round() as u8
+    };
+    ColorU {
+        r: channel(src.r, dst.r),
+        g: channel(src.g, dst.g),
+        b: channel(src.b, dst.b),
+        a: (a * 255.).round() as u8,
+    }
+}
+
+/// Which lines to draw along a run of text.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Decorations {
+    pub underline: bool,
+    pub strikethrough: bool,
+    pub overline: bool,
+}
+
+/// Draw the decoration lines for the glyphs, in the same space as `draw_glyphs` would place them.
+///
+/// The lines span the horizontal advance of the run. Underline and strikethrough use the
+/// metrics suggested by the font and are skipped if it has none. The overline sits on the ascender
+/// and uses the underline thickness.
+pub fn draw_decorations<F: Font + ?Sized>(font: &F, size: f32, glyphs: &[PositionedGlyph], decorations: Decorations) -> Outline {
+    let scale = Transform2F::from_scale(Vector2F::splat(size)) * font.font_matrix();
+    let width: f32 = glyphs.iter().map(|g| g.advance.x()).sum();
+    let underline = font.underline_metrics();
+
+    let mut lines = vec![];
+    if decorations.underline {
+        lines.extend(underline);
+    }
+    if decorations.strikethrough {
+        lines.extend(font.strikeout_metrics());
+    }
+    if decorations.overline {
+        if let (Some(vmetrics), Some(underline)) = (font.vmetrics(), underline) {
+            lines.push(LineMetrics { position: vmetrics.ascent, thickness: underline.thickness });
+        }
+    }
+
+    let mut outline = Outline::new();
+    for line in lines {
+        let rect = RectF::from_points(
+            Vector2F::new(0.0, line.position - line.thickness),
+            Vector2F::new(width, line.position)
+        );
+        outline.push_contour(Contour::from_rect(scale * rect));
+    }
+    outline
+}
+
+// every character is one unit wide, ascent 0.75 and descent -0.25
+#[cfg(test)]
+struct MonoFont(crate::Name);
+#[cfg(test)]
+impl Font for MonoFont {
+    fn num_glyphs(&self) -> u32 { 128 }
+    fn font_matrix(&self) -> Transform2F { Transform2F::default() }
+    fn glyph(&self, _gid: GlyphId) -> Option<crate::Glyph> { None }
+    fn name(&self) -> &crate::Name { &self.0 }
+    fn gid_for_unicode_codepoint(&self, codepoint: u32) -> Option<GlyphId> { Some(GlyphId(codepoint)) }
+    fn positioned_advance(&self, _gid: GlyphId, _features: &[Tag]) -> f32 { 1.0 }
+    fn vmetrics(&self) -> Option<crate::VMetrics> { Some(crate::VMetrics { ascent: 0.75, descent: -0.25, line_gap: 0. }) }
+}
+
+#[test]
+fn test_blend_over() {
+    let white = ColorU { r: 255, g: 255, b: 255, a: 255 };
+    let half_red = ColorU { r: 255, g: 0, b: 0, a: 128 };
+    assert_eq!(blend_over(white, half_red), ColorU { r: 255, g: 127, b: 127, a: 255 });
+
+    let transparent = ColorU { r: 0, g: 0, b: 0, a: 0 };
+    assert_eq!(blend_over(transparent, half_red), half_red);
+}
+
+#[test]
+fn test_bidi_mirror() {
+    assert_eq!(bidi_mirror('('), Some(')'));
+    assert_eq!(bidi_mirror(']'), Some('['));
+    assert_eq!(bidi_mirror('\u{300C}'), Some('\u{300D}'));
+    assert_eq!(bidi_mirror('a'), None);
+}
+
+#[test]
+fn test_relayout_from() {
+    use crate::{Glyph, Name};
+    use pathfinder_geometry::transform2d::Transform2F;
+
+    // every ASCII character is its own glyph, one unit wide. "A" and "V" kern.
+    struct AsciiFont(Name);
+    impl Font for AsciiFont {
+        fn num_glyphs(&self) -> u32 { 128 }
+        fn font_matrix(&self) -> Transform2F { Transform2F::default() }
+        fn glyph(&self, _gid: GlyphId) -> Option<Glyph> { None }
+        fn name(&self) -> &Name { &self.0 }
+        fn gid_for_unicode_codepoint(&self, codepoint: u32) -> Option<GlyphId> {
+            if codepoint < 128 { Some(GlyphId(codepoint)) } else { None }
+        }
+        fn positioned_advance(&self, _gid: GlyphId, _features: &[crate::opentype::Tag]) -> f32 { 1.0 }
+        fn kerning(&self, left: GlyphId, right: GlyphId) -> f32 {
+            if (left.0, right.0) == ('A' as u32, 'V' as u32) { -0.25 } else { 0.0 }
+        }
+    }
+    let font = AsciiFont(Name::default());
+
+    let mut layout = TextLayout::new(&font, "xxAB", Direction::LeftToRight);
+    layout.relayout_from(3, "Vé");
+    let fresh = layout_run(&font, "xxAVé", Direction::LeftToRight);
+    assert_eq!(layout.text(), "xxAVé");
+    assert_eq!(layout.glyphs().len(), fresh.len());
+    for (a, b) in layout.glyphs().iter().zip(&fresh) {
+        assert_eq!((a.gid, &a.cluster, a.advance), (b.gid, &b.cluster, b.advance));
+    }
+    assert_eq!(layout.glyphs()[2].advance.x(), 0.75);
+
+    let width: f32 = fresh.iter().map(|g| g.advance.x()).sum();
+    assert_eq!(font.text_advance("xxAVé", &[]), width);
+}
+
+#[test]
+fn test_fallback_metrics() {
+    use crate::{Glyph, Name};
+    use pathfinder_geometry::transform2d::Transform2F;
+
+    // a font without hhea or OS/2
+    struct BareFont(Name);
+    impl Font for BareFont {
+        fn num_glyphs(&self) -> u32 { 1 }
+        fn font_matrix(&self) -> Transform2F { Transform2F::default() }
+        fn glyph(&self, _gid: GlyphId) -> Option<Glyph> { None }
+        fn name(&self) -> &Name { &self.0 }
+    }
+    let font = BareFont(Name::default());
+    assert!(font.vmetrics().is_none());
+    let font = font.with_fallback_metrics(0.8, -0.2, 0.1);
+    let vmetrics = font.vmetrics().unwrap();
+    assert_eq!((vmetrics.ascent, vmetrics.descent, vmetrics.line_gap), (0.8, -0.2, 0.1));
+    assert_eq!(line_extent(&font), (-0.2, 0.8));
+}
+
+#[test]
+fn test_cursive_attachment() {
+    use crate::{Glyph, Name};
+    use crate::opentype::gpos::EntryExit;
+    use pathfinder_geometry::transform2d::Transform2F;
+
+    // "a" is 100 wide and joins on both sides, "b" only has an entry
+    struct JoiningFont(Name);
+    impl Font for JoiningFont {
+        fn num_glyphs(&self) -> u32 { 128 }
+        fn font_matrix(&self) -> Transform2F { Transform2F::default() }
+        fn glyph(&self, _gid: GlyphId) -> Option<Glyph> { None }
+        fn name(&self) -> &Name { &self.0 }
+        fn gid_for_unicode_codepoint(&self, codepoint: u32) -> Option<GlyphId> { Some(GlyphId(codepoint)) }
+        fn positioned_advance(&self, _gid: GlyphId, _features: &[crate::opentype::Tag]) -> f32 { 100.0 }
+        fn cursive_anchors(&self, gid: GlyphId) -> Option<EntryExit> {
+            match gid.0 as u8 {
+                b'a' => Some(EntryExit { entry: Some((0, 0)), exit: Some((90, 20)), right_to_left: false }),
+                b'b' => Some(EntryExit { entry: Some((10, 0)), exit: None, right_to_left: false }),
+                _ => None
+            }
+        }
+    }
+    let font = JoiningFont(Name::default());
+
+    let glyphs = layout_run(&font, "aab", Direction::LeftToRight);
+    assert_eq!(glyphs[0].advance.x(), 90.);
+    assert_eq!(glyphs[1].advance.x(), 80.);
+    assert_eq!(glyphs[2].advance.x(), 100.);
+    let ys: Vec<f32> = glyphs.iter().map(|g| g.offset.y()).collect();
+    assert_eq!(ys, vec![0., 20., 40.]);
+
+    // the same anchors in a right-to-left run
+    let glyphs = layout_run(&font, "ab", Direction::RightToLeft);
+    assert_eq!(glyphs[0].gid, GlyphId('b' as u32));
+    assert_eq!(glyphs[0].advance.x(), -80.);
+    assert_eq!(glyphs[1].offset.y(), 0.);
+    assert_eq!(glyphs[0].offset.y(), 20.);
+}
+
+#[test]
+fn test_mark_attachment() {
+    use crate::{Glyph, Name};
+    use pathfinder_geometry::transform2d::Transform2F;
+
+    // "m" attaches to "a", and "n" to "m"
+    struct MarkFont(Name);
+    impl Font for MarkFont {
+        fn num_glyphs(&self) -> u32 { 128 }
+        fn font_matrix(&self) -> Transform2F { Transform2F::default() }
+        fn glyph(&self, _gid: GlyphId) -> Option<Glyph> { None }
+        fn name(&self) -> &Name { &self.0 }
+        fn gid_for_unicode_codepoint(&self, codepoint: u32) -> Option<GlyphId> { Some(GlyphId(codepoint)) }
+        fn positioned_advance(&self, _gid: GlyphId, _features: &[crate::opentype::Tag]) -> f32 { 100.0 }
+        fn mark_attachment(&self, base: GlyphId, mark: GlyphId) -> Option<Vector2F> {
+            match (base.0 as u8, mark.0 as u8) {
+                (b'a', b'm') => Some(Vector2F::new(40., 60.)),
+                (b'm', b'n') => Some(Vector2F::new(0., 30.)),
+                _ => None
+            }
+        }
+    }
+    let font = MarkFont(Name::default());
+
+    let glyphs = layout_run(&font, "amnb", Direction::LeftToRight);
+    let advances: Vec<f32> = glyphs.iter().map(|g| g.advance.x()).collect();
+    assert_eq!(advances, vec![100., 0., 0., 100.]);
+    assert_eq!(glyphs[1].offset, Vector2F::new(-60., 60.));
+    assert_eq!(glyphs[2].offset, Vector2F::new(-60., 90.));
+    assert_eq!(glyphs[3].offset, Vector2F::default());
+
+    // the second "m" skips the first one and attaches to the "a". "n" does not attach to "a" directly.
+    let glyphs = layout_run(&font, "ammn", Direction::LeftToRight);
+    assert_eq!(glyphs[2].offset, Vector2F::new(-60., 60.));
+    let glyphs = layout_run(&font, "an", Direction::LeftToRight);
+    assert_eq!(glyphs[1].offset, Vector2F::default());
+    assert_eq!(glyphs[1].advance.x(), 100.);
+
+    // right to left, the mark is drawn before the base
+    let glyphs = layout_run(&font, "am", Direction::RightToLeft);
+    assert_eq!(glyphs[0].gid, GlyphId('m' as u32));
+    assert_eq!(glyphs[0].advance.x(), 0.);
+    assert_eq!(glyphs[0].offset, Vector2F::new(40., 60.));
+}
+
+#[test]
+fn test_shaper() {
+    use crate::{Glyph, Name};
+    use crate::opentype::gsub::{Substitution, GlyphList, apply_lookup};
+    use pathfinder_geometry::transform2d::Transform2F;
+    use std::collections::HashMap;
+
+    // "f" "i" forms a ligature (glyph 1) with liga, "a" becomes glyph 2 with locl
+    struct LigatureFont(Name);
+    impl Font for LigatureFont {
+        fn num_glyphs(&self) -> u32 { 128 }
+        fn font_matrix(&self) -> Transform2F { Transform2F::default() }
+        fn glyph(&self, _gid: GlyphId) -> Option<Glyph> { None }
+        fn name(&self) -> &Name { &self.0 }
+        fn gid_for_unicode_codepoint(&self, codepoint: u32) -> Option<GlyphId> { Some(GlyphId(codepoint)) }
+        fn positioned_advance(&self, _gid: GlyphId, _features: &[Tag]) -> f32 { 100.0 }
+        fn apply_substitutions(&self, glyphs: &mut Vec<(GlyphId, Range<usize>)>, features: &[Tag]) -> bool {
+            let mut applied = false;
+            if features.contains(&Tag(*b"locl")) {
+                let mut single = HashMap::new();
+                single.insert(b'a' as u16, 2);
+                applied |= apply_lookup(&[Substitution::Single(single)], glyphs);
+            }
+            if features.contains(&Tag(*b"liga")) {
+                let mut ligatures = HashMap::new();
+                ligatures.insert(b'f' as u16, vec![(GlyphList::new(vec![b'i' as u16]), 1)]);
+                applied |= apply_lookup(&[Substitution::Ligatures(ligatures)], glyphs);
+            }
+            applied
+        }
+    }
+    let font = LigatureFont(Name::default());
+
+    let glyphs = Shaper::new(&font).shape("fia", Direction::LeftToRight);
+    let gids: Vec<u32> = glyphs.iter().map(|g| g.gid.0).collect();
+    assert_eq!(gids, vec![1, 2]);
+    assert_eq!(glyphs[0].cluster, 0 .. 2);
+
+    // substitutions happen in logical order
+    let glyphs = Shaper::with_features(&font, &[Tag(*b"liga")]).shape("fia", Direction::RightToLeft);
+    let gids: Vec<u32> = glyphs.iter().map(|g| g.gid.0).collect();
+    assert_eq!(gids, vec![b'a' as u32, 1]);
+
+    assert_eq!(layout_run(&font, "fi", Direction::LeftToRight).len(), 2);
+}
+
+#[test]
+fn test_paragraph() {
+    // the lines are ascent minus descent (one unit) apart
+    let font = MonoFont(crate::Name::default());
+    let text = "aa bb cc\ndd";
+
+    let lines = Paragraph::new(&font, 1.0).lines(text);
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0].width, 8.);
+    assert_eq!(lines[1].origin, Vector2F::new(0., -1.));
+    assert_eq!(lines[1].glyphs[0].cluster, 9 .. 10);
+
+    let lines = Paragraph::new(&font, 1.0).max_width(5.).alignment(Alignment::Center).line_spacing(2.).lines(text);
+    let widths: Vec<f32> = lines.iter().map(|line| line.width).collect();
+    assert_eq!(widths, vec![5., 2., 2.]);
+    assert_eq!(lines[1].glyphs[0].cluster, 6 .. 7);
+    assert_eq!(lines[1].origin, Vector2F::new(1.5, -2.));
+    assert_eq!(lines[2].origin, Vector2F::new(1.5, -4.));
+
+    // a word wider than the maximum is not broken
+    let lines = Paragraph::new(&font, 1.0).max_width(2.).alignment(Alignment::Right).lines("abcd e");
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0].glyphs.len(), 4);
+    assert_eq!(lines[0].origin.x(), -2.);
+    assert_eq!(lines[1].origin.x(), 1.);
+}
+
+#[test]
+fn test_glyph_cells() {
+    let font = MonoFont(crate::Name::default());
+    let glyphs = layout_run(&font, "aé", Direction::LeftToRight);
+
+    let cells = glyph_cells(&font, 10., &glyphs);
+    assert_eq!(cells.len(), 2);
+    assert_eq!(cells[0], (0 .. 1, RectF::from_points(Vector2F::new(0., -2.5), Vector2F::new(10., 7.5))));
+    // the next cell starts where the previous one ends
+    assert_eq!(cells[1], (1 .. 3, RectF::from_points(Vector2F::new(10., -2.5), Vector2F::new(20., 7.5))));
+}
+
+#[test]
+fn test_line_background() {
+    let font = MonoFont(crate::Name::default());
+    let glyphs = layout_run(&font, "abc", Direction::LeftToRight);
+
+    // the line height, not the ink of the (empty) glyphs
+    let background = draw_line_background(&font, 10., &glyphs);
+    assert_eq!(background.contours().len(), 1);
+    assert_eq!(background.bounds(), RectF::from_points(Vector2F::new(0., -2.5), Vector2F::new(30., 7.5)));
+}
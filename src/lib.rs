@@ -5,6 +5,8 @@
 use std::fmt;
 use std::convert::TryInto;
 use std::any::TypeId;
+use std::collections::HashSet;
+use std::ops::Range;
 use nom::{IResult, Err::*, error::VerboseError};
 use tuple::{TupleElements};
 use pdf_encoding::Encoding;
@@ -14,6 +16,7 @@ pub use svg::SvgGlyph;
 
 use pathfinder_geometry::{rect::RectF, vector::Vector2F, transform2d::Transform2F};
 use pathfinder_content::outline::{Outline, Contour};
+use pathfinder_color::ColorU;
 
 #[derive(Clone)]
 pub struct Glyph {
@@ -23,6 +26,19 @@ pub struct Glyph {
     /// transform by font_matrix to scale it to 1em
     pub path: Outline,
 }
+impl Glyph {
+    /// The horizontal advance of this glyph (same as `metrics.advance`).
+    #[inline]
+    pub fn advance_width(&self) -> f32 {
+        self.metrics.advance
+    }
+    
+    /// The left side bearing of this glyph (same as `metrics.lsb`).
+    #[inline]
+    pub fn left_side_bearing(&self) -> f32 {
+        self.metrics.lsb
+    }
+}
 
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
 pub struct GlyphId(pub u32);
@@ -33,18 +49,143 @@ pub struct VMetrics {
     pub ascent: f32,
     pub descent: f32,
 }
+/// Slope of the text cursor, as given by the `hhea` table.
+///
+/// An upright font has a rise of 1 and a run of 0.
+#[derive(Copy, Clone, Debug)]
+pub struct CaretSlope {
+    pub rise: f32,
+    pub run: f32,
+    /// horizontal shift of the caret (used for slanted highlights)
+    pub offset: f32,
+}
+impl CaretSlope {
+    /// horizontal displacement per unit of vertical distance
+    pub fn skew(&self) -> f32 {
+        if self.rise == 0.0 {
+            0.0
+        } else {
+            self.run / self.rise
+        }
+    }
+}
+/// Placement of a line drawn along the text, like an underline or a strikeout.
+///
+/// `position` is the top edge of the line relative to the baseline, both values are in glyph space.
+#[derive(Copy, Clone, Debug)]
+pub struct LineMetrics {
+    pub position: f32,
+    pub thickness: f32,
+}
+/// Properties of a glyph, as given by the AAT `prop` table.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct GlyphProps {
+    /// Unicode bidirectional class (0 = L, 1 = R, 2 = AL, 3 = EN, …)
+    pub direction: u8,
+    /// the glyph is drawn on top of the previous one
+    pub floater: bool,
+    pub hangs_left: bool,
+    pub hangs_right: bool,
+    /// the glyph has a mirrored form, which is `mirror_offset` glyphs away
+    pub mirrored: bool,
+    pub mirror_offset: i8,
+}
+impl GlyphProps {
+    pub fn from_bits(bits: u16) -> Self {
+        // the pair offset is a signed 4 bit value
+        let offset = ((bits >> 8) & 0xF) as i8;
+        GlyphProps {
+            direction: (bits & 0x1F) as u8,
+            floater: bits & 0x8000 != 0,
+            hangs_left: bits & 0x4000 != 0,
+            hangs_right: bits & 0x2000 != 0,
+            mirrored: bits & 0x1000 != 0,
+            mirror_offset: if offset >= 8 { offset - 16 } else { offset },
+        }
+    }
+}
+/// An axis of a variable font, from the `fvar` table.
+#[derive(Clone, Debug)]
+pub struct Axis {
+    /// like `wght` or `wdth`
+    pub tag: Tag,
+    pub min: f32,
+    pub default: f32,
+    pub max: f32,
+    /// the axis should not be shown in user interfaces
+    pub hidden: bool,
+    /// `name` table entry of the axis name
+    pub name_id: u16,
+}
+/// Encoding of an embedded bitmap
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Tiff,
+}
+/// An embedded color bitmap of a glyph, from the `sbix` or `CBDT` table.
+#[derive(Clone, Debug)]
+pub struct BitmapGlyph {
+    /// pixels per em of the strike the bitmap belongs to
+    pub ppem: u16,
+    /// position of the bitmap's lower left corner relative to the glyph origin, in pixels (y up)
+    pub origin: (i16, i16),
+    pub format: ImageFormat,
+    /// the encoded image
+    pub data: Vec<u8>,
+}
 #[derive(Copy, Clone, Default)]
 pub struct HMetrics {
     pub lsb: f32,
     pub advance: f32
 }
 
+/// Stem hints declared in a glyph's charstring.
+///
+/// Each stem is `(edge, width)` in glyph space: horizontal stems are given by y-coordinates,
+/// vertical stems by x-coordinates. Negative widths are used for ghost (edge) hints.
+#[derive(Default, Debug, Clone)]
+pub struct GlyphHints {
+    pub horizontal: Vec<(f32, f32)>,
+    pub vertical: Vec<(f32, f32)>,
+}
+
 #[derive(Default, Debug, Clone)]
 pub struct Name {
     pub family: Option<String>,
     pub subfamily: Option<String>,
     pub postscript_name: Option<String>,
     pub full_name: Option<String>,
+
+    /// all name records that could be decoded, in every language the font has them in
+    pub localized: Vec<LocalizedName>,
+}
+impl Name {
+    /// Look up a name id in the language given by the BCP-47 tag `lang`.
+    ///
+    /// An exact tag match is preferred over one where only the primary language agrees.
+    /// If the font has no such record, the English one is returned.
+    pub fn localized(&self, name_id: u16, lang: &str) -> Option<&str> {
+        let best = |lang: &str| self.localized.iter()
+            .filter(|r| r.name_id == name_id)
+            .filter_map(|r| r.language.as_ref().map(|l| (opentype::language::language_match(l, lang), r)))
+            .filter(|&(score, _)| score > 0)
+            .max_by_key(|&(score, _)| score)
+            .map(|(_, r)| r.value.as_str());
+        best(lang).or_else(|| best("en-US"))
+    }
+}
+
+/// A single record of the 'name' table
+#[derive(Debug, Clone)]
+pub struct LocalizedName {
+    pub name_id: u16,
+    pub platform_id: u16,
+    pub language_id: u16,
+    /// the BCP-47 tag of the language, if it is known
+    pub language: Option<String>,
+    pub value: String,
 }
 
 pub trait Font: 'static {
@@ -62,6 +203,22 @@ pub trait Font: 'static {
     /// Note, that a *gid* is only meaningful within one font and cannot be transfered to another font.
     fn glyph(&self, gid: GlyphId) -> Option<Glyph>;
     
+    /// Like `glyph`, but tells a missing glyph from a broken one.
+    ///
+    /// Formats that cannot tell them apart report every glyph they do not have as missing.
+    fn try_glyph(&self, gid: GlyphId) -> Result<Glyph, GlyphError> {
+        self.glyph(gid).ok_or(GlyphError::Missing(gid))
+    }
+    
+    /// The number of contours of the glyph `gid`.
+    ///
+    /// For TrueType outlines this is numberOfContours of the glyph header,
+    /// which is negative for composite glyphs and 0 for empty ones.
+    /// For other outlines it is the number of subpaths (moveto operations).
+    fn num_contours(&self, gid: GlyphId) -> Option<i16> {
+        self.glyph(gid).map(|glyph| glyph.path.contours().len() as i16)
+    }
+    
     #[cfg(feature="svg")]
     fn svg_glyph(&self, gid: GlyphId) -> Option<&SvgGlyph> {
         None
@@ -77,11 +234,31 @@ pub trait Font: 'static {
     
     /// Get the *gid* for the glyph with the given *name*.
     ///
-    /// Returns None if the underlying font does not define any names, or does not contain a glyph with this name.
-    fn gid_for_name(&self, _name: &str) -> Option<GlyphId> {
+    /// Fonts without glyph names map the name to a codepoint (following the Adobe Glyph List,
+    /// `uniXXXX` and `uXXXXXX`) and look that up in the character map instead.
+    /// Returns None if the font does not contain a glyph with this name.
+    fn gid_for_name(&self, name: &str) -> Option<GlyphId> {
+        glyphname::gid_for_name_via_unicode(self, name)
+    }
+    
+    /// Get the *gid* for the glyph with the given CID.
+    ///
+    /// Returns None unless the font is a CID-keyed CFF font (or an OpenType font with one).
+    fn gid_for_cid(&self, _cid: u32) -> Option<GlyphId> {
         None
     }
     
+    /// Find a glyph given either a codepoint or a glyph name.
+    ///
+    /// Understands `U+XXXX`, `0xXX`, decimal numbers and single characters as codepoints
+    /// (looked up in the character map). Everything else, including `uniXXXX`, is taken as a glyph name.
+    fn resolve_glyph(&self, spec: &str) -> Option<GlyphId> {
+        match glyphname::parse_codepoint(spec) {
+            Some(codepoint) => self.gid_for_unicode_codepoint(codepoint),
+            None => self.gid_for_name(spec)
+        }
+    }
+    
     /// Get the *gid* for the glyph that corresponds to the single unicode scalar `codepoint`.
     ///
     /// Returns None if the font if the codepoint cannot be mapped to a glyph for whatever reason.
@@ -92,6 +269,71 @@ pub trait Font: 'static {
             .and_then(|cp| self.gid_for_codepoint(cp as u32))
     }
     
+    /// The fraction (0 to 1) of a built-in sample of characters of `script` (an OpenType script tag) that the font has glyphs for.
+    ///
+    /// Returns 0 for scripts without a sample.
+    fn script_coverage(&self, script: Tag) -> f32 {
+        let sample = match script::script_sample(script) {
+            Some(sample) => sample,
+            None => return 0.0
+        };
+        let (covered, total) = sample.chars().fold((0, 0), |(covered, total), c| {
+            match self.gid_for_unicode_codepoint(c as u32) {
+                Some(gid) if gid.0 != 0 => (covered + 1, total + 1),
+                _ => (covered, total + 1)
+            }
+        });
+        covered as f32 / total as f32
+    }
+    /// The script most of the codepoints in the cmap belong to, as an OpenType script tag.
+    ///
+    /// Codepoints are classified by Unicode block. Returns None if the font has no cmap or none of the blocks are known.
+    fn primary_script(&self) -> Option<Tag> {
+        let cmap = self.cmap()?;
+        script::dominant_script(cmap.items().filter(|&(_, gid)| gid.0 != 0).map(|(cp, _)| cp))
+    }
+    
+    /// The unicode character map of this font, if it has one.
+    fn cmap(&self) -> Option<&CMap> {
+        None
+    }
+    
+    /// (platform, encoding, format) of the `cmap` subtable used for codepoint lookups.
+    ///
+    /// If a font has several, a format 12 subtable with full Unicode coverage is preferred,
+    /// then a format 4 BMP subtable, then other Unicode subtables and last the Macintosh Roman one.
+    fn selected_cmap(&self) -> Option<(u16, u16, u16)> {
+        self.cmap().and_then(|cmap| cmap.selected())
+    }
+    
+    /// The set of *gid*s that are referenced by any entry of the character map.
+    ///
+    /// Only the subtable of `selected_cmap` (and the variation sequences of a format 14 subtable)
+    /// are looked at, gids reached only through other subtables are not included.
+    /// Empty if the font has no character map.
+    fn cmap_covered_gids(&self) -> HashSet<u32> {
+        self.cmap().map(|cmap| cmap.gids()).unwrap_or_default()
+    }
+    
+    /// The stem hints of the glyph `gid`, as declared in its charstring (CFF and Type 1 outlines only).
+    ///
+    /// The hints are not applied to the outline returned by `glyph`.
+    fn glyph_hints(&self, _gid: GlyphId) -> Option<GlyphHints> {
+        None
+    }
+    
+    /// The path operations drawn by the charstring of the glyph `gid` (CFF outlines only).
+    ///
+    /// Relative operands are resolved to absolute coordinates and control points are kept.
+    fn cff_glyph_segments(&self, _gid: GlyphId) -> Option<Vec<CffSegment>> {
+        None
+    }
+    
+    /// The glyph count implied by each table, to detect broken (e.g. badly subsetted) fonts.
+    fn glyph_count_report(&self) -> GlyphCountReport {
+        GlyphCountReport::default()
+    }
+    
     /// The "native encoding" of this font.
     ///
     /// Returns None if this term does not apply or it isn't defined.
@@ -112,16 +354,319 @@ pub trait Font: 'static {
     }
     
     /// Vertical metrics of the font (common across all glyphs)
+    ///
+    /// OpenType fonts use the typographic metrics of `OS/2` if it asks for them (USE_TYPO_METRICS), and those of `hhea` otherwise.
     fn vmetrics(&self) -> Option<VMetrics> {
         None
     }
     
+    /// The weight class, from 1 to 1000 (400 is regular, 700 bold).
+    ///
+    /// OpenType fonts give it in `OS/2`, CFF and Type1 fonts have a weight name (like "Bold") that is mapped to the class.
+    fn weight(&self) -> Option<u16> {
+        None
+    }
+    
+    /// Whether the font is italic (or oblique)
+    fn is_italic(&self) -> bool {
+        false
+    }
+
+    /// Wrap the font so that `vmetrics` falls back to the given values (in glyph space)
+    /// when the font lacks `hhea` and `OS/2`.
+    fn with_fallback_metrics(self, ascent: f32, descent: f32, line_gap: f32) -> FallbackMetrics<Self> where Self: Sized {
+        FallbackMetrics::new(Box::new(self), ascent, descent, line_gap)
+    }
+    
+    /// Slope of the caret, for italic and oblique fonts
+    fn caret_slope(&self) -> Option<CaretSlope> {
+        None
+    }
+    
+    /// The variation axes, empty unless this is a variable font
+    fn variation_axes(&self) -> Vec<Axis> {
+        Vec::new()
+    }
+    
+    /// The default value of each variation axis
+    fn default_coords(&self) -> Vec<f32> {
+        self.variation_axes().iter().map(|axis| axis.default).collect()
+    }
+    
+    /// Number of named instances (like "Bold" or "Condensed Light") of a variable font
+    fn num_named_instances(&self) -> usize {
+        0
+    }
+    
+    /// The layers of a color glyph (from the `COLR` and `CPAL` tables), bottom first.
+    ///
+    /// Each layer is a glyph and its color from the given palette, `None` stands for the text color.
+    /// Colors keep their alpha, so the layers have to be painted with source-over blending.
+    fn color_glyph_layers(&self, _gid: GlyphId, _palette: usize) -> Option<Vec<(GlyphId, Option<ColorU>)>> {
+        None
+    }
+    
+    /// An embedded color bitmap of the glyph (from the `sbix` or `CBDT` table) for rendering at `ppem` pixels per em.
+    ///
+    /// The smallest strike of at least `ppem` is used, otherwise the largest one. The image is not decoded,
+    /// and has to be scaled by `ppem / bitmap.ppem`.
+    fn bitmap_glyph(&self, _gid: GlyphId, _ppem: u16) -> Option<BitmapGlyph> {
+        None
+    }
+    
+    /// Height of capital letters above the baseline (in glyph space).
+    ///
+    /// Taken from the `OS/2` table if possible, otherwise measured from the glyph for 'H'.
+    fn cap_height(&self) -> Option<f32> {
+        geometry::ink_top(self, 'H')
+    }
+    
+    /// Height of lowercase letters above the baseline (in glyph space).
+    ///
+    /// Taken from the `OS/2` table if possible, otherwise measured from the glyph for 'x'.
+    fn x_height(&self) -> Option<f32> {
+        geometry::ink_top(self, 'x')
+    }
+    
+    /// Suggested underline, from the `post` table
+    fn underline_metrics(&self) -> Option<LineMetrics> {
+        None
+    }
+    
+    /// Suggested strikeout, from the `OS/2` table
+    fn strikeout_metrics(&self) -> Option<LineMetrics> {
+        None
+    }
+    
+    /// Horizontal tracking (letter spacing) for the given point size, from the `trak` table.
+    ///
+    /// The value is in glyph space and should be added to the advance of every glyph.
+    fn tracking(&self, _size: f32) -> Option<f32> {
+        None
+    }
+    
+    /// Whether the advance of the glyph is stored in the font.
+    ///
+    /// False for glyphs past the last `hmtx` entry, which reuse its advance, and for sfnt fonts without an `hmtx` table.
+    /// Type1 and bare CFF fonts store the advance in the charstring of every glyph, so this is true by default.
+    fn advance_is_explicit(&self, _gid: GlyphId) -> bool {
+        true
+    }
+    
+    /// Whether the font has Graphite shaping tables (`Silf`).
+    ///
+    /// Graphite fonts need a Graphite shaper for correct shaping; their OpenType layout tables may be missing or incomplete.
+    fn is_graphite(&self) -> bool {
+        false
+    }
+    
+    /// The features of a Graphite font, from the `Feat` table.
+    fn graphite_features(&self) -> &[GraphiteFeature] {
+        &[]
+    }
+    
+    /// Directionality and mirroring of the glyph, from the AAT `prop` table
+    fn glyph_properties(&self, _gid: GlyphId) -> Option<GlyphProps> {
+        None
+    }
+
+    /// Offset of the glyph's baseline from the natural baseline (in glyph space), from the AAT `bsln` table.
+    ///
+    /// Only tables that give distances are supported, not those with control points.
+    fn aat_baseline(&self, _gid: GlyphId) -> Option<f32> {
+        None
+    }
+    
+    /// The advance of the glyph (like `glyph(gid).metrics.advance`), in glyph space.
+    ///
+    /// Fonts with a `hmtx` table read it from there without composing the outline.
+    /// Returns None if there is no such glyph.
+    fn advance(&self, gid: GlyphId) -> Option<f32> {
+        self.glyph(gid).map(|g| g.advance_width())
+    }
+    
+    /// Advance of the glyph in isolation, including the single adjustment positioning (`GPOS` lookup type 1)
+    /// of the given features.
+    ///
+    /// Fonts without positioning data return the plain advance.
+    fn positioned_advance(&self, gid: GlyphId, _features: &[Tag]) -> f32 {
+        self.advance(gid).unwrap_or(0.0)
+    }
+    
+    /// Apply the `GSUB` lookups of the given features to the glyphs, in lookup order.
+    ///
+    /// The glyphs are paired with the byte range of the text they stand for (see `opentype::gsub::apply_lookup`).
+    /// Returns whether any glyph was substituted. Fonts without substitutions leave the glyphs unchanged.
+    fn apply_substitutions(&self, _glyphs: &mut Vec<(GlyphId, Range<usize>)>, _features: &[Tag]) -> bool {
+        false
+    }
+    
+    /// Like `apply_substitutions`, but calls `record` after each lookup with its feature and index,
+    /// whether it changed the glyphs and the glyphs after it.
+    ///
+    /// Fonts that do not apply the lookups one by one apply them all without calling `record`.
+    fn trace_substitutions(&self, glyphs: &mut Vec<(GlyphId, Range<usize>)>, features: &[Tag], _record: &mut dyn FnMut(Tag, u16, bool, &[(GlyphId, Range<usize>)])) -> bool {
+        self.apply_substitutions(glyphs, features)
+    }
+    
+    /// Set the advances of a run of glyphs in visual order: the positioned advance (with `features`)
+    /// plus the kerning with the following glyph.
+    ///
+    /// Fonts with `GPOS` adjustments apply them one lookup at a time and call `record` after each step,
+    /// with whether it changed an advance and the glyphs after it.
+    fn position_glyphs(&self, glyphs: &mut [PositionedGlyph], features: &[Tag], _record: &mut dyn FnMut(ShapeStep, bool, &[PositionedGlyph])) {
+        for i in 0 .. glyphs.len() {
+            let mut advance = self.positioned_advance(glyphs[i].gid, features);
+            if let Some(next) = glyphs.get(i + 1) {
+                advance += self.kerning(glyphs[i].gid, next.gid);
+            }
+            glyphs[i].advance = Vector2F::new(advance, 0.0);
+        }
+    }
+    
     /// Kerning distance for the given glyph pair
     fn kerning(&self, _left: GlyphId, _right: GlyphId) -> f32 {
         0.0
     }
+    
+    /// The cursive attachment anchors of the glyph (`GPOS` lookup type 3), used to join connected scripts.
+    fn cursive_anchors(&self, _gid: GlyphId) -> Option<EntryExit> {
+        None
+    }
+    
+    /// Where `mark` is placed when it follows `base`, relative to the origin of `base` (in glyph space).
+    ///
+    /// This covers mark-to-base (`GPOS` lookup type 4) and mark-to-mark (lookup type 6) attachment,
+    /// where `base` is the preceding mark.
+    fn mark_attachment(&self, _base: GlyphId, _mark: GlyphId) -> Option<Vector2F> {
+        None
+    }
+    
+    /// The pen x position of each glyph of a horizontal run (in glyph space), starting at 0.
+    ///
+    /// Between two glyphs the pen moves by the advance of the first, their kerning and `tracking`.
+    fn run_positions(&self, gids: &[GlyphId], tracking: f32) -> Vec<f32> {
+        let mut positions = Vec::with_capacity(gids.len());
+        let mut x = 0.0;
+        for (i, &gid) in gids.iter().enumerate() {
+            positions.push(x);
+            x += self.positioned_advance(gid, &[]) + tracking;
+            if let Some(&next) = gids.get(i + 1) {
+                x += self.kerning(gid, next);
+            }
+        }
+        positions
+    }
+    
+    /// The advance of `text` laid out in one horizontal line (in glyph space).
+    ///
+    /// Sums the positioned advances (with `features`) and the kerning of consecutive glyphs, like `layout` would place them,
+    /// but without collecting the glyphs. Characters without a glyph use the `.notdef` glyph.
+    fn text_advance(&self, text: &str, features: &[Tag]) -> f32 {
+        let mut advance = 0.0;
+        let mut prev = None;
+        for c in text.chars() {
+            let gid = self.gid_for_unicode_codepoint(c as u32).unwrap_or_else(|| self.get_notdef_gid());
+            if let Some(prev) = prev {
+                advance += self.kerning(prev, gid);
+            }
+            advance += self.positioned_advance(gid, features);
+            prev = Some(gid);
+        }
+        advance
+    }
+    
+    /// Vertical kerning for the glyph `top` followed by `bottom` in vertical text (from the y advance of `GPOS` pair adjustments)
+    fn vertical_kerning(&self, _top: GlyphId, _bottom: GlyphId) -> f32 {
+        0.0
+    }
     fn name(&self) -> &Name;
 
+    /// The full name of the font, like "Source Sans Pro Bold Italic".
+    fn full_name(&self) -> Option<String> {
+        self.name().full_name.clone()
+    }
+
+    /// The family name of the font, like "Source Sans Pro".
+    fn family_name(&self) -> Option<String> {
+        self.name().family.clone()
+    }
+
+    /// The style within the family, like "Bold Italic".
+    fn subfamily_name(&self) -> Option<String> {
+        self.name().subfamily.clone()
+    }
+
+    /// The PostScript name of the font, like "SourceSansPro-BoldIt".
+    fn postscript_name(&self) -> Option<String> {
+        self.name().postscript_name.clone()
+    }
+
+    /// The name with the given id in the language `lang` (a BCP-47 tag like "de" or "zh-TW"), falling back to English.
+    fn localized_name(&self, name_id: u16, lang: &str) -> Option<String> {
+        self.name().localized(name_id, lang).map(|s| s.to_owned())
+    }
+    
+    /// Load every glyph of the font with `try_glyph`, to find the broken ones.
+    ///
+    /// The result has one entry for each *gid* below `num_glyphs()`.
+    fn parse_all_glyphs(&self) -> Vec<Result<Glyph, GlyphError>> {
+        (0 .. self.num_glyphs()).map(|n| self.try_glyph(GlyphId(n))).collect()
+    }
+    
+    /// Interpolate between the glyphs `from` (at `t = 0`) and `to` (at `t = 1`).
+    ///
+    /// Returns None if either glyph does not exist or the outlines are not compatible
+    /// (different number of contours or different segments).
+    fn morph_glyphs(&self, from: GlyphId, to: GlyphId, t: f32) -> Option<Glyph> {
+        let from = self.glyph(from)?;
+        let to = self.glyph(to)?;
+        geometry::morph_glyphs(&from, &to, t)
+    }
+
+    /// The filled area of the glyph outline in glyph units, with holes subtracted.
+    fn glyph_area(&self, gid: GlyphId) -> Option<f32> {
+        self.glyph(gid).map(|glyph| geometry::outline_area(&glyph.path))
+    }
+
+    /// The total length of the contours of the glyph outline in glyph units.
+    fn glyph_perimeter(&self, gid: GlyphId) -> Option<f32> {
+        self.glyph(gid).map(|glyph| geometry::outline_perimeter(&glyph.path))
+    }
+
+    /// The contours of the glyph outline, each outer contour immediately followed by the holes it contains,
+    /// for fillers that expect outer contours first.
+    fn glyph_sorted_contours(&self, gid: GlyphId) -> Option<Vec<Contour>> {
+        self.glyph(gid).map(|glyph| geometry::sorted_contours(&glyph.path))
+    }
+
+    /// The bounding box of the glyph outline in pixels at `size` pixels per em.
+    ///
+    /// This uses the scale of `draw_text`, but with y pointing down like raster output,
+    /// so the top of the glyph has the smaller y coordinate.
+    fn glyph_pixel_bounds(&self, gid: GlyphId, size: f32) -> Option<RectF> {
+        let glyph = self.glyph(gid)?;
+        let transform = Transform2F::from_scale(Vector2F::new(size, -size)) * self.font_matrix();
+        Some(transform * glyph.path.bounds())
+    }
+
+    /// The outline of the glyph with overlapping and self-intersecting contours merged
+    /// into their union (under the nonzero fill rule).
+    ///
+    /// Curves are flattened to line segments, and filled areas are on the left of each contour.
+    fn resolve_overlaps(&self, gid: GlyphId) -> Option<Outline> {
+        self.glyph(gid).map(|glyph| geometry::union_outline(&glyph.path))
+    }
+
+    /// The glyph `gid` with a synthetic bold applied.
+    ///
+    /// Each contour is dilated by `strength` (in glyph space units) and the advance grows by `2 * strength`.
+    fn synthetic_bold(&self, gid: GlyphId, strength: f32) -> Option<Glyph> {
+        let mut glyph = self.glyph(gid)?;
+        geometry::embolden(&mut glyph, strength);
+        Some(glyph)
+    }
+
     #[doc(hidden)]
     // this function must return the type id of the impl
     unsafe fn _type_id(&self) -> TypeId {
@@ -148,6 +693,14 @@ mod postscript;
 pub mod opentype;
 mod parsers;
 mod eexec;
+mod geometry;
+mod layout;
+mod wrapper;
+mod reader;
+mod glyphname;
+mod script;
+mod collection;
+mod subset;
 
 #[cfg(feature="woff")]
 mod woff;
@@ -156,9 +709,20 @@ mod woff;
 mod svg;
 
 pub use truetype::TrueTypeFont;
-pub use cff::CffFont;
+pub use cff::{CffFont, CffSegment, Ros};
 pub use type1::Type1Font;
 pub use opentype::{OpenTypeFont};
+pub use layout::{PositionedGlyph, TextLayout, Paragraph, TextLine, Alignment, Shaper, Decorations, Direction, layout, draw_text, draw_text_into, draw_glyphs, draw_decorations, draw_styled_runs, draw_color_glyph, blend_over, glyph_cells, draw_line_background, layout_run, bidi_mirror};
+pub use wrapper::{Oriented, CoordinateSystem, FallbackMetrics};
+pub use reader::{parse_reader, parse_reader_with_options};
+pub use collection::{Collection, parse_collection, parse_index};
+pub use subset::subset;
+pub use glyphname::glyph_name_to_codepoint;
+use opentype::cmap::CMap;
+use opentype::Tag;
+use opentype::graphite::GraphiteFeature;
+use opentype::gpos::EntryExit;
+use opentype::shape::ShapeStep;
 
 pub type R<'a, T> = IResult<&'a [u8], T, VerboseError<&'a [u8]>>;
 
@@ -201,19 +765,20 @@ impl From<f32> for Value {
     }
 }
 impl Value {
+    /// The integer, or `None` for a float
     #[inline]
-    fn to_int(self) -> i32 {
+    fn to_int(self) -> Option<i32> {
         match self {
-            Value::Int(i) => i,
-            Value::Float(_) => panic!("tried to cast a float to int")
+            Value::Int(i) => Some(i),
+            Value::Float(_) => None
         }
     }
+    /// The integer, or `None` for a float or a negative integer
     #[inline]
-    fn to_uint(self) -> u32 {
+    fn to_uint(self) -> Option<u32> {
         match self {
-            Value::Int(i) if i >= 0 => i as u32,
-            Value::Int(_) => panic!("expected a unsigned int"),
-            Value::Float(_) => panic!("tried to cast a float to int")
+            Value::Int(i) if i >= 0 => Some(i as u32),
+            _ => None
         }
     }
     #[inline]
@@ -267,13 +832,23 @@ pub struct Context<T=(), U=()> {
 }
 
 impl<T, U> Context<T, U> where T: TryIndex, U: TryIndex {
+    /// The local subroutine `idx` (before adding the bias), or `None` if there is no such subroutine.
     #[inline]
-    pub fn subr(&self, idx: i32) -> &[u8] {
-        self.subrs.try_index((idx + self.subr_bias) as usize).expect("requested subroutine not found")
+    pub fn subr(&self, idx: i32) -> Option<&[u8]> {
+        let idx = idx + self.subr_bias;
+        if idx < 0 {
+            return None;
+        }
+        self.subrs.try_index(idx as usize)
     }
+    /// The global subroutine `idx` (before adding the bias), or `None` if there is no such subroutine.
     #[inline]
-    pub fn global_subr(&self, idx: i32) -> &[u8] {
-        self.global_subrs.try_index((idx + self.global_subr_bias) as usize).expect("requested global subroutine not found")
+    pub fn global_subr(&self, idx: i32) -> Option<&[u8]> {
+        let idx = idx + self.global_subr_bias;
+        if idx < 0 {
+            return None;
+        }
+        self.global_subrs.try_index(idx as usize)
     }
 }
 
@@ -288,9 +863,17 @@ pub struct State {
     pub stem_hints: u32,
     pub delta_width: Option<f32>,
     pub first_stack_clearing_operator: bool,
-    pub flex_sequence: Option<Vec<Vector2F>>
+    pub flex_sequence: Option<Vec<Vector2F>>,
+    pub hints: GlyphHints,
+    /// accented character (seac): offset of the accent, and the codes of base and accent in the standard encoding
+    pub seac: Option<(Vector2F, u8, u8)>,
+    /// number of subroutine calls being executed
+    pub subr_depth: u32,
 }
 
+/// The nesting limit of subroutine calls in charstrings (as in the Type 2 charstring format)
+const MAX_SUBR_DEPTH: u32 = 10;
+
 impl State {
     #[inline]
     pub fn new() -> State {
@@ -305,7 +888,10 @@ impl State {
             stem_hints: 0,
             delta_width: None,
             first_stack_clearing_operator: true,
-            flex_sequence: None
+            flex_sequence: None,
+            hints: GlyphHints::default(),
+            seac: None,
+            subr_depth: 0,
         }
     }
     #[inline]
@@ -321,6 +907,36 @@ impl State {
         self.delta_width = None;
         self.first_stack_clearing_operator = true;
         self.flex_sequence = None;
+        self.hints.horizontal.clear();
+        self.hints.vertical.clear();
+        self.seac = None;
+        self.subr_depth = 0;
+    }
+    
+    /// record an accented character (seac) with the accent moved by `offset`.
+    /// The base and accent are given by their codes in the standard encoding.
+    fn set_seac(&mut self, offset: Vector2F, bchar: Value, achar: Value) {
+        match (bchar, achar) {
+            (Value::Int(bchar @ 0 ..= 255), Value::Int(achar @ 0 ..= 255)) => self.seac = Some((offset, bchar as u8, achar as u8)),
+            _ => warn!("seac: codes {:?} and {:?} are out of range", bchar, achar)
+        }
+    }
+    
+    /// record the stems given as (edge, width) pairs on the stack.
+    /// Each edge is relative to the end of the previous stem.
+    fn push_stems(&mut self, horizontal: bool) {
+        let mut edge = 0.0;
+        let stems = self.stack.chunks_exact(2).map(|pair| {
+            let start = edge + pair[0].to_float();
+            let width = pair[1].to_float();
+            edge = start + width;
+            (start, width)
+        });
+        if horizontal {
+            self.hints.horizontal.extend(stems);
+        } else {
+            self.hints.vertical.extend(stems);
+        }
     }
 
     #[inline]
@@ -346,25 +962,27 @@ impl State {
     pub fn push(&mut self, v: impl Into<Value>) {
         self.stack.push(v.into());
     }
+    /// the top of the stack, or `None` if it is empty
     #[inline]
-    pub fn pop(&mut self) -> Value {
-        self.stack.pop().expect("no value on the stack")
+    pub fn pop(&mut self) -> Option<Value> {
+        self.stack.pop()
     }
+    /// the top T::N values, or `None` if there are fewer
     #[inline]
-    fn pop_tuple<T>(&mut self) -> T where
+    fn pop_tuple<T>(&mut self) -> Option<T> where
         T: TupleElements<Element=Value>
     {
-        let range = self.stack.len() - T::N ..;
-        T::from_iter(self.stack.drain(range)).unwrap()
+        let start = self.stack.len().checked_sub(T::N)?;
+        T::from_iter(self.stack.drain(start ..))
     }
-    /// get stack[0 .. T::N] as a tuple
+    /// get stack[0 .. T::N] as a tuple, or `None` if there are fewer values
     /// does not modify the stack
     #[inline]
-    pub fn args<T>(&mut self) -> T where
+    pub fn args<T>(&mut self) -> Option<T> where
         T: TupleElements<Element=Value>
     {
         trace!("get {} args from {:?}", T::N, self.stack);
-        T::from_iter(self.stack.iter().cloned()).unwrap()
+        T::from_iter(self.stack.iter().cloned())
     }
 }
 
@@ -408,25 +1026,262 @@ pub enum FontType {
     Cff,
 }
 
+/// Selects which optional tables `parse_with_options` loads.
+///
+/// Skipped tables are treated as if the font did not contain them.
+/// Only sfnt based fonts (TrueType, OpenType and WOFF) have optional tables.
+///
+/// The `max_*` limits and `check_glyphs` are only enforced by `try_parse_with_options`, `None` means unlimited.
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// GSUB, GPOS, kern, GDEF, BASE, MATH, JSTF, trak, prop and bsln
+    pub layout: bool,
+    
+    /// the naming table
+    pub names: bool,
+    
+    /// color glyphs (SVG, COLR and CPAL) and color bitmaps (sbix, CBDT and CBLC)
+    pub color: bool,
+    
+    /// total size of the decompressed tables of WOFF and WOFF2 fonts (in bytes)
+    pub max_decompressed_size: Option<u64>,
+    
+    /// number of glyphs
+    pub max_glyphs: Option<u32>,
+    
+    /// nesting of composite glyphs (a composite of simple glyphs has depth 1)
+    pub max_composite_depth: Option<u32>,
+    
+    /// outline points of a single glyph, including the points of its components
+    pub max_points: Option<u32>,
+    
+    /// load every glyph while parsing and fail on the first broken one.
+    /// Otherwise glyphs are only loaded when they are used, and broken ones are missing.
+    pub check_glyphs: bool,
+}
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            layout: true,
+            names: true,
+            color: true,
+            max_decompressed_size: None,
+            max_glyphs: None,
+            max_composite_depth: None,
+            max_points: None,
+            check_glyphs: false,
+        }
+    }
+}
+impl ParseOptions {
+    /// Only load what is needed for outlines, metrics and the character map.
+    pub fn outlines_only() -> Self {
+        ParseOptions {
+            layout: false,
+            names: false,
+            color: false,
+            .. ParseOptions::default()
+        }
+    }
+
+    
+    /// Whether the table with the given tag should be loaded.
+    pub fn wants_table(&self, tag: &[u8; 4]) -> bool {
+        match tag {
+            b"GSUB" | b"GPOS" | b"kern" | b"GDEF" | b"BASE" | b"MATH" | b"JSTF" | b"trak" | b"prop" | b"bsln" |
+            b"Silf" | b"Glat" | b"Gloc" | b"Feat" => self.layout,
+            b"name" | b"ltag" => self.names,
+            b"SVG " | b"COLR" | b"CPAL" | b"sbix" | b"CBDT" | b"CBLC" => self.color,
+            _ => true
+        }
+    }
+}
+
+/// Errors when reading a font
+#[derive(Debug)]
+pub enum FontError {
+    /// reading from the source failed
+    Io(std::io::Error),
+    /// the data does not start with the magic bytes of a supported font format
+    UnknownMagic([u8; 4]),
+    /// the data ended before the font did
+    Truncated,
+    /// the font is larger than a limit of the `ParseOptions`
+    LimitExceeded(Limit),
+    /// the font uses a format or table this crate cannot read
+    UnsupportedTable(&'static str),
+    /// the font data is malformed
+    Parse(String),
+}
+
+/// Why a glyph could not be loaded
+#[derive(Debug, Clone)]
+pub enum GlyphError {
+    /// the font has no glyph with this *gid*
+    Missing(GlyphId),
+    /// the glyph data is broken
+    Invalid(GlyphId, String),
+}
+impl fmt::Display for GlyphError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GlyphError::Missing(gid) => write!(f, "glyph {} is missing", gid.0),
+            GlyphError::Invalid(gid, ref msg) => write!(f, "glyph {} is invalid: {}", gid.0, msg),
+        }
+    }
+}
+impl std::error::Error for GlyphError {}
+
+/// The resource limits of `ParseOptions`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Limit {
+    DecompressedSize,
+    Glyphs,
+    CompositeDepth,
+    Points,
+}
+
+// the value of a `try_glyph`-like result, warning about broken glyphs
+pub(crate) fn warn_invalid<T>(result: Result<T, GlyphError>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(GlyphError::Missing(_)) => None,
+        Err(e) => {
+            warn!("{}", e);
+            None
+        }
+    }
+}
+
+// with `options.check_glyphs`, fails with `FontError::Parse` for the first broken glyph
+pub(crate) fn check_glyphs(font: &impl Font, options: &ParseOptions) -> Result<(), FontError> {
+    if !options.check_glyphs {
+        return Ok(());
+    }
+    for gid in 0 .. font.num_glyphs() {
+        if let Err(e @ GlyphError::Invalid(..)) = font.try_glyph(GlyphId(gid)) {
+            return Err(FontError::Parse(e.to_string()));
+        }
+    }
+    Ok(())
+}
+// the boxed font, after `check_glyphs`
+fn checked(font: impl Font + Send + Sync + 'static, options: &ParseOptions) -> Result<Box<dyn Font + Send + Sync + 'static>, FontError> {
+    check_glyphs(&font, options)?;
+    Ok(Box::new(font))
+}
+
+// fails with `LimitExceeded(which)` if `value` is above `limit`
+pub(crate) fn check_limit<T: PartialOrd>(limit: Option<T>, value: T, which: Limit) -> Result<(), FontError> {
+    match limit {
+        Some(max) if value > max => Err(FontError::LimitExceeded(which)),
+        _ => Ok(())
+    }
+}
+impl fmt::Display for FontError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FontError::Io(ref e) => write!(f, "I/O error: {}", e),
+            FontError::UnknownMagic(magic) => write!(f, "unknown magic {:?}", magic),
+            FontError::Truncated => write!(f, "unexpected end of data"),
+            FontError::LimitExceeded(limit) => write!(f, "limit exceeded: {:?}", limit),
+            FontError::UnsupportedTable(table) => write!(f, "unsupported: {}", table),
+            FontError::Parse(ref msg) => write!(f, "invalid font: {}", msg),
+        }
+    }
+}
+impl std::error::Error for FontError {}
+impl From<std::io::Error> for FontError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => FontError::Truncated,
+            _ => FontError::Io(e)
+        }
+    }
+}
+
 pub fn parse(data: &[u8]) -> Box<dyn Font + Send + Sync + 'static> {
-    let magic: &[u8; 4] = data[0 .. 4].try_into().unwrap();
-    info!("font magic: {:?} ({:?})", magic, String::from_utf8_lossy(&*magic));
-    match magic {
-        &[0x80, 1, _, _] => Box::new(Type1Font::parse_pfb(data)) as _,
-        b"OTTO" | [0,1,0,0] => Box::new(OpenTypeFont::parse(data)) as _,
-        b"ttcf" | b"typ1" => unimplemented!(), // Box::new(TrueTypeFont::parse(data, 0)) as _,
-        b"true" => Box::new(TrueTypeFont::parse(data)) as _,
-        b"%!PS" => Box::new(Type1Font::parse_postscript(data)) as _,
+    parse_with_options(data, &ParseOptions::default())
+}
+
+/// Panics on data it cannot read, see `try_parse_with_options` for the error.
+pub fn parse_with_options(data: &[u8], options: &ParseOptions) -> Box<dyn Font + Send + Sync + 'static> {
+    let unlimited = ParseOptions {
+        max_decompressed_size: None,
+        max_glyphs: None,
+        max_composite_depth: None,
+        max_points: None,
+        check_glyphs: false,
+        .. options.clone()
+    };
+    try_parse_with_options(data, &unlimited).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Like `parse`, but returns an error instead of panicking on input it cannot read.
+pub fn try_parse(data: &[u8]) -> Result<Box<dyn Font + Send + Sync + 'static>, FontError> {
+    try_parse_with_options(data, &ParseOptions::default())
+}
+
+/// Like `parse_with_options`, but enforces the limits of `options` and returns an error instead of panicking.
+///
+/// The glyph count is checked before the glyphs are parsed, and the glyph shapes
+/// before they are turned into outlines. For WOFF and WOFF2 the decompressed size is
+/// checked before decompressing. Limits are not enforced for Type1 and bare CFF fonts.
+/// Missing or malformed tables that the outlines need are reported as `FontError::Parse`,
+/// other malformed tables are skipped.
+pub fn try_parse_with_options(data: &[u8], options: &ParseOptions) -> Result<Box<dyn Font + Send + Sync + 'static>, FontError> {
+    let magic: [u8; 4] = data.get(0 .. 4).ok_or(FontError::Truncated)?.try_into().unwrap();
+    info!("font magic: {:?} ({:?})", magic, String::from_utf8_lossy(&magic));
+    match &magic {
+        b"OTTO" | [0,1,0,0] => {
+            let tables = opentype::parse_tables(data).map_err(|_| FontError::Truncated)?.1;
+            Ok(Box::new(OpenTypeFont::try_from_tables(tables, options)?) as _)
+        }
+        b"true" => {
+            let tables = opentype::parse_tables(data).map_err(|_| FontError::Truncated)?.1;
+            Ok(Box::new(TrueTypeFont::try_from_tables(tables, options)?) as _)
+        }
+        b"ttcf" => Collection::parse(data)?.try_get(0, options),
+        b"typ1" => Err(FontError::UnsupportedTable("typ1")),
 
         #[cfg(feature="woff")]
-        b"wOFF" => Box::new(woff::parse_woff(data).get()) as _,
+        b"wOFF" => Ok(Box::new(woff::parse_woff(data, options)?) as _),
 
         #[cfg(feature="woff")]
-        b"wOF2" => Box::new(woff::parse_woff2(data).get()) as _,
-        
-        &[1, _, _, _] => Box::new(CffFont::parse(data, 0)) as _,
-        &[37, 33, _, _] => Box::new(Type1Font::parse_pfa(data)) as _,
-        magic => panic!("unknown magic {:?}", magic)
+        b"wOF2" => Ok(Box::new(woff::parse_woff2(data, options)?) as _),
+
+        [0x80, 1, _, _] => checked(Type1Font::try_parse_pfb(data)?, options),
+        b"%!PS" => checked(Type1Font::try_parse_postscript(data)?, options),
+        [37, 33, _, _] => checked(Type1Font::try_parse_pfa(data)?, options),
+        [1, _, _, _] => checked(CffFont::try_parse(data, 0)?, options),
+        _ => Err(FontError::UnknownMagic(magic))
+    }
+}
+
+/// The number of glyphs implied by each table of a font.
+///
+/// `None` means the table is not present (or does not apply to this format).
+/// A healthy font has the same count everywhere.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GlyphCountReport {
+    /// `numGlyphs` of the `maxp` table
+    pub maxp: Option<u32>,
+    /// number of `loca` entries minus one
+    pub loca: Option<u32>,
+    /// metrics in `hmtx` (`numberOfHMetrics` plus the trailing left side bearings)
+    pub hmtx: Option<u32>,
+    /// number of CharStrings in CFF outlines
+    pub cff: Option<u32>,
+}
+impl GlyphCountReport {
+    /// true if all present tables agree
+    pub fn is_consistent(&self) -> bool {
+        let counts = [self.maxp, self.loca, self.hmtx, self.cff];
+        let mut counts = counts.iter().filter_map(|&c| c);
+        match counts.next() {
+            Some(first) => counts.all(|n| n == first),
+            None => true
+        }
     }
 }
 
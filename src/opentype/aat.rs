@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use crate::{R, parsers::*};
+use nom::{
+    number::complete::be_u16,
+    sequence::tuple,
+};
+
+// the header of binary searched lookups. returns (unit size, number of units)
+fn bin_search_header(i: &[u8]) -> R<(u16, u16)> {
+    let (i, unit_size) = be_u16(i)?;
+    let (i, n_units) = be_u16(i)?;
+    let (i, _search_range) = be_u16(i)?;
+    let (i, _entry_selector) = be_u16(i)?;
+    let (i, _range_shift) = be_u16(i)?;
+    Ok((i, (unit_size, n_units)))
+}
+
+/// Parse an AAT lookup table with 16 bit values into a map gid -> value.
+///
+/// Entries with the glyph 0xFFFF terminate binary searched tables and are skipped.
+pub fn parse_lookup(data: &[u8]) -> R<HashMap<u16, u16>> {
+    let (i, format) = be_u16(data)?;
+    let mut map = HashMap::new();
+    let i = match format {
+        // simple array, one value for every glyph
+        0 => {
+            for (gid, value) in iterator_n(i, be_u16, i.len() / 2).enumerate() {
+                map.insert(gid as u16, value);
+            }
+            &i[i.len() ..]
+        }
+        // segment single
+        2 => {
+            let (i, (_unit_size, n_units)) = bin_search_header(i)?;
+            for (last, first, value) in iterator_n(i, tuple((be_u16, be_u16, be_u16)), n_units) {
+                if last == 0xFFFF {
+                    continue;
+                }
+                for gid in first ..= last {
+                    map.insert(gid, value);
+                }
+            }
+            i
+        }
+        // segment array
+        4 => {
+            let (i, (_unit_size, n_units)) = bin_search_header(i)?;
+            for (last, first, offset) in iterator_n(i, tuple((be_u16, be_u16, be_u16)), n_units) {
+                if last == 0xFFFF || first > last {
+                    continue;
+                }
+                let values = match data.get(offset as usize ..) {
+                    Some(values) => iterator_n(values, be_u16, last - first + 1),
+                    None => {
+                        warn!("AAT lookup: values of {} ..= {} are past the end", first, last);
+                        continue;
+                    }
+                };
+                for (gid, value) in (first ..= last).zip(values) {
+                    map.insert(gid, value);
+                }
+            }
+            i
+        }
+        // single table
+        6 => {
+            let (i, (_unit_size, n_units)) = bin_search_header(i)?;
+            for (gid, value) in iterator_n(i, tuple((be_u16, be_u16)), n_units) {
+                if gid != 0xFFFF {
+                    map.insert(gid, value);
+                }
+            }
+            i
+        }
+        // trimmed array
+        8 => {
+            let (i, first_glyph) = be_u16(i)?;
+            let (i, glyph_count) = be_u16(i)?;
+            for (gid, value) in (first_glyph ..= u16::MAX).zip(iterator_n(i, be_u16, glyph_count)) {
+                map.insert(gid, value);
+            }
+            i
+        }
+        n => {
+            warn!("unsupported AAT lookup format {}", n);
+            i
+        }
+    };
+    Ok((i, map))
+}
+
+#[test]
+fn test_lookup() {
+    let segment_single = [
+        0, 2, // format
+        0, 6, 0, 2, 0, 12, 0, 1, 0, 0, // binary search header
+        0, 12, 0, 10, 0, 3, // 10 ..= 12 -> 3
+        0xFF, 0xFF, 0xFF, 0xFF, 0, 0, // terminator
+    ];
+    let map = parse_lookup(&segment_single).unwrap().1;
+    assert_eq!(map.len(), 3);
+    assert_eq!(map[&11], 3);
+
+    let trimmed = [
+        0, 8, // format
+        0, 20, 0, 2, // first glyph, count
+        0, 7, 0, 9,
+    ];
+    let map = parse_lookup(&trimmed).unwrap().1;
+    assert_eq!(map[&20], 7);
+    assert_eq!(map[&21], 9);
+    assert_eq!(map.get(&22), None);
+
+    // glyphs past the largest glyph id are dropped
+    let trimmed = [
+        0, 8, // format
+        0xFF, 0xFF, 0, 2, // first glyph, count
+        0, 7, 0, 9,
+    ];
+    let map = parse_lookup(&trimmed).unwrap().1;
+    assert_eq!(map.len(), 1);
+    assert_eq!(map[&0xFFFF], 7);
+
+    let segment_array = [
+        0, 4, // format
+        0, 6, 0, 2, 0, 12, 0, 1, 0, 0, // binary search header
+        0, 11, 0, 10, 0, 24, // 10 ..= 11 -> values at 24
+        0, 13, 0, 12, 0xFF, 0, // 12 ..= 13 -> values past the end
+        0, 1, 0, 2,
+    ];
+    let map = parse_lookup(&segment_array).unwrap().1;
+    assert_eq!(map[&10], 1);
+    assert_eq!(map[&11], 2);
+    assert_eq!(map.get(&12), None);
+}
@@ -0,0 +1,72 @@
+use crate::R;
+use crate::opentype::gvar::f2dot14;
+use nom::{
+    number::complete::be_u16,
+    multi::count,
+    sequence::tuple,
+};
+
+/// The axis variations table: a piecewise linear map of the normalized coordinate of each axis
+#[derive(Clone, Debug)]
+pub struct Avar {
+    /// (from, to) pairs in increasing order, one list for every axis
+    pub segment_maps: Vec<Vec<(f32, f32)>>,
+}
+impl Avar {
+    /// Map the normalized coordinates (as given by `Fvar::normalize`).
+    pub fn map(&self, coords: &mut [f32]) {
+        for (coord, map) in coords.iter_mut().zip(&self.segment_maps) {
+            *coord = map_coord(map, *coord);
+        }
+    }
+}
+
+// an empty map (or one that cannot interpolate) leaves the coordinate unchanged
+fn map_coord(map: &[(f32, f32)], value: f32) -> f32 {
+    if map.len() < 2 {
+        return value;
+    }
+    match map.iter().position(|&(from, _)| from >= value) {
+        Some(0) => map[0].1,
+        Some(k) => {
+            let ((from_a, to_a), (from_b, to_b)) = (map[k - 1], map[k]);
+            to_a + (to_b - to_a) * (value - from_a) / (from_b - from_a)
+        }
+        None => map[map.len() - 1].1
+    }
+}
+
+pub fn parse_avar(data: &[u8]) -> R<Avar> {
+    let (i, _major_version) = be_u16(data)?;
+    let (i, _minor_version) = be_u16(i)?;
+    let (i, _reserved) = be_u16(i)?;
+    let (mut i, axis_count) = be_u16(i)?;
+    let mut segment_maps = Vec::with_capacity(axis_count as usize);
+    for _ in 0 .. axis_count {
+        let (rest, position_map_count) = be_u16(i)?;
+        let (rest, map) = count(tuple((f2dot14, f2dot14)), position_map_count as usize)(rest)?;
+        segment_maps.push(map);
+        i = rest;
+    }
+    Ok((i, Avar { segment_maps }))
+}
+
+#[test]
+fn test_avar() {
+    let data = [
+        0, 1, 0, 0, 0, 0, 0, 2, // version, reserved, two axes
+        0, 4, // -1 -> -1, 0 -> 0, 0.5 -> 0.75, 1 -> 1
+        0xC0, 0, 0xC0, 0,  0, 0, 0, 0,  0x20, 0, 0x30, 0,  0x40, 0, 0x40, 0,
+        0, 0, // no map
+    ];
+    let avar = parse_avar(&data).unwrap().1;
+    let mut coords = [0.5, 0.5];
+    avar.map(&mut coords);
+    assert_eq!(coords, [0.75, 0.5]);
+    let mut coords = [0.25, -0.5];
+    avar.map(&mut coords);
+    assert_eq!(coords, [0.375, -0.5]);
+    let mut coords = [0.75, 0.];
+    avar.map(&mut coords);
+    assert_eq!(coords, [0.875, 0.]);
+}
@@ -1,6 +1,7 @@
 use nom::{
     number::complete::{be_i16, be_u16},
     sequence::{tuple},
+    error::{make_error, ErrorKind},
 };
 use crate::{R};
 use crate::parsers::{*};
@@ -10,7 +11,9 @@ pub fn parse_base(data: &[u8]) -> R<()> {
     let (i, major) = be_u16(data)?;
     let (i, minor) = be_u16(i)?;
 
-    assert_eq!(major, 1);
+    if major != 1 {
+        return Err(nom::Err::Failure(make_error(data, ErrorKind::Verify)));
+    }
     let (i, horiz_offset) = offset(i)?;
     let (i, vertical_offset) = offset(i)?;
 
@@ -27,8 +30,10 @@ fn parse_axis_table(data: &[u8]) -> R<()> {
     let (i, base_tag_list_offset) = offset(data)?;
     let (i, base_script_list_offset) = offset(i)?;
 
-    let (_, base_tag_list) = parse_base_tag_list(base_tag_list_offset.of(data).unwrap())?;
-    let (_, ()) = parse_base_script_list(base_script_list_offset.of(data).unwrap(), base_tag_list)?;
+    if let (Some(tag_list), Some(script_list)) = (base_tag_list_offset.of(data), base_script_list_offset.of(data)) {
+        let (_, base_tag_list) = parse_base_tag_list(tag_list)?;
+        let (_, ()) = parse_base_script_list(script_list, base_tag_list)?;
+    }
 
     Ok((i, ()))
 }
@@ -42,25 +47,30 @@ fn parse_base_tag_list(data: &[u8]) -> R<Array<Tag>> {
 fn parse_base_script_list<'a>(data: &'a [u8], tags: Array<Tag>) -> R<'a, ()> {
     let (i, base_script_count) = be_u16(data)?;
     for (script_tag, offset) in iterator_n(i, tuple((tag, offset)), base_script_count) {
-        let (_, (default_baseline_idx, baselines)) = parse_base_script_table(offset.of(data).unwrap())?;
+        let (_, (default_baseline_idx, baselines)) = parse_base_script_table(offset_into(data, offset.0 as usize)?.1)?;
         for (base_tag, base_pos) in tags.iter().zip(baselines) {
             println!("{:?} @ {}", base_tag, base_pos);
         }
     }
     Ok((i, ()))
 }
-fn parse_base_script_table(data: &[u8]) -> R<(u16, impl Iterator<Item=i16> + '_)> {
+fn parse_base_script_table(data: &[u8]) -> R<(u16, Vec<i16>)> {
     let (i, base_values_offset) = offset(data)?;
     let (i, default_min_max_offset) = offset(data)?;
-    let (_, base_values) = parse_base_values_table(base_values_offset.of(data).unwrap())?;
+    let base_values = match base_values_offset.of(data) {
+        Some(values) => parse_base_values_table(values)?.1,
+        None => (0, vec![])
+    };
 
     Ok((i, base_values))
 }
-fn parse_base_values_table(data: &[u8]) -> R<(u16, impl Iterator<Item=i16> + '_)> {
+fn parse_base_values_table(data: &[u8]) -> R<(u16, Vec<i16>)> {
     let (i, default_baseline_idx) = be_u16(data)?;
     let (i, base_coord_count) = be_u16(i)?;
-    let array = iterator_n(i, offset, base_coord_count)
-        .map(move |off| parse_base_coord(off.of(data).unwrap()).unwrap().1);
+    let mut array = Vec::with_capacity(base_coord_count as usize);
+    for off in iterator_n(i, offset, base_coord_count) {
+        array.push(parse_base_coord(offset_into(data, off.0 as usize)?.1)?.1);
+    }
     Ok((i, (default_baseline_idx, array)))
 }
 fn parse_base_coord(i: &[u8]) -> R<i16> {
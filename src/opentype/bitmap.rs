@@ -0,0 +1,310 @@
+use crate::{R, BitmapGlyph, ImageFormat, parsers::*};
+use nom::{
+    number::complete::{be_u8, be_i8, be_u16, be_i16, be_u32},
+    bytes::complete::take,
+    multi::count,
+    sequence::tuple,
+};
+
+// the strike for `ppem`: the smallest one that is large enough, otherwise the largest
+fn pick_strike(ppems: impl Iterator<Item=u16>, ppem: u16) -> Option<usize> {
+    let ppems: Vec<u16> = ppems.collect();
+    ppems.iter().enumerate()
+        .filter(|&(_, &p)| p >= ppem)
+        .min_by_key(|&(_, &p)| p)
+        .or_else(|| ppems.iter().enumerate().max_by_key(|&(_, &p)| p))
+        .map(|(idx, _)| idx)
+}
+
+/// The Apple standard bitmap graphics table
+#[derive(Clone, Debug)]
+pub struct Sbix {
+    data: Vec<u8>,
+    num_glyphs: u32,
+    // (ppem, offset) of each strike
+    strikes: Vec<(u16, usize)>,
+}
+impl Sbix {
+    /// The image of `gid` in the strike closest to `ppem`.
+    pub fn glyph(&self, gid: u32, ppem: u16) -> Option<BitmapGlyph> {
+        let idx = pick_strike(self.strikes.iter().map(|&(ppem, _)| ppem), ppem)?;
+        let (ppem, offset) = self.strikes[idx];
+        let (origin, format, image) = match self.strike_glyph(offset, gid)? {
+            Ok(image) => image,
+            // 'dupe' names the glyph with the same image
+            Err(dupe) => self.strike_glyph(offset, dupe as u32)?.ok()?
+        };
+        Some(BitmapGlyph { ppem, origin, format, data: image.to_vec() })
+    }
+    // Ok((origin, format, image)), or Err(gid) of a 'dupe'
+    fn strike_glyph(&self, strike: usize, gid: u32) -> Option<Result<((i16, i16), ImageFormat, &[u8]), u16>> {
+        if gid >= self.num_glyphs {
+            return None;
+        }
+        let strike = self.data.get(strike ..)?;
+        let (_, (start, end)) = glyph_range(strike.get(4 + 4 * gid as usize ..)?).ok()?;
+        let (image, (x, y, tag)) = glyph_header(strike.get(start as usize .. end as usize)?).ok()?;
+        let format = match tag {
+            b"png " => ImageFormat::Png,
+            b"jpg " => ImageFormat::Jpeg,
+            b"tiff" => ImageFormat::Tiff,
+            b"dupe" => return Some(Err(dupe_glyph(image).ok()?.1)),
+            _ => return None
+        };
+        Some(Ok(((x, y), format, image)))
+    }
+}
+
+fn glyph_range(i: &[u8]) -> R<(u32, u32)> {
+    tuple((be_u32, be_u32))(i)
+}
+fn glyph_header(i: &[u8]) -> R<(i16, i16, &[u8])> {
+    tuple((be_i16, be_i16, take(4usize)))(i)
+}
+fn dupe_glyph(i: &[u8]) -> R<u16> {
+    be_u16(i)
+}
+
+pub fn parse_sbix(data: &[u8], num_glyphs: u32) -> R<Sbix> {
+    let (i, _version) = be_u16(data)?;
+    let (i, _flags) = be_u16(i)?;
+    let (i, num_strikes) = be_u32(i)?;
+    let (i, offsets) = count(be_u32, num_strikes as usize)(i)?;
+    let mut strikes = Vec::with_capacity(offsets.len());
+    for offset in offsets {
+        match data.get(offset as usize ..).and_then(|strike| be_u16(strike).ok()) {
+            Some((_, ppem)) => strikes.push((ppem, offset as usize)),
+            None => warn!("skipping the invalid sbix strike at {}", offset)
+        }
+    }
+    Ok((i, Sbix { data: data.to_vec(), num_glyphs, strikes }))
+}
+
+// (height, width, bearing x, bearing y) in pixels
+type Metrics = (u8, u8, i8, i8);
+
+fn small_metrics(i: &[u8]) -> R<Metrics> {
+    let (i, (height, width, x, y, _advance)) = tuple((be_u8, be_u8, be_i8, be_i8, be_u8))(i)?;
+    Ok((i, (height, width, x, y)))
+}
+fn big_metrics(i: &[u8]) -> R<Metrics> {
+    let (i, (height, width, x, y, _advance)) = tuple((be_u8, be_u8, be_i8, be_i8, be_u8))(i)?;
+    let (i, _vertical) = take(3usize)(i)?;
+    Ok((i, (height, width, x, y)))
+}
+
+// where the images of an index subtable are
+#[derive(Clone, Debug)]
+enum Locations {
+    // formats 1 and 3: offset of each glyph and the end of the last one
+    Offsets(Vec<u32>),
+    // format 2: images of the same size and metrics
+    Constant(u32, Metrics),
+    // format 4: (gid, offset) pairs and the end of the last one
+    Sparse(Vec<(u16, u32)>),
+    // format 5: like format 2, for the listed glyphs
+    ConstantSparse(u32, Metrics, Vec<u16>),
+}
+
+#[derive(Clone, Debug)]
+struct IndexSubtable {
+    first: u16,
+    last: u16,
+    image_format: u16,
+    image_data_offset: u32,
+    locations: Locations,
+}
+impl IndexSubtable {
+    // (offset into CBDT, length, metrics given by the index)
+    fn location(&self, gid: u16) -> Option<(u32, u32, Option<Metrics>)> {
+        let n = (gid - self.first) as usize;
+        let (start, end, metrics) = match self.locations {
+            Locations::Offsets(ref offsets) => (*offsets.get(n)?, *offsets.get(n + 1)?, None),
+            Locations::Constant(size, metrics) => ((n as u32).checked_mul(size)?, (n as u32 + 1).checked_mul(size)?, Some(metrics)),
+            Locations::Sparse(ref pairs) => {
+                let idx = pairs.iter().position(|&(g, _)| g == gid)?;
+                (pairs[idx].1, pairs.get(idx + 1)?.1, None)
+            }
+            Locations::ConstantSparse(size, metrics, ref gids) => {
+                let idx = gids.binary_search(&gid).ok()? as u32;
+                (idx.checked_mul(size)?, (idx + 1).checked_mul(size)?, Some(metrics))
+            }
+        };
+        if end <= start {
+            return None;
+        }
+        Some((self.image_data_offset.checked_add(start)?, end - start, metrics))
+    }
+}
+
+fn parse_index_subtable(data: &[u8], first: u16, last: u16) -> R<IndexSubtable> {
+    let (i, index_format) = be_u16(data)?;
+    let (i, image_format) = be_u16(i)?;
+    let (i, image_data_offset) = be_u32(i)?;
+    let n = (last - first) as usize + 1;
+    let (i, locations) = match index_format {
+        1 => {
+            let (i, offsets) = count(be_u32, n + 1)(i)?;
+            (i, Locations::Offsets(offsets))
+        }
+        2 => {
+            let (i, (size, metrics)) = tuple((be_u32, big_metrics))(i)?;
+            (i, Locations::Constant(size, metrics))
+        }
+        3 => {
+            let (i, offsets) = count(be_u16, n + 1)(i)?;
+            (i, Locations::Offsets(offsets.into_iter().map(|o| o as u32).collect()))
+        }
+        4 => {
+            let (i, num) = be_u32(i)?;
+            let (i, pairs) = count(tuple((be_u16, be_u16)), num as usize + 1)(i)?;
+            (i, Locations::Sparse(pairs.into_iter().map(|(gid, o)| (gid, o as u32)).collect()))
+        }
+        5 => {
+            let (i, (size, metrics, num)) = tuple((be_u32, big_metrics, be_u32))(i)?;
+            let (i, gids) = count(be_u16, num as usize)(i)?;
+            (i, Locations::ConstantSparse(size, metrics, gids))
+        }
+        _ => (i, Locations::Offsets(vec![]))
+    };
+    Ok((i, IndexSubtable { first, last, image_format, image_data_offset, locations }))
+}
+
+/// Color bitmaps of the `CBDT` table, located by the `CBLC` table
+#[derive(Clone, Debug)]
+pub struct Cbdt {
+    data: Vec<u8>,
+    // (ppem, index subtables) of each strike
+    strikes: Vec<(u16, Vec<IndexSubtable>)>,
+}
+impl Cbdt {
+    /// The image of `gid` in the strike closest to `ppem`.
+    ///
+    /// Only PNG images (formats 17, 18 and 19) are read.
+    pub fn glyph(&self, gid: u32, ppem: u16) -> Option<BitmapGlyph> {
+        let idx = pick_strike(self.strikes.iter().map(|&(ppem, _)| ppem), ppem)?;
+        let (ppem, ref subtables) = self.strikes[idx];
+        if gid > 0xFFFF {
+            return None;
+        }
+        let gid = gid as u16;
+        let subtable = subtables.iter().find(|s| s.first <= gid && gid <= s.last)?;
+        let (offset, len, index_metrics) = subtable.location(gid)?;
+        let glyph = self.data.get(offset as usize .. offset as usize + len as usize)?;
+        let (i, metrics) = match (subtable.image_format, index_metrics) {
+            (17, _) => small_metrics(glyph).ok()?,
+            (18, _) => big_metrics(glyph).ok()?,
+            (19, Some(metrics)) => (glyph, metrics),
+            _ => return None
+        };
+        let (_, image) = image_data(i).ok()?;
+        let (height, _width, x, y) = metrics;
+        Some(BitmapGlyph {
+            ppem,
+            origin: (x as i16, y as i16 - height as i16),
+            format: ImageFormat::Png,
+            data: image.to_vec()
+        })
+    }
+}
+
+fn image_data(i: &[u8]) -> R<&[u8]> {
+    let (i, len) = be_u32(i)?;
+    take(len)(i)
+}
+
+pub fn parse_cbdt<'a>(cblc: &'a [u8], cbdt: &[u8]) -> R<'a, Cbdt> {
+    let (i, _major_version) = be_u16(cblc)?;
+    let (i, _minor_version) = be_u16(i)?;
+    let (mut i, num_sizes) = be_u32(i)?;
+    let mut strikes = Vec::with_capacity(num_sizes as usize);
+    for _ in 0 .. num_sizes {
+        let array_offset = parse(&mut i, be_u32)?;
+        let _tables_size = parse(&mut i, be_u32)?;
+        let num_subtables = parse(&mut i, be_u32)?;
+        let _color_ref = parse(&mut i, be_u32)?;
+        let _line_metrics = parse(&mut i, take(24usize))?;
+        let _glyph_range = parse(&mut i, tuple((be_u16, be_u16)))?;
+        let (ppem, _ppem_y) = parse(&mut i, tuple((be_u8, be_u8)))?;
+        let _bit_depth_and_flags = parse(&mut i, tuple((be_u8, be_i8)))?;
+
+        let array = cblc.get(array_offset as usize ..).unwrap_or(&[]);
+        let (_, entries) = count(tuple((be_u16, be_u16, be_u32)), num_subtables as usize)(array)?;
+        let mut subtables = Vec::with_capacity(entries.len());
+        for (first, last, offset) in entries {
+            if last < first {
+                continue;
+            }
+            let (_, subtable) = parse_index_subtable(array.get(offset as usize ..).unwrap_or(&[]), first, last)?;
+            subtables.push(subtable);
+        }
+        strikes.push((ppem as u16, subtables));
+    }
+    Ok((i, Cbdt { data: cbdt.to_vec(), strikes }))
+}
+
+#[test]
+fn test_sbix() {
+    let data = [
+        0, 1, 0, 1, 0, 0, 0, 2, // version, flags, two strikes
+        0, 0, 0, 16, 0, 0, 0, 41,
+        // 20 ppem: glyph 0 is empty, glyph 1 a png
+        0, 20, 0, 72, 0, 0, 0, 16, 0, 0, 0, 16, 0, 0, 0, 25,
+        0, 0, 0, 0, b'p', b'n', b'g', b' ', 9,
+        // 40 ppem: glyph 0 is a dupe of glyph 1
+        0, 40, 0, 72, 0, 0, 0, 16, 0, 0, 0, 26, 0, 0, 0, 37,
+        0, 0, 0, 0, b'd', b'u', b'p', b'e', 0, 1,
+        0, 2, 0xFF, 0xFE, b'p', b'n', b'g', b' ', 1, 2, 3,
+    ];
+    let sbix = parse_sbix(&data, 2).unwrap().1;
+    assert!(sbix.glyph(0, 16).is_none());
+    assert!(sbix.glyph(2, 16).is_none());
+    assert_eq!(sbix.glyph(1, 16).unwrap().data, [9]);
+
+    let glyph = sbix.glyph(0, 30).unwrap();
+    assert_eq!(glyph.ppem, 40);
+    assert_eq!(glyph.origin, (2, -2));
+    assert_eq!(glyph.format, ImageFormat::Png);
+    assert_eq!(glyph.data, [1, 2, 3]);
+    // larger than all strikes: the largest one
+    assert_eq!(sbix.glyph(1, 100).unwrap().ppem, 40);
+
+    // a strike outside of the table is skipped
+    let mut broken = data.to_vec();
+    broken[7] = 3;
+    broken.splice(16 .. 16, [0, 0, 0xFF, 0].iter().cloned());
+    for offset in [11, 15].iter() {
+        broken[*offset] += 4;
+    }
+    let sbix = parse_sbix(&broken, 2).unwrap().1;
+    assert_eq!(sbix.glyph(1, 16).unwrap().data, [9]);
+    assert_eq!(sbix.glyph(0, 100).unwrap().ppem, 40);
+}
+
+#[test]
+fn test_cbdt() {
+    let mut cblc = vec![
+        0, 3, 0, 0, 0, 0, 0, 1, // version, one size
+        0, 0, 0, 56, 0, 0, 0, 24, 0, 0, 0, 1, 0, 0, 0, 0, // index subtable array at 56, one subtable
+    ];
+    cblc.extend_from_slice(&[0; 24]);
+    cblc.extend_from_slice(&[0, 3, 0, 4, 109, 109, 32, 1]); // glyphs 3 ..= 4, 109 ppem
+    cblc.extend_from_slice(&[
+        0, 3, 0, 4, 0, 0, 0, 8, // glyphs 3 ..= 4
+        0, 1, 0, 17, 0, 0, 0, 4, // index format 1, image format 17, data at 4
+        0, 0, 0, 0, 0, 0, 0, 11, 0, 0, 0, 11,
+    ]);
+    let cbdt = [
+        0, 3, 0, 0, // version
+        10, 12, 1, 8, 14, 0, 0, 0, 2, 0x89, b'P', // 12 x 10 pixels, top left at (1, 8)
+    ];
+    let table = parse_cbdt(&cblc, &cbdt).unwrap().1;
+    let glyph = table.glyph(3, 20).unwrap();
+    assert_eq!(glyph.ppem, 109);
+    assert_eq!(glyph.origin, (1, -2));
+    assert_eq!(glyph.data, [0x89, b'P']);
+    assert!(table.glyph(4, 20).is_none());
+    assert!(table.glyph(5, 20).is_none());
+    // not truncated to glyph 3
+    assert!(table.glyph(0x10003, 20).is_none());
+}
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use crate::R;
+use crate::opentype::aat::parse_lookup;
+use nom::{
+    number::complete::{be_u16, be_i16, be_u32},
+    multi::count,
+};
+
+/// Where the 32 baselines of a `bsln` table are.
+#[derive(Clone, Debug)]
+pub enum Baselines {
+    /// Distance of each baseline from the natural baseline (formats 0 and 1)
+    Distances(Vec<i16>),
+    /// Point of the standard glyph that each baseline passes through (formats 2 and 3).
+    /// 0xFFFF marks undefined baselines.
+    ControlPoints { standard_glyph: u16, points: Vec<u16> },
+}
+
+/// The AAT baseline table
+#[derive(Clone, Debug)]
+pub struct Bsln {
+    pub default_baseline: u16,
+    pub baselines: Baselines,
+    classes: HashMap<u16, u16>,
+}
+impl Bsln {
+    /// The baseline class of the glyph. Glyphs without an entry use the default baseline.
+    pub fn class(&self, gid: u16) -> u16 {
+        self.classes.get(&gid).cloned().unwrap_or(self.default_baseline)
+    }
+    /// Distance of the glyph's baseline from the natural baseline.
+    ///
+    /// None for tables with control points or if the class is out of range.
+    pub fn delta(&self, gid: u16) -> Option<i16> {
+        match self.baselines {
+            Baselines::Distances(ref deltas) => deltas.get(self.class(gid) as usize).cloned(),
+            Baselines::ControlPoints { .. } => None
+        }
+    }
+}
+
+pub fn parse_bsln(data: &[u8]) -> R<Bsln> {
+    let (i, _version) = be_u32(data)?;
+    let (i, format) = be_u16(i)?;
+    let (i, default_baseline) = be_u16(i)?;
+    let (i, baselines) = match format {
+        0 | 1 => {
+            let (i, deltas) = count(be_i16, 32)(i)?;
+            (i, Baselines::Distances(deltas))
+        }
+        _ => {
+            let (i, standard_glyph) = be_u16(i)?;
+            let (i, points) = count(be_u16, 32)(i)?;
+            (i, Baselines::ControlPoints { standard_glyph, points })
+        }
+    };
+    let (i, classes) = match format {
+        1 | 3 => parse_lookup(i)?,
+        _ => (i, HashMap::new())
+    };
+    Ok((i, Bsln { default_baseline, baselines, classes }))
+}
+
+#[test]
+fn test_bsln() {
+    let mut data = vec![
+        0, 1, 0, 0, // version
+        0, 1, // format 1: distances and a lookup
+        0, 0, // default: roman
+    ];
+    // roman at 0, ideographic centered at 380, ideographic low at -120
+    data.extend_from_slice(&[0, 0, 1, 124, 0xFF, 0x88]);
+    data.extend_from_slice(&[0; 58]);
+    data.extend_from_slice(&[
+        0, 8, // trimmed array
+        0, 5, 0, 2, // glyphs 5 and 6
+        0, 2, 0, 1,
+    ]);
+    let bsln = parse_bsln(&data).unwrap().1;
+    assert_eq!(bsln.class(5), 2);
+    assert_eq!(bsln.delta(5), Some(-120));
+    assert_eq!(bsln.delta(6), Some(380));
+    assert_eq!(bsln.delta(7), Some(0));
+}
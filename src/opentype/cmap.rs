@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::RangeInclusive;
 use crate::{GlyphId, R, parsers::*};
 use nom::{
@@ -8,12 +8,20 @@ use nom::{
 };
 use tuple::T4;
 
+// the last Unicode codepoint
+const MAX_CODEPOINT: u32 = 0x10FFFF;
+
 #[derive(Debug, Clone)]
 pub struct CMap {
     single_codepoint: HashMap<u32, u32>,
-    double_codepoint: HashMap<(u32, u32), u32>
+    double_codepoint: HashMap<(u32, u32), u32>,
+    selected: Option<(u16, u16, u16)>,
 }
 impl CMap {
+    /// (platform, encoding, format) of the subtable used for single codepoints
+    pub fn selected(&self) -> Option<(u16, u16, u16)> {
+        self.selected
+    }
     pub fn get_codepoint(&self, cp: u32) -> Option<u32> {
         self.single_codepoint.get(&cp).cloned()
     }
@@ -23,6 +31,13 @@ impl CMap {
     pub fn items<'a>(&'a self) -> impl Iterator<Item=(u32, GlyphId)> + 'a {
         self.single_codepoint.iter().map(|(&cp, &gid)| (cp, GlyphId(gid)))
     }
+    /// all gids any codepoint (or variation sequence) maps to
+    pub fn gids(&self) -> HashSet<u32> {
+        self.single_codepoint.values()
+            .chain(self.double_codepoint.values())
+            .cloned()
+            .collect()
+    }
     pub fn codepoints(&self, max_ranges: usize) -> Vec<RangeInclusive<u32>> {
         use itertools::Itertools;
 
@@ -68,19 +83,56 @@ impl CMap {
     }
 }
 
+/// How good a subtable is for mapping Unicode to glyphs, lower is better.
+///
+/// 0. Windows, full Unicode range (3, 10) in format 12
+/// 1. Unicode platform, full Unicode range (0, 4) and (0, 6) in format 12
+/// 2. Windows, BMP (3, 1) in format 4
+/// 3. Unicode platform, BMP (0, 3) in format 4
+/// 4. other Unicode subtables of a supported format
+/// 5. the Macintosh Roman subtable (1, 0)
+///
+/// Variation sequences (format 14) are not ranked, they are always used in addition.
+fn subtable_rank(platform: u16, encoding: u16, format: u16) -> Option<u8> {
+    match (platform, encoding, format) {
+        (_, _, 14) => None,
+        (3, 10, 12) => Some(0),
+        (0, 4, 12) | (0, 6, 12) => Some(1),
+        (3, 1, 4) => Some(2),
+        (0, 3, 4) => Some(3),
+        (0, _, f) | (3, 1, f) | (3, 10, f) if matches!(f, 0 | 4 | 6 | 10 | 12 | 13) => Some(4),
+        (1, 0, 0) | (1, 0, 6) => Some(5),
+        _ => None
+    }
+}
+
 pub fn parse_cmap(input: &[u8]) -> R<CMap> {
     let (i, _version) = be_u16(input)?;
     let (i, num_tables) = be_u16(i)?;
     
-    let tables = iterator(i, tuple((be_u16, be_u16, be_u32))).take(num_tables as usize)
-        .filter_map(|entry| match entry {
-            (0, _, off) | (1, 0, off) | (3, 10, off) | (3, 1, off) => Some(off),
-            (platform, encoding, _) => {
-                warn!("unsupported cmap platform={}, encoding={}", platform, encoding);
-                None
+    let mut selected: Option<(u8, (u16, u16, u16), &[u8])> = None;
+    let mut variation_tables = vec![];
+    for (platform, encoding, off) in iterator(i, tuple((be_u16, be_u16, be_u32))).take(num_tables as usize) {
+        let table = match input.get(off as usize ..) {
+            Some(table) => table,
+            None => continue
+        };
+        let (_, format) = be_u16(table)?;
+        if format == 14 {
+            variation_tables.push(table);
+            continue;
+        }
+        match subtable_rank(platform, encoding, format) {
+            Some(rank) => {
+                if selected.map_or(true, |(best, _, _)| rank < best) {
+                    selected = Some((rank, (platform, encoding, format), table));
+                }
             }
-        })
-        .filter_map(|off| input.get(off as usize ..));
+            None => warn!("unsupported cmap platform={}, encoding={}, format={}", platform, encoding, format)
+        }
+    }
+    debug!("selected cmap {:?}", selected.map(|(_, ids, _)| ids));
+    let tables = selected.iter().map(|&(_, _, table)| table).chain(variation_tables);
     
     let mut cmap = HashMap::new();
     let mut cmap2 = HashMap::new();
@@ -91,7 +143,7 @@ pub fn parse_cmap(input: &[u8]) -> R<CMap> {
         match format {
             0 => {
                 let (i, len) = be_u16(i)?;
-                let (_i, data) = take(len - 4)(i)?; // aleady have 4 header bytes
+                let (_i, data) = take(len.saturating_sub(4))(i)?; // aleady have 4 header bytes
                 
                 let (i, _language) = be_u16(data)?;
                 for (code, gid) in iterator(i, be_u8).enumerate() {
@@ -102,7 +154,7 @@ pub fn parse_cmap(input: &[u8]) -> R<CMap> {
             }
             4 => {
                 let (i, len) = be_u16(i)?;
-                let (_i, data) = take(len - 4)(i)?; // aleady have 4 header bytes
+                let (_i, data) = take(len.saturating_sub(4))(i)?; // aleady have 4 header bytes
                 
                 let (i, _language) = be_u16(data)?;
                 let (i, segCountX2) = be_u16(i)?;
@@ -134,11 +186,11 @@ pub fn parse_cmap(input: &[u8]) -> R<CMap> {
                         }
                     } else {
                         for c in start ..= end {
-                            let index = 2 * (n as u16 + (c - start)) + offset - segCountX2;
-                            if index as usize > glyph_data.len() - 2 {
-                                break;
-                            }
-                            let (_, gid) = be_u16(&glyph_data[index as usize ..])?;
+                            let index = match (2 * (n + (c - start) as usize) + offset as usize).checked_sub(segCountX2 as usize) {
+                                Some(index) if index + 2 <= glyph_data.len() => index,
+                                _ => break
+                            };
+                            let (_, gid) = be_u16(&glyph_data[index ..])?;
                             let gid = gid.wrapping_add(delta);
                             if gid != 0 {
                                 trace!("codepoint {} -> gid {}", c, gid);
@@ -162,13 +214,13 @@ pub fn parse_cmap(input: &[u8]) -> R<CMap> {
             12 => {
                 let (i, _reserved) = be_u16(i)?;
                 let (i, len) = be_u32(i)?;
-                let (_i, data) = take(len - 8)(i)?; // aleady have 8 header bytes
+                let (_i, data) = take(len.saturating_sub(8))(i)?; // aleady have 8 header bytes
                 
                 let (i, _language) = be_u32(data)?;
                 let (i, num_groups) = be_u32(i)?;
                 for (start_code, end_code, start_gid) in iterator(i, tuple((be_u32, be_u32, be_u32))).take(num_groups as usize) {
                     trace!("start_code={}, end_code={}, start_gid={}", start_code, end_code, start_gid);
-                    for (code, gid) in (start_code ..= end_code).zip(start_gid ..) {
+                    for (code, gid) in (start_code ..= end_code).zip(start_gid ..= u32::MAX) {
                         trace!("codepoint {} -> gid {}", code, gid);
                         if gid != 0 {
                             cmap.insert(code, gid);
@@ -176,14 +228,48 @@ pub fn parse_cmap(input: &[u8]) -> R<CMap> {
                     }
                 }
             }
+            10 => {
+                let (i, _reserved) = be_u16(i)?;
+                let (i, _len) = be_u32(i)?;
+                let (i, _language) = be_u32(i)?;
+                let (i, start_code) = be_u32(i)?;
+                let (i, num_chars) = be_u32(i)?;
+                for (code, gid) in (start_code ..= u32::MAX).zip(iterator(i, be_u16).take(num_chars as usize)) {
+                    if gid != 0 {
+                        cmap.insert(code, gid as u32);
+                    }
+                }
+            }
+            // many-to-one: every codepoint of a group maps to the same glyph
+            13 => {
+                let (i, _reserved) = be_u16(i)?;
+                let (i, _len) = be_u32(i)?;
+                let (i, _language) = be_u32(i)?;
+                let (i, num_groups) = be_u32(i)?;
+                // groups beyond the last Unicode codepoint are cut off, and overlapping groups
+                // add at most as many mappings as there are codepoints
+                let mut remaining = MAX_CODEPOINT as usize + 1;
+                for (start_code, end_code, gid) in iterator(i, tuple((be_u32, be_u32, be_u32))).take(num_groups as usize) {
+                    let codes = start_code ..= end_code.min(MAX_CODEPOINT);
+                    let len = codes.size_hint().0;
+                    if len > remaining {
+                        warn!("cmap format 13: too many codepoints");
+                        break;
+                    }
+                    remaining -= len;
+                    if gid != 0 {
+                        cmap.extend(codes.map(|code| (code, gid)));
+                    }
+                }
+            }
             14 => {
                 let (i, length) = be_u32(i)?;
-                let i = &i[.. length as usize - 6];
+                let i = &i[.. (length as usize).saturating_sub(6).min(i.len())];
                 
                 let (i, num_var_selector_records) = be_u32(i)?;
                 for (var_selector, default_uvs_offset, non_default_uvs_offset) in iterator(i, tuple((be_u24, be_u32, be_u32))).take(num_var_selector_records as usize) {
                     if default_uvs_offset != 0 {
-                        let i = &table[default_uvs_offset as usize ..];
+                        let i = offset_into(table, default_uvs_offset as usize)?.1;
                         let (i, num_unicode_value_ranges) = be_u32(i)?;
                         for (start_unicode_value, additional_count) in iterator(i, tuple((be_u24, be_u8))).take(num_unicode_value_ranges as usize) {
                             for cp in start_unicode_value ..= start_unicode_value + additional_count as u32 {
@@ -192,7 +278,7 @@ pub fn parse_cmap(input: &[u8]) -> R<CMap> {
                         }
                     }
                     if non_default_uvs_offset != 0 {
-                        let i = &table[non_default_uvs_offset as usize ..];
+                        let i = offset_into(table, non_default_uvs_offset as usize)?.1;
                         let (i, num_uvs_mappings) = be_u32(i)?;
                         for (unicode_value, glyph_id) in iterator(i, tuple((be_u24, be_u16))).take(num_uvs_mappings as usize) {
                             if glyph_id != 0 {
@@ -202,7 +288,7 @@ pub fn parse_cmap(input: &[u8]) -> R<CMap> {
                     }
                 }
             }
-            n => unimplemented!("cmap format {}", n),
+            n => warn!("unsupported cmap format {}", n),
         }
     }
 
@@ -214,6 +300,113 @@ pub fn parse_cmap(input: &[u8]) -> R<CMap> {
 
     Ok((&[], CMap {
         single_codepoint: cmap,
-        double_codepoint: cmap2
+        double_codepoint: cmap2,
+        selected: selected.map(|(_, ids, _)| ids),
     }))
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_cmap_preference() {
+    // format 6 subtables: first code, then the gids
+    let format6 = |first: u16, gids: &[u16]| {
+        let mut t = vec![0, 6, 0, 0, 0, 0];
+        t.extend_from_slice(&first.to_be_bytes());
+        t.extend_from_slice(&(gids.len() as u16).to_be_bytes());
+        for gid in gids {
+            t.extend_from_slice(&gid.to_be_bytes());
+        }
+        t
+    };
+    let mac = format6(0x41, &[1, 2]);
+    let windows = format6(0x41, &[3]);
+    let mut data = vec![0, 0, 0, 2];
+    data.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 20]);
+    data.extend_from_slice(&[0, 3, 0, 1, 0, 0, 0, 20 + mac.len() as u8]);
+    data.extend_from_slice(&mac);
+    data.extend_from_slice(&windows);
+
+    let (_, cmap) = parse_cmap(&data).unwrap();
+    assert_eq!(cmap.selected(), Some((3, 1, 6)));
+    assert_eq!(cmap.get_codepoint(0x41), Some(3));
+    assert_eq!(cmap.get_codepoint(0x42), None);
+}
+
+#[test]
+fn test_cmap_format12() {
+    let mut format12 = vec![0, 12, 0, 0,  0, 0, 0, 28,  0, 0, 0, 0,  0, 0, 0, 1];
+    format12.extend_from_slice(&[0, 1, 0xF6, 0x00,  0, 1, 0xF6, 0x4F,  0, 0, 0, 100]);
+    let bmp = [0, 6, 0, 12, 0, 0, 0xF6, 0x00, 0, 1, 0, 7];
+    let mut data = vec![0, 0, 0, 2];
+    data.extend_from_slice(&[0, 3, 0, 1, 0, 0, 0, 20]);
+    data.extend_from_slice(&[0, 3, 0, 10, 0, 0, 0, 32]);
+    data.extend_from_slice(&bmp);
+    data.extend_from_slice(&format12);
+
+    let (_, cmap) = parse_cmap(&data).unwrap();
+    assert_eq!(cmap.selected(), Some((3, 10, 12)));
+    assert_eq!(cmap.get_codepoint(0x1F600), Some(100));
+    assert_eq!(cmap.get_codepoint(0x1F64F), Some(179));
+    assert_eq!(cmap.get_codepoint(0xF600), None);
+
+    // glyph ids end at the largest value
+    let format12 = [
+        0, 12, 0, 0,  0, 0, 0, 28,  0, 0, 0, 0,  0, 0, 0, 1,
+        0, 0, 0, 0x41,  0, 0, 0, 0x42,  0xFF, 0xFF, 0xFF, 0xFF,
+    ];
+    let mut data = vec![0, 0, 0, 1,  0, 3, 0, 10,  0, 0, 0, 12];
+    data.extend_from_slice(&format12);
+    let (_, cmap) = parse_cmap(&data).unwrap();
+    assert_eq!(cmap.get_codepoint(0x41), Some(u32::MAX));
+    assert_eq!(cmap.get_codepoint(0x42), None);
+}
+
+#[test]
+fn test_cmap_format10_and_13() {
+    let format13 = [
+        0, 13, 0, 0,  0, 0, 0, 40,  0, 0, 0, 0,  0, 0, 0, 2,
+        0, 0, 0, 0x20,  0, 0, 0, 0x7E,  0, 0, 0, 1,
+        0, 1, 0, 0,  0, 1, 0xFF, 0xFF,  0, 0, 0, 2,
+    ];
+    let mut data = vec![0, 0, 0, 1,  0, 3, 0, 10,  0, 0, 0, 12];
+    data.extend_from_slice(&format13);
+    let (_, cmap) = parse_cmap(&data).unwrap();
+    assert_eq!(cmap.selected(), Some((3, 10, 13)));
+    assert_eq!(cmap.get_codepoint(0x41), Some(1));
+    assert_eq!(cmap.get_codepoint(0x1F600), Some(2));
+    assert_eq!(cmap.get_codepoint(0x7F), None);
+
+    // a group up to the largest code, repeated: cut off at the last Unicode codepoint,
+    // and the repetitions are dropped once they exceed the number of codepoints
+    let mut format13 = vec![0, 13, 0, 0,  0, 0, 0, 0,  0, 0, 0, 0,  0, 0, 0, 3];
+    for _ in 0 .. 3 {
+        format13.extend_from_slice(&[0, 8, 0, 0,  0xFF, 0xFF, 0xFF, 0xFF,  0, 0, 0, 3]);
+    }
+    let mut data = vec![0, 0, 0, 1,  0, 3, 0, 10,  0, 0, 0, 12];
+    data.extend_from_slice(&format13);
+    let (_, cmap) = parse_cmap(&data).unwrap();
+    assert_eq!(cmap.get_codepoint(0x10FFFF), Some(3));
+    assert_eq!(cmap.get_codepoint(0x110000), None);
+    assert_eq!(cmap.items().count(), 0x10FFFF - 0x80000 + 1);
+
+    let format10 = [
+        0, 10, 0, 0,  0, 0, 0, 24,  0, 0, 0, 0,
+        0, 1, 0xF6, 0,  0, 0, 0, 2,  0, 5, 0, 6,
+    ];
+    let mut data = vec![0, 0, 0, 1,  0, 0, 0, 4,  0, 0, 0, 12];
+    data.extend_from_slice(&format10);
+    let (_, cmap) = parse_cmap(&data).unwrap();
+    assert_eq!(cmap.get_codepoint(0x1F600), Some(5));
+    assert_eq!(cmap.get_codepoint(0x1F601), Some(6));
+    assert_eq!(cmap.get_codepoint(0x1F602), None);
+
+    // codes end at the largest value
+    let format10 = [
+        0, 10, 0, 0,  0, 0, 0, 24,  0, 0, 0, 0,
+        0xFF, 0xFF, 0xFF, 0xFF,  0, 0, 0, 2,  0, 5, 0, 6,
+    ];
+    let mut data = vec![0, 0, 0, 1,  0, 0, 0, 4,  0, 0, 0, 12];
+    data.extend_from_slice(&format10);
+    let (_, cmap) = parse_cmap(&data).unwrap();
+    assert_eq!(cmap.get_codepoint(u32::MAX), Some(5));
+    assert_eq!(cmap.items().count(), 1);
+}
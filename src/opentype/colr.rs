@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use crate::{R, parsers::*};
+use nom::{
+    number::complete::{be_u8, be_u16, be_u32},
+    sequence::tuple,
+};
+use pathfinder_color::ColorU;
+
+/// The color table (version 0: layers of plain glyphs)
+#[derive(Clone, Debug)]
+pub struct Colr {
+    // base gid -> range in `layers`
+    base_glyphs: HashMap<u16, (u16, u16)>,
+    // (gid, palette index)
+    layers: Vec<(u16, u16)>,
+}
+impl Colr {
+    /// The layers of the color glyph `gid`, bottom first, as (gid, palette entry).
+    ///
+    /// A palette entry of `None` means the text color.
+    pub fn layers(&self, gid: u16) -> Option<impl Iterator<Item=(u16, Option<u16>)> + '_> {
+        let &(first, num) = self.base_glyphs.get(&gid)?;
+        let layers = self.layers.get(first as usize .. first as usize + num as usize)?;
+        Some(layers.iter().map(|&(gid, idx)| (gid, if idx == 0xFFFF { None } else { Some(idx) })))
+    }
+}
+
+pub fn parse_colr(data: &[u8]) -> R<Colr> {
+    let (i, _version) = be_u16(data)?;
+    let (i, num_base_glyphs) = be_u16(i)?;
+    let (i, base_glyphs_offset) = be_u32(i)?;
+    let (i, layers_offset) = be_u32(i)?;
+    let (i, num_layers) = be_u16(i)?;
+
+    // records past the end are skipped
+    let base_glyphs = iterator_n(data.get(base_glyphs_offset as usize ..).unwrap_or(&[]), tuple((be_u16, be_u16, be_u16)), num_base_glyphs)
+        .map(|(gid, first, num)| (gid, (first, num)))
+        .collect();
+    let layers = iterator_n(data.get(layers_offset as usize ..).unwrap_or(&[]), tuple((be_u16, be_u16)), num_layers).collect();
+
+    Ok((i, Colr { base_glyphs, layers }))
+}
+
+/// The color palette table
+#[derive(Clone, Debug)]
+pub struct Cpal {
+    pub palettes: Vec<Vec<ColorU>>,
+}
+impl Cpal {
+    pub fn color(&self, palette: usize, entry: u16) -> Option<ColorU> {
+        self.palettes.get(palette)?.get(entry as usize).cloned()
+    }
+}
+
+// colors are stored as BGRA
+fn color_record(i: &[u8]) -> R<ColorU> {
+    let (i, (b, g, r, a)) = tuple((be_u8, be_u8, be_u8, be_u8))(i)?;
+    Ok((i, ColorU { r, g, b, a }))
+}
+
+pub fn parse_cpal(data: &[u8]) -> R<Cpal> {
+    let (i, _version) = be_u16(data)?;
+    let (i, num_entries) = be_u16(i)?;
+    let (i, num_palettes) = be_u16(i)?;
+    let (i, _num_color_records) = be_u16(i)?;
+    let (i, color_records_offset) = be_u32(i)?;
+
+    // colors past the end are missing from their palette
+    let records = data.get(color_records_offset as usize ..).unwrap_or(&[]);
+    let palettes = iterator_n(i, be_u16, num_palettes)
+        .map(|first| iterator_n(records.get(first as usize * 4 ..).unwrap_or(&[]), color_record, num_entries).collect())
+        .collect();
+
+    Ok((i, Cpal { palettes }))
+}
+
+#[test]
+fn test_cpal_alpha() {
+    let data = [
+        0, 0, // version
+        0, 2, 0, 1, 0, 2, // 2 entries, 1 palette, 2 records
+        0, 0, 0, 14, // records offset
+        0, 0, // first record of palette 0
+        0x30, 0x20, 0x10, 0x80, // BGRA
+        0, 0, 0xFF, 0xFF,
+    ];
+    let cpal = parse_cpal(&data).unwrap().1;
+    assert_eq!(cpal.color(0, 0), Some(ColorU { r: 0x10, g: 0x20, b: 0x30, a: 0x80 }));
+    assert_eq!(cpal.color(0, 1), Some(ColorU { r: 0xFF, g: 0, b: 0, a: 0xFF }));
+    assert_eq!(cpal.color(1, 0), None);
+}
+
+#[test]
+fn test_bad_offsets() {
+    let cpal = [
+        0, 0, // version
+        0, 1, 0, 2, 0, 1, // 1 entry, 2 palettes, 1 record
+        0, 0, 0, 16, // records offset
+        0, 0, 0, 9, // palette 1 starts past the end
+        0x30, 0x20, 0x10, 0x80,
+    ];
+    let cpal = parse_cpal(&cpal).unwrap().1;
+    assert_eq!(cpal.color(0, 0), Some(ColorU { r: 0x10, g: 0x20, b: 0x30, a: 0x80 }));
+    assert_eq!(cpal.color(1, 0), None);
+
+    let colr = [
+        0, 0, 0, 1, // version, 1 base glyph
+        0, 0, 0xFF, 0, 0, 0, 0xFF, 0, 0, 1, // base glyphs and layers past the end
+    ];
+    let colr = parse_colr(&colr).unwrap().1;
+    assert!(colr.layers(0).is_none());
+}
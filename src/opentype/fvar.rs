@@ -0,0 +1,137 @@
+use crate::{R, Axis, Name, parsers::*};
+use crate::opentype::{tag, fixed, Tag};
+use nom::number::complete::be_u16;
+
+/// The font variations table
+#[derive(Clone, Debug)]
+pub struct Fvar {
+    pub axes: Vec<Axis>,
+    pub instances: Vec<NamedInstance>,
+}
+
+#[derive(Clone, Debug)]
+pub struct NamedInstance {
+    pub subfamily_name_id: u16,
+    pub postscript_name_id: Option<u16>,
+    /// one value for every axis
+    pub coords: Vec<f32>,
+}
+
+impl Fvar {
+    /// Map user coordinates (like `wght` 700) to normalized ones, one for every axis.
+    ///
+    /// Axes that are not given are at their default (0). Unknown tags are ignored.
+    pub fn normalize(&self, coords: &[(Tag, f32)]) -> Vec<f32> {
+        self.axes.iter().map(|axis| {
+            match coords.iter().rev().find(|&&(tag, _)| tag == axis.tag) {
+                Some(&(_, value)) => normalize(axis, value),
+                None => 0.0
+            }
+        }).collect()
+    }
+    /// The user coordinates of the named instance with the given subfamily or PostScript name.
+    pub fn named_instance(&self, name: &Name, instance_name: &str) -> Option<Vec<(Tag, f32)>> {
+        let matches = |name_id: u16| name.localized.iter().any(|n| n.name_id == name_id && n.value == instance_name);
+        let instance = self.instances.iter().find(|instance| {
+            matches(instance.subfamily_name_id) || instance.postscript_name_id.map(matches).unwrap_or(false)
+        })?;
+        Some(self.axes.iter().map(|axis| axis.tag).zip(instance.coords.iter().cloned()).collect())
+    }
+}
+
+// the default normalization (without `avar`)
+fn normalize(axis: &Axis, value: f32) -> f32 {
+    let value = value.max(axis.min).min(axis.max);
+    if value < axis.default {
+        (value - axis.default) / (axis.default - axis.min)
+    } else if value > axis.default {
+        (value - axis.default) / (axis.max - axis.default)
+    } else {
+        0.0
+    }
+}
+
+fn axis(i: &[u8]) -> R<Axis> {
+    let (i, tag) = tag(i)?;
+    let (i, min) = fixed(i)?;
+    let (i, default) = fixed(i)?;
+    let (i, max) = fixed(i)?;
+    let (i, flags) = be_u16(i)?;
+    let (i, name_id) = be_u16(i)?;
+    Ok((i, Axis { tag, min, default, max, hidden: flags & 1 != 0, name_id }))
+}
+
+pub fn parse_fvar(data: &[u8]) -> R<Fvar> {
+    let (i, _major_version) = be_u16(data)?;
+    let (i, _minor_version) = be_u16(i)?;
+    let (i, axes_offset) = be_u16(i)?;
+    let (i, _reserved) = be_u16(i)?;
+    let (i, axis_count) = be_u16(i)?;
+    let (i, axis_size) = be_u16(i)?;
+    let (i, instance_count) = be_u16(i)?;
+    let (i, instance_size) = be_u16(i)?;
+
+    let axes_data = offset_into(data, axes_offset as usize)?.1;
+    let mut axes = Vec::with_capacity(axis_count as usize);
+    for n in 0 .. axis_count as usize {
+        axes.push(axis(offset_into(axes_data, n * axis_size as usize)?.1)?.1);
+    }
+
+    // the instances follow the axes
+    let instances_data = offset_into(axes_data, axis_count as usize * axis_size as usize)?.1;
+    let has_postscript_name = instance_size as usize >= 6 + 4 * axis_count as usize;
+    let mut instances = Vec::with_capacity(instance_count as usize);
+    for n in 0 .. instance_count as usize {
+        let i = offset_into(instances_data, n * instance_size as usize)?.1;
+        let (i, subfamily_name_id) = be_u16(i)?;
+        let (i, _flags) = be_u16(i)?;
+        let coords = iterator_n(i, fixed, axis_count).collect();
+        let postscript_name_id = if has_postscript_name {
+            let (_, id) = be_u16(offset_into(i, 4 * axis_count as usize)?.1)?;
+            Some(id)
+        } else {
+            None
+        };
+        instances.push(NamedInstance { subfamily_name_id, postscript_name_id, coords });
+    }
+
+    Ok((i, Fvar { axes, instances }))
+}
+
+#[test]
+fn test_fvar() {
+    let data = [
+        0, 1, 0, 0, // version
+        0, 16, 0, 2, // axes offset, reserved
+        0, 1, 0, 20, // one axis of 20 bytes
+        0, 1, 0, 8, // one instance of 8 bytes (no PostScript name)
+        b'w', b'g', b'h', b't',
+        0, 100, 0, 0, 1, 144, 0, 0, 3, 132, 0, 0, // 100, 400, 900
+        0, 0, 1, 0, // flags, name id
+        1, 2, 0, 0, 2, 188, 0, 0, // instance: name 258, weight 700
+    ];
+    let fvar = parse_fvar(&data).unwrap().1;
+    assert_eq!(fvar.axes.len(), 1);
+    let axis = &fvar.axes[0];
+    assert_eq!(axis.tag, crate::opentype::Tag(*b"wght"));
+    assert_eq!((axis.min, axis.default, axis.max), (100., 400., 900.));
+    assert_eq!(axis.name_id, 256);
+    assert_eq!(fvar.instances[0].subfamily_name_id, 258);
+    assert_eq!(fvar.instances[0].postscript_name_id, None);
+    assert_eq!(fvar.instances[0].coords, vec![700.]);
+
+    // axes past the end
+    let mut broken = data;
+    broken[5] = 200;
+    assert!(parse_fvar(&broken).is_err());
+}
+
+#[test]
+fn test_normalize() {
+    let wght = Axis { tag: Tag(*b"wght"), min: 100., default: 400., max: 900., hidden: false, name_id: 256 };
+    let fvar = Fvar { axes: vec![wght], instances: vec![] };
+    assert_eq!(fvar.normalize(&[(Tag(*b"wght"), 650.)]), vec![0.5]);
+    assert_eq!(fvar.normalize(&[(Tag(*b"wght"), 250.)]), vec![-0.5]);
+    assert_eq!(fvar.normalize(&[(Tag(*b"wght"), 1000.)]), vec![1.0]);
+    assert_eq!(fvar.normalize(&[(Tag(*b"wdth"), 50.)]), vec![0.0]);
+}
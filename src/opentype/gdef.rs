@@ -2,6 +2,7 @@ use crate::{R, parsers::*};
 use super::parse_class_def;
 use nom::{
     number::complete::{be_u16},
+    error::{make_error, ErrorKind},
 };
 use std::collections::HashMap;
 
@@ -9,8 +10,9 @@ pub fn parse_gdef(data: &[u8]) -> R<GDef> {
     let (i, major) = be_u16(data)?;
     let (i, minor) = be_u16(i)?;
 
-    assert_eq!(major, 1);
-    assert!(matches!(minor, 1 ..= 3));
+    if major != 1 || !matches!(minor, 0 ..= 3) {
+        return Err(nom::Err::Failure(make_error(data, ErrorKind::Verify)));
+    }
 
     let (i, glyph_class_offset) = offset(i)?;
     let (i, attach_offset) = offset(i)?;
@@ -18,7 +20,9 @@ pub fn parse_gdef(data: &[u8]) -> R<GDef> {
     let (i, mark_attach_class_def_offset) = offset(i)?;
 
     let mut mark_classes = HashMap::new();
-    parse_class_def(mark_attach_class_def_offset.of(data).unwrap(), &mut mark_classes)?;
+    if let Some(class_def) = mark_attach_class_def_offset.of(data) {
+        parse_class_def(class_def, &mut mark_classes)?;
+    }
 
     Ok((i, GDef { mark_classes }))
 }
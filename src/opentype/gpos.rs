@@ -1,30 +1,128 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use nom::{
     bytes::complete::{take},
     number::complete::{be_i16, be_u16, be_u32},
     sequence::{tuple},
+    error::{make_error, ErrorKind},
 };
-use crate::{R, GlyphId};
+use crate::{R, GlyphId, PositionedGlyph};
+use pathfinder_geometry::vector::Vector2F;
 use crate::parsers::{*};
-use crate::opentype::{Maxp, parse_lookup_list, coverage_table};
-use itertools::{Itertools};
+use crate::opentype::{Maxp, Tag, shape::ShapeStep, parse_lookup_list, coverage_table, parse_class_def, gsub::{Feature, parse_feature_list, feature_lookups}};
 
 
 #[derive(Default, Clone)]
 pub struct GPos {
+    /// the `kern` table, used if there are no pair adjustments
     pub kern: KernTable,
-    pub mark_to_base: HashMap<(u16, u16), (i16, i16)>
+    /// lookup index -> pair adjustments (x advance of the first glyph) of the lookup
+    pub pair_adjustments: BTreeMap<u16, KernTable>,
+    /// y advance adjustments of glyph pairs, for vertical text
+    pub vertical_kern: KernTable,
+    pub mark_to_base: HashMap<(u16, u16), (i16, i16)>,
+    /// (first mark, second mark) -> position of the second mark relative to the first
+    pub mark_to_mark: HashMap<(u16, u16), (i16, i16)>,
+    features: Vec<Feature>,
+    // lookup index -> (gid -> x advance adjustment)
+    single_adjustments: HashMap<u16, HashMap<u16, i16>>,
+    /// entry and exit anchors for cursive attachment, of the first subtable that covers the glyph
+    pub cursive: HashMap<u16, EntryExit>,
+}
+
+/// The anchors that connect a glyph to its neighbours in cursive scripts.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct EntryExit {
+    /// joins the exit anchor of the (logically) preceding glyph
+    pub entry: Option<(i16, i16)>,
+    /// joins the entry anchor of the (logically) following glyph
+    pub exit: Option<(i16, i16)>,
+    /// the lookup flag RIGHT_TO_LEFT: the preceding glyph is placed relative to the following one
+    pub right_to_left: bool,
 }
 impl GPos {
     pub fn from_kern(kern: KernTable) -> GPos {
         GPos {
             kern,
-            mark_to_base: HashMap::new()
+            .. GPos::default()
+        }
+    }
+    /// Sum of the advance adjustments (single adjustment lookups) of the given features for this glyph.
+    ///
+    /// Every lookup is applied at most once, even if several of the features reference it.
+    pub fn single_advance(&self, gid: u16, features: &[Tag]) -> i16 {
+        self.feature_lookups(features).iter()
+            .filter_map(|&(_, idx)| self.single_adjustment(idx, gid))
+            .sum()
+    }
+    /// The lookups of the given features, see `gsub::feature_lookups`.
+    pub fn feature_lookups(&self, features: &[Tag]) -> Vec<(Tag, u16)> {
+        feature_lookups(&self.features, features)
+    }
+    /// The advance adjustment of `gid` by lookup `idx`, if it is a single adjustment lookup that covers the glyph.
+    pub fn single_adjustment(&self, idx: u16, gid: u16) -> Option<i16> {
+        self.single_adjustments.get(&idx)?.get(&gid).cloned()
+    }
+    /// Kerning of the pair: the sum of all pair adjustment lookups that have it, or the `kern` table if there are none.
+    pub fn kerning(&self, left: u16, right: u16) -> Option<i16> {
+        if self.pair_adjustments.is_empty() {
+            return self.kern.get(left, right);
+        }
+        self.pair_adjustments.values()
+            .filter_map(|table| table.get(left, right))
+            .fold(None, |sum, kern| Some(sum.unwrap_or(0i16).saturating_add(kern)))
+    }
+    /// The adjustments of the advances: the single adjustment lookups of the given features and all pair adjustment
+    /// lookups in lookup order, or the `kern` table.
+    pub fn adjustment_steps(&self, features: &[Tag]) -> Vec<ShapeStep> {
+        let mut lookups: Vec<(u16, ShapeStep)> = self.feature_lookups(features).into_iter()
+            .filter(|(_, lookup)| self.single_adjustments.contains_key(lookup))
+            .map(|(feature, lookup)| (lookup, ShapeStep::SingleAdjustment { feature, lookup }))
+            .chain(self.pair_adjustments.keys().map(|&lookup| (lookup, ShapeStep::PairAdjustment { lookup })))
+            .collect();
+        lookups.sort_by_key(|&(lookup, _)| lookup);
+        let mut steps: Vec<ShapeStep> = lookups.into_iter().map(|(_, step)| step).collect();
+        if self.pair_adjustments.is_empty() && !self.kern.is_empty() {
+            steps.push(ShapeStep::Kerning);
+        }
+        steps
+    }
+    /// Apply one of the `adjustment_steps` to the advances of the glyphs (in visual order).
+    /// Returns whether any advance changed.
+    pub fn apply_adjustment(&self, step: ShapeStep, glyphs: &mut [PositionedGlyph]) -> bool {
+        let pairs = match step {
+            ShapeStep::SingleAdjustment { lookup, .. } => {
+                let mut fired = false;
+                for glyph in glyphs.iter_mut() {
+                    if let Some(adjustment) = self.single_adjustment(lookup, glyph.gid.0 as u16) {
+                        glyph.advance = glyph.advance + Vector2F::new(adjustment as f32, 0.0);
+                        fired |= adjustment != 0;
+                    }
+                }
+                return fired;
+            }
+            ShapeStep::PairAdjustment { lookup } => match self.pair_adjustments.get(&lookup) {
+                Some(table) => table,
+                None => return false
+            },
+            ShapeStep::Kerning => &self.kern,
+            _ => return false
+        };
+        let mut fired = false;
+        for i in 1 .. glyphs.len() {
+            let kern = pairs.get(glyphs[i - 1].gid.0 as u16, glyphs[i].gid.0 as u16).unwrap_or(0);
+            if kern != 0 {
+                glyphs[i - 1].advance = glyphs[i - 1].advance + Vector2F::new(kern as f32, 0.0);
+                fired = true;
+            }
         }
+        fired
     }
     pub fn get_mark_to_base(&self, base: GlyphId, mark: GlyphId) -> Option<(i16, i16)> {
         self.mark_to_base.get(&(base.0 as u16, mark.0 as u16)).cloned()
     }
+    pub fn get_mark_to_mark(&self, first: GlyphId, second: GlyphId) -> Option<(i16, i16)> {
+        self.mark_to_mark.get(&(first.0 as u16, second.0 as u16)).cloned()
+    }
 }
 
 
@@ -33,17 +131,50 @@ pub struct KernTable {
     pub left_classes: HashMap<u16, u16>, // gid -> class id,
     pub right_classes: HashMap<u16, u16>, // gid -> class id,
     pub class_pairs: HashMap<(u16, u16), i16>,
-    pub glyph_pairs: HashMap<(u16, u16), i16>
+    pub glyph_pairs: HashMap<(u16, u16), i16>,
+    /// class based subtables, in lookup order
+    pub class_subtables: Vec<ClassKern>,
 }
 impl KernTable {
+    pub fn is_empty(&self) -> bool {
+        self.glyph_pairs.is_empty() && self.class_pairs.is_empty() && self.class_subtables.is_empty()
+    }
+    /// Kerning of the pair. Pairs of glyphs take precedence over classes,
+    /// `class_pairs` over the class subtables.
+    /// Of the class subtables, the first one that covers `left` is used.
     pub fn get(&self, left: u16, right: u16) -> Option<i16> {
         if let Some(&kern) = self.glyph_pairs.get(&(left, right)) {
             return Some(kern);
         }
-        let &left_class = self.left_classes.get(&left)?;
-        let &right_class = self.right_classes.get(&right)?;
-        let &kern = self.class_pairs.get(&(left_class, right_class))?;
-        Some(kern)
+        if let (Some(&left_class), Some(&right_class)) = (self.left_classes.get(&left), self.right_classes.get(&right)) {
+            if let Some(&kern) = self.class_pairs.get(&(left_class, right_class)) {
+                return Some(kern);
+            }
+        }
+        self.class_subtables.iter()
+            .find(|table| table.coverage.contains(&left))
+            .and_then(|table| table.get(left, right))
+    }
+}
+
+/// Kerning by glyph classes (pair adjustment format 2)
+#[derive(Default, Clone)]
+pub struct ClassKern {
+    pub coverage: HashSet<u16>,
+    pub first_classes: HashMap<u16, u16>, // gid -> class id
+    pub second_classes: HashMap<u16, u16>, // gid -> class id
+    pub class_2_count: u16,
+    pub values: Vec<i16>, // class 1 major
+}
+impl ClassKern {
+    /// Glyphs without a class are in class 0.
+    pub fn get(&self, left: u16, right: u16) -> Option<i16> {
+        if !self.coverage.contains(&left) {
+            return None;
+        }
+        let class_1 = self.first_classes.get(&left).cloned().unwrap_or(0) as usize;
+        let class_2 = self.second_classes.get(&right).cloned().unwrap_or(0) as usize;
+        self.values.get(class_1 * self.class_2_count as usize + class_2).cloned()
     }
 }
 
@@ -51,26 +182,29 @@ impl KernTable {
 pub fn parse_gpos<'a>(data: &'a [u8], maxp: &Maxp) -> R<'a, GPos> {
     debug!("parse GPOS");
     let (i, major_version) = be_u16(data)?;
-    assert_eq!(major_version, 1);
     let (i, minor_version) = be_u16(i)?;
     
     let (i, _script_list_off) = be_u16(i)?;
-    let (i, _feature_list_off) = be_u16(i)?;
+    let (i, feature_list_off) = be_u16(i)?;
     let (i, lookup_list_off) = be_u16(i)?;
     
-    let (i, _feature_variations_offset) = match minor_version {
-        0 => (i, 0),
-        1 => be_u32(i)?,
-        v => panic!("unsupported GPOS version 1.{}", v)
+    let (i, _feature_variations_offset) = match (major_version, minor_version) {
+        (1, 0) => (i, 0),
+        (1, 1) => be_u32(i)?,
+        _ => return Err(nom::Err::Failure(make_error(data, ErrorKind::Verify)))
     };
     
     let mut gpos = GPos::default();
+    gpos.features = parse_feature_list(offset_into(data, feature_list_off as usize)?.1)?.1;
     
-    parse_lookup_list(&data[lookup_list_off as usize ..], |lookup_idx, data, lookup_type, _lookup_flag| {
+    parse_lookup_list(offset_into(data, lookup_list_off as usize)?.1, |lookup_idx, data, lookup_type, lookup_flag| {
         debug!("lookup type {}", lookup_type);
         match lookup_type { 
-            2 => parse_pair_adjustment(data, &mut gpos.kern, maxp.num_glyphs)?.1,
-            4 => parse_mark_to_base_attachment(data, &mut gpos)?.1,
+            1 => parse_single_adjustment(data, gpos.single_adjustments.entry(lookup_idx as u16).or_default())?.1,
+            2 => parse_pair_adjustment(data, gpos.pair_adjustments.entry(lookup_idx as u16).or_default(), &mut gpos.vertical_kern, maxp.num_glyphs)?.1,
+            3 => parse_cursive_attachment(data, lookup_flag & 1 != 0, &mut gpos.cursive)?.1,
+            4 => parse_mark_attachment(data, &mut gpos.mark_to_base)?.1,
+            6 => parse_mark_attachment(data, &mut gpos.mark_to_mark)?.1,
             _ => {}
         }
         Ok((data, ()))
@@ -108,50 +242,84 @@ fn value_record(flags: u16) -> impl Fn(&[u8]) -> R<ValueRecord> {
     }
 }
 
-fn parse_pair_adjustment<'a>(data: &'a [u8], kern: &mut KernTable, _num_glyphs: u16) -> R<'a, ()> {
+fn parse_single_adjustment<'a>(data: &'a [u8], adjustments: &mut HashMap<u16, i16>) -> R<'a, ()> {
+    let (i, format) = be_u16(data)?;
+    let (i, coverage_off) = offset(i)?;
+    let (i, value_format) = be_u16(i)?;
+    let coverage = coverage_table(offset_into(data, coverage_off.0 as usize)?.1)?.1;
+    match format {
+        1 => {
+            let (i, record) = value_record(value_format)(i)?;
+            if record.x_advance != 0 {
+                for gid in coverage {
+                    adjustments.entry(gid).or_insert(record.x_advance);
+                }
+            }
+            Ok((i, ()))
+        }
+        2 => {
+            let (i, value_count) = be_u16(i)?;
+            for (gid, record) in coverage.zip(iterator_n(i, value_record(value_format), value_count)) {
+                if record.x_advance != 0 {
+                    // the first subtable that covers a glyph wins
+                    adjustments.entry(gid).or_insert(record.x_advance);
+                }
+            }
+            Ok((i, ()))
+        }
+        n => {
+            warn!("unsupported single adjustment format {}", n);
+            Ok((i, ()))
+        }
+    }
+}
+
+fn parse_pair_adjustment<'a>(data: &'a [u8], kern: &mut KernTable, vertical_kern: &mut KernTable, _num_glyphs: u16) -> R<'a, ()> {
     let (i, format) = be_u16(data)?;
+    let (i, coverage_off) = offset(i)?;
+    let (i, value_format_1) = be_u16(i)?;
+    let (i, value_format_2) = be_u16(i)?;
+    let coverage = coverage_table(offset_into(data, coverage_off.0 as usize)?.1)?.1;
     match format {
         1 => {
-            let (i, coverage_off) = offset(i)?;
-            let (i, value_format_1) = be_u16(i)?;
-            let (i, value_format_2) = be_u16(i)?;
             let (i, pair_set_count) = be_u16(i)?;
-            let (i, _pair_set_offsets) = be_u16(i)?;
-            
-            let mut coverage = coverage_table(coverage_off.of(data).unwrap())?.1;
-            for offset in iterator_n(i, be_u16, pair_set_count) {
-                let first_glyph = coverage.next().unwrap();
-                let i = &data[offset as usize ..];
+            for (first_glyph, offset) in coverage.zip(iterator_n(i, be_u16, pair_set_count)) {
+                let i = offset_into(data, offset as usize)?.1;
                 let (i, pair_value_count) = be_u16(i)?;
                 
                 let iter = iterator_n(i, tuple((be_u16, value_record(value_format_1), value_record(value_format_2))), pair_value_count);
                 for (second_glyph, value_record_1, _value_record_2) in iter {
-                    //debug!("{:?}/{}: {:?} {:?}", first_glyph, second_glyph, value_record_1, value_record_2);
-                    kern.glyph_pairs.insert((first_glyph, second_glyph), value_record_1.x_advance);
+                    // the first subtable that has the pair wins
+                    kern.glyph_pairs.entry((first_glyph, second_glyph)).or_insert(value_record_1.x_advance);
+                    if value_record_1.y_advance != 0 {
+                        vertical_kern.glyph_pairs.entry((first_glyph, second_glyph)).or_insert(value_record_1.y_advance);
+                    }
                 }
             }
         },
         2 => {
-            let (i, _coverage_off) = be_u16(i)?;
-            let (i, value_format_1) = be_u16(i)?;
-            let (i, value_format_2) = be_u16(i)?;
-            let (i, _class_def_1_offset) = be_u16(i)?;
-            let (i, _class_def_2_offset) = be_u16(i)?;
+            let (i, class_def_1_offset) = offset(i)?;
+            let (i, class_def_2_offset) = offset(i)?;
             let (i, class_1_count) = be_u16(i)?;
             let (i, class_2_count) = be_u16(i)?;
             
-            let iter = (0 .. class_1_count).cartesian_product(0 .. class_2_count)
-                .zip(iterator_n(i, tuple((value_record(value_format_1), value_record(value_format_2))), class_1_count * class_2_count));
+            let mut horizontal = ClassKern {
+                coverage: coverage.collect(),
+                class_2_count,
+                .. ClassKern::default()
+            };
+            parse_class_def(offset_into(data, class_def_1_offset.0 as usize)?.1, &mut horizontal.first_classes)?;
+            parse_class_def(offset_into(data, class_def_2_offset.0 as usize)?.1, &mut horizontal.second_classes)?;
             
-            kern.class_pairs.reserve(class_1_count as usize * class_2_count as usize);
-
-            for ((class_1, class_2), (value_record_1, _value_record_2)) in iter {
-                if value_record_1.x_advance != 0 {
-                    kern.class_pairs.insert((class_1, class_2), value_record_1.x_advance);
-                }
+            let records = iterator_n(i, tuple((value_record(value_format_1), value_record(value_format_2))), class_1_count as usize * class_2_count as usize);
+            let (x, y): (Vec<i16>, Vec<i16>) = records.map(|(record_1, _record_2)| (record_1.x_advance, record_1.y_advance)).unzip();
+            if y.iter().any(|&v| v != 0) {
+                vertical_kern.class_subtables.push(ClassKern { values: y, .. horizontal.clone() });
             }
+            horizontal.values = x;
+            kern.class_subtables.push(horizontal);
         }
-        n => panic!("unsupported pair adjustment format {}", n)
+        n => warn!("unsupported pair adjustment format {}", n)
     }
     Ok((i, ()))
 }
@@ -162,7 +330,7 @@ fn parse_mark_array_table(data: &[u8]) -> R<impl Iterator<Item=(u16, (i16, i16))
     let iter = iterator_n(i, move |i| {
         let (i, mark_class) = be_u16(i)?;
         let (i, mark_anchor_offset) = offset(i)?;
-        let (_, pos) = parse_anchor_table(mark_anchor_offset.of(data).unwrap())?;
+        let (_, pos) = parse_anchor_table(offset_into(data, mark_anchor_offset.0 as usize)?.1)?;
         Ok((i, (mark_class, pos)))
     }, mark_count);
     Ok((i, iter))
@@ -174,36 +342,72 @@ fn parse_anchor_table(i: &[u8]) -> R<(i16, i16)> {
     Ok((i, (x, y)))
 }
 
-fn parse_mark_to_base_attachment<'a>(data: &'a [u8], gpos: &mut GPos) -> R<'a, ()> {
+fn parse_cursive_attachment<'a>(data: &'a [u8], right_to_left: bool, cursive: &mut HashMap<u16, EntryExit>) -> R<'a, ()> {
     let (i, format) = be_u16(data)?;
-    assert_eq!(format, 1);
+    if format != 1 {
+        warn!("unknown cursive attachment format {}", format);
+        return Ok((i, ()));
+    }
+    let (i, coverage_offset) = offset(i)?;
+    let (i, entry_exit_count) = be_u16(i)?;
+    let coverage = coverage_table(offset_into(data, coverage_offset.0 as usize)?.1)?.1;
+    let anchor = |off: Offset| match off.of(data) {
+        Some(anchor_data) => parse_anchor_table(anchor_data).map(|(_, pos)| Some(pos)),
+        None => Ok(None)
+    };
+    for (gid, (entry_offset, exit_offset)) in coverage.zip(iterator_n(i, tuple((offset, offset)), entry_exit_count)) {
+        let entry_exit = EntryExit { entry: anchor(entry_offset)?, exit: anchor(exit_offset)?, right_to_left };
+        cursive.entry(gid).or_insert(entry_exit);
+    }
+    Ok((i, ()))
+}
+
+// mark-to-base and mark-to-mark attachment have the same layout, the base array takes the place of the mark2 array.
+// `map` gets (base or first mark, mark) -> offset of the mark from the glyph it attaches to
+fn parse_mark_attachment<'a>(data: &'a [u8], map: &mut HashMap<(u16, u16), (i16, i16)>) -> R<'a, ()> {
+    let (i, format) = be_u16(data)?;
+    if format != 1 {
+        warn!("unknown mark attachment format {}", format);
+        return Ok((i, ()));
+    }
     let (i, mark_coverage_offset) = offset(i)?;
     let (i, base_coverage_offset) = offset(i)?;
     let (i, mark_class_count) = be_u16(i)?;
     let (i, mark_array_offset) = offset(i)?;
     let (i, base_array_offset) = offset(i)?;
 
-    let mark_coverage = coverage_table(mark_coverage_offset.of(data).unwrap())?.1;
-    let base_coverage: Vec<_> = coverage_table(base_coverage_offset.of(data).unwrap())?.1.collect();
+    let mark_coverage = coverage_table(offset_into(data, mark_coverage_offset.0 as usize)?.1)?.1;
+    let base_coverage: Vec<_> = coverage_table(offset_into(data, base_coverage_offset.0 as usize)?.1)?.1.collect();
 
-    let base_array: Vec<_> = parse_base_array(base_array_offset.of(data).unwrap(), mark_class_count)?.1.flat_map(|arr| arr.iter()).collect();
+    let base_array = parse_base_array(offset_into(data, base_array_offset.0 as usize)?.1, mark_class_count)?.1;
 
-    for (mark_gid, (mark_class, mark_anchor)) in mark_coverage.zip(parse_mark_array_table(mark_array_offset.of(data).unwrap())?.1) {
-        assert!(mark_class < mark_class_count);
+    for (mark_gid, (mark_class, mark_anchor)) in mark_coverage.zip(parse_mark_array_table(offset_into(data, mark_array_offset.0 as usize)?.1)?.1) {
+        if mark_class >= mark_class_count {
+            warn!("mark class {} of gid {} is out of range", mark_class, mark_gid);
+            continue;
+        }
         for (base_nr, &base_gid) in base_coverage.iter().enumerate() {
-            let base_anchor = base_array[base_nr * mark_class_count as usize + mark_class as usize];
-            gpos.mark_to_base.insert((base_gid, mark_gid), (base_anchor.0 - mark_anchor.0, base_anchor.1 - mark_anchor.1));
+            if let Some(&Some(base_anchor)) = base_array.get(base_nr * mark_class_count as usize + mark_class as usize) {
+                map.entry((base_gid, mark_gid)).or_insert((base_anchor.0.wrapping_sub(mark_anchor.0), base_anchor.1.wrapping_sub(mark_anchor.1)));
+            }
         }
     }
 
     Ok((i, ()))
 }
 
-fn parse_base_array<'a>(data: &'a [u8], mark_class_count: u16) -> R<'a, impl Iterator<Item=ArrayMap<'a, Offset, impl Fn(Offset) -> (i16, i16) + 'a>>> {
+// the anchor of each base glyph and mark class, `None` where the offset is NULL
+fn parse_base_array(data: &[u8], mark_class_count: u16) -> R<Vec<Option<(i16, i16)>>> {
     let (i, base_count) = be_u16(data)?;
-    let p = array_map::<Offset, _, _>(mark_class_count, move |off: Offset| parse_anchor_table(off.of(data).unwrap()).unwrap().1);
-    let iter = iterator_n(i, p, base_count);
-    Ok((i, iter))
+    let n = base_count as usize * mark_class_count as usize;
+    let mut anchors = Vec::with_capacity(n);
+    for off in iterator_n(i, offset, n) {
+        anchors.push(match off.of(data) {
+            Some(anchor_data) => Some(parse_anchor_table(anchor_data)?.1),
+            None => None
+        });
+    }
+    Ok((i, anchors))
 }
 
 fn anchor_table(i: &[u8]) -> R<(i16, i16)> {
@@ -212,3 +416,82 @@ fn anchor_table(i: &[u8]) -> R<(i16, i16)> {
     let (i, y) = be_i16(i)?;
     Ok((i, (x, y)))
 }
+
+#[test]
+fn test_single_adjustment() {
+    let data = [
+        0, 2, // format
+        0, 12, // coverage offset
+        0, 4, // value format: x advance only
+        0, 2, // value count
+        0xFF, 0xCE, // -50
+        0, 30,
+        0, 1, 0, 2, 0, 5, 0, 7, // coverage: glyphs 5 and 7
+    ];
+    let mut adjustments = HashMap::new();
+    parse_single_adjustment(&data, &mut adjustments).unwrap();
+    assert_eq!(adjustments.get(&5), Some(&-50));
+    assert_eq!(adjustments.get(&7), Some(&30));
+    assert_eq!(adjustments.get(&6), None);
+}
+
+#[test]
+fn test_class_pair_adjustment() {
+    let data = [
+        0, 2, // format
+        0, 24, // coverage offset
+        0, 4, 0, 0, // value formats: x advance of the first glyph
+        0, 32, 0, 42, // class definitions
+        0, 2, 0, 2, // class counts
+        0, 0,  0xFF, 0xE2, // class 0: 0, -30
+        0, 0,  0xFF, 0xCE, // class 1: 0, -50
+        0, 1, 0, 2, 0, 10, 0, 11, // coverage: glyphs 10 and 11
+        0, 1, 0, 10, 0, 2, 0, 0, 0, 1, // first classes: 10 -> 0, 11 -> 1
+        0, 2, 0, 1, 0, 20, 0, 21, 0, 1, // second classes: 20 ..= 21 -> 1
+    ];
+    let (mut kern, mut vertical_kern) = (KernTable::default(), KernTable::default());
+    parse_pair_adjustment(&data, &mut kern, &mut vertical_kern, 0).unwrap();
+    assert_eq!(kern.get(10, 20), Some(-30));
+    assert_eq!(kern.get(11, 21), Some(-50));
+    assert_eq!(kern.get(11, 5), Some(0));
+    assert_eq!(kern.get(12, 20), None);
+    assert!(vertical_kern.class_subtables.is_empty());
+}
+
+#[test]
+fn test_kern_table_classes() {
+    let mut kern = KernTable::default();
+    kern.glyph_pairs.insert((1, 2), -10);
+    kern.left_classes.insert(1, 1);
+    kern.right_classes.insert(3, 2);
+    kern.class_pairs.insert((1, 2), -20);
+    kern.class_subtables.push(ClassKern {
+        coverage: [1].iter().cloned().collect(),
+        class_2_count: 1,
+        values: vec![-30],
+        .. ClassKern::default()
+    });
+    assert!(!kern.is_empty());
+    assert_eq!(kern.get(1, 2), Some(-10));
+    assert_eq!(kern.get(1, 3), Some(-20));
+    assert_eq!(kern.get(1, 4), Some(-30));
+    assert_eq!(kern.get(2, 3), None);
+}
+
+#[test]
+fn test_cursive_attachment() {
+    let data = [
+        0, 1, // format
+        0, 14, // coverage offset
+        0, 2, // two records
+        0, 22, 0, 28, // glyph 3: entry and exit
+        0, 0, 0, 22, // glyph 4: exit only
+        0, 1, 0, 2, 0, 3, 0, 4, // coverage: glyphs 3 and 4
+        0, 1, 0, 10, 0xFF, 0xF6, // anchor (10, -10)
+        0, 1, 1, 0, 0, 50, // anchor (256, 50)
+    ];
+    let mut cursive = HashMap::new();
+    parse_cursive_attachment(&data, true, &mut cursive).unwrap();
+    assert_eq!(cursive[&3], EntryExit { entry: Some((10, -10)), exit: Some((256, 50)), right_to_left: true });
+    assert_eq!(cursive[&4], EntryExit { entry: None, exit: Some((10, -10)), right_to_left: true });
+}
@@ -0,0 +1,75 @@
+use crate::{R, parsers::*};
+use nom::{
+    number::complete::{be_u16, be_i16, be_u32},
+    sequence::tuple,
+};
+
+/// The Graphite feature table (`Feat`)
+#[derive(Clone, Debug, Default)]
+pub struct Feat {
+    pub features: Vec<GraphiteFeature>,
+}
+
+#[derive(Clone, Debug)]
+pub struct GraphiteFeature {
+    /// usually four ASCII characters, like an OpenType tag
+    pub id: u32,
+    pub flags: u16,
+    /// name id of the feature label in the `name` table
+    pub name_id: u16,
+    /// (value, name id of the label)
+    pub settings: Vec<(i16, u16)>,
+}
+impl GraphiteFeature {
+    /// The value used if the feature is not set, which is the first setting.
+    pub fn default_value(&self) -> i16 {
+        self.settings.first().map(|&(value, _)| value).unwrap_or(0)
+    }
+}
+
+pub fn parse_feat(data: &[u8]) -> R<Feat> {
+    let (i, version) = be_u32(data)?;
+    let (i, num_features) = be_u16(i)?;
+    let (i, _reserved) = be_u16(i)?;
+    let (mut i, _reserved) = be_u32(i)?;
+
+    let mut features = Vec::with_capacity(num_features as usize);
+    for _ in 0 .. num_features {
+        let id = if version >= 0x00020000 {
+            parse(&mut i, be_u32)?
+        } else {
+            parse(&mut i, be_u16)? as u32
+        };
+        let num_settings = parse(&mut i, be_u16)?;
+        if version >= 0x00020000 {
+            let _reserved = parse(&mut i, be_u16)?;
+        }
+        let settings_offset = parse(&mut i, be_u32)?;
+        let flags = parse(&mut i, be_u16)?;
+        let name_id = parse(&mut i, be_u16)?;
+
+        let settings = data.get(settings_offset as usize ..)
+            .map(|s| iterator_n(s, tuple((be_i16, be_u16)), num_settings).collect())
+            .unwrap_or_default();
+        features.push(GraphiteFeature { id, flags, name_id, settings });
+    }
+    Ok((i, Feat { features }))
+}
+
+#[test]
+fn test_feat() {
+    let data = [
+        0, 2, 0, 0, // version 2.0
+        0, 1, 0, 0, 0, 0, 0, 0, // one feature
+        b's', b'm', b'c', b'p', 0, 2, 0, 0, 0, 0, 0, 28, 0x80, 0, 1, 0, // id, 2 settings at 28, flags, label 256
+        0, 0, 1, 1, // 0: label 257
+        0, 1, 1, 2, // 1: label 258
+    ];
+    let (_, feat) = parse_feat(&data).unwrap();
+    assert_eq!(feat.features.len(), 1);
+    let feature = &feat.features[0];
+    assert_eq!(feature.id.to_be_bytes(), *b"smcp");
+    assert_eq!(feature.name_id, 256);
+    assert_eq!(feature.settings, [(0, 257), (1, 258)]);
+    assert_eq!(feature.default_value(), 0);
+}
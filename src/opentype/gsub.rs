@@ -1,17 +1,22 @@
 use std::collections::HashMap;
+use std::ops::Range;
 use nom::{
     number::complete::{be_u16, be_u32},
     multi::count,
     bytes::complete::take,
     sequence::tuple,
+    error::{make_error, ErrorKind},
 };
 use crate::{R, GlyphId};
-use crate::parsers::{iterator_n};
+use crate::parsers::{iterator_n, offset_into};
 use crate::opentype::{parse_lookup_list, coverage_table, tag, Tag};
 
 #[derive(Debug, Clone)]
 pub struct GlyphList(Vec<u16>);
 impl GlyphList {
+    pub fn new(glyphs: Vec<u16>) -> Self {
+        GlyphList(glyphs)
+    }
     pub fn matches(&self, mut glyphs: impl Iterator<Item=GlyphId>) -> Option<usize> {
         for &a in &self.0 {
             match glyphs.next() {
@@ -49,26 +54,117 @@ impl GSub {
         lang.feature_list.iter()
         .map(move |&FeatureIdx(idx)| self.features[idx as usize].tag)
     }
+    /// The lookups of the given features, see `feature_lookups`.
+    pub fn feature_lookups(&self, features: &[Tag]) -> Vec<(Tag, u16)> {
+        feature_lookups(&self.features, features)
+    }
+    /// The subtables of lookup `idx` (empty for lookup types that are not supported).
+    pub fn lookup(&self, idx: u16) -> &[Substitution] {
+        self.lookup.get(idx as usize).map(|subtables| &subtables[..]).unwrap_or(&[])
+    }
+}
+
+/// The lookups referenced by the given features as (feature, lookup index), in lookup order.
+///
+/// Every lookup is listed once, with the first of the features that references it.
+pub(crate) fn feature_lookups(all_features: &[Feature], features: &[Tag]) -> Vec<(Tag, u16)> {
+    let mut lookups: Vec<(Tag, u16)> = features.iter()
+        .flat_map(|&tag| all_features.iter().filter(move |feature| feature.tag == tag))
+        .flat_map(|feature| feature.lookup_indices.iter().map(move |&idx| (feature.tag, idx)))
+        .collect();
+    // stable, so the first feature of each lookup is kept
+    lookups.sort_by_key(|&(_, idx)| idx);
+    lookups.dedup_by_key(|&mut (_, idx)| idx);
+    lookups
 }
 
 #[derive(Debug, Clone)]
 pub enum Substitution {
     Single(HashMap<u16, u16>),
+    /// one glyph replaced by a sequence of glyphs
+    Multiple(HashMap<u16, Vec<u16>>),
     Ligatures(HashMap<u16, Vec<(GlyphList, u16)>>),
 }
+impl Substitution {
+    /// Substitute the glyph at `pos` (and for ligatures the following ones).
+    ///
+    /// The glyphs are paired with the byte range of the text they stand for, ligatures cover all of their components
+    /// and the glyphs of a multiple substitution share the range of the glyph they replace.
+    /// Returns false if the subtable does not apply at `pos`.
+    pub fn apply_at(&self, glyphs: &mut Vec<(GlyphId, Range<usize>)>, pos: usize) -> bool {
+        let first = glyphs[pos].0.0 as u16;
+        match *self {
+            Substitution::Single(ref map) => match map.get(&first) {
+                Some(&gid) => {
+                    glyphs[pos].0 = GlyphId(gid as u32);
+                    true
+                }
+                None => false
+            }
+            Substitution::Multiple(ref map) => match map.get(&first) {
+                Some(sequence) => {
+                    let cluster = glyphs[pos].1.clone();
+                    glyphs.splice(pos ..= pos, sequence.iter().map(|&gid| (GlyphId(gid as u32), cluster.clone())));
+                    true
+                }
+                None => false
+            }
+            Substitution::Ligatures(ref map) => {
+                let ligatures = match map.get(&first) {
+                    Some(ligatures) => ligatures,
+                    None => return false
+                };
+                for &(ref components, ligature) in ligatures {
+                    if let Some(n) = components.matches(glyphs[pos + 1 ..].iter().map(|&(gid, _)| gid)) {
+                        let start = glyphs[pos ..= pos + n].iter().map(|(_, c)| c.start).min().unwrap();
+                        let end = glyphs[pos ..= pos + n].iter().map(|(_, c)| c.end).max().unwrap();
+                        glyphs.drain(pos + 1 ..= pos + n);
+                        glyphs[pos] = (GlyphId(ligature as u32), start .. end);
+                        return true;
+                    }
+                }
+                false
+            }
+        }
+    }
+}
+
+/// Apply a lookup at every position of `glyphs`. At each position the first subtable that applies is used.
+/// Glyphs inserted by a multiple substitution are skipped.
+///
+/// Returns whether any glyph was substituted.
+pub fn apply_lookup(subtables: &[Substitution], glyphs: &mut Vec<(GlyphId, Range<usize>)>) -> bool {
+    let mut applied = false;
+    let mut pos = 0;
+    while pos < glyphs.len() {
+        let len = glyphs.len();
+        match subtables.iter().find(|sub| sub.apply_at(glyphs, pos)) {
+            // continue after the sequence, which may be empty
+            Some(Substitution::Multiple(_)) => {
+                applied = true;
+                pos = pos + 1 + glyphs.len() - len;
+            }
+            Some(_) => {
+                applied = true;
+                pos += 1;
+            }
+            None => pos += 1
+        }
+    }
+    applied
+}
 
 pub fn parse_gsub(data: &[u8]) -> R<GSub> {
     debug!("parse GSUB");
     let (i, major_version) = be_u16(data)?;
-    assert_eq!(major_version, 1);
     let (i, minor_version) = be_u16(i)?;
     
     let (i, script_list_off) = be_u16(i)?;
     let (i, feature_list_off) = be_u16(i)?;
     let (i, lookup_list_off) = be_u16(i)?;
     
-    let (_, script_list) = parse_script_list(&data[script_list_off as usize ..])?;
-    let (_, feature_list) = parse_feature_list(&data[feature_list_off as usize ..])?;
+    let (_, script_list) = parse_script_list(offset_into(data, script_list_off as usize)?.1)?;
+    let (_, feature_list) = parse_feature_list(offset_into(data, feature_list_off as usize)?.1)?;
 /*
     let print_lang = |lang: &LanguageSystem| {
         if let Some(required) = lang.required_feature {
@@ -90,35 +186,20 @@ pub fn parse_gsub(data: &[u8]) -> R<GSub> {
         }
     }
 */
-    let (i, _feature_variations_offset) = match minor_version {
-        0 => (i, 0),
-        1 => be_u32(i)?,
-        v => panic!("unsupported GPOS version 1.{}", v)
+    let (i, _feature_variations_offset) = match (major_version, minor_version) {
+        (1, 0) => (i, 0),
+        (1, 1) => be_u32(i)?,
+        _ => return Err(nom::Err::Failure(make_error(data, ErrorKind::Verify)))
     };
     
     let mut lookup = Vec::new();
     
-    parse_lookup_list(&data[lookup_list_off as usize ..], |lookup_idx, data, lookup_type, _lookup_flag| {
+    parse_lookup_list(offset_into(data, lookup_list_off as usize)?.1, |lookup_idx, data, lookup_type, _lookup_flag| {
         while lookup_idx >= lookup.len() {
             lookup.push(Vec::new());
         }
-        let mut push = |sub| lookup[lookup_idx].push(sub);
-
-        match lookup_type { 
-            // Single · Replace one glyph with one glyph
-            1 => push(parse_single_subst(data)?.1), 
-
-            2 => debug!("font has Multiple substitutions"), // Multiple · Replace one glyph with more than one glyph
-            3 => debug!("font has Altername substitutions"), // Alternate · Replace one glyph with one of many glyphs
-
-            // Ligature · Replace multiple glyphs with one glyph
-            4 => push(parse_ligatures(data)?.1),
-            
-            5 => debug!("font has Context substitutions"), // Context · Replace one or more glyphs in context
-            6 => debug!("font has Chaining Context substitutions"), // Chaining Context · Replace one or more glyphs in chained context
-            7 => debug!("font has Extension Substitution"), // Extension Substitution · Extension mechanism for other substitutions (i.e. this excludes the Extension type substitution itself)
-            8 => debug!("font has Reverse chaining context single substitutions"), // Reverse chaining context single · Applied in reverse order, replace single glyph in chaining context
-            _ => {},
+        if let Some(sub) = parse_subst(data, lookup_type)?.1 {
+            lookup[lookup_idx].push(sub);
         }
         Ok((data, ()))
     })?;
@@ -130,6 +211,53 @@ pub fn parse_gsub(data: &[u8]) -> R<GSub> {
     }))
 }
 
+// a subtable of the given lookup type, or None for the types that are not supported
+fn parse_subst(data: &[u8], lookup_type: u16) -> R<Option<Substitution>> {
+    let sub = match lookup_type { 
+        // Single · Replace one glyph with one glyph
+        1 => parse_single_subst(data)?.1,
+
+        // Multiple · Replace one glyph with more than one glyph
+        2 => parse_multiple_subst(data)?.1,
+
+        // Ligature · Replace multiple glyphs with one glyph
+        4 => parse_ligatures(data)?.1,
+
+        // Extension Substitution · a 32 bit offset to a subtable of another type (but not another extension)
+        7 => {
+            let (i, _format) = be_u16(data)?;
+            let (i, extension_lookup_type) = be_u16(i)?;
+            let (_, extension_offset) = be_u32(i)?;
+            if extension_lookup_type == 7 {
+                warn!("nested GSUB extension subtable");
+                return Ok((data, None));
+            }
+            return parse_subst(data.get(extension_offset as usize ..).unwrap_or(&[]), extension_lookup_type);
+        }
+
+        // Alternate, Context, Chaining Context and Reverse chaining context single
+        n => {
+            debug!("font has substitutions of type {}", n);
+            return Ok((data, None));
+        }
+    };
+    Ok((data, Some(sub)))
+}
+
+fn parse_multiple_subst(data: &[u8]) -> R<Substitution> {
+    let (i, _format) = be_u16(data)?;
+    let (i, coverage_offset) = be_u16(i)?;
+    let (i, sequence_count) = be_u16(i)?;
+    let (_, coverage) = coverage_table(offset_into(data, coverage_offset as usize)?.1)?;
+    let mut subs = HashMap::with_capacity(sequence_count as usize);
+    for (gid, offset) in coverage.zip(iterator_n(i, be_u16, sequence_count)) {
+        let (i, glyph_count) = be_u16(offset_into(data, offset as usize)?.1)?;
+        let (_, sequence) = count(be_u16, glyph_count as usize)(i)?;
+        subs.insert(gid, sequence);
+    }
+    Ok((i, Substitution::Multiple(subs)))
+}
+
 fn parse_single_subst(data: &[u8]) -> R<Substitution> {
     let (i, format) = be_u16(data)?;
     let mut subs = HashMap::new();
@@ -137,7 +265,7 @@ fn parse_single_subst(data: &[u8]) -> R<Substitution> {
         1 => {
             let (i, coverage_offset) = be_u16(i)?;
             let (i, delta_gid) = be_u16(i)?;
-            let (_, coverage) = coverage_table(&data[coverage_offset as usize ..])?;
+            let (_, coverage) = coverage_table(offset_into(data, coverage_offset as usize)?.1)?;
             for gid in coverage {
                 subs.insert(gid, gid.wrapping_add(delta_gid));
             }
@@ -145,13 +273,13 @@ fn parse_single_subst(data: &[u8]) -> R<Substitution> {
         2 => {
             let (i, coverage_offset) = be_u16(i)?;
             let (i, glyph_count) = be_u16(i)?;
-            let (_, coverage) = coverage_table(&data[coverage_offset as usize ..])?;
+            let (_, coverage) = coverage_table(offset_into(data, coverage_offset as usize)?.1)?;
             let replacements = iterator_n(i, be_u16, glyph_count);
             for (gid, replacement_gid) in coverage.zip(replacements) {
                 subs.insert(gid, replacement_gid);
             }
         }
-        _ => panic!("unsupported single substitution format {}", format)
+        _ => warn!("unsupported single substitution format {}", format)
     }
     Ok((i, Substitution::Single(subs)))
 }
@@ -159,23 +287,25 @@ fn parse_single_subst(data: &[u8]) -> R<Substitution> {
 
 fn parse_ligatures(data: &[u8]) -> R<Substitution> {
     let (i, format) = be_u16(data)?;
-    assert_eq!(format, 1);
+    if format != 1 {
+        return Err(nom::Err::Failure(make_error(data, ErrorKind::Verify)));
+    }
 
     let (i, coverage_offset) = be_u16(i)?;
     let (i, ligature_set_count) = be_u16(i)?;
-    let (_, coverage) = coverage_table(&data[coverage_offset as usize ..])?;
+    let (_, coverage) = coverage_table(offset_into(data, coverage_offset as usize)?.1)?;
     let mut ligatures = HashMap::with_capacity(ligature_set_count as usize);
 
     for (first, offset) in coverage.zip(iterator_n(i, be_u16, ligature_set_count)) {
-        let set_data = &data[offset as usize ..];
+        let set_data = offset_into(data, offset as usize)?.1;
         let (i, ligature_count) = be_u16(set_data)?;
         let entry = ligatures.entry(first).or_insert_with(|| Vec::with_capacity(ligature_count as usize));
 
         for set_offest in iterator_n(i, be_u16, ligature_count) {
-            let data = &set_data[set_offest as usize ..];
+            let data = offset_into(set_data, set_offest as usize)?.1;
             let (i, ligature_glyph) = be_u16(data)?;
             let (i, component_count) = be_u16(i)?;
-            let (_, components) = count(be_u16, component_count as usize - 1)(i)?;
+            let (_, components) = count(be_u16, (component_count as usize).saturating_sub(1))(i)?;
             
             entry.push((GlyphList(components), ligature_glyph));
         }
@@ -199,14 +329,14 @@ fn parse_script_list(data: &[u8]) -> R<Vec<Script>> {
     let (i, script_count) = be_u16(data)?;
     let mut scripts = Vec::with_capacity(script_count as usize);
     for (tag, offset) in iterator_n(i, tuple((take(4usize), be_u16)), script_count) {
-        scripts.push(parse_script_table(&data[offset as usize .. ])?.1);
+        scripts.push(parse_script_table(offset_into(data, offset as usize)?.1)?.1);
     }
     Ok((i, scripts))
 }
 fn parse_script_table(data: &[u8]) -> R<Script> {
     let (i, default_lang_offset) = be_u16(data)?;
     let default_language = if default_lang_offset != 0 {
-        Some(parse_language_system_table(&data[default_lang_offset as usize ..])?.1)
+        Some(parse_language_system_table(offset_into(data, default_lang_offset as usize)?.1)?.1)
     } else {
         None
     };
@@ -214,7 +344,7 @@ fn parse_script_table(data: &[u8]) -> R<Script> {
     let (i, lang_sys_count) = be_u16(i)?;
     let mut languages = Vec::with_capacity(lang_sys_count as usize);
     for (tag, lang_sys_offset) in iterator_n(i, tuple((tag, be_u16)), lang_sys_count) {
-        languages.push((tag, parse_language_system_table(&data[lang_sys_offset as usize ..])?.1));
+        languages.push((tag, parse_language_system_table(offset_into(data, lang_sys_offset as usize)?.1)?.1));
     }
     Ok((i, Script {
         default_language,
@@ -238,9 +368,9 @@ pub struct LanguageSystem {
 }
 
 #[derive(Debug, Clone)]
-struct Feature {
-    tag: Tag,
-    lookup_indices: Vec<u16>
+pub(crate) struct Feature {
+    pub(crate) tag: Tag,
+    pub(crate) lookup_indices: Vec<u16>
 }
 // returns (requiredFeatureIndex, FeatureList)
 fn parse_language_system_table(i: &[u8]) -> R<LanguageSystem> {
@@ -255,13 +385,13 @@ fn parse_language_system_table(i: &[u8]) -> R<LanguageSystem> {
     }))
 }
 
-fn parse_feature_list(data: &[u8]) -> R<Vec<Feature>> {
+pub(crate) fn parse_feature_list(data: &[u8]) -> R<Vec<Feature>> {
     let (i, feature_count) = be_u16(data)?;
     let mut features = Vec::with_capacity(feature_count as usize);
     for (tag, feature_offset) in iterator_n(i, tuple((tag, be_u16)), feature_count) {
         features.push(Feature {
             tag,
-            lookup_indices: parse_feature_table(&data[feature_offset as usize ..])?.1.collect()
+            lookup_indices: parse_feature_table(offset_into(data, feature_offset as usize)?.1)?.1.collect()
         });
     }
     Ok((i, features))
@@ -272,3 +402,56 @@ fn parse_feature_table(i: &[u8]) -> R<impl Iterator<Item=u16> + '_> {
     let (i, lookup_index_count) = be_u16(i)?;
     Ok((i, iterator_n(i, be_u16, lookup_index_count)))
 }
+
+#[test]
+fn test_apply_lookup() {
+    let mut single = HashMap::new();
+    single.insert(1, 5);
+    let mut ligatures = HashMap::new();
+    ligatures.insert(2, vec![(GlyphList(vec![3, 3]), 9), (GlyphList(vec![3]), 8)]);
+    let lookup = [Substitution::Single(single), Substitution::Ligatures(ligatures)];
+
+    // glyph 1, then 2 3 forms a ligature and a 3 is left over
+    let mut glyphs: Vec<_> = [1, 2, 3, 4, 3].iter().enumerate().map(|(i, &gid)| (GlyphId(gid), i .. i + 1)).collect();
+    assert!(apply_lookup(&lookup, &mut glyphs));
+    assert_eq!(glyphs, vec![(GlyphId(5), 0 .. 1), (GlyphId(8), 1 .. 3), (GlyphId(4), 3 .. 4), (GlyphId(3), 4 .. 5)]);
+    assert!(!apply_lookup(&lookup[.. 1], &mut glyphs));
+
+    // the inserted glyphs are not substituted again
+    let mut multiple = HashMap::new();
+    multiple.insert(1, vec![1, 1, 6]);
+    let mut glyphs = vec![(GlyphId(1), 0 .. 1), (GlyphId(1), 1 .. 2)];
+    assert!(apply_lookup(&[Substitution::Multiple(multiple)], &mut glyphs));
+    let gids: Vec<u32> = glyphs.iter().map(|&(gid, _)| gid.0).collect();
+    assert_eq!(gids, vec![1, 1, 6, 1, 1, 6]);
+    assert_eq!(glyphs[2].1, 0 .. 1);
+
+    // an empty sequence deletes the glyph, the next one is still substituted
+    let mut multiple = HashMap::new();
+    multiple.insert(1, vec![]);
+    let mut glyphs = vec![(GlyphId(1), 0 .. 1), (GlyphId(1), 1 .. 2), (GlyphId(2), 2 .. 3)];
+    assert!(apply_lookup(&[Substitution::Multiple(multiple)], &mut glyphs));
+    assert_eq!(glyphs, vec![(GlyphId(2), 2 .. 3)]);
+
+    let features = vec![
+        Feature { tag: Tag(*b"liga"), lookup_indices: vec![3, 1] },
+        Feature { tag: Tag(*b"ccmp"), lookup_indices: vec![1, 0] },
+    ];
+    assert_eq!(feature_lookups(&features, &[Tag(*b"liga"), Tag(*b"ccmp")]), vec![
+        (Tag(*b"ccmp"), 0), (Tag(*b"liga"), 1), (Tag(*b"liga"), 3)
+    ]);
+}
+
+#[test]
+fn test_extension_subst() {
+    // extension subtable pointing to a single substitution 5 -> 7
+    let data = [
+        0, 1, 0, 1, 0, 0, 0, 8, // extension header
+        0, 1, 0, 6, 0, 2, // single substitution format 1, delta 2
+        0, 1, 0, 1, 0, 5, // coverage: glyph 5
+    ];
+    let sub = parse_subst(&data, 7).unwrap().1.unwrap();
+    let mut glyphs = vec![(GlyphId(5), 0 .. 1)];
+    assert!(sub.apply_at(&mut glyphs, 0));
+    assert_eq!(glyphs[0].0, GlyphId(7));
+}
@@ -0,0 +1,362 @@
+use crate::{R, parsers::*};
+use nom::{
+    number::complete::{be_u8, be_i8, be_u16, be_i16, be_u32},
+    bytes::complete::take,
+    multi::count,
+    combinator::map,
+};
+use pathfinder_geometry::vector::Vector2F;
+
+/// The glyph variations table
+#[derive(Clone, Debug)]
+pub struct Gvar {
+    axis_count: usize,
+    shared_tuples: Vec<Vec<f32>>,
+    // the glyph variation data array
+    data: Vec<u8>,
+    // glyph count + 1 offsets into `data`
+    offsets: Vec<u32>,
+}
+
+// 2.14 fixed point number
+pub(crate) fn f2dot14(i: &[u8]) -> R<f32> {
+    let (i, v) = be_i16(i)?;
+    Ok((i, v as f32 / 16384.))
+}
+
+pub fn parse_gvar(data: &[u8]) -> R<Gvar> {
+    let (i, _major_version) = be_u16(data)?;
+    let (i, _minor_version) = be_u16(i)?;
+    let (i, axis_count) = be_u16(i)?;
+    let (i, shared_tuple_count) = be_u16(i)?;
+    let (i, shared_tuples_offset) = be_u32(i)?;
+    let (i, glyph_count) = be_u16(i)?;
+    let (i, flags) = be_u16(i)?;
+    let (i, data_offset) = be_u32(i)?;
+    let (i, offsets) = if flags & 1 != 0 {
+        count(be_u32, glyph_count as usize + 1)(i)?
+    } else {
+        count(map(be_u16, |n| 2 * n as u32), glyph_count as usize + 1)(i)?
+    };
+
+    let (mut tuples, _) = take(shared_tuples_offset as usize)(data)?;
+    let mut shared_tuples = Vec::with_capacity(shared_tuple_count as usize);
+    for _ in 0 .. shared_tuple_count {
+        shared_tuples.push(parse(&mut tuples, count(f2dot14, axis_count as usize))?);
+    }
+    let (variations, _) = take(data_offset as usize)(data)?;
+
+    Ok((i, Gvar {
+        axis_count: axis_count as usize,
+        shared_tuples,
+        data: variations.to_vec(),
+        offsets
+    }))
+}
+
+// packed point numbers. `None` stands for all points.
+fn packed_points(i: &[u8]) -> R<Option<Vec<u16>>> {
+    let (mut i, first) = be_u8(i)?;
+    let n = if first & 0x80 != 0 {
+        let second = parse(&mut i, be_u8)?;
+        (first as usize & 0x7F) << 8 | second as usize
+    } else {
+        first as usize
+    };
+    if n == 0 {
+        return Ok((i, None));
+    }
+    let mut points = Vec::with_capacity(n);
+    let mut point = 0u16;
+    while points.len() < n {
+        let control = parse(&mut i, be_u8)?;
+        for _ in 0 ..= control & 0x7F {
+            let delta = if control & 0x80 != 0 {
+                parse(&mut i, be_u16)?
+            } else {
+                parse(&mut i, be_u8)? as u16
+            };
+            point = point.wrapping_add(delta);
+            points.push(point);
+        }
+    }
+    points.truncate(n);
+    Ok((i, Some(points)))
+}
+
+// `n` packed deltas
+fn packed_deltas(mut i: &[u8], n: usize) -> R<Vec<f32>> {
+    let mut deltas = Vec::with_capacity(n);
+    while deltas.len() < n {
+        let control = parse(&mut i, be_u8)?;
+        for _ in 0 ..= control & 0x3F {
+            let delta = if control & 0x80 != 0 {
+                0
+            } else if control & 0x40 != 0 {
+                parse(&mut i, be_i16)?
+            } else {
+                parse(&mut i, be_i8)? as i16
+            };
+            deltas.push(delta as f32);
+        }
+    }
+    deltas.truncate(n);
+    Ok((i, deltas))
+}
+
+/// How much a tuple variation applies at the normalized `coords`.
+fn tuple_scalar(coords: &[f32], peak: &[f32], region: Option<(&[f32], &[f32])>) -> f32 {
+    let mut scalar = 1.0;
+    for (axis, &peak) in peak.iter().enumerate() {
+        let coord = coords.get(axis).cloned().unwrap_or(0.0);
+        if peak == 0.0 || coord == peak {
+            continue;
+        }
+        if coord == 0.0 {
+            return 0.0;
+        }
+        match region {
+            Some((start, end)) => {
+                let (start, end) = (start[axis], end[axis]);
+                if coord <= start || coord >= end {
+                    return 0.0;
+                }
+                scalar *= if coord < peak {
+                    (coord - start) / (peak - start)
+                } else {
+                    (end - coord) / (end - peak)
+                };
+            }
+            None => {
+                if coord < peak.min(0.0) || coord > peak.max(0.0) {
+                    return 0.0;
+                }
+                scalar *= coord / peak;
+            }
+        }
+    }
+    scalar
+}
+
+impl Gvar {
+    /// The deltas of the points of glyph `gid` at the normalized `coords`.
+    ///
+    /// `points` are the original coordinates, including the four phantom points at the end,
+    /// and `end_points` the index of the last point of each contour (empty for composite glyphs,
+    /// where each component offset is one point).
+    pub fn deltas(&self, gid: u32, coords: &[f32], points: &[Vector2F], end_points: &[u16]) -> Vec<Vector2F> {
+        let mut deltas = vec![Vector2F::default(); points.len()];
+        let start = self.offsets.get(gid as usize).cloned().unwrap_or(0) as usize;
+        let end = self.offsets.get(gid as usize + 1).cloned().unwrap_or(0) as usize;
+        if start < end && end <= self.data.len() && coords.iter().any(|&c| c != 0.0) {
+            if let Err(e) = self.add_deltas(&self.data[start .. end], coords, points, end_points, &mut deltas) {
+                warn!("broken variation data of glyph {}: {:?}", gid, e);
+            }
+        }
+        deltas
+    }
+
+    fn add_deltas(&self, data: &[u8], coords: &[f32], points: &[Vector2F], end_points: &[u16], deltas: &mut [Vector2F]) -> R<()> {
+        let (i, tuple_count) = be_u16(data)?;
+        let (mut headers, data_offset) = be_u16(i)?;
+        let (mut serialized, _) = take(data_offset as usize)(data)?;
+        let shared_points = if tuple_count & 0x8000 != 0 {
+            parse(&mut serialized, packed_points)?
+        } else {
+            None
+        };
+
+        let mut tuple_deltas = vec![Vector2F::default(); points.len()];
+        let mut touched = vec![false; points.len()];
+        for _ in 0 .. tuple_count & 0x0FFF {
+            let size = parse(&mut headers, be_u16)?;
+            let index = parse(&mut headers, be_u16)?;
+            let embedded_peak;
+            let peak = if index & 0x8000 != 0 {
+                embedded_peak = parse(&mut headers, count(f2dot14, self.axis_count))?;
+                &embedded_peak[..]
+            } else {
+                match self.shared_tuples.get(index as usize & 0x0FFF) {
+                    Some(tuple) => &tuple[..],
+                    None => {
+                        warn!("shared tuple {} out of range", index & 0x0FFF);
+                        &[]
+                    }
+                }
+            };
+            let region = if index & 0x4000 != 0 {
+                let start = parse(&mut headers, count(f2dot14, self.axis_count))?;
+                let end = parse(&mut headers, count(f2dot14, self.axis_count))?;
+                Some((start, end))
+            } else {
+                None
+            };
+            let mut tuple_data = parse(&mut serialized, take(size as usize))?;
+
+            let scalar = tuple_scalar(coords, peak, region.as_ref().map(|(s, e)| (&s[..], &e[..])));
+            if scalar == 0.0 {
+                continue;
+            }
+            let point_numbers = if index & 0x2000 != 0 {
+                parse(&mut tuple_data, packed_points)?
+            } else {
+                shared_points.clone()
+            };
+            let n = point_numbers.as_ref().map(|p| p.len()).unwrap_or(points.len());
+            let xs = parse(&mut tuple_data, |i| packed_deltas(i, n))?;
+            let ys = parse(&mut tuple_data, |i| packed_deltas(i, n))?;
+
+            match point_numbers {
+                None => {
+                    for (delta, (&x, &y)) in deltas.iter_mut().zip(xs.iter().zip(&ys)) {
+                        *delta = *delta + Vector2F::new(x, y) * scalar;
+                    }
+                }
+                Some(point_numbers) => {
+                    for (t, d) in touched.iter_mut().zip(tuple_deltas.iter_mut()) {
+                        *t = false;
+                        *d = Vector2F::default();
+                    }
+                    for (&p, (&x, &y)) in point_numbers.iter().zip(xs.iter().zip(&ys)) {
+                        if let Some(d) = tuple_deltas.get_mut(p as usize) {
+                            *d = Vector2F::new(x, y);
+                            touched[p as usize] = true;
+                        }
+                    }
+                    interpolate_untouched(points, end_points, &touched, &mut tuple_deltas);
+                    for (delta, &d) in deltas.iter_mut().zip(&tuple_deltas) {
+                        *delta = *delta + d * scalar;
+                    }
+                }
+            }
+        }
+        Ok((serialized, ()))
+    }
+}
+
+// infer the delta of one coordinate from the touched neighbours `a` and `b`
+fn interpolate(p: f32, (a, da): (f32, f32), (b, db): (f32, f32)) -> f32 {
+    let ((x1, d1), (x2, d2)) = if a <= b { ((a, da), (b, db)) } else { ((b, db), (a, da)) };
+    if x1 == x2 {
+        if d1 == d2 { d1 } else { 0.0 }
+    } else if p <= x1 {
+        d1
+    } else if p >= x2 {
+        d2
+    } else {
+        d1 + (p - x1) * (d2 - d1) / (x2 - x1)
+    }
+}
+
+/// Interpolation of untouched points (IUP).
+///
+/// When a variation tuple only has deltas for some points, the deltas of the remaining points
+/// of each contour are inferred from the nearest touched points before and after them.
+/// `points` are the original coordinates, `end_points` holds the index of the last point of each contour.
+/// The deltas of all points where `touched` is false are overwritten.
+pub fn interpolate_untouched(points: &[Vector2F], end_points: &[u16], touched: &[bool], deltas: &mut [Vector2F]) {
+    let mut start = 0;
+    for &end in end_points {
+        let end = end as usize + 1;
+        if end > points.len() {
+            warn!("contour end {} beyond the {} points", end, points.len());
+            break;
+        }
+        let range = start .. end;
+        start = end;
+
+        let touched_points: Vec<usize> = range.clone().filter(|&i| touched[i]).collect();
+        match touched_points.len() {
+            0 => {
+                for i in range {
+                    deltas[i] = Vector2F::default();
+                }
+                continue;
+            }
+            1 => {
+                let delta = deltas[touched_points[0]];
+                for i in range {
+                    deltas[i] = delta;
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        // pairs of consecutive touched points (wrapping around the contour)
+        let pairs = touched_points.iter().cloned()
+            .zip(touched_points.iter().cloned().cycle().skip(1));
+        for (a, b) in pairs {
+            let untouched = if a < b {
+                (a + 1 .. b).chain(0 .. 0)
+            } else {
+                (a + 1 .. range.end).chain(range.start .. b)
+            };
+            let (pa, pb) = (points[a], points[b]);
+            let (da, db) = (deltas[a], deltas[b]);
+            for i in untouched {
+                let p = points[i];
+                deltas[i] = Vector2F::new(
+                    interpolate(p.x(), (pa.x(), da.x()), (pb.x(), db.x())),
+                    interpolate(p.y(), (pa.y(), da.y()), (pb.y(), db.y())),
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_iup() {
+    let v = Vector2F::new;
+    let points = [
+        v(0., 0.), v(50., 0.), v(100., 0.), v(100., 100.), v(0., 100.), // first contour
+        v(0., 0.), v(10., 10.), // nothing touched
+        v(0., 0.), v(10., 10.), // one point touched
+    ];
+    let end_points = [4, 6, 8];
+    let touched = [true, false, true, false, false, false, false, false, true];
+    let mut deltas = [Vector2F::default(); 9];
+    deltas[0] = v(10., 0.);
+    deltas[2] = v(20., 0.);
+    deltas[6] = v(99., 99.); // garbage, must be cleared
+    deltas[8] = v(3., 4.);
+
+    interpolate_untouched(&points, &end_points, &touched, &mut deltas);
+    assert_eq!(deltas[0], v(10., 0.));
+    assert_eq!(deltas[1], v(15., 0.));
+    assert_eq!(deltas[2], v(20., 0.));
+    assert_eq!(deltas[3], v(20., 0.));
+    assert_eq!(deltas[4], v(10., 0.));
+    assert_eq!(deltas[5], v(0., 0.));
+    assert_eq!(deltas[6], v(0., 0.));
+    assert_eq!(deltas[7], v(3., 4.));
+    assert_eq!(deltas[8], v(3., 4.));
+}
+
+#[test]
+fn test_gvar_deltas() {
+    let data = [
+        0, 1, 0, 0, // version
+        0, 1, 0, 0, 0, 0, 0, 24, // one axis, no shared tuples
+        0, 1, 0, 0, 0, 0, 0, 24, // one glyph, short offsets
+        0, 0, 0, 9,
+        // glyph 0
+        0, 1, 0, 10, // one tuple
+        0, 7, 0xA0, 0, 0x40, 0, // 7 bytes, embedded peak 1.0, private points
+        1, 0, 0, // point 0
+        0, 10, 0, 20, // delta (10, 20)
+        0, // padding
+    ];
+    let gvar = parse_gvar(&data).unwrap().1;
+    let v = Vector2F::new;
+    let mut points = vec![v(0., 0.), v(100., 0.), v(100., 100.)];
+    points.extend(std::iter::repeat(v(0., 0.)).take(4));
+
+    // the only touched point moves the whole contour, but not the phantom points
+    let deltas = gvar.deltas(0, &[0.5], &points, &[2]);
+    assert_eq!(&deltas[.. 3], &[v(5., 10.); 3]);
+    assert_eq!(&deltas[3 ..], &[v(0., 0.); 4]);
+
+    assert_eq!(gvar.deltas(0, &[-0.5], &points, &[2]), vec![v(0., 0.); 7]);
+    assert_eq!(gvar.deltas(0, &[1.0], &points, &[2])[1], v(10., 20.));
+}
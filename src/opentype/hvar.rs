@@ -0,0 +1,191 @@
+use crate::{R, parsers::*};
+use crate::opentype::gvar::f2dot14;
+use nom::{
+    number::complete::{be_u8, be_i8, be_u16, be_i16, be_u24, be_u32, be_i32},
+    multi::count,
+    sequence::tuple,
+};
+
+/// Deltas of values (like advances) that are indexed by (outer, inner) pairs
+#[derive(Clone, Debug)]
+pub struct ItemVariationStore {
+    // (start, peak, end) for every axis of every region
+    regions: Vec<Vec<(f32, f32, f32)>>,
+    data: Vec<ItemVariationData>,
+}
+#[derive(Clone, Debug)]
+struct ItemVariationData {
+    region_indices: Vec<u16>,
+    // one row per item with one delta for each of the regions
+    deltas: Vec<Vec<i32>>,
+}
+impl ItemVariationStore {
+    /// The delta of item `inner` of the data `outer` at the normalized `coords`.
+    pub fn delta(&self, outer: u16, inner: u16, coords: &[f32]) -> f32 {
+        let data = match self.data.get(outer as usize) {
+            Some(data) => data,
+            None => return 0.0
+        };
+        let row = match data.deltas.get(inner as usize) {
+            Some(row) => row,
+            None => return 0.0
+        };
+        data.region_indices.iter().zip(row)
+            .map(|(&region, &delta)| match self.regions.get(region as usize) {
+                Some(axes) => region_scalar(axes, coords) * delta as f32,
+                None => 0.0
+            })
+            .sum()
+    }
+}
+
+fn region_scalar(axes: &[(f32, f32, f32)], coords: &[f32]) -> f32 {
+    let mut scalar = 1.0;
+    for (axis, &(start, peak, end)) in axes.iter().enumerate() {
+        let coord = coords.get(axis).cloned().unwrap_or(0.0);
+        // axes that do not take part, and invalid ranges
+        if peak == 0.0 || start > peak || peak > end || (start < 0.0 && end > 0.0) || coord == peak {
+            continue;
+        }
+        if coord <= start || coord >= end {
+            return 0.0;
+        }
+        scalar *= if coord < peak {
+            (coord - start) / (peak - start)
+        } else {
+            (end - coord) / (end - peak)
+        };
+    }
+    scalar
+}
+
+pub fn parse_item_variation_store(data: &[u8]) -> R<ItemVariationStore> {
+    let (i, _format) = be_u16(data)?;
+    let (i, region_list_offset) = be_u32(i)?;
+    let (i, data_count) = be_u16(i)?;
+    let (i, data_offsets) = count(be_u32, data_count as usize)(i)?;
+
+    let (r, axis_count) = be_u16(data.get(region_list_offset as usize ..).unwrap_or(&[]))?;
+    let (r, region_count) = be_u16(r)?;
+    let (_, regions) = count(count(tuple((f2dot14, f2dot14, f2dot14)), axis_count as usize), region_count as usize)(r)?;
+
+    let mut item_data = Vec::with_capacity(data_count as usize);
+    for offset in data_offsets {
+        let d = data.get(offset as usize ..).unwrap_or(&[]);
+        let (d, item_count) = be_u16(d)?;
+        let (d, word_delta_count) = be_u16(d)?;
+        let (d, region_index_count) = be_u16(d)?;
+        let (mut d, region_indices) = count(be_u16, region_index_count as usize)(d)?;
+
+        // the first `words` deltas are 16 bit (32 bit with long words), the others 8 bit (16 bit)
+        let long_words = word_delta_count & 0x8000 != 0;
+        let words = (word_delta_count & 0x7FFF) as usize;
+        let mut deltas = Vec::with_capacity(item_count as usize);
+        for _ in 0 .. item_count {
+            let mut row = Vec::with_capacity(region_index_count as usize);
+            for n in 0 .. region_index_count as usize {
+                let delta = match (n < words, long_words) {
+                    (true, true) => parse(&mut d, be_i32)?,
+                    (true, false) | (false, true) => parse(&mut d, be_i16)? as i32,
+                    (false, false) => parse(&mut d, be_i8)? as i32,
+                };
+                row.push(delta);
+            }
+            deltas.push(row);
+        }
+        item_data.push(ItemVariationData { region_indices, deltas });
+    }
+    Ok((i, ItemVariationStore { regions, data: item_data }))
+}
+
+/// Maps glyphs to (outer, inner) indices of an `ItemVariationStore`
+#[derive(Clone, Debug)]
+pub struct DeltaSetIndexMap {
+    entries: Vec<(u16, u16)>,
+}
+impl DeltaSetIndexMap {
+    /// Glyphs past the end of the map use the last entry.
+    pub fn get(&self, gid: u32) -> Option<(u16, u16)> {
+        self.entries.get(gid as usize).or_else(|| self.entries.last()).cloned()
+    }
+}
+
+pub fn parse_delta_set_index_map(data: &[u8]) -> R<DeltaSetIndexMap> {
+    let (i, format) = be_u8(data)?;
+    let (i, entry_format) = be_u8(i)?;
+    let (mut i, map_count) = match format {
+        0 => { let (i, n) = be_u16(i)?; (i, n as u32) }
+        _ => be_u32(i)?
+    };
+    let entry_size = ((entry_format & 0x30) >> 4) + 1;
+    let inner_bits = (entry_format & 0x0F) as u32 + 1;
+    let mut entries = Vec::with_capacity(map_count as usize);
+    for _ in 0 .. map_count {
+        let entry = match entry_size {
+            1 => parse(&mut i, be_u8)? as u32,
+            2 => parse(&mut i, be_u16)? as u32,
+            3 => parse(&mut i, be_u24)?,
+            _ => parse(&mut i, be_u32)?,
+        };
+        entries.push(((entry >> inner_bits) as u16, (entry & ((1 << inner_bits) - 1)) as u16));
+    }
+    Ok((i, DeltaSetIndexMap { entries }))
+}
+
+/// The horizontal metrics variations table
+#[derive(Clone, Debug)]
+pub struct Hvar {
+    store: ItemVariationStore,
+    advance_map: Option<DeltaSetIndexMap>,
+}
+impl Hvar {
+    /// The change of the advance of `gid` at the normalized `coords`.
+    ///
+    /// Without an advance mapping the glyph id is the inner index of the first item data.
+    pub fn advance_delta(&self, gid: u32, coords: &[f32]) -> f32 {
+        let (outer, inner) = match self.advance_map {
+            Some(ref map) => match map.get(gid) {
+                Some(idx) => idx,
+                None => return 0.0
+            },
+            None => (0, gid as u16)
+        };
+        self.store.delta(outer, inner, coords)
+    }
+}
+
+pub fn parse_hvar(data: &[u8]) -> R<Hvar> {
+    let (i, _major_version) = be_u16(data)?;
+    let (i, _minor_version) = be_u16(i)?;
+    let (i, store_offset) = be_u32(i)?;
+    let (i, advance_map_offset) = be_u32(i)?;
+    let (i, _lsb_map_offset) = be_u32(i)?;
+    let (i, _rsb_map_offset) = be_u32(i)?;
+    let (_, store) = parse_item_variation_store(data.get(store_offset as usize ..).unwrap_or(&[]))?;
+    let advance_map = match advance_map_offset {
+        0 => None,
+        off => Some(parse_delta_set_index_map(data.get(off as usize ..).unwrap_or(&[]))?.1)
+    };
+    Ok((i, Hvar { store, advance_map }))
+}
+
+#[test]
+fn test_hvar() {
+    let data = [
+        0, 1, 0, 0,  0, 0, 0, 20,  0, 0, 0, 0,  0, 0, 0, 0,  0, 0, 0, 0, // header, no mappings
+        0, 1,  0, 0, 0, 12,  0, 1,  0, 0, 0, 22, // store: region list and one item data
+        0, 1, 0, 1,  0, 0, 0x40, 0, 0x40, 0, // one axis, one region: 0 .. 1 peaking at 1
+        0, 3, 0, 1, 0, 1, 0, 0, // three items with one 16 bit delta of region 0
+        0, 10, 0, 20, 0xFF, 0xE2,
+    ];
+    let hvar = parse_hvar(&data).unwrap().1;
+    assert_eq!(hvar.advance_delta(0, &[1.0]), 10.);
+    assert_eq!(hvar.advance_delta(2, &[0.5]), -15.);
+    assert_eq!(hvar.advance_delta(1, &[0.0]), 0.);
+    assert_eq!(hvar.advance_delta(1, &[-0.5]), 0.);
+
+    // two bytes per entry, four inner bits: glyph 0 -> (0, 2), glyph 1 -> (0, 1)
+    let map = parse_delta_set_index_map(&[0, 0x13, 0, 2, 0, 2, 0, 1]).unwrap().1;
+    assert_eq!(map.get(0), Some((0, 2)));
+    assert_eq!(map.get(5), Some((0, 1)));
+}
@@ -0,0 +1,153 @@
+use crate::{R, parsers::*};
+use crate::opentype::{tag, Tag};
+use nom::{
+    number::complete::be_u16,
+    sequence::tuple,
+};
+
+/// The justification table
+#[derive(Clone, Debug)]
+pub struct Jstf {
+    pub scripts: Vec<(Tag, JstfScript)>,
+}
+impl Jstf {
+    pub fn script(&self, script: Tag) -> Option<&JstfScript> {
+        self.scripts.iter().find(|&&(tag, _)| tag == script).map(|(_, script)| script)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct JstfScript {
+    /// glyphs that may be inserted to lengthen a line (like the arabic kashida)
+    pub extender_glyphs: Vec<u16>,
+    pub default_language: Option<JstfLangSys>,
+    pub languages: Vec<(Tag, JstfLangSys)>,
+}
+impl JstfScript {
+    /// The justification data of the language, or the default if the language has none.
+    pub fn language(&self, lang: Tag) -> Option<&JstfLangSys> {
+        self.languages.iter().find(|&&(tag, _)| tag == lang).map(|(_, lang)| lang)
+            .or(self.default_language.as_ref())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct JstfLangSys {
+    /// to be tried in order, until the line has the desired length
+    pub priorities: Vec<JstfPriority>,
+}
+
+/// What to do at one priority level.
+///
+/// The JstfMax lookups (own GPOS lookups of the JSTF table) are not read.
+#[derive(Clone, Debug, Default)]
+pub struct JstfPriority {
+    pub shrinkage: JstfModifications,
+    pub extension: JstfModifications,
+}
+
+/// GSUB and GPOS lookup indices to enable or disable
+#[derive(Clone, Debug, Default)]
+pub struct JstfModifications {
+    pub enable_gsub: Vec<u16>,
+    pub disable_gsub: Vec<u16>,
+    pub enable_gpos: Vec<u16>,
+    pub disable_gpos: Vec<u16>,
+}
+
+fn lookup_list(data: &[u8], offset: u16) -> R<Vec<u16>> {
+    if offset == 0 {
+        return Ok((data, vec![]));
+    }
+    let (i, _) = offset_into(data, offset as usize)?;
+    let (i, count) = be_u16(i)?;
+    Ok((i, iterator_n(i, be_u16, count).collect()))
+}
+
+fn parse_priority(data: &[u8]) -> R<JstfPriority> {
+    let (i, offsets) = tuple((be_u16, be_u16, be_u16, be_u16, be_u16))(data)?;
+    let (shrink_enable_gsub, shrink_disable_gsub, shrink_enable_gpos, shrink_disable_gpos, _shrink_max) = offsets;
+    let (i, offsets) = tuple((be_u16, be_u16, be_u16, be_u16, be_u16))(i)?;
+    let (ext_enable_gsub, ext_disable_gsub, ext_enable_gpos, ext_disable_gpos, _ext_max) = offsets;
+
+    Ok((i, JstfPriority {
+        shrinkage: JstfModifications {
+            enable_gsub: lookup_list(data, shrink_enable_gsub)?.1,
+            disable_gsub: lookup_list(data, shrink_disable_gsub)?.1,
+            enable_gpos: lookup_list(data, shrink_enable_gpos)?.1,
+            disable_gpos: lookup_list(data, shrink_disable_gpos)?.1,
+        },
+        extension: JstfModifications {
+            enable_gsub: lookup_list(data, ext_enable_gsub)?.1,
+            disable_gsub: lookup_list(data, ext_disable_gsub)?.1,
+            enable_gpos: lookup_list(data, ext_enable_gpos)?.1,
+            disable_gpos: lookup_list(data, ext_disable_gpos)?.1,
+        },
+    }))
+}
+
+fn parse_lang_sys(data: &[u8]) -> R<JstfLangSys> {
+    let (i, count) = be_u16(data)?;
+    let mut priorities = Vec::with_capacity(count as usize);
+    for offset in iterator_n(i, be_u16, count) {
+        priorities.push(parse_priority(offset_into(data, offset as usize)?.1)?.1);
+    }
+    Ok((i, JstfLangSys { priorities }))
+}
+
+fn parse_script(data: &[u8]) -> R<JstfScript> {
+    let (i, extender_offset) = be_u16(data)?;
+    let (i, default_lang_offset) = be_u16(i)?;
+    let (i, lang_count) = be_u16(i)?;
+
+    let extender_glyphs = lookup_list(data, extender_offset)?.1;
+    let default_language = match default_lang_offset {
+        0 => None,
+        off => Some(parse_lang_sys(offset_into(data, off as usize)?.1)?.1)
+    };
+    let mut languages = Vec::with_capacity(lang_count as usize);
+    for (tag, offset) in iterator_n(i, tuple((tag, be_u16)), lang_count) {
+        languages.push((tag, parse_lang_sys(offset_into(data, offset as usize)?.1)?.1));
+    }
+    Ok((i, JstfScript { extender_glyphs, default_language, languages }))
+}
+
+pub fn parse_jstf(data: &[u8]) -> R<Jstf> {
+    let (i, _major_version) = be_u16(data)?;
+    let (i, _minor_version) = be_u16(i)?;
+    let (i, script_count) = be_u16(i)?;
+    let mut scripts = Vec::with_capacity(script_count as usize);
+    for (tag, offset) in iterator_n(i, tuple((tag, be_u16)), script_count) {
+        scripts.push((tag, parse_script(offset_into(data, offset as usize)?.1)?.1));
+    }
+    Ok((i, Jstf { scripts }))
+}
+
+#[test]
+fn test_jstf() {
+    let data = [
+        0, 1, 0, 0, 0, 1, // version, one script
+        b'a', b'r', b'a', b'b', 0, 12,
+        // script at 12
+        0, 6, 0, 10, 0, 0, // extenders at +6, default language at +10, no languages
+        0, 1, 0, 7, // one extender glyph: 7
+        // default language at 22
+        0, 1, 0, 4, // one priority at +4
+        // priority at 26
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // no shrinkage
+        0, 20, 0, 0, 0, 0, 0, 0, 0, 0, // extension: enable GSUB lookups at +20
+        0, 2, 0, 3, 0, 5,
+    ];
+    let jstf = parse_jstf(&data).unwrap().1;
+    let script = jstf.script(Tag(*b"arab")).unwrap();
+    assert_eq!(script.extender_glyphs, [7]);
+    let lang = script.language(Tag(*b"URD ")).unwrap();
+    assert_eq!(lang.priorities.len(), 1);
+    assert_eq!(lang.priorities[0].extension.enable_gsub, [3, 5]);
+    assert!(lang.priorities[0].shrinkage.enable_gpos.is_empty());
+
+    // a script past the end
+    let mut broken = data;
+    broken[11] = 200;
+    assert!(parse_jstf(&broken).is_err());
+}
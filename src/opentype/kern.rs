@@ -1,12 +1,15 @@
-use crate::{R, parsers::*, opentype::gpos::KernTable};
+use crate::{R, parsers::*, opentype::gpos::{KernTable, ClassKern}};
 use nom::{
     number::complete::{be_u8, be_u16, be_i16, be_u32},
     bytes::complete::take,
     sequence::tuple,
-    combinator::map,
+    multi::count,
+    error::{make_error, ErrorKind},
+    Err::Failure,
 };
 
-fn parse_kern_format0<'a>(i: &'a [u8], table: &mut KernTable) -> R<'a, ()> {
+// Values of several subtables add up, unless `replace` is set.
+fn parse_kern_format0<'a>(i: &'a [u8], table: &mut KernTable, replace: bool) -> R<'a, ()> {
     let (i, n_pairs) = be_u16(i)?;
     let (i, _search_range) = be_u16(i)?;
     let (i, _entry_selector) = be_u16(i)?;
@@ -14,34 +17,50 @@ fn parse_kern_format0<'a>(i: &'a [u8], table: &mut KernTable) -> R<'a, ()> {
     
     table.glyph_pairs.reserve(n_pairs as usize);
     for (left, right, kern) in iterator(i, tuple((be_u16, be_u16, be_i16))).take(n_pairs as usize) {
-        table.glyph_pairs.insert((left, right), kern);
+        let value = table.glyph_pairs.entry((left, right)).or_insert(0);
+        *value = if replace { kern } else { value.saturating_add(kern) };
     }
     Ok((i, ()))
 }
 
-fn parse_kern_format2<'a>(data: &'a [u8], table: &mut KernTable) -> R<'a, ()> {
-    let (i, _row_width) = be_u16(data)?;
+// The offsets of the class tables and the kerning array are from the start of the subtable (`data`),
+// whose header has `header_len` bytes. Left class values are offsets of rows, right class values offsets within a row.
+// Unlike format 0, class subtables do not add up: like GPOS class pairs, the first one that covers the left glyph is used.
+fn parse_kern_format2<'a>(data: &'a [u8], header_len: usize, table: &mut KernTable) -> R<'a, ()> {
+    let (i, _header) = take(header_len)(data)?;
+    let (i, row_width) = be_u16(i)?;
     let (i, left_class_table_off) = be_u16(i)?;
     let (i, right_class_table_off) = be_u16(i)?;
-    let (i, _array_off) = be_u16(i)?;
-    
-    let num_left = be_u16(&data[left_class_table_off as usize + 2 ..])?.1 as usize;
-    let num_right = be_u16(&data[right_class_table_off as usize + 2 ..])?.1 as usize;
-    table.glyph_pairs.reserve(num_left * num_right);
+    let (i, array_off) = be_u16(i)?;
+    if row_width < 2 {
+        return Ok((i, ()));
+    }
 
-    let class_table = |off| {
-        let (i, first_glyph) = be_u16(&data[off as usize ..])?;
+    // gid -> class value
+    let class_table = |off: u16| -> R<'a, Vec<(u16, u16)>> {
+        let (i, first_glyph) = be_u16(data.get(off as usize ..).unwrap_or(&[]))?;
         let (i, n_glyphs) = be_u16(i)?;
-        let (_, offsets) = take(n_glyphs as usize * 2)(i)?;
-        Ok((first_glyph ..).zip(iterator(offsets, map(be_u16, |n| n as usize))))
+        let (i, values) = count(be_u16, n_glyphs as usize)(i)?;
+        Ok((i, (first_glyph ..= u16::MAX).zip(values).collect()))
     };
-    for (left_gid, left_off) in class_table(left_class_table_off)? {
-        for (right_gid, right_off) in class_table(right_class_table_off)? {
-            let off = left_off + right_off;
-            let (_, kern) = be_i16(&data[off .. off + 2])?;
-            table.glyph_pairs.insert((left_gid, right_gid), kern);
+
+    let mut kern = ClassKern { class_2_count: row_width / 2, .. ClassKern::default() };
+    let mut num_rows = 0;
+    for (gid, value) in class_table(left_class_table_off)?.1 {
+        // glyphs that point before the array are not kerned
+        if value >= array_off {
+            let row = (value - array_off) / row_width;
+            num_rows = num_rows.max(row as usize + 1);
+            kern.coverage.insert(gid);
+            kern.first_classes.insert(gid, row);
         }
     }
+    for (gid, value) in class_table(right_class_table_off)?.1 {
+        kern.second_classes.insert(gid, value / 2);
+    }
+    let array = data.get(array_off as usize ..).unwrap_or(&[]);
+    kern.values = count(be_i16, num_rows * kern.class_2_count as usize)(array)?.1;
+    table.class_subtables.push(kern);
     Ok((i, ()))
 }
 
@@ -51,31 +70,123 @@ pub fn parse_kern(input: &[u8]) -> R<KernTable> {
     match version {
         0 => parse_kern_ms(input),
         1 => parse_kern_apple(input),
-        _ => panic!("stahp!")
+        _ => Err(Failure(make_error(input, ErrorKind::Verify)))
     }
 }
 
 pub fn parse_kern_apple(i: &[u8]) -> R<KernTable> {
-    let (_i, version) = be_u32(i)?;
-    assert_eq!(version, 0x00010000);
+    let (i, version) = be_u32(i)?;
+    if version != 0x00010000 {
+        return Err(Failure(make_error(i, ErrorKind::Verify)));
+    }
     
-    unimplemented!()
+    let mut table = KernTable::default();
+    let (mut i, n_tables) = be_u32(i)?;
+    for _ in 0 .. n_tables {
+        let subtable = i;
+        let (length, coverage, _tuple_index) = parse(&mut i, tuple((be_u32, be_u16, be_u16)))?;
+        let data = parse(&mut i, take((length as usize).saturating_sub(8)))?;
+        let format = coverage & 0xFF;
+        debug!("format={}, coverage={:04x}", format, coverage);
+        // vertical (0x8000), cross-stream (0x4000) and variation (0x2000) subtables are not used
+        match (format, coverage & 0xE000) {
+            (0, 0) => parse_kern_format0(data, &mut table, false)?.1,
+            (2, 0) => parse_kern_format2(&subtable[.. 8 + data.len()], 8, &mut table)?.1,
+            _ => warn!("unsupported kern subtable format {} (coverage {:04x})", format, coverage)
+        }
+    }
+    Ok((i, table))
 }
 pub fn parse_kern_ms(i: &[u8]) -> R<KernTable> {
     let (i, version) = be_u16(i)?;
-    assert_eq!(version, 0);
+    if version != 0 {
+        return Err(Failure(make_error(i, ErrorKind::Verify)));
+    }
     
     let mut table = KernTable::default();
     let (mut i, n_tables) = be_u16(i)?;
     for _ in 0 .. n_tables {
+        let subtable = i;
         let (_version, length, format, coverage) = parse(&mut i, tuple((be_u16, be_u16, be_u8, be_u8)))?;
         debug!("format={}, coverage={:02x}", format, coverage);
-        let data = parse(&mut i, take(length as usize - 6))?;
-        match (format, coverage) {
-            (0, 0x01) => parse_kern_format0(data, &mut table)?.1,
-            (2, 0x01) => parse_kern_format2(data, &mut table)?.1,
-            (f, _) => panic!("invalid kern subtable format {}", f)
+        let data = parse(&mut i, take((length as usize).saturating_sub(6)))?;
+        // horizontal (bit 0) values that are neither minimums (bit 1) nor cross-stream (bit 2).
+        // Bit 3 makes the values replace those of earlier subtables.
+        match (format, coverage & 0x07) {
+            (0, 0x01) => parse_kern_format0(data, &mut table, coverage & 0x08 != 0)?.1,
+            (2, 0x01) => parse_kern_format2(&subtable[.. 6 + data.len()], 6, &mut table)?.1,
+            (f, _) => warn!("unsupported kern subtable format {} (coverage {:02x})", f, coverage)
         }
     }
     Ok((i, table))
 }
+
+#[test]
+fn test_kern_format2() {
+    let data = [
+        0, 0, 0, 1, // version 0, one subtable
+        0, 0, 0, 42, 2, 0x01, // format 2, horizontal
+        0, 6, 0, 14, 0, 22, 0, 30, // three columns, class tables and array
+        0, 10, 0, 2, 0, 30, 0, 36, // left: glyphs 10 and 11 in rows 0 and 1
+        0, 20, 0, 2, 0, 2, 0, 4, // right: glyphs 20 and 21 in columns 1 and 2
+        0, 0, 0xFF, 0xF6, 0xFF, 0xEC, // 0 -10 -20
+        0, 0, 0, 30, 0, 40, // 0 30 40
+    ];
+    let table = parse_kern(&data).unwrap().1;
+    assert_eq!(table.get(10, 20), Some(-10));
+    assert_eq!(table.get(10, 21), Some(-20));
+    assert_eq!(table.get(11, 20), Some(30));
+    assert_eq!(table.get(11, 21), Some(40));
+    assert_eq!(table.get(10, 5), Some(0));
+    assert_eq!(table.get(12, 20), None);
+
+    // the left class table starts at the largest glyph id
+    let mut data = data;
+    data[18 .. 20].copy_from_slice(&[0xFF, 0xFF]);
+    let table = parse_kern(&data).unwrap().1;
+    assert_eq!(table.get(0xFFFF, 20), Some(-10));
+    assert_eq!(table.get(10, 20), None);
+}
+
+#[test]
+fn test_kern_subtables() {
+    let data = [
+        0, 0, 0, 3, // version 0, three subtables
+        0, 0, 0, 20, 0, 0x01, 0, 1, 0, 6, 0, 0, 0, 0, // format 0, one pair
+        0, 10, 0, 20, 0xFF, 0xF6, // 10 20 -> -10
+        0, 0, 0, 26, 0, 0x01, 0, 2, 0, 12, 0, 1, 0, 0, // two pairs, added to the first subtable
+        0, 10, 0, 20, 0xFF, 0xFB, 0, 11, 0, 20, 0, 7,
+        0, 0, 0, 20, 0, 0x03, 0, 1, 0, 6, 0, 0, 0, 0, // minimum values
+        0, 10, 0, 20, 0, 100,
+    ];
+    let table = parse_kern(&data).unwrap().1;
+    assert_eq!(table.get(10, 20), Some(-15));
+    assert_eq!(table.get(11, 20), Some(7));
+}
+
+#[test]
+fn test_kern_apple() {
+    let data = [
+        0, 1, 0, 0, 0, 0, 0, 3, // version 1.0, three subtables
+        0, 0, 0, 22, 0, 0, 0, 0, 0, 1, 0, 6, 0, 0, 0, 0, // format 0, horizontal
+        0, 10, 0, 20, 0xFF, 0xF6,
+        0, 0, 0, 22, 0x80, 0, 0, 0, 0, 1, 0, 6, 0, 0, 0, 0, // vertical
+        0, 10, 0, 21, 0, 50,
+        0, 0, 0, 34, 0, 2, 0, 0, 0, 4, 0, 16, 0, 22, 0, 30, // format 2: two columns
+        0, 30, 0, 1, 0, 30, // left: glyph 30 in row 0
+        0, 40, 0, 2, 0, 0, 0, 2, // right: glyph 41 in column 1
+        0, 0, 0xFF, 0xE2, // 0 -30
+    ];
+    let table = parse_kern(&data).unwrap().1;
+    assert_eq!(table.get(10, 20), Some(-10));
+    assert_eq!(table.get(10, 21), None);
+    assert_eq!(table.get(30, 41), Some(-30));
+    assert_eq!(table.get(30, 40), Some(0));
+}
+
+#[test]
+fn test_kern_version() {
+    assert!(parse_kern(&[0, 2, 0, 0]).is_err());
+    assert!(parse_kern(&[0, 1, 0, 1, 0, 0, 0, 0]).is_err());
+    assert!(parse_kern_ms(&[0, 1, 0, 0]).is_err());
+}
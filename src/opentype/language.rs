@@ -0,0 +1,100 @@
+use crate::R;
+use crate::parsers::iterator_n;
+use nom::{
+    number::complete::{be_u16, be_u32},
+    sequence::tuple,
+};
+
+/// BCP-47 tags for the Windows language ids (LCIDs) that are common in fonts
+static WINDOWS_LANGUAGES: &[(u16, &str)] = &[
+    (0x0401, "ar-SA"), (0x0402, "bg-BG"), (0x0403, "ca-ES"), (0x0404, "zh-TW"),
+    (0x0405, "cs-CZ"), (0x0406, "da-DK"), (0x0407, "de-DE"), (0x0408, "el-GR"),
+    (0x0409, "en-US"), (0x040A, "es-ES"), (0x040B, "fi-FI"), (0x040C, "fr-FR"),
+    (0x040D, "he-IL"), (0x040E, "hu-HU"), (0x040F, "is-IS"), (0x0410, "it-IT"),
+    (0x0411, "ja-JP"), (0x0412, "ko-KR"), (0x0413, "nl-NL"), (0x0414, "nb-NO"),
+    (0x0415, "pl-PL"), (0x0416, "pt-BR"), (0x0418, "ro-RO"), (0x0419, "ru-RU"),
+    (0x041A, "hr-HR"), (0x041B, "sk-SK"), (0x041D, "sv-SE"), (0x041E, "th-TH"),
+    (0x041F, "tr-TR"), (0x0421, "id-ID"), (0x0422, "uk-UA"), (0x0424, "sl-SI"),
+    (0x0425, "et-EE"), (0x0426, "lv-LV"), (0x0427, "lt-LT"), (0x0429, "fa-IR"),
+    (0x042A, "vi-VN"), (0x0439, "hi-IN"), (0x043E, "ms-MY"), (0x0804, "zh-CN"),
+    (0x0807, "de-CH"), (0x0809, "en-GB"), (0x080A, "es-MX"), (0x080C, "fr-BE"),
+    (0x0810, "it-CH"), (0x0813, "nl-BE"), (0x0814, "nn-NO"), (0x0816, "pt-PT"),
+    (0x0C04, "zh-HK"), (0x0C07, "de-AT"), (0x0C09, "en-AU"), (0x0C0A, "es-ES"),
+    (0x0C0C, "fr-CA"), (0x1004, "zh-SG"), (0x1009, "en-CA"), (0x100C, "fr-CH"),
+    (0x1404, "zh-MO"), (0x1409, "en-NZ"), (0x1809, "en-IE"),
+];
+
+/// BCP-47 tags for the Macintosh language ids
+static MAC_LANGUAGES: &[&str] = &[
+    "en", "fr", "de", "it", "nl", "sv", "es", "da", "pt", "no", "he", "ja", "ar", "fi", "el", "is",
+    "mt", "tr", "hr", "zh-Hant", "ur", "hi", "th", "ko", "lt", "pl", "hu", "et", "lv", "se", "fo", "fa",
+    "ru", "zh-Hans", "nl-BE", "ga", "sq", "ro", "cs", "sk", "sl", "yi", "sr", "mk", "bg", "uk",
+];
+
+pub fn windows_language(id: u16) -> Option<&'static str> {
+    WINDOWS_LANGUAGES.iter().find(|&&(lcid, _)| lcid == id).map(|&(_, tag)| tag)
+}
+
+pub fn mac_language(id: u16) -> Option<&'static str> {
+    MAC_LANGUAGES.get(id as usize).cloned()
+}
+
+// characters 0x80 to 0xFF of the Mac OS Roman encoding
+static MAC_ROMAN: &str = "\
+    ÄÅÇÉÑÖÜáàâäãåçéèêëíìîïñóòôöõúùûü\
+    †°¢£§•¶ß®©™´¨≠ÆØ∞±≤≥¥µ∂∑∏π∫ªºΩæø\
+    ¿¡¬√ƒ≈∆«»…\u{A0}ÀÃÕŒœ–—“”‘’÷◊ÿŸ⁄€‹›ﬁﬂ\
+    ‡·‚„‰ÂÊÁËÈÍÎÏÌÓÔ\u{F8FF}ÒÚÛÙıˆ˜¯˘˙˚¸˝˛ˇ";
+
+/// Decode a string in the Mac OS Roman encoding (used by Macintosh platform name records).
+pub fn decode_mac_roman(data: &[u8]) -> String {
+    data.iter().map(|&b| match b {
+        0 ..= 0x7F => b as char,
+        _ => MAC_ROMAN.chars().nth(b as usize - 0x80).unwrap()
+    }).collect()
+}
+
+/// Compare two BCP-47 tags.
+///
+/// Returns 2 for an exact match, 1 if only the primary language agrees and 0 otherwise.
+pub fn language_match(a: &str, b: &str) -> u8 {
+    if a.eq_ignore_ascii_case(b) {
+        return 2;
+    }
+    let primary = |s: &str| s.split(|c| c == '-' || c == '_').next().unwrap_or("").to_ascii_lowercase();
+    if primary(a) == primary(b) { 1 } else { 0 }
+}
+
+/// The AAT language tag table. Unicode platform name records use an index into it as language id.
+pub fn parse_ltag(data: &[u8]) -> R<Vec<String>> {
+    let (i, _version) = be_u32(data)?;
+    let (i, _flags) = be_u32(i)?;
+    let (i, num_tags) = be_u32(i)?;
+    let tags = iterator_n(i, tuple((be_u16, be_u16)), num_tags as usize)
+        .map(|(offset, length)| {
+            data.get(offset as usize .. offset as usize + length as usize)
+                .and_then(|s| std::str::from_utf8(s).ok())
+                .unwrap_or("")
+                .to_owned()
+        })
+        .collect();
+    Ok((i, tags))
+}
+
+#[test]
+fn test_ltag() {
+    let mut data = vec![0, 0, 0, 1,  0, 0, 0, 0,  0, 0, 0, 2];
+    data.extend_from_slice(&[0, 20, 0, 2,  0, 22, 0, 7]);
+    data.extend_from_slice(b"enzh-Hant");
+    let (_, tags) = parse_ltag(&data).unwrap();
+    assert_eq!(tags, ["en", "zh-Hant"]);
+    assert_eq!(language_match("zh-hant", &tags[1]), 2);
+    assert_eq!(language_match("en-GB", windows_language(0x409).unwrap()), 1);
+    assert_eq!(mac_language(11), Some("ja"));
+}
+
+#[test]
+fn test_mac_roman() {
+    assert_eq!(MAC_ROMAN.chars().count(), 128);
+    assert_eq!(decode_mac_roman(b"Caf\x8E \xA9"), "Café ©");
+}
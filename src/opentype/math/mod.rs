@@ -3,9 +3,10 @@
 use std::collections::{HashMap, HashSet};
 use crate::R;
 use crate::opentype::coverage_table;
-use crate::parsers::{Parser, FixedSize, array_iter};
+use crate::parsers::{Parser, FixedSize, array_iter, offset_into};
 
 use nom::number::complete::{be_i16, be_u16};
+use nom::error::{make_error, ErrorKind};
 pub mod assembly;
 
 pub fn parse_math(data: &[u8]) -> R<MathHeader> {
@@ -35,7 +36,7 @@ macro_rules! parse_field {
     ($start:expr, $input:expr, ?$ptr:ident $parser:ident, $field:expr) => ({
         let (i, offset) = <$ptr as Parser>::parse($input)?;
         if offset != 0 {
-            let data = &$start[offset as usize ..];
+            let data = offset_into($start, offset as usize)?.1;
             let (_, value) = <$parser as Parser>::parse(data)?;
             Ok((i, value))
         } else {
@@ -44,9 +45,11 @@ macro_rules! parse_field {
     });
     ($start:expr, $input:expr, @ $ptr:ident $parser:ident, $field:expr) => ({
         let (i, offset) = <$ptr as Parser>::parse($input)?;
-        assert_ne!(offset, 0, stringify!($field));
+        if offset == 0 {
+            return Err(nom::Err::Failure(make_error($input, ErrorKind::Verify)));
+        }
 
-        let data = &$start[offset as usize ..];
+        let data = offset_into($start, offset as usize)?.1;
         let (_, value) = <$parser as Parser>::parse(data)?;
         Ok((i, value))
     });
@@ -316,7 +319,7 @@ impl Parser for MathItalicsCorrectionInfo {
     type Output = MathItalicsCorrectionInfo;
     fn parse(data: &[u8]) -> R<Self> {
         let (i, italics_correction_coverage_offset) = be_u16(data)?;
-        let (_, italics_correction_coverage) = coverage_table(&data[italics_correction_coverage_offset as usize ..])?;
+        let (_, italics_correction_coverage) = coverage_table(offset_into(data, italics_correction_coverage_offset as usize)?.1)?;
         let (i, italics_correction_count) = be_u16(i)?;
         let (i, italics_correction) = array_iter::<MathValueRecord>(i, italics_correction_count as usize)?;
         let map = italics_correction_coverage.zip(italics_correction).collect();
@@ -337,7 +340,7 @@ impl Parser for MathTopAccentAttachment {
     type Output = MathTopAccentAttachment;
     fn parse(data: &[u8]) -> R<Self> {
         let (i, top_accent_coverage_offset) = be_u16(data)?;
-        let (_, top_accent_coverage) = coverage_table(&data[top_accent_coverage_offset as usize ..])?;
+        let (_, top_accent_coverage) = coverage_table(offset_into(data, top_accent_coverage_offset as usize)?.1)?;
         let (i, top_accent_attachment_count) = be_u16(i)?;
         let (i, top_accent_attachment) = array_iter::<MathValueRecord>(i, top_accent_attachment_count as usize)?;
         let map = top_accent_coverage.zip(top_accent_attachment).collect();
@@ -412,7 +415,7 @@ impl Parser for MathGlyphConstruction {
         let (i, glyph_assembly_offset) = be_u16(data)?;
         let glyph_assembly = match glyph_assembly_offset {
             0 => None,
-            off => Some(GlyphAssembly::parse(&data[off as usize ..])?.1)
+            off => Some(GlyphAssembly::parse(offset_into(data, off as usize)?.1)?.1)
         };
         
         let (i, variant_count) = be_u16(i)?;
@@ -443,21 +446,21 @@ impl Parser for MathVariants {
         let (i, vert_glyph_construction_offsets) = array_iter::<uint16>(i, vert_glyph_count as usize)?;
         let (i, horiz_glyph_construction_offsets) = array_iter::<uint16>(i, horiz_glyph_count as usize)?;
 
-        let vert_glyph_construction = if vert_glyph_coverage_offset != 0 {
-            let (_, vert_glyph_coverage) = coverage_table(&data[vert_glyph_coverage_offset as usize ..])?;
-            let vert_glyph_construction = vert_glyph_construction_offsets.map(|off| MathGlyphConstruction::parse(&data[off as usize ..]).unwrap().1);
-            vert_glyph_coverage.zip(vert_glyph_construction).collect()
-        } else {
-            HashMap::new()
-        };
+        let mut vert_glyph_construction = HashMap::new();
+        if vert_glyph_coverage_offset != 0 {
+            let (_, vert_glyph_coverage) = coverage_table(offset_into(data, vert_glyph_coverage_offset as usize)?.1)?;
+            for (gid, off) in vert_glyph_coverage.zip(vert_glyph_construction_offsets) {
+                vert_glyph_construction.insert(gid, MathGlyphConstruction::parse(offset_into(data, off as usize)?.1)?.1);
+            }
+        }
         
-        let horiz_glyph_construction = if horiz_glyph_coverage_offset != 0 {
-            let (_, horiz_glyph_coverage) = coverage_table(&data[horiz_glyph_coverage_offset as usize ..])?;
-            let horiz_glyph_construction = horiz_glyph_construction_offsets.map(|off| MathGlyphConstruction::parse(&data[off as usize ..]).unwrap().1);
-            horiz_glyph_coverage.zip(horiz_glyph_construction).collect()
-        } else {
-            HashMap::new()
-        };
+        let mut horiz_glyph_construction = HashMap::new();
+        if horiz_glyph_coverage_offset != 0 {
+            let (_, horiz_glyph_coverage) = coverage_table(offset_into(data, horiz_glyph_coverage_offset as usize)?.1)?;
+            for (gid, off) in horiz_glyph_coverage.zip(horiz_glyph_construction_offsets) {
+                horiz_glyph_construction.insert(gid, MathGlyphConstruction::parse(offset_into(data, off as usize)?.1)?.1);
+            }
+        }
         
         Ok((i, MathVariants {
             min_connector_overlap,
@@ -513,25 +516,27 @@ impl Parser for MathKernInfo {
         use itertools::Itertools;
 
         let (i, coverage_offset) = be_u16(data)?;
-        let (_, coverage) = coverage_table(&data[coverage_offset as usize ..])?;
+        let (_, coverage) = coverage_table(offset_into(data, coverage_offset as usize)?.1)?;
         let (i, kern_count) = be_u16(i)?;
         let (i, records) = array_iter::<uint16>(i, 4 * kern_count as usize)?;
 
-        let parse_kern = |off| if off > 0 {
-            MathKern::parse(&data[off as usize ..]).unwrap().1
-        } else {
-            MathKern::default()
-        };
-
-        let records = records.tuples().map(|(a, b, c, d)| {
-            MathKernInfoRecord {
-                top_right: parse_kern(a),
-                top_left: parse_kern(b),
-                bottom_right: parse_kern(c),
-                bottom_left: parse_kern(d),
+        // a missing kern table is empty
+        fn parse_kern(data: &[u8], off: u16) -> R<MathKern> {
+            match off {
+                0 => Ok((data, MathKern::default())),
+                off => MathKern::parse(offset_into(data, off as usize)?.1)
             }
-        });
-        let entries = coverage.zip(records).collect();
+        }
+
+        let mut entries = HashMap::new();
+        for (gid, (a, b, c, d)) in coverage.zip(records.tuples()) {
+            entries.insert(gid, MathKernInfoRecord {
+                top_right: parse_kern(data, a)?.1,
+                top_left: parse_kern(data, b)?.1,
+                bottom_right: parse_kern(data, c)?.1,
+                bottom_left: parse_kern(data, d)?.1,
+            });
+        }
 
         Ok((i, MathKernInfo { entries }))
     }
@@ -2,10 +2,13 @@
 
 use std::convert::TryInto;
 use std::collections::HashMap;
-use std::ops::{Deref};
-use crate::{Font, R, IResultExt, VMetrics, HMetrics, Glyph, GlyphId, Name, FontInfo, FontType};
-use crate::truetype::{Shape, parse_shapes, get_outline};
-use crate::cff::{read_cff};
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+use crate::{Font, R, IResultExt, VMetrics, HMetrics, Glyph, GlyphId, Name, LocalizedName, FontInfo, FontType, ParseOptions, CaretSlope, GlyphCountReport, GlyphHints, LineMetrics, GlyphProps, Axis, BitmapGlyph, PositionedGlyph, FontError, GlyphError, Limit, check_limit, check_glyphs, warn_invalid};
+use crate::truetype::{Glyphs, check_shapes, metrics_glyph, OutlineCache, GlyfVariations};
+use crate::glyphname::gid_for_name_via_unicode;
+use crate::geometry::ink_top;
+use crate::cff::{read_cff, CffSegment, Charstrings, outline_segments};
 use pdf_encoding::Encoding;
 use crate::parsers::{*};
 use nom::{
@@ -14,8 +17,10 @@ use nom::{
     combinator::map,
     bytes::complete::take,
     sequence::tuple,
+    error::{make_error, ErrorKind},
 };
 use pathfinder_content::outline::{Outline};
+use pathfinder_color::ColorU;
 use pathfinder_geometry::{vector::Vector2F, transform2d::Transform2F, rect::RectF};
 use itertools::{Either};
 
@@ -29,18 +34,81 @@ pub mod kern;
 pub mod math;
 pub mod gdef;
 pub mod base;
+pub mod post;
+pub mod gvar;
+pub mod trak;
+pub mod os2;
+pub mod aat;
+pub mod prop;
+pub mod bsln;
+pub mod fvar;
+pub mod avar;
+pub mod hvar;
+pub mod colr;
+pub mod bitmap;
+pub mod jstf;
+pub mod language;
+pub mod zapf;
+pub mod graphite;
+pub mod shape;
 
 use math::{parse_math, MathHeader};
-use gpos::{parse_gpos, GPos};
+use gpos::{parse_gpos, GPos, EntryExit};
+use shape::ShapeStep;
 use gsub::{GSub, parse_gsub};
 use cmap::{CMap, parse_cmap};
 use gdef::{GDef, parse_gdef};
 use kern::parse_kern;
 use base::parse_base;
+use post::{Post, parse_post};
+use trak::{Trak, parse_trak};
+use os2::{Os2, parse_os2};
+use prop::{Prop, parse_prop};
+use bsln::{Bsln, parse_bsln};
+use fvar::{Fvar, parse_fvar};
+use colr::{Colr, Cpal, parse_colr, parse_cpal};
+use bitmap::{Sbix, Cbdt, parse_sbix, parse_cbdt};
+use jstf::{Jstf, parse_jstf};
+use language::parse_ltag;
+use zapf::{Zapf, parse_zapf};
+use graphite::{Feat, GraphiteFeature, parse_feat};
+
+// CFF charstrings are interpreted and glyf outlines parsed and composed when needed
+#[derive(Clone)]
+enum Outlines {
+    Cff(Charstrings),
+    Glyf(Glyphs, OutlineCache),
+}
+impl Outlines {
+    fn get(&self, gid: u32) -> Option<Outline> {
+        warn_invalid(self.try_get(gid))
+    }
+    fn try_get(&self, gid: u32) -> Result<Outline, GlyphError> {
+        match *self {
+            Outlines::Cff(ref charstrings) => charstrings.try_get(gid).map(|(outline, _, _, _)| outline),
+            Outlines::Glyf(ref glyphs, ref cache) => cache.try_outline(glyphs, gid),
+        }
+    }
+    // the glyph that has the metrics of `gid`, see `metrics_glyph`
+    fn metrics_gid(&self, gid: u32) -> u32 {
+        match *self {
+            Outlines::Cff(_) => gid,
+            Outlines::Glyf(ref glyphs, _) => metrics_glyph(glyphs, gid),
+        }
+    }
+    fn len(&self) -> usize {
+        match *self {
+            Outlines::Cff(ref charstrings) => charstrings.len(),
+            Outlines::Glyf(ref glyphs, _) => glyphs.len(),
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct OpenTypeFont {
-    outlines: Vec<Outline>,
+    outlines: Outlines,
+    // cid -> gid of CID-keyed CFF outlines
+    cid_map: Option<HashMap<u16, u16>>,
     pub gpos: Option<GPos>,
     pub cmap: Option<CMap>,
     hmtx: Option<Hmtx>,
@@ -48,18 +116,43 @@ pub struct OpenTypeFont {
     pub gsub: Option<GSub>,
     pub math: Option<MathHeader>,
     pub gdef: Option<GDef>,
+    pub post: Option<Post>,
+    pub trak: Option<Trak>,
+    pub os2: Option<Os2>,
+    pub prop: Option<Prop>,
+    pub bsln: Option<Bsln>,
+    pub fvar: Option<Fvar>,
+    // default glyf outlines and gvar deltas of variable fonts
+    variations: Option<Arc<GlyfVariations>>,
+    pub colr: Option<Colr>,
+    pub cpal: Option<Cpal>,
+    pub sbix: Option<Sbix>,
+    pub cbdt: Option<Cbdt>,
+    pub jstf: Option<Jstf>,
+    pub zapf: Option<Zapf>,
+    pub feat: Option<Feat>,
+    // has a `Silf` table
+    graphite: bool,
     vmetrics: Option<VMetrics>,
+    caret_slope: Option<CaretSlope>,
+    // macStyle of `head`
+    mac_style: Option<u16>,
 
     #[cfg(feature="svg")]
     svg:  Option<SvgTable>,
 
     font_matrix: Transform2F,
     num_glyphs: u32,
+    glyph_counts: GlyphCountReport,
     name: Name
 }
 impl OpenTypeFont {
     pub fn parse(data: &[u8]) -> Self {
-        let tables = parse_tables(data).get();
+        OpenTypeFont::parse_with_options(data, &ParseOptions::default())
+    }
+    pub fn parse_with_options(data: &[u8], options: &ParseOptions) -> Self {
+        let mut tables = parse_tables(data).get();
+        tables.retain_wanted(options);
         for (tag, _) in tables.entries() {
             debug!("tag: {:?} ({:?})", tag, std::str::from_utf8(&tag));
         }
@@ -68,8 +161,8 @@ impl OpenTypeFont {
     }
     pub fn info(data: &[u8]) -> FontInfo {
         let tables = parse_tables(data).get();
-        let name = tables.get(b"name").map(|data| parse_name(data).get()).unwrap_or_default();
-        let cmap = tables.get(b"cmap").map(|data| parse_cmap(data).get());
+        let name = read_name(&tables);
+        let cmap = tables.parse_optional(b"cmap", parse_cmap);
 
         FontInfo {
             name,
@@ -77,48 +170,105 @@ impl OpenTypeFont {
             codepoints: cmap.map(|cmap| cmap.codepoints(10)).unwrap_or_default(),
         }
     }
-    pub fn from_hmtx_glyf_and_tables(hmtx: Option<Hmtx>, glyf: Option<Vec<Shape>>, tables: Tables<impl Deref<Target=[u8]>>) -> Self {
-        let outlines: Vec<_>;
+    pub fn from_hmtx_glyf_and_tables(hmtx: Option<Hmtx>, glyf: Option<Glyphs>, tables: Tables<impl Deref<Target=[u8]>>) -> Self {
+        OpenTypeFont::try_from_hmtx_glyf_and_tables(hmtx, glyf, tables).unwrap_or_else(|e| panic!("{}", e))
+    }
+    /// Like `from_hmtx_glyf_and_tables`, but returns an error if the outlines or the tables they need are missing or broken.
+    ///
+    /// Broken tables that are not needed for the outlines are skipped.
+    pub fn try_from_hmtx_glyf_and_tables(hmtx: Option<Hmtx>, glyf: Option<Glyphs>, tables: Tables<impl Deref<Target=[u8]>>) -> Result<Self, FontError> {
+        let mut glyph_counts = glyph_count_report(&tables);
+        let outlines;
+        let mut variations = None;
         let font_matrix;
         let bbox;
-        if let Some(cff) = tables.get(b"CFF ") {
-            let slot = read_cff(cff).get().slot(0);
+        let mut cff_name = None;
+        let mut cid_map = None;
+        // a broken head table is an error even if the outlines do not need it
+        let head = match tables.contains(b"head") {
+            true => Some(tables.parse_required(b"head", parse_head)?),
+            false => None
+        };
+        if tables.contains(b"CFF ") {
+            let slot = tables.parse_required(b"CFF ", read_cff)?.try_slot(0)?;
+            glyph_counts.cff = Some(slot.num_glyphs() as u32);
+            cff_name = Some(slot.name());
+            cid_map = slot.cid_map()?;
             bbox = slot.bbox();
-            outlines = slot.outlines().map(|(outline, _, _)| outline).collect();
+            outlines = Outlines::Cff(slot.charstrings()?);
             font_matrix = slot.font_matrix();
         } else {
-            let head = parse_head(tables.get(b"head").expect("no head")).get();
+            let head = head.as_ref().ok_or_else(|| FontError::Parse("no head table".into()))?;
             bbox = Some(head.bbox());
             font_matrix = Transform2F::from_scale(Vector2F::splat(1.0 / head.units_per_em as f32));
-            outlines = glyf.map(|shapes| (0 .. shapes.len()).filter_map(|idx| get_outline(&shapes, idx as u32)).collect()).unwrap_or_default();
+            variations = hmtx.as_ref().and_then(|hmtx| GlyfVariations::from_tables(&tables, hmtx)).map(Arc::new);
+            outlines = Outlines::Glyf(glyf.unwrap_or_else(|| Glyphs::Shapes(vec![])), OutlineCache::default());
         }
 
         #[cfg(feature="svg")]
-        let svg = tables.get(b"SVG ").map(|data| parse_svg(data).unwrap().1);
+        let svg = tables.parse_optional(b"SVG ", parse_svg);
         
-        let maxp = tables.get(b"maxp").map(|data| parse_maxp(data).get());
+        let maxp = tables.parse_optional(b"maxp", parse_maxp);
         let num_glyphs = maxp.as_ref().map(|maxp| maxp.num_glyphs as u32).unwrap_or(outlines.len() as u32);
 
-        let gpos = if let Some(data) = tables.get(b"GPOS") {
-            let maxp = maxp.as_ref().expect("no maxp");
-            Some(parse_gpos(data, &maxp).get())
-        } else if let Some(data) = tables.get(b"kern") {
-            Some(GPos::from_kern(parse_kern(data).get()))
-        } else {
-            None
+        let mut gpos = match maxp {
+            Some(ref maxp) => tables.parse_optional(b"GPOS", |data| parse_gpos(data, maxp)),
+            None => None
         };
+        // the kern table is used if there is no GPOS table or it has no pair adjustments
+        if let Some(kern) = tables.parse_optional(b"kern", parse_kern) {
+            match gpos {
+                Some(ref mut gpos) if gpos.pair_adjustments.is_empty() => gpos.kern = kern,
+                Some(_) => {}
+                None => gpos = Some(GPos::from_kern(kern))
+            }
+        }
         
-        let gsub = tables.get(b"GSUB").map(|data| parse_gsub(data).get());
+        let gsub = tables.parse_optional(b"GSUB", parse_gsub);
         
-        let cmap = tables.get(b"cmap").map(|data| parse_cmap(data).get());
-        let math = tables.get(b"MATH").map(|data| parse_math(data).get());
-        let vmetrics = tables.get(b"hhea").map(|data| parse_hhea(data).get().into());
-        let name = tables.get(b"name").map(|data| parse_name(data).get()).unwrap_or_default();
-        let gdef = tables.get(b"gdef").map(|data| parse_gdef(data).get());
-        tables.get(b"BASE").map(|data| parse_base(data).get());
-
-        OpenTypeFont {
+        let cmap = tables.parse_optional(b"cmap", parse_cmap);
+        let math = tables.parse_optional(b"MATH", parse_math);
+        let hhea = tables.parse_optional(b"hhea", parse_hhea);
+        let caret_slope = hhea.as_ref().map(|hhea| hhea.caret_slope());
+        let hhea_vmetrics = hhea.map(|hhea| hhea.into());
+        let mut name = read_name(&tables);
+        // fonts without a name table still have the names of the CFF table
+        if let Some(cff_name) = cff_name {
+            name.postscript_name = name.postscript_name.or(cff_name.postscript_name);
+            name.full_name = name.full_name.or(cff_name.full_name);
+            name.family = name.family.or(cff_name.family);
+        }
+        let gdef = tables.parse_optional(b"gdef", parse_gdef);
+        tables.parse_optional(b"BASE", parse_base);
+        let post = tables.parse_optional(b"post", parse_post);
+        let trak = tables.parse_optional(b"trak", parse_trak);
+        let os2 = tables.parse_optional(b"OS/2", parse_os2);
+        let vmetrics = select_vmetrics(hhea_vmetrics, os2.as_ref());
+        let mac_style = head.map(|head| head.mac_style);
+        let prop = tables.parse_optional(b"prop", parse_prop);
+        let bsln = tables.parse_optional(b"bsln", parse_bsln);
+        let fvar = tables.parse_optional(b"fvar", parse_fvar);
+        let colr = tables.parse_optional(b"COLR", parse_colr);
+        let cpal = tables.parse_optional(b"CPAL", parse_cpal);
+        let sbix = tables.parse_optional(b"sbix", |data| parse_sbix(data, num_glyphs));
+        let cbdt = match (tables.get(b"CBLC"), tables.get(b"CBDT")) {
+            (Some(cblc), Some(cbdt)) => match parse_cbdt(cblc, cbdt) {
+                Ok((_, cbdt)) => Some(cbdt),
+                Err(_) => {
+                    warn!("skipping the invalid CBLC and CBDT tables");
+                    None
+                }
+            }
+            _ => None
+        };
+        let jstf = tables.parse_optional(b"JSTF", parse_jstf);
+        let zapf = tables.parse_optional(b"Zapf", |data| parse_zapf(data, num_glyphs));
+        let feat = tables.parse_optional(b"Feat", parse_feat);
+        let graphite = tables.contains(b"Silf");
+
+        Ok(OpenTypeFont {
             outlines,
+            cid_map,
             gpos,
             cmap,
             hmtx,
@@ -126,29 +276,72 @@ impl OpenTypeFont {
             gsub,
             math,
             gdef,
+            post,
+            trak,
+            os2,
+            prop,
+            bsln,
+            fvar,
+            variations,
+            colr,
+            cpal,
+            sbix,
+            cbdt,
+            jstf,
+            zapf,
+            feat,
+            graphite,
             vmetrics,
+            caret_slope,
+            mac_style,
 
             #[cfg(feature="svg")]
             svg,
 
             font_matrix,
             num_glyphs,
+            glyph_counts,
             name
-        }
+        })
     }
     pub fn from_tables<T>(tables: Tables<T>) -> Self where T: Deref<Target=[u8]> {
-        let head = parse_head(tables.get(b"head").expect("no head")).get();
-        let maxp = parse_maxp(tables.get(b"maxp").expect("no maxp")).get();
-        let hhea = parse_hhea(tables.get(b"hhea").expect("no hhea")).get();
-        
-        let glyf = tables.get(b"glyf").map(|data| {
-            let loca = parse_loca(tables.get(b"loca").expect("no loca"), &head, &maxp).get();
-            parse_shapes(&loca, data)
-        });
-        
-        let hmtx = tables.get(b"hmtx").map(|data| parse_hmtx(data, &hhea, &maxp).get());
-        
-        OpenTypeFont::from_hmtx_glyf_and_tables(hmtx, glyf, tables)
+        OpenTypeFont::try_from_tables(tables, &ParseOptions::default()).unwrap_or_else(|e| panic!("{}", e))
+    }
+    /// Like `from_tables`, but drops the tables `options` does not want, enforces its limits
+    /// and returns an error if the outlines or the tables they need are missing or broken.
+    pub fn try_from_tables<T>(mut tables: Tables<T>, options: &ParseOptions) -> Result<Self, FontError> where T: Deref<Target=[u8]> {
+        tables.retain_wanted(options);
+        check_glyph_count(&tables, options)?;
+        let glyf = parse_glyf(&tables)?;
+        if let Some(ref glyphs) = glyf {
+            check_shapes(glyphs, options)?;
+        }
+        let hmtx = parse_hmtx_table(&tables)?;
+        let font = OpenTypeFont::try_from_hmtx_glyf_and_tables(hmtx, glyf, tables)?;
+        check_glyphs(&font, options)?;
+        Ok(font)
+    }
+    /// The font at a point of the design space, given in user coordinates (like `wght` 700).
+    ///
+    /// Only `glyf` outlines are varied (by `gvar`, with `avar` and `HVAR`), see `TrueTypeFont::instance`.
+    /// Other fonts are returned unchanged.
+    pub fn instance(&self, coords: &[(Tag, f32)]) -> OpenTypeFont {
+        let mut font = self.clone();
+        if let (Some(fvar), Some(variations)) = (&self.fvar, &self.variations) {
+            let (shapes, hmtx) = variations.instance(&fvar.normalize(coords));
+            font.outlines = Outlines::Glyf(Glyphs::Shapes(shapes), OutlineCache::default());
+            font.hmtx = Some(hmtx);
+        }
+        font
+    }
+    /// The named instance (from `fvar`) with the given subfamily or PostScript name, like "Bold".
+    pub fn named_instance(&self, name: &str) -> Option<OpenTypeFont> {
+        let coords = self.fvar.as_ref()?.named_instance(&self.name, name)?;
+        Some(self.instance(&coords))
+    }
+    /// Move this font to the given user coordinates, like `instance`.
+    pub fn set_variation(&mut self, coords: &[(Tag, f32)]) {
+        *self = self.instance(coords);
     }
     pub fn glyph_metrics(&self, gid: u16) -> Option<HMetrics> {
         self.hmtx.as_ref().map(|hmtx| hmtx.metrics_for_gid(gid))
@@ -162,18 +355,43 @@ impl Font for OpenTypeFont {
         self.font_matrix
     }
     fn glyph(&self, gid: GlyphId) -> Option<Glyph> {
-        self.outlines.get(gid.0 as usize).map(|outline| {
+        warn_invalid(self.try_glyph(gid))
+    }
+    fn try_glyph(&self, gid: GlyphId) -> Result<Glyph, GlyphError> {
+        self.outlines.try_get(gid.0).map(|outline| {
             Glyph {
-                path: outline.clone(),
-                metrics: self.hmtx.as_ref().map(|m| m.metrics_for_gid(gid.0 as u16)).unwrap_or_default()
+                path: outline,
+                metrics: self.hmtx.as_ref().map(|m| m.metrics_for_gid(self.outlines.metrics_gid(gid.0) as u16)).unwrap_or_default()
             }
         })
     }
 
+    fn num_contours(&self, gid: GlyphId) -> Option<i16> {
+        match self.outlines {
+            Outlines::Glyf(ref glyphs, _) => glyphs.num_contours(gid.0),
+            Outlines::Cff(_) => self.outlines.get(gid.0).map(|outline| outline.contours().len() as i16)
+        }
+    }
+
     #[cfg(feature="svg")]
     fn svg_glyph(&self, gid: GlyphId) -> Option<&SvgGlyph> {
         self.svg.as_ref().and_then(|svg| svg.glyphs.get(&(gid.0 as u16)))
     }
+    fn glyph_hints(&self, gid: GlyphId) -> Option<GlyphHints> {
+        match self.outlines {
+            Outlines::Cff(ref charstrings) => charstrings.get(gid.0).map(|(_, _, _, hints)| hints),
+            Outlines::Glyf(..) => None
+        }
+    }
+    fn cff_glyph_segments(&self, gid: GlyphId) -> Option<Vec<CffSegment>> {
+        match self.outlines {
+            Outlines::Cff(ref charstrings) => charstrings.get(gid.0).map(|(outline, _, _, _)| outline_segments(&outline)),
+            Outlines::Glyf(..) => None
+        }
+    }
+    fn glyph_count_report(&self) -> GlyphCountReport {
+        self.glyph_counts.clone()
+    }
     fn gid_for_codepoint(&self, codepoint: u32) -> Option<GlyphId> {
         self.gid_for_unicode_codepoint(codepoint)
     }
@@ -183,8 +401,20 @@ impl Font for OpenTypeFont {
             None => None
         }
     }
-    fn gid_for_name(&self, _name: &str) -> Option<GlyphId> {
-        None
+    fn gid_for_name(&self, name: &str) -> Option<GlyphId> {
+        self.post.as_ref().and_then(|post| post.gid_for_name(name))
+            .or_else(|| self.zapf.as_ref().and_then(|zapf| zapf.gid_for_name(name)))
+            .map(|gid| GlyphId(gid as u32))
+            .or_else(|| gid_for_name_via_unicode(self, name))
+    }
+    fn gid_for_cid(&self, cid: u32) -> Option<GlyphId> {
+        if cid > 0xFFFF {
+            return None;
+        }
+        self.cid_map.as_ref()?.get(&(cid as u16)).map(|&gid| GlyphId(gid as u32))
+    }
+    fn cmap(&self) -> Option<&CMap> {
+        self.cmap.as_ref()
     }
     fn encoding(&self) -> Option<Encoding> {
         None
@@ -195,14 +425,168 @@ impl Font for OpenTypeFont {
     fn vmetrics(&self) -> Option<VMetrics> {
         self.vmetrics
     }
+    fn caret_slope(&self) -> Option<CaretSlope> {
+        self.caret_slope
+    }
+    fn weight(&self) -> Option<u16> {
+        font_weight(self.os2.as_ref(), self.mac_style)
+    }
+    fn is_italic(&self) -> bool {
+        font_is_italic(self.os2.as_ref(), self.mac_style)
+    }
+    fn is_graphite(&self) -> bool {
+        self.graphite
+    }
+    fn graphite_features(&self) -> &[GraphiteFeature] {
+        self.feat.as_ref().map(|feat| &feat.features[..]).unwrap_or_default()
+    }
+    fn advance_is_explicit(&self, gid: GlyphId) -> bool {
+        self.hmtx.as_ref().map_or(false, |hmtx| hmtx.is_explicit(gid.0 as u16))
+    }
+    fn cap_height(&self) -> Option<f32> {
+        match self.os2.as_ref().and_then(|os2| os2.cap_height) {
+            Some(h) if h > 0 => Some(h as f32),
+            _ => ink_top(self, 'H')
+        }
+    }
+    fn x_height(&self) -> Option<f32> {
+        match self.os2.as_ref().and_then(|os2| os2.x_height) {
+            Some(h) if h > 0 => Some(h as f32),
+            _ => ink_top(self, 'x')
+        }
+    }
+    fn underline_metrics(&self) -> Option<LineMetrics> {
+        self.post.as_ref().map(|post| post.underline())
+    }
+    fn strikeout_metrics(&self) -> Option<LineMetrics> {
+        self.os2.as_ref().map(|os2| os2.strikeout())
+    }
+    fn glyph_properties(&self, gid: GlyphId) -> Option<GlyphProps> {
+        self.prop.as_ref().map(|prop| prop.get(gid.0 as u16))
+    }
+    fn aat_baseline(&self, gid: GlyphId) -> Option<f32> {
+        self.bsln.as_ref()?.delta(gid.0 as u16).map(|delta| delta as f32)
+    }
+    fn variation_axes(&self) -> Vec<Axis> {
+        self.fvar.as_ref().map(|fvar| fvar.axes.clone()).unwrap_or_default()
+    }
+    fn num_named_instances(&self) -> usize {
+        self.fvar.as_ref().map(|fvar| fvar.instances.len()).unwrap_or(0)
+    }
+    fn color_glyph_layers(&self, gid: GlyphId, palette: usize) -> Option<Vec<(GlyphId, Option<ColorU>)>> {
+        let layers = self.colr.as_ref()?.layers(gid.0 as u16)?;
+        Some(layers.map(|(gid, entry)| {
+            let color = entry.and_then(|entry| self.cpal.as_ref()?.color(palette, entry));
+            (GlyphId(gid as u32), color)
+        }).collect())
+    }
+    fn bitmap_glyph(&self, gid: GlyphId, ppem: u16) -> Option<BitmapGlyph> {
+        match (&self.sbix, &self.cbdt) {
+            (Some(sbix), _) => sbix.glyph(gid.0, ppem),
+            (None, Some(cbdt)) => cbdt.glyph(gid.0, ppem),
+            (None, None) => None
+        }
+    }
+    fn tracking(&self, size: f32) -> Option<f32> {
+        self.trak.as_ref()?.horizontal.as_ref()?.tracking(0.0, size)
+    }
+    fn advance(&self, gid: GlyphId) -> Option<f32> {
+        if gid.0 >= self.num_glyphs {
+            return None;
+        }
+        match self.hmtx {
+            Some(ref hmtx) => Some(hmtx.metrics_for_gid(self.outlines.metrics_gid(gid.0) as u16).advance),
+            None => Some(0.0)
+        }
+    }
+    fn positioned_advance(&self, gid: GlyphId, features: &[Tag]) -> f32 {
+        let advance = self.advance(gid).unwrap_or(0.0);
+        match self.gpos {
+            Some(ref gpos) => advance + gpos.single_advance(gid.0 as u16, features) as f32,
+            None => advance
+        }
+    }
+    fn apply_substitutions(&self, glyphs: &mut Vec<(GlyphId, Range<usize>)>, features: &[Tag]) -> bool {
+        self.trace_substitutions(glyphs, features, &mut |_, _, _, _| {})
+    }
+    fn trace_substitutions(&self, glyphs: &mut Vec<(GlyphId, Range<usize>)>, features: &[Tag], record: &mut dyn FnMut(Tag, u16, bool, &[(GlyphId, Range<usize>)])) -> bool {
+        let gsub = match self.gsub {
+            Some(ref gsub) => gsub,
+            None => return false
+        };
+        let mut applied = false;
+        for (feature, idx) in gsub.feature_lookups(features) {
+            let fired = gsub::apply_lookup(gsub.lookup(idx), glyphs);
+            record(feature, idx, fired, glyphs);
+            applied |= fired;
+        }
+        applied
+    }
+    fn position_glyphs(&self, glyphs: &mut [PositionedGlyph], features: &[Tag], record: &mut dyn FnMut(ShapeStep, bool, &[PositionedGlyph])) {
+        for glyph in glyphs.iter_mut() {
+            glyph.advance = Vector2F::new(self.advance(glyph.gid).unwrap_or(0.0), 0.0);
+        }
+        if let Some(ref gpos) = self.gpos {
+            for step in gpos.adjustment_steps(features) {
+                let fired = gpos.apply_adjustment(step, glyphs);
+                record(step, fired, glyphs);
+            }
+        }
+    }
     fn kerning(&self, left: GlyphId, right: GlyphId) -> f32 {
-        self.gpos.as_ref().and_then(|gpos| gpos.kern.get(left.0 as u16, right.0 as u16).map(|k| k as f32)).unwrap_or(0.0)
+        self.gpos.as_ref().and_then(|gpos| gpos.kerning(left.0 as u16, right.0 as u16).map(|k| k as f32)).unwrap_or(0.0)
+    }
+    fn vertical_kerning(&self, top: GlyphId, bottom: GlyphId) -> f32 {
+        self.gpos.as_ref().and_then(|gpos| gpos.vertical_kern.get(top.0 as u16, bottom.0 as u16).map(|k| k as f32)).unwrap_or(0.0)
+    }
+    fn cursive_anchors(&self, gid: GlyphId) -> Option<EntryExit> {
+        self.gpos.as_ref()?.cursive.get(&(gid.0 as u16)).cloned()
+    }
+    fn mark_attachment(&self, base: GlyphId, mark: GlyphId) -> Option<Vector2F> {
+        let gpos = self.gpos.as_ref()?;
+        let (x, y) = gpos.get_mark_to_base(base, mark).or_else(|| gpos.get_mark_to_mark(base, mark))?;
+        Some(Vector2F::new(x as f32, y as f32))
     }
     fn name(&self) -> &Name {
         &self.name
     }
 }
 
+// the glyphs of the glyf table, if there is one
+fn parse_glyf(tables: &Tables<impl Deref<Target=[u8]>>) -> Result<Option<Glyphs>, FontError> {
+    let data = match tables.get(b"glyf") {
+        Some(data) => data,
+        None => return Ok(None)
+    };
+    let head = tables.parse_required(b"head", parse_head)?;
+    let maxp = tables.parse_required(b"maxp", parse_maxp)?;
+    let loca = tables.parse_required(b"loca", |i| parse_loca(i, &head, &maxp))?;
+    Ok(Some(Glyphs::from_glyf(&loca, data)?))
+}
+// the hmtx table, if there is one
+fn parse_hmtx_table(tables: &Tables<impl Deref<Target=[u8]>>) -> Result<Option<Hmtx>, FontError> {
+    if !tables.contains(b"hmtx") {
+        return Ok(None);
+    }
+    let maxp = tables.parse_required(b"maxp", parse_maxp)?;
+    let hhea = tables.parse_required(b"hhea", parse_hhea)?;
+    Ok(Some(tables.parse_required(b"hmtx", |i| parse_hmtx(i, &hhea, &maxp))?))
+}
+
+/// Check the number of glyphs (of `maxp` and CFF outlines) against the limit of `options`.
+pub fn check_glyph_count(tables: &Tables<impl Deref<Target=[u8]>>, options: &ParseOptions) -> Result<(), FontError> {
+    if options.max_glyphs.is_none() {
+        return Ok(());
+    }
+    let report = glyph_count_report(tables);
+    check_limit(options.max_glyphs, report.maxp.unwrap_or(0), Limit::Glyphs)?;
+    if let Some(cff) = tables.get(b"CFF ") {
+        let cff = read_cff(cff).map_err(|_| FontError::Truncated)?.1;
+        check_limit(options.max_glyphs, cff.try_slot(0)?.num_glyphs() as u32, Limit::Glyphs)?;
+    }
+    Ok(())
+}
+
 pub struct Tables<T> {
     // (tag, data)
     pub entries: HashMap<[u8; 4], T>
@@ -220,28 +604,302 @@ impl<T: Deref<Target=[u8]>> Tables<T> {
     pub fn contains(&self, tag: &[u8; 4]) -> bool {
         self.entries.contains_key(tag)
     }
+    /// remove all tables that `options` asks to skip
+    pub fn retain_wanted(&mut self, options: &ParseOptions) {
+        self.entries.retain(|tag, _| options.wants_table(tag));
+    }
+    /// Parse a table the font can not do without. A missing or broken table is an error.
+    pub fn parse_required<'a, U>(&'a self, tag: &[u8; 4], parser: impl FnOnce(&'a [u8]) -> R<'a, U>) -> Result<U, FontError> {
+        let data = self.get(tag).ok_or_else(|| FontError::Parse(format!("no {} table", String::from_utf8_lossy(tag))))?;
+        match parser(data) {
+            Ok((_, table)) => Ok(table),
+            Err(_) => Err(FontError::Parse(format!("invalid {} table", String::from_utf8_lossy(tag))))
+        }
+    }
+    /// Parse a table the font can do without. A broken table is skipped with a warning.
+    pub fn parse_optional<'a, U>(&'a self, tag: &[u8; 4], parser: impl FnOnce(&'a [u8]) -> R<'a, U>) -> Option<U> {
+        match parser(self.get(tag)?) {
+            Ok((_, table)) => Some(table),
+            Err(_) => {
+                warn!("skipping the invalid {} table", String::from_utf8_lossy(tag));
+                None
+            }
+        }
+    }
 }
 // (tag, content)
 pub fn parse_tables(data: &[u8]) -> R<Tables<&[u8]>> {
-    let (i, _magic) = take(4usize)(data)?; 
+    parse_tables_at(data, 0)
+}
+
+/// Parse the table directory that starts at `offset`.
+///
+/// Table offsets are relative to the start of `data`, as in font collections.
+pub fn parse_tables_at(data: &[u8], offset: usize) -> R<Tables<&[u8]>> {
+    let directory = data.get(offset ..).unwrap_or_default();
+    let (i, _magic) = take(4usize)(directory)?;
     let (i, num_tables) = be_u16(i)?;
     let (i, _search_range) = be_u16(i)?;
     let (i, _entry_selector) = be_u16(i)?;
     let (mut i, _range_shift) = be_u16(i)?;
     
+    // tables are looked up by tag in a map, so the order of the directory does not matter.
+    // it should still be sorted though, and other parsers may rely on it.
     let mut entries = HashMap::with_capacity(num_tables as usize);
+    let mut last_tag: Option<&[u8]> = None;
     for _ in 0 .. num_tables {
         let (tag, _, off, len) = parse(&mut i, tuple((take(4usize), be_u32, be_u32, be_u32)))?;
-        entries.insert(
-            tag.try_into().expect("slice too short"),
-            data.get(off as usize .. off as usize + len as usize).expect("out of bounds")
-        );
+        if let Some(last) = last_tag {
+            if last >= tag {
+                warn!("table directory is not sorted: {:?} after {:?}", String::from_utf8_lossy(tag), String::from_utf8_lossy(last));
+            }
+        }
+        last_tag = Some(tag);
+        let table = match data.get(off as usize .. off as usize + len as usize) {
+            Some(table) => table,
+            None => return Err(nom::Err::Failure(make_error(i, ErrorKind::Eof)))
+        };
+        entries.insert(tag.try_into().expect("slice too short"), table);
         debug!("tag: {:?} ({:?})", tag, std::str::from_utf8(&tag));
     }
     
     Ok((i, Tables { entries }))
 }
 
+#[test]
+fn test_unsorted_table_directory() {
+    let mut data = vec![
+        0, 1, 0, 0, // magic
+        0, 2, 0, 0, 0, 0, 0, 0, // two tables
+    ];
+    data.extend_from_slice(b"name\0\0\0\0\0\0\0\x2c\0\0\0\x04");
+    data.extend_from_slice(b"head\0\0\0\0\0\0\0\x30\0\0\0\x02");
+    data.extend_from_slice(b"NAMEHD");
+
+    let tables = parse_tables(&data).get();
+    assert_eq!(tables.get(b"name"), Some(&b"NAME"[..]));
+    assert_eq!(tables.get(b"head"), Some(&b"HD"[..]));
+    assert_eq!(tables.get(b"glyf"), None);
+}
+
+// assemble an sfnt file from the given tables (for tests)
+#[cfg(test)]
+pub(crate) fn build_sfnt(magic: &[u8; 4], tables: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+    let mut data = magic.to_vec();
+    data.extend_from_slice(&(tables.len() as u16).to_be_bytes());
+    data.extend_from_slice(&[0; 6]);
+    let mut offset = 12 + 16 * tables.len();
+    for &(tag, table) in tables {
+        data.extend_from_slice(tag);
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&(offset as u32).to_be_bytes());
+        data.extend_from_slice(&(table.len() as u32).to_be_bytes());
+        offset += table.len();
+    }
+    for &(_, table) in tables {
+        data.extend_from_slice(table);
+    }
+    data
+}
+
+// a CFF based font with one square glyph (and .notdef)
+#[cfg(test)]
+pub(crate) fn test_otto_font() -> Vec<u8> {
+    let cff = [
+        1, 0, 4, 1, // header
+        0, 1, 1, 1, 2, b'A', // name index
+        0, 1, 1, 1, 6, 24 + 139, 17, 139, 139, 18, // top dict: CharStrings at 24, empty Private
+        0, 0, // string index
+        0, 0, // global subroutines
+        0, 2, 1, 1, 2, 15, // CharStrings index
+        14, // .notdef: endchar
+        239, 239, 21, 248, 136, 6, 248, 136, 7, 252, 136, 6, 14, // 100 100 rmoveto 500 hlineto 500 vlineto -500 hlineto endchar
+    ];
+    let mut head = vec![0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0x5F, 0x0F, 0x3C, 0xF5, 0, 0, 3, 232];
+    head.extend_from_slice(&[0; 16]); // created, modified
+    head.extend_from_slice(&[0, 0, 0, 0, 3, 232, 3, 232]); // bbox
+    head.extend_from_slice(&[0; 10]);
+    let mut hhea = vec![0, 1, 0, 0, 3, 32, 255, 56, 0, 0];
+    hhea.extend_from_slice(&[0; 24]);
+    hhea.extend_from_slice(&[0, 2]); // numberOfHMetrics
+    let maxp = [0, 0, 0x50, 0, 0, 2];
+    let hmtx = [0, 250, 0, 0, 2, 88, 0, 100];
+
+    build_sfnt(b"OTTO", &[(b"CFF ", &cff), (b"head", &head), (b"hhea", &hhea), (b"hmtx", &hmtx), (b"maxp", &maxp)])
+}
+
+#[test]
+fn test_class_def() {
+    // format 1, starting at the largest glyph id: the glyphs after it are dropped
+    let mut classes = HashMap::new();
+    parse_class_def(&[0, 1, 0xFF, 0xFF, 0, 2, 0, 3, 0, 4], &mut classes).unwrap();
+    assert_eq!(classes.len(), 1);
+    assert_eq!(classes[&0xFFFF], 3);
+}
+
+#[test]
+fn test_otto_glyph() {
+    let data = test_otto_font();
+    let font = OpenTypeFont::parse(&data);
+    assert_eq!(font.num_glyphs(), 2);
+
+    let glyph = font.glyph(GlyphId(1)).unwrap();
+    assert_eq!(glyph.path.contours().len(), 1);
+    assert_eq!(glyph.path.bounds(), RectF::from_points(Vector2F::new(100., 100.), Vector2F::new(600., 600.)));
+    assert_eq!(glyph.metrics.advance, 600.);
+}
+
+#[test]
+fn test_cff_glyph_segments() {
+    use crate::cff::CffSegment::*;
+    let font = OpenTypeFont::parse(&test_otto_font());
+    assert_eq!(font.cff_glyph_segments(GlyphId(1)).unwrap(), vec![
+        MoveTo(Vector2F::new(100., 100.)),
+        LineTo(Vector2F::new(600., 100.)),
+        LineTo(Vector2F::new(600., 600.)),
+        LineTo(Vector2F::new(100., 600.)),
+        ClosePath,
+    ]);
+    assert_eq!(font.cff_glyph_segments(GlyphId(0)), Some(vec![]));
+}
+
+#[test]
+fn test_advance() {
+    let font = OpenTypeFont::parse(&test_otto_font());
+    assert_eq!(font.advance(GlyphId(0)), Some(250.));
+    assert_eq!(font.advance(GlyphId(1)), Some(600.));
+    assert_eq!(font.advance(GlyphId(2)), None);
+    assert_eq!(font.positioned_advance(GlyphId(1), &[]), 600.);
+}
+
+#[test]
+fn test_glyph_pixel_bounds() {
+    let font = OpenTypeFont::parse(&test_otto_font());
+    let bounds = font.glyph_pixel_bounds(GlyphId(1), 10.).unwrap();
+    assert!((bounds.origin() - Vector2F::new(1., -6.)).length() < 1e-4);
+    assert!((bounds.lower_right() - Vector2F::new(6., -1.)).length() < 1e-4);
+    assert!(font.glyph_pixel_bounds(GlyphId(2), 10.).is_none());
+}
+
+#[test]
+fn test_maxp_versions() {
+    // version 0.5 is only as long as the glyph count
+    let font = OpenTypeFont::parse(&test_otto_font());
+    assert_eq!(font.num_glyphs(), 2);
+    let maxp = parse_maxp(&[0, 0, 0x50, 0, 0, 2]).unwrap().1;
+    assert_eq!((maxp.num_glyphs, maxp.profile), (2, None));
+
+    let mut data = vec![0, 1, 0, 0, 0, 3];
+    data.extend((1 ..= 13u16).flat_map(|n| n.to_be_bytes().to_vec()));
+    let (rest, maxp) = parse_maxp(&data).unwrap();
+    assert!(rest.is_empty());
+    let profile = maxp.profile.unwrap();
+    assert_eq!((profile.max_points, profile.max_contours), (1, 2));
+    assert_eq!(profile.max_component_depth, 13);
+    // a truncated profile does not hide the glyph count
+    let maxp = parse_maxp(&data[.. 10]).unwrap().1;
+    assert_eq!((maxp.num_glyphs, maxp.profile), (3, None));
+}
+
+#[test]
+fn test_cff_postscript_name() {
+    let font = OpenTypeFont::parse(&test_otto_font());
+    assert_eq!(font.postscript_name().as_deref(), Some("A"));
+    assert_eq!(font.family_name(), None);
+}
+
+#[test]
+fn test_style_and_vmetrics() {
+    let data = test_otto_font();
+    let font = OpenTypeFont::parse(&data);
+    assert_eq!(font.weight(), Some(400));
+    assert!(!font.is_italic());
+    let vmetrics = font.vmetrics().unwrap();
+    assert_eq!((vmetrics.ascent, vmetrics.descent, vmetrics.line_gap), (800., -200., 0.));
+
+    // bold italic with USE_TYPO_METRICS
+    let mut os2 = vec![0, 0, 0, 0, 2, 188, 0, 5, 0, 0];
+    os2.extend_from_slice(&[0; 52]);
+    os2.extend_from_slice(&[0, 0x81, 0, 0, 0, 0]);
+    os2.extend_from_slice(&[3, 132, 255, 6, 0, 100, 3, 132, 0, 250]);
+    let mut tables = parse_tables(&data).unwrap().1;
+    tables.entries.insert(*b"OS/2", &os2[..]);
+    let font = OpenTypeFont::from_tables(tables);
+    assert_eq!(font.weight(), Some(700));
+    assert!(font.is_italic());
+    let vmetrics = font.vmetrics().unwrap();
+    assert_eq!((vmetrics.ascent, vmetrics.descent, vmetrics.line_gap), (900., -250., 100.));
+}
+
+#[test]
+fn test_wrong_head_magic() {
+    let mut data = test_otto_font();
+    let pos = data.windows(4).position(|w| w == [0x5F, 0x0F, 0x3C, 0xF5]).unwrap();
+    data[pos .. pos + 4].copy_from_slice(&[0; 4]);
+    let font = OpenTypeFont::parse(&data);
+    let glyph = font.glyph(GlyphId(1)).unwrap();
+    assert_eq!(glyph.path.contours().len(), 1);
+}
+
+#[test]
+fn test_try_parse() {
+    use crate::try_parse;
+
+    assert!(try_parse(&test_otto_font()).is_ok());
+    assert!(matches!(try_parse(b"OT"), Err(FontError::Truncated)));
+    assert!(matches!(try_parse(b"typ1\0\0\0\0"), Err(FontError::UnsupportedTable("typ1"))));
+
+    // head version 2.0
+    let mut data = test_otto_font();
+    let pos = data.windows(4).position(|w| w == [0x5F, 0x0F, 0x3C, 0xF5]).unwrap();
+    data[pos - 11] = 2;
+    assert!(matches!(try_parse(&data), Err(FontError::Parse(_))));
+
+    // a broken table that the outlines do not need is skipped, a broken one they need is an error
+    let data = test_otto_font();
+    let mut tables = parse_tables(&data).unwrap().1;
+    tables.entries.insert(*b"post", &[0, 3]);
+    assert!(OpenTypeFont::try_from_tables(tables, &ParseOptions::default()).is_ok());
+    let mut tables = parse_tables(&data).unwrap().1;
+    tables.entries.insert(*b"CFF ", &[1, 0, 4, 1]);
+    assert!(matches!(OpenTypeFont::try_from_tables(tables, &ParseOptions::default()), Err(FontError::Parse(_))));
+
+    // bare CFF without indices, pfb with an unknown segment type and PostScript without a font
+    assert!(matches!(try_parse(&[1, 0, 4, 1]), Err(FontError::Truncated)));
+    assert!(matches!(try_parse(&[0x80, 1, 0, 0, 0, 0, 0x80, 9, 0, 0, 0, 0]), Err(FontError::Parse(_))));
+    assert!(matches!(try_parse(b"%!PS-AdobeFont-1.0\n"), Err(FontError::Parse(_))));
+}
+
+#[test]
+fn test_gid_for_cid() {
+    let mut font = OpenTypeFont::parse(&test_otto_font());
+    assert_eq!(font.gid_for_cid(0x41), None);
+    font.cid_map = Some(vec![(0x41, 1)].into_iter().collect());
+    assert_eq!(font.gid_for_cid(0x41), Some(GlyphId(1)));
+    // not truncated to CID 0x41
+    assert_eq!(font.gid_for_cid(0x10041), None);
+}
+
+#[test]
+fn test_parse_all_glyphs() {
+    let font = OpenTypeFont::parse(&test_otto_font());
+    let glyphs = font.parse_all_glyphs();
+    assert_eq!(glyphs.len(), 2);
+    assert!(glyphs.iter().all(|g| g.is_ok()));
+}
+
+#[test]
+fn test_glyph_limit() {
+    let data = test_otto_font();
+    let options = ParseOptions { max_glyphs: Some(1), .. ParseOptions::default() };
+    match crate::try_parse_with_options(&data, &options) {
+        Err(FontError::LimitExceeded(Limit::Glyphs)) => {}
+        _ => panic!("expected the glyph limit to be exceeded")
+    }
+    let options = ParseOptions { max_glyphs: Some(2), .. ParseOptions::default() };
+    assert!(crate::try_parse_with_options(&data, &options).is_ok());
+}
+
 pub struct Head {
     pub units_per_em: u16,
     pub index_to_loc_format: i16,
@@ -249,6 +907,7 @@ pub struct Head {
     pub x_max: i16,
     pub y_min: i16,
     pub y_max: i16,
+    pub mac_style: u16,
 }
 impl Head {
     pub fn bbox(&self) -> RectF {
@@ -257,15 +916,19 @@ impl Head {
         RectF::from_points(bb_min, bb_max)
     }
 }
-pub fn parse_head(i: &[u8]) -> R<Head> {
-    let (i, major) = be_u16(i)?;
+pub fn parse_head(data: &[u8]) -> R<Head> {
+    let (i, major) = be_u16(data)?;
     let (i, minor) = be_u16(i)?;
-    assert_eq!((major, minor), (1, 0));
+    if (major, minor) != (1, 0) {
+        return Err(nom::Err::Failure(make_error(data, ErrorKind::Verify)));
+    }
     
     let (i, _revision) = be_i32(i)?;
     let (i, _cksum) = be_u32(i)?;
     let (i, magic) = be_i32(i)?;
-    assert_eq!(magic, 0x5F0F3CF5);
+    if magic != 0x5F0F3CF5 {
+        warn!("head table has the wrong magic number 0x{:08X}", magic);
+    }
     
     let (i, _flags) = be_u16(i)?;
     let (i, units_per_em) = be_u16(i)?;
@@ -278,34 +941,119 @@ pub fn parse_head(i: &[u8]) -> R<Head> {
     let (i, x_max) = be_i16(i)?;
     let (i, y_max) = be_i16(i)?;
     
-    let (i, _mac_style) = be_u16(i)?;
+    let (i, mac_style) = be_u16(i)?;
     
     let (i, _lowest_rec_ppem) = be_u16(i)?;
     
     let (i, _font_direction_hint) = be_u16(i)?;
     let (i, index_to_loc_format) = be_i16(i)?;
     let (i, glyph_data_format) = be_u16(i)?;
-    assert_eq!(glyph_data_format, 0);
+    if glyph_data_format != 0 {
+        return Err(nom::Err::Failure(make_error(data, ErrorKind::Verify)));
+    }
     
     Ok((i, Head {
         units_per_em,
         index_to_loc_format,
-        x_min, x_max, y_min, y_max
+        x_min, x_max, y_min, y_max,
+        mac_style,
     }))
 }
 pub struct Maxp {
-    pub num_glyphs: u16
+    pub num_glyphs: u16,
+    /// only in version 1.0 (TrueType outlines). Version 0.5 (CFF outlines) has just the glyph count.
+    pub profile: Option<MaxpProfile>,
+}
+/// The maximum profile of TrueType outlines and instructions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaxpProfile {
+    pub max_points: u16,
+    pub max_contours: u16,
+    pub max_composite_points: u16,
+    pub max_composite_contours: u16,
+    pub max_zones: u16,
+    pub max_twilight_points: u16,
+    pub max_storage: u16,
+    pub max_function_defs: u16,
+    pub max_instruction_defs: u16,
+    pub max_stack_elements: u16,
+    pub max_size_of_instructions: u16,
+    pub max_component_elements: u16,
+    pub max_component_depth: u16,
+}
+fn parse_maxp_profile(i: &[u8]) -> R<MaxpProfile> {
+    let (i, max_points) = be_u16(i)?;
+    let (i, max_contours) = be_u16(i)?;
+    let (i, max_composite_points) = be_u16(i)?;
+    let (i, max_composite_contours) = be_u16(i)?;
+    let (i, max_zones) = be_u16(i)?;
+    let (i, max_twilight_points) = be_u16(i)?;
+    let (i, max_storage) = be_u16(i)?;
+    let (i, max_function_defs) = be_u16(i)?;
+    let (i, max_instruction_defs) = be_u16(i)?;
+    let (i, max_stack_elements) = be_u16(i)?;
+    let (i, max_size_of_instructions) = be_u16(i)?;
+    let (i, max_component_elements) = be_u16(i)?;
+    let (i, max_component_depth) = be_u16(i)?;
+    Ok((i, MaxpProfile {
+        max_points, max_contours, max_composite_points, max_composite_contours,
+        max_zones, max_twilight_points, max_storage, max_function_defs, max_instruction_defs,
+        max_stack_elements, max_size_of_instructions, max_component_elements, max_component_depth
+    }))
 }
 pub fn parse_maxp(i: &[u8]) -> R<Maxp> {
-    let (i, _version) = be_i32(i)?;
+    let (i, version) = be_u32(i)?;
     let (i, num_glyphs) = be_u16(i)?;
-    Ok((i, Maxp { num_glyphs }))
+    match version {
+        0x00005000 => Ok((i, Maxp { num_glyphs, profile: None })),
+        0x00010000 => match parse_maxp_profile(i) {
+            Ok((i, profile)) => Ok((i, Maxp { num_glyphs, profile: Some(profile) })),
+            Err(_) => {
+                warn!("truncated maxp 1.0");
+                Ok((i, Maxp { num_glyphs, profile: None }))
+            }
+        }
+        v => {
+            // read the glyph count, which is all we rely on
+            warn!("unknown maxp version {:08x}", v);
+            Ok((i, Maxp { num_glyphs, profile: None }))
+        }
+    }
 }
 pub fn parse_loca<'a>(i: &'a [u8], head: &Head, maxp: &Maxp) -> R<'a, Vec<u32>> {
     match head.index_to_loc_format {
         0 => count(map(be_u16, |n| 2 * n as u32), maxp.num_glyphs as usize + 1)(i),
         1 => count(be_u32, maxp.num_glyphs as usize + 1)(i),
-        _ => panic!("invalid index_to_loc_format")
+        _ => Err(nom::Err::Failure(make_error(i, ErrorKind::Verify)))
+    }
+}
+
+/// The glyph counts implied by maxp, loca and hmtx. (CFF is not looked at.)
+pub fn glyph_count_report(tables: &Tables<impl Deref<Target=[u8]>>) -> GlyphCountReport {
+    let maxp = tables.get(b"maxp").and_then(|data| parse_maxp(data).ok()).map(|(_, maxp)| maxp);
+    let head = tables.get(b"head").and_then(|data| parse_head(data).ok()).map(|(_, head)| head);
+    let hhea = tables.get(b"hhea").and_then(|data| parse_hhea(data).ok()).map(|(_, hhea)| hhea);
+    
+    let loca = match (tables.get(b"loca"), head) {
+        (Some(data), Some(head)) => {
+            let entry_size = if head.index_to_loc_format == 0 { 2 } else { 4 };
+            Some((data.len() / entry_size).saturating_sub(1) as u32)
+        }
+        _ => None
+    };
+    let hmtx = match (tables.get(b"hmtx"), hhea) {
+        (Some(data), Some(hhea)) => {
+            let n = hhea.number_of_hmetrics as usize;
+            Some((n + data.len().saturating_sub(4 * n) / 2) as u32)
+        }
+        _ => None
+    };
+    
+    GlyphCountReport {
+        maxp: maxp.map(|maxp| maxp.num_glyphs as u32),
+        loca,
+        hmtx,
+        cff: None
     }
 }
 
@@ -313,8 +1061,20 @@ pub struct Hhea {
     line_gap: i16,
     ascender: i16,
     descender: i16,
+    caret_slope_rise: i16,
+    caret_slope_run: i16,
+    caret_offset: i16,
     number_of_hmetrics: u16 
 }
+impl Hhea {
+    pub fn caret_slope(&self) -> CaretSlope {
+        CaretSlope {
+            rise: self.caret_slope_rise as f32,
+            run: self.caret_slope_run as f32,
+            offset: self.caret_offset as f32,
+        }
+    }
+}
 pub fn parse_hhea(i: &[u8]) -> R<Hhea> {
     let (i, _majorVersion) = be_u16(i)?;
     let (i, _minorVersion) = be_u16(i)?;
@@ -325,9 +1085,9 @@ pub fn parse_hhea(i: &[u8]) -> R<Hhea> {
     let (i, _minLeftSideBearing) = be_i16(i)?;
     let (i, _minRightSideBearing) = be_i16(i)?;
     let (i, _xMaxExtent) = be_i16(i)?;
-    let (i, _caretSlopeRise) = be_i16(i)?;
-    let (i, _caretSlopeRun) = be_i16(i)?;
-    let (i, _caretOffset) = be_i16(i)?;
+    let (i, caret_slope_rise) = be_i16(i)?;
+    let (i, caret_slope_run) = be_i16(i)?;
+    let (i, caret_offset) = be_i16(i)?;
     let (i, _) = be_i16(i)?;
     let (i, _) = be_i16(i)?;
     let (i, _) = be_i16(i)?;
@@ -340,9 +1100,31 @@ pub fn parse_hhea(i: &[u8]) -> R<Hhea> {
         line_gap,
         ascender,
         descender,
+        caret_slope_rise,
+        caret_slope_run,
+        caret_offset,
         number_of_hmetrics
     }))
 }
+/// The metrics of `hhea`, unless `OS/2` asks for its typographic metrics or `hhea` has none.
+pub fn select_vmetrics(hhea: Option<VMetrics>, os2: Option<&Os2>) -> Option<VMetrics> {
+    match (hhea, os2) {
+        (_, Some(os2)) if os2.use_typo_metrics() => Some(os2.typo_vmetrics()),
+        (Some(hhea), _) if hhea.ascent != 0.0 || hhea.descent != 0.0 => Some(hhea),
+        (hhea, os2) => os2.map(|os2| os2.typo_vmetrics()).or(hhea)
+    }
+}
+/// The weight class of `OS/2`, or the bold bit of the `head` macStyle if there is none
+pub fn font_weight(os2: Option<&Os2>, mac_style: Option<u16>) -> Option<u16> {
+    match os2 {
+        Some(os2) if os2.weight_class > 0 => Some(os2.weight_class),
+        _ => mac_style.map(|style| if style & 1 != 0 { 700 } else { 400 })
+    }
+}
+/// Whether `OS/2` or the `head` macStyle mark the font as italic
+pub fn font_is_italic(os2: Option<&Os2>, mac_style: Option<u16>) -> bool {
+    os2.map_or(false, |os2| os2.is_italic()) || mac_style.map_or(false, |style| style & 2 != 0)
+}
 impl Into<VMetrics> for Hhea {
     fn into(self) -> VMetrics {
         VMetrics {
@@ -359,6 +1141,19 @@ pub struct Hmtx {
     last_advance: u16
 }
 impl Hmtx {
+    /// whether `gid` has its own advance (rather than reusing the last one)
+    pub fn is_explicit(&self, gid: u16) -> bool {
+        (gid as usize) < self.metrics.len()
+    }
+    /// The metrics with the advance of each glyph changed by `deltas` (indexed by *gid*).
+    pub fn with_advance_deltas(&self, deltas: &[f32]) -> Hmtx {
+        let metrics = (0 .. deltas.len().max(self.metrics.len())).map(|gid| {
+            let HMetrics { advance, lsb } = self.metrics_for_gid(gid as u16);
+            let delta = deltas.get(gid).cloned().unwrap_or(0.0);
+            ((advance + delta).round().max(0.0) as u16, lsb as i16)
+        }).collect();
+        Hmtx { metrics, lsbs: vec![], last_advance: self.last_advance }
+    }
     pub fn metrics_for_gid(&self, gid: u16) -> HMetrics {
         let (advance, lsb) = self.metrics.get(gid as usize).cloned().unwrap_or_else(|| {
             (self.last_advance, self.lsbs.get(gid as usize - self.metrics.len()).cloned().unwrap_or(0))
@@ -369,6 +1164,14 @@ impl Hmtx {
         }
     }
 }
+#[test]
+fn test_advance_is_explicit() {
+    let hmtx = Hmtx { metrics: vec![(500, 0), (600, 10)], lsbs: vec![20], last_advance: 600 };
+    assert!(hmtx.is_explicit(1));
+    assert!(!hmtx.is_explicit(2));
+    assert_eq!(hmtx.metrics_for_gid(2).advance, 600.);
+}
+
 pub fn parse_hmtx<'a>(i: &'a [u8], hhea: &Hhea, _maxp: &Maxp) -> R<'a, Hmtx> {
     let num_metrics = hhea.number_of_hmetrics;
     let (i, metrics) = count(
@@ -430,13 +1233,13 @@ pub fn parse_skript_list(data: &[u8]) -> R<()> {
     
     for (tag, offset) in iterator_n(i, tuple((take(4usize), be_u16)), script_count) {
         debug!("script {}", String::from_utf8_lossy(tag));
-        let script_data = &data[offset as usize ..];
+        let script_data = offset_into(data, offset as usize)?.1;
         
         let (i, _default_lang_sys_off) = be_u16(script_data)?;
         let (i, sys_lang_count) = be_u16(i)?;
         
         for (_tag, offset) in iterator_n(i, tuple((take(4usize), be_u16)), sys_lang_count) {
-            let i = &script_data[offset as usize ..];
+            let i = offset_into(script_data, offset as usize)?.1;
             let (i, _lookup_order) = be_u16(i)?;
             let (i, _required_feature_index) = be_u16(i)?;
             let (i, feature_index_count) = be_u16(i)?;
@@ -451,13 +1254,13 @@ pub fn parse_skript_list(data: &[u8]) -> R<()> {
 pub fn parse_lookup_list(data: &[u8], mut inner: impl FnMut(usize, &[u8], u16, u16) -> R<()>) -> R<()> {
     let (i, lookup_count) = be_u16(data)?;
     for (lookup_idx, table_off) in iterator_n(i, be_u16, lookup_count).enumerate() {
-        let table_data = &data[table_off as usize ..];
+        let table_data = offset_into(data, table_off as usize)?.1;
         let (i, lookup_type) = be_u16(table_data)?;
         let (i, lookup_flag) = be_u16(i)?;
         let (i, subtable_count) = be_u16(i)?;
         
         for subtable_off in iterator_n(i, be_u16, subtable_count) {
-            inner(lookup_idx, &table_data[subtable_off as usize ..], lookup_type, lookup_flag)?;
+            inner(lookup_idx, offset_into(table_data, subtable_off as usize)?.1, lookup_type, lookup_flag)?;
         }
     }
     Ok((i, ()))
@@ -471,7 +1274,7 @@ pub fn parse_class_def<'a>(data: &'a [u8], map: &mut HashMap<u16, u16>) -> R<'a,
             let (i, start_glyph_id) = be_u16(i)?;
             let (i, glyph_count) = be_u16(i)?;
             map.reserve(glyph_count as usize);
-            for (gid, class) in (start_glyph_id ..).zip(iterator_n(i, be_u16, glyph_count)) {
+            for (gid, class) in (start_glyph_id ..= u16::MAX).zip(iterator_n(i, be_u16, glyph_count)) {
                 map.insert(gid, class);
             }
         }
@@ -484,13 +1287,13 @@ pub fn parse_class_def<'a>(data: &'a [u8], map: &mut HashMap<u16, u16>) -> R<'a,
                 }
             }
         }
-        f => panic!("invalid class list format {}", f)
+        _ => return Err(nom::Err::Failure(make_error(data, ErrorKind::Verify)))
     }
     Ok((i, ()))
 }
 
-pub fn coverage_table<'a>(i: &'a [u8]) -> R<impl Iterator<Item=u16> + 'a> {
-    let (i, format) = be_u16(i)?;
+pub fn coverage_table<'a>(data: &'a [u8]) -> R<impl Iterator<Item=u16> + 'a> {
+    let (i, format) = be_u16(data)?;
     debug!("coverage table format {}", format);
     match format {
         1 => {
@@ -504,7 +1307,19 @@ pub fn coverage_table<'a>(i: &'a [u8]) -> R<impl Iterator<Item=u16> + 'a> {
                     .flat_map(|(start, end, _i)| start ..= end)
             )))
         },
-        n => panic!("invalid coverage format {}", n)
+        _ => Err(nom::Err::Failure(make_error(data, ErrorKind::Verify)))
+    }
+}
+
+// which record to use for the names of `Name` if there are several, lower is better:
+// Windows English (United States), Macintosh English, other Windows languages, Unicode platform
+fn name_record_rank(platform_id: u16, language_id: u16) -> u8 {
+    match (platform_id, language_id) {
+        (3, 0x409) => 0,
+        (1, 0) => 1,
+        (3, _) => 2,
+        (0, _) => 3,
+        _ => 4,
     }
 }
 
@@ -516,27 +1331,65 @@ pub fn parse_name(data: &[u8]) -> R<Name> {
         0 | 1 => {
             let (i, count) = be_u16(i)?;
             let (i, string_offset) = be_u16(i)?;
-            let string_data = &data[string_offset as usize ..];
+            let string_data = offset_into(data, string_offset as usize)?.1;
+            let lang_tags: Vec<String> = match format {
+                1 => {
+                    let lang_tag_data = data.get(6 + 12 * count as usize ..).unwrap_or_default();
+                    let (lang_tag_data, lang_tag_count) = be_u16(lang_tag_data)?;
+                    iterator_n(lang_tag_data, tuple((be_u16, be_u16)), lang_tag_count)
+                        .map(|(length, offset)| {
+                            string_data.get(offset as usize .. offset as usize + length as usize)
+                                .and_then(|s| utf16_be(s).ok())
+                                .unwrap_or_default()
+                        })
+                        .collect()
+                }
+                _ => vec![]
+            };
+            // rank of the record used for family, subfamily, full name and postscript name
+            let mut ranks = [u8::max_value(); 4];
             for name_record in iterator_n(i, tuple((be_u16, be_u16, be_u16, be_u16, be_u16, be_u16)), count) {
                 let (platform_id, encoding_id, language_id, name_id, length, offset) = name_record;
                 //debug!("platform_id={}, encoding_id={}, language_id={}, name_id={}", platform_id, encoding_id, language_id, name_id);
 
-                let encoded = &string_data[offset as usize .. offset as usize + length as usize];
+                let encoded = match string_data.get(offset as usize .. offset as usize + length as usize) {
+                    Some(encoded) => encoded,
+                    None => continue
+                };
                 //debug!("string: {:?}", encoded);
 
-                let field = match name_id {
-                    1 => &mut name.family,
-                    2 => &mut name.subfamily,
-                    4 => &mut name.full_name,
-                    6 => &mut name.postscript_name,
-                    _ => continue,
+                let value = match (platform_id, encoding_id) {
+                    (0, _) | (3, 0) | (3, 1) | (3, 10) => utf16_be(encoded).ok(),
+                    (1, 0) => Some(language::decode_mac_roman(encoded)),
+                    _ => None
+                };
+                let value = match value {
+                    Some(value) => value,
+                    None => continue
                 };
 
-                match (platform_id, encoding_id) {
-                    (0, _) => *field = String::from_utf8(encoded.into()).ok(),
-                    (3, 1) => *field = utf16_be(encoded).ok(),
-                    _ => {}
+                let rank = name_record_rank(platform_id, language_id);
+                let field = match name_id {
+                    1 => Some((&mut ranks[0], &mut name.family)),
+                    2 => Some((&mut ranks[1], &mut name.subfamily)),
+                    4 => Some((&mut ranks[2], &mut name.full_name)),
+                    6 => Some((&mut ranks[3], &mut name.postscript_name)),
+                    _ => None,
+                };
+                if let Some((best, field)) = field {
+                    if rank < *best {
+                        *best = rank;
+                        *field = Some(value.clone());
+                    }
                 }
+
+                let language = match platform_id {
+                    1 => language::mac_language(language_id).map(String::from),
+                    3 if language_id < 0x8000 => language::windows_language(language_id).map(String::from),
+                    0 | 3 if format == 1 && language_id >= 0x8000 => lang_tags.get(language_id as usize - 0x8000).cloned(),
+                    _ => None
+                };
+                name.localized.push(LocalizedName { name_id, platform_id, language_id, language, value });
             }
         }
         _ => {}
@@ -544,6 +1397,34 @@ pub fn parse_name(data: &[u8]) -> R<Name> {
     Ok((i, name))
 }
 
+#[test]
+fn test_localized_name() {
+    // format 0, two Windows records for name id 1: English "Ab" and German "Cd"
+    let mut data = vec![0, 0,  0, 2,  0, 30];
+    data.extend_from_slice(&[0, 3, 0, 1, 0x04, 0x09, 0, 1, 0, 4, 0, 0]);
+    data.extend_from_slice(&[0, 3, 0, 1, 0x04, 0x07, 0, 1, 0, 4, 0, 4]);
+    data.extend_from_slice(&[0, b'A', 0, b'b', 0, b'C', 0, b'd']);
+    let name = parse_name(&data).get();
+    assert_eq!(name.localized(1, "de"), Some("Cd"));
+    assert_eq!(name.localized(1, "en-GB"), Some("Ab"));
+    assert_eq!(name.localized(1, "ja"), Some("Ab"));
+    assert_eq!(name.localized(2, "de"), None);
+    assert_eq!(name.family.as_deref(), Some("Ab"));
+}
+
+/// Parse the 'name' table and resolve the languages of Unicode platform records through the 'ltag' table, if there is one.
+pub fn read_name(tables: &Tables<impl Deref<Target=[u8]>>) -> Name {
+    let mut name = tables.parse_optional(b"name", parse_name).unwrap_or_default();
+    if let Some(tags) = tables.parse_optional(b"ltag", parse_ltag) {
+        for record in name.localized.iter_mut().filter(|r| r.platform_id == 0) {
+            if let Some(tag) = tags.get(record.language_id as usize) {
+                record.language = Some(tag.clone());
+            }
+        }
+    }
+    name
+}
+
 fn utf16_be(data: &[u8]) -> Result<String, std::string::FromUtf16Error> {
     let wide: Vec<u16> = data.chunks_exact(2).map(|c| {
         match c {
@@ -591,3 +1472,8 @@ fn tag(i: &[u8]) -> R<Tag> {
     let (i, s) = take(4usize)(i)?;
     Ok((i, Tag(s.try_into().unwrap())))
 }
+// 16.16 fixed point number
+pub(crate) fn fixed(i: &[u8]) -> R<f32> {
+    let (i, v) = be_i32(i)?;
+    Ok((i, v as f32 / 65536.))
+}
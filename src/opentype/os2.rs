@@ -0,0 +1,145 @@
+use crate::{R, LineMetrics, VMetrics, parsers::*};
+use nom::{
+    number::complete::{be_u16, be_i16, be_u32},
+    bytes::complete::take,
+};
+
+/// The OS/2 and Windows metrics table
+#[derive(Clone, Debug)]
+pub struct Os2 {
+    pub version: u16,
+    pub avg_char_width: i16,
+    pub weight_class: u16,
+    pub width_class: u16,
+    pub fs_type: u16,
+    pub strikeout_size: i16,
+    pub strikeout_position: i16,
+    pub family_class: i16,
+    pub panose: [u8; 10],
+    pub vendor_id: [u8; 4],
+    pub fs_selection: u16,
+    pub typo_ascender: i16,
+    pub typo_descender: i16,
+    pub typo_line_gap: i16,
+    pub win_ascent: u16,
+    pub win_descent: u16,
+
+    /// version 2 and later
+    pub x_height: Option<i16>,
+    /// version 2 and later
+    pub cap_height: Option<i16>,
+}
+impl Os2 {
+    pub fn strikeout(&self) -> LineMetrics {
+        LineMetrics {
+            position: self.strikeout_position as f32,
+            thickness: self.strikeout_size as f32,
+        }
+    }
+    /// fsSelection marks the font as italic or oblique
+    pub fn is_italic(&self) -> bool {
+        self.fs_selection & (1 | 1 << 9) != 0
+    }
+    /// fsSelection asks for the typographic metrics instead of those of `hhea` (USE_TYPO_METRICS)
+    pub fn use_typo_metrics(&self) -> bool {
+        self.fs_selection & 1 << 7 != 0
+    }
+    /// The typographic ascender, descender and line gap
+    pub fn typo_vmetrics(&self) -> VMetrics {
+        VMetrics {
+            line_gap: self.typo_line_gap as f32,
+            ascent: self.typo_ascender as f32,
+            descent: self.typo_descender as f32,
+        }
+    }
+}
+
+pub fn parse_os2(data: &[u8]) -> R<Os2> {
+    let (i, version) = be_u16(data)?;
+    let (i, avg_char_width) = be_i16(i)?;
+    let (i, weight_class) = be_u16(i)?;
+    let (i, width_class) = be_u16(i)?;
+    let (i, fs_type) = be_u16(i)?;
+    let (i, _subscript_and_superscript) = take(16usize)(i)?;
+    let (i, strikeout_size) = be_i16(i)?;
+    let (i, strikeout_position) = be_i16(i)?;
+    let (i, family_class) = be_i16(i)?;
+    let (i, panose) = take(10usize)(i)?;
+    let (i, _unicode_range) = take(16usize)(i)?;
+    let (i, vendor_id) = take(4usize)(i)?;
+    let (i, fs_selection) = be_u16(i)?;
+    let (i, _first_char_index) = be_u16(i)?;
+    let (i, _last_char_index) = be_u16(i)?;
+    let (i, typo_ascender) = be_i16(i)?;
+    let (i, typo_descender) = be_i16(i)?;
+    let (i, typo_line_gap) = be_i16(i)?;
+    let (i, win_ascent) = be_u16(i)?;
+    let (mut i, win_descent) = be_u16(i)?;
+
+    let (mut x_height, mut cap_height) = (None, None);
+    if version >= 1 {
+        let _code_page_range = parse(&mut i, take(8usize))?;
+    }
+    if version >= 2 {
+        x_height = Some(parse(&mut i, be_i16)?);
+        cap_height = Some(parse(&mut i, be_i16)?);
+        let _default_char = parse(&mut i, be_u16)?;
+        let _break_char = parse(&mut i, be_u16)?;
+        let _max_context = parse(&mut i, be_u16)?;
+    }
+    if version >= 5 {
+        let _optical_point_sizes = parse(&mut i, be_u32)?;
+    }
+
+    let mut panose_array = [0; 10];
+    panose_array.copy_from_slice(panose);
+    let mut vendor_id_array = [0; 4];
+    vendor_id_array.copy_from_slice(vendor_id);
+
+    Ok((i, Os2 {
+        version,
+        avg_char_width,
+        weight_class,
+        width_class,
+        fs_type,
+        strikeout_size,
+        strikeout_position,
+        family_class,
+        panose: panose_array,
+        vendor_id: vendor_id_array,
+        fs_selection,
+        typo_ascender,
+        typo_descender,
+        typo_line_gap,
+        win_ascent,
+        win_descent,
+        x_height,
+        cap_height,
+    }))
+}
+
+/// The weight class of a weight name like "Bold" or "Semi Bold", as found in CFF and Type1 fonts
+pub fn weight_class_for_name(name: &str) -> Option<u16> {
+    let name: String = name.chars().filter(|c| c.is_ascii_alphabetic()).map(|c| c.to_ascii_lowercase()).collect();
+    let class = match name.as_str() {
+        "thin" | "hairline" => 100,
+        "extralight" | "ultralight" => 200,
+        "light" => 300,
+        "regular" | "normal" | "book" | "roman" | "plain" => 400,
+        "medium" => 500,
+        "semibold" | "demibold" | "demi" => 600,
+        "bold" => 700,
+        "extrabold" | "ultrabold" | "heavy" => 800,
+        "black" | "ultrablack" => 900,
+        _ => return None
+    };
+    Some(class)
+}
+
+#[test]
+fn test_weight_class_for_name() {
+    assert_eq!(weight_class_for_name("Bold"), Some(700));
+    assert_eq!(weight_class_for_name("Semi Bold"), Some(600));
+    assert_eq!(weight_class_for_name("extra-light"), Some(200));
+    assert_eq!(weight_class_for_name("Fancy"), None);
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use crate::{R, LineMetrics, parsers::*};
+use nom::{
+    number::complete::{be_u8, be_i16, be_u16, be_i32, be_u32},
+    bytes::complete::take,
+};
+
+#[derive(Clone, Debug)]
+pub struct Post {
+    /// in degrees, counter-clockwise from the vertical
+    pub italic_angle: f32,
+    pub underline_position: i16,
+    pub underline_thickness: i16,
+    pub is_fixed_pitch: bool,
+
+    // name -> gid
+    names: HashMap<String, u16>,
+}
+impl Post {
+    /// The *gid* of the glyph with the given name.
+    ///
+    /// If several glyphs share the name, the lowest *gid* is returned.
+    pub fn gid_for_name(&self, name: &str) -> Option<u16> {
+        self.names.get(name).cloned()
+    }
+    pub fn underline(&self) -> LineMetrics {
+        LineMetrics {
+            position: self.underline_position as f32,
+            thickness: self.underline_thickness as f32,
+        }
+    }
+}
+
+pub fn parse_post(data: &[u8]) -> R<Post> {
+    let (i, version) = be_u32(data)?;
+    let (i, italic_angle) = be_i32(i)?;
+    let (i, underline_position) = be_i16(i)?;
+    let (i, underline_thickness) = be_i16(i)?;
+    let (i, is_fixed_pitch) = be_u32(i)?;
+    let (i, _min_mem_type42) = be_u32(i)?;
+    let (i, _max_mem_type42) = be_u32(i)?;
+    let (i, _min_mem_type1) = be_u32(i)?;
+    let (mut i, _max_mem_type1) = be_u32(i)?;
+
+    let mut names = HashMap::new();
+    let mut add_name = |name: &str, gid: u16| {
+        match names.entry(name.to_owned()) {
+            Entry::Vacant(e) => {
+                e.insert(gid);
+            }
+            Entry::Occupied(e) => {
+                warn!("post: glyph name {:?} is used by gid {} and {}", name, e.get(), gid);
+            }
+        }
+    };
+    match version {
+        0x00010000 => {
+            for (gid, &name) in MAC_GLYPH_NAMES.iter().enumerate() {
+                add_name(name, gid as u16);
+            }
+        }
+        0x00020000 => {
+            let num_glyphs = parse(&mut i, be_u16)?;
+            let (mut strings, indices) = take(num_glyphs as usize * 2)(i)?;
+
+            // pascal strings, in the order they are referenced
+            let mut custom_names = vec![];
+            while strings.len() > 0 {
+                let len = parse(&mut strings, be_u8)?;
+                let name = parse(&mut strings, take(len))?;
+                custom_names.push(String::from_utf8_lossy(name));
+            }
+
+            // gids are visited in ascending order, so collisions resolve to the lowest gid
+            for (gid, index) in iterator(indices, be_u16).enumerate() {
+                let index = index as usize;
+                match MAC_GLYPH_NAMES.get(index) {
+                    Some(&name) => add_name(name, gid as u16),
+                    None => match custom_names.get(index - MAC_GLYPH_NAMES.len()) {
+                        Some(name) => add_name(name.as_ref(), gid as u16),
+                        None => warn!("post: invalid name index {} for gid {}", index, gid)
+                    }
+                }
+            }
+        }
+        0x00025000 => warn!("post: version 2.5 is deprecated and not supported"),
+        0x00030000 => {} // no glyph names
+        v => warn!("post: unknown version {:#x}", v)
+    }
+
+    Ok((i, Post {
+        italic_angle: italic_angle as f32 / 65536.,
+        underline_position,
+        underline_thickness,
+        is_fixed_pitch: is_fixed_pitch != 0,
+        names
+    }))
+}
+
+#[test]
+fn test_post_duplicate_names() {
+    use crate::IResultExt;
+
+    let mut data = vec![
+        0, 2, 0, 0, // version 2.0
+        0, 0, 0, 0, // italic angle
+        0xff, 0x9c, // underline position -100
+        0, 50,      // underline thickness
+        0, 0, 0, 0, // fixed pitch
+    ];
+    data.extend_from_slice(&[0; 16]); // memory usage
+    data.extend_from_slice(&[
+        0, 5,       // num glyphs
+        0, 0,       // .notdef
+        1, 3,       // 259: "b"
+        1, 2,       // 258: "a"
+        0, 36,      // A
+        1, 4,       // 260: "a" again
+    ]);
+    data.extend_from_slice(b"\x01a\x01b\x01a");
+
+    let post = parse_post(&data).get();
+    assert_eq!(post.underline_position, -100);
+    assert_eq!(post.gid_for_name(".notdef"), Some(0));
+    assert_eq!(post.gid_for_name("b"), Some(1));
+    assert_eq!(post.gid_for_name("a"), Some(2));
+    assert_eq!(post.gid_for_name("A"), Some(3));
+    assert_eq!(post.gid_for_name("c"), None);
+}
+
+/// The standard Macintosh glyph order (used by post table versions 1 and 2)
+pub static MAC_GLYPH_NAMES: [&str; 258] = [
+    ".notdef", ".null", "nonmarkingreturn", "space", "exclam", "quotedbl", "numbersign", "dollar",
+    "percent", "ampersand", "quotesingle", "parenleft", "parenright", "asterisk", "plus", "comma",
+    "hyphen", "period", "slash", "zero", "one", "two", "three", "four",
+    "five", "six", "seven", "eight", "nine", "colon", "semicolon", "less",
+    "equal", "greater", "question", "at", "A", "B", "C", "D",
+    "E", "F", "G", "H", "I", "J", "K", "L",
+    "M", "N", "O", "P", "Q", "R", "S", "T",
+    "U", "V", "W", "X", "Y", "Z", "bracketleft", "backslash",
+    "bracketright", "asciicircum", "underscore", "grave", "a", "b", "c", "d",
+    "e", "f", "g", "h", "i", "j", "k", "l",
+    "m", "n", "o", "p", "q", "r", "s", "t",
+    "u", "v", "w", "x", "y", "z", "braceleft", "bar",
+    "braceright", "asciitilde", "Adieresis", "Aring", "Ccedilla", "Eacute", "Ntilde", "Odieresis",
+    "Udieresis", "aacute", "agrave", "acircumflex", "adieresis", "atilde", "aring", "ccedilla",
+    "eacute", "egrave", "ecircumflex", "edieresis", "iacute", "igrave", "icircumflex", "idieresis",
+    "ntilde", "oacute", "ograve", "ocircumflex", "odieresis", "otilde", "uacute", "ugrave",
+    "ucircumflex", "udieresis", "dagger", "degree", "cent", "sterling", "section", "bullet",
+    "paragraph", "germandbls", "registered", "copyright", "trademark", "acute", "dieresis", "notequal",
+    "AE", "Oslash", "infinity", "plusminus", "lessequal", "greaterequal", "yen", "mu",
+    "partialdiff", "summation", "product", "pi", "integral", "ordfeminine", "ordmasculine", "Omega",
+    "ae", "oslash", "questiondown", "exclamdown", "logicalnot", "radical", "florin", "approxequal",
+    "Delta", "guillemotleft", "guillemotright", "ellipsis", "nonbreakingspace", "Agrave", "Atilde", "Otilde",
+    "OE", "oe", "endash", "emdash", "quotedblleft", "quotedblright", "quoteleft", "quoteright",
+    "divide", "lozenge", "ydieresis", "Ydieresis", "fraction", "currency", "guilsinglleft", "guilsinglright",
+    "fi", "fl", "daggerdbl", "periodcentered", "quotesinglbase", "quotedblbase", "perthousand", "Acircumflex",
+    "Ecircumflex", "Aacute", "Edieresis", "Egrave", "Iacute", "Icircumflex", "Idieresis", "Igrave",
+    "Oacute", "Ocircumflex", "apple", "Ograve", "Uacute", "Ucircumflex", "Ugrave", "dotlessi",
+    "circumflex", "tilde", "macron", "breve", "dotaccent", "ring", "cedilla", "hungarumlaut",
+    "ogonek", "caron", "Lslash", "lslash", "Scaron", "scaron", "Zcaron", "zcaron",
+    "brokenbar", "Eth", "eth", "Yacute", "yacute", "Thorn", "thorn", "minus",
+    "multiply", "onesuperior", "twosuperior", "threesuperior", "onehalf", "onequarter", "threequarters", "franc",
+    "Gbreve", "gbreve", "Idotaccent", "Scedilla", "scedilla", "Cacute", "cacute", "Ccaron",
+    "ccaron", "dcroat",
+];
@@ -0,0 +1,27 @@
+use std::collections::HashMap;
+use crate::{R, GlyphProps};
+use crate::opentype::aat::parse_lookup;
+use nom::number::complete::{be_u16, be_u32};
+
+/// The AAT glyph properties table
+#[derive(Clone, Debug)]
+pub struct Prop {
+    default: u16,
+    props: HashMap<u16, u16>,
+}
+impl Prop {
+    pub fn get(&self, gid: u16) -> GlyphProps {
+        GlyphProps::from_bits(self.props.get(&gid).cloned().unwrap_or(self.default))
+    }
+}
+
+pub fn parse_prop(data: &[u8]) -> R<Prop> {
+    let (i, _version) = be_u32(data)?;
+    let (i, format) = be_u16(i)?;
+    let (i, default) = be_u16(i)?;
+    let (i, props) = match format {
+        0 => (i, HashMap::new()),
+        _ => parse_lookup(i)?
+    };
+    Ok((i, Prop { default, props }))
+}
@@ -0,0 +1,107 @@
+use crate::PositionedGlyph;
+use crate::opentype::{OpenTypeFont, Tag};
+use crate::layout::{Direction, shape_traced};
+
+/// A step of the shaping pipeline.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ShapeStep {
+    /// characters mapped to glyphs by the character map
+    Cmap,
+    /// a `GSUB` lookup of the feature
+    Substitution { feature: Tag, lookup: u16 },
+    /// a `GPOS` single adjustment lookup of the feature
+    SingleAdjustment { feature: Tag, lookup: u16 },
+    /// a `GPOS` pair adjustment lookup (these are applied with or without their feature)
+    PairAdjustment { lookup: u16 },
+    /// kerning by the `kern` table, in fonts without `GPOS` pair adjustments
+    Kerning,
+    /// cursive attachment (`GPOS` lookup type 3)
+    CursiveAttachment,
+    /// mark-to-base and mark-to-mark attachment (`GPOS` lookup types 4 and 6)
+    MarkAttachment,
+}
+
+/// The glyph buffer after one step of shaping.
+#[derive(Clone, Debug)]
+pub struct ShapeStage {
+    pub step: ShapeStep,
+    /// whether the step changed the buffer
+    pub fired: bool,
+    pub glyphs: Vec<PositionedGlyph>,
+}
+
+/// The record of `OpenTypeFont::shape_debug`, one stage per step.
+#[derive(Clone, Debug)]
+pub struct ShapeTrace {
+    pub stages: Vec<ShapeStage>,
+}
+impl ShapeTrace {
+    /// The result of the last stage.
+    pub fn glyphs(&self) -> &[PositionedGlyph] {
+        self.stages.last().map(|stage| &stage.glyphs[..]).unwrap_or(&[])
+    }
+    /// The steps that changed the buffer, in order.
+    pub fn fired(&self) -> impl Iterator<Item=ShapeStep> + '_ {
+        self.stages.iter().filter(|stage| stage.fired).map(|stage| stage.step)
+    }
+}
+
+impl OpenTypeFont {
+    /// Shape left-to-right `text` like `Shaper::with_features(font, features)` and record the glyph buffer
+    /// after every step, to find out why text renders wrong.
+    ///
+    /// The `GSUB` lookups of `features` are applied in lookup order, then the `GPOS` single adjustments of `features`
+    /// and all pair adjustments (or the `kern` table), followed by cursive and mark attachment.
+    /// Every lookup has a stage, `fired` tells which ones changed something.
+    pub fn shape_debug(&self, text: &str, features: &[Tag]) -> ShapeTrace {
+        let mut stages = vec![];
+        shape_traced(self, text, Direction::LeftToRight, features, Some(&mut stages));
+        ShapeTrace { stages }
+    }
+}
+
+#[test]
+fn test_shape_debug() {
+    let font = OpenTypeFont::parse(&crate::opentype::test_otto_font());
+    let trace = font.shape_debug("ab", &[Tag(*b"liga")]);
+    let steps: Vec<_> = trace.stages.iter().map(|stage| stage.step).collect();
+    assert_eq!(steps, vec![ShapeStep::Cmap, ShapeStep::CursiveAttachment, ShapeStep::MarkAttachment]);
+    assert_eq!(trace.fired().collect::<Vec<_>>(), vec![ShapeStep::Cmap]);
+
+    // without a cmap both characters are .notdef
+    let glyphs = trace.glyphs();
+    assert_eq!(glyphs.len(), 2);
+    assert_eq!(glyphs[1].cluster, 1 .. 2);
+    assert_eq!(glyphs[0].advance.x(), 250.);
+}
+
+#[test]
+fn test_shape_debug_pair_lookups() {
+    use crate::{Font, GlyphId, Shaper};
+    use crate::opentype::gpos::{GPos, KernTable};
+
+    // two pair adjustment lookups that both kern .notdef against itself
+    let mut font = OpenTypeFont::parse(&crate::opentype::test_otto_font());
+    let mut gpos = GPos::default();
+    for &(lookup, kern) in &[(3, -10), (5, -20)] {
+        let mut table = KernTable::default();
+        table.glyph_pairs.insert((0, 0), kern);
+        gpos.pair_adjustments.insert(lookup, table);
+    }
+    font.gpos = Some(gpos);
+
+    let trace = font.shape_debug("ab", &[]);
+    let steps: Vec<_> = trace.stages.iter().map(|stage| stage.step).collect();
+    assert_eq!(steps, vec![
+        ShapeStep::Cmap, ShapeStep::PairAdjustment { lookup: 3 }, ShapeStep::PairAdjustment { lookup: 5 },
+        ShapeStep::CursiveAttachment, ShapeStep::MarkAttachment
+    ]);
+    assert_eq!(trace.stages[1].glyphs[0].advance.x(), 240.);
+    assert_eq!(trace.glyphs()[0].advance.x(), 220.);
+    assert_eq!(trace.glyphs()[1].advance.x(), 250.);
+
+    // the lookups add up, as in the shaper
+    assert_eq!(font.kerning(GlyphId(0), GlyphId(0)), -30.);
+    let shaped = Shaper::with_features(&font, &[]).shape("ab", Direction::LeftToRight);
+    assert_eq!(shaped[0].advance.x(), 220.);
+}
@@ -0,0 +1,98 @@
+use crate::{R, parsers::*};
+use crate::opentype::fixed;
+use nom::{
+    number::complete::{be_u16, be_i16, be_u32},
+    sequence::tuple,
+};
+
+/// The AAT tracking table
+#[derive(Clone, Debug)]
+pub struct Trak {
+    pub horizontal: Option<TrackData>,
+    pub vertical: Option<TrackData>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TrackData {
+    /// point sizes, ascending
+    pub sizes: Vec<f32>,
+    pub tracks: Vec<Track>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Track {
+    /// 0 is normal, -1 tight and 1 loose
+    pub track: f32,
+    pub name_index: u16,
+    /// tracking in font units for each of the sizes
+    pub values: Vec<i16>,
+}
+
+impl TrackData {
+    /// The tracking of the given track at the given point size (in font units).
+    ///
+    /// Values between the listed sizes are interpolated linearly, outside they are clamped.
+    pub fn tracking(&self, track: f32, size: f32) -> Option<f32> {
+        let values = &self.tracks.iter().find(|t| t.track == track)?.values;
+        let pos = self.sizes.iter().position(|&s| s >= size);
+        Some(match pos {
+            Some(0) => *values.get(0)? as f32,
+            Some(i) => {
+                let (s0, s1) = (self.sizes[i - 1], self.sizes[i]);
+                let (v0, v1) = (*values.get(i - 1)? as f32, *values.get(i)? as f32);
+                if s1 == s0 {
+                    return Some(v1);
+                }
+                v0 + (v1 - v0) * (size - s0) / (s1 - s0)
+            }
+            None => *values.last()? as f32
+        })
+    }
+}
+
+fn parse_track_data<'a>(data: &'a [u8], offset: usize) -> R<'a, TrackData> {
+    let (i, _) = offset_into(data, offset)?;
+    let (i, n_tracks) = be_u16(i)?;
+    let (i, n_sizes) = be_u16(i)?;
+    let (i, size_table_offset) = be_u32(i)?;
+
+    let sizes = iterator_n(offset_into(data, size_table_offset as usize)?.1, fixed, n_sizes).collect();
+
+    let mut tracks = Vec::with_capacity(n_tracks as usize);
+    for (track, name_index, offset) in iterator_n(i, tuple((fixed, be_u16, be_u16)), n_tracks) {
+        let values = iterator_n(offset_into(data, offset as usize)?.1, be_i16, n_sizes).collect();
+        tracks.push(Track { track, name_index, values });
+    }
+
+    Ok((i, TrackData { sizes, tracks }))
+}
+
+pub fn parse_trak(data: &[u8]) -> R<Trak> {
+    let (i, _version) = be_u32(data)?;
+    let (i, _format) = be_u16(i)?;
+    let (i, horiz_offset) = be_u16(i)?;
+    let (i, vert_offset) = be_u16(i)?;
+    let (i, _reserved) = be_u16(i)?;
+
+    let horizontal = match horiz_offset {
+        0 => None,
+        off => Some(parse_track_data(data, off as usize)?.1)
+    };
+    let vertical = match vert_offset {
+        0 => None,
+        off => Some(parse_track_data(data, off as usize)?.1)
+    };
+
+    Ok((i, Trak { horizontal, vertical }))
+}
+
+#[test]
+fn test_bad_offset() {
+    let data = [
+        0, 1, 0, 0, 0, 0, // version, format
+        0, 12, 0, 0, 0, 0, // horizontal data at 12, no vertical data
+        0, 1, 0, 1, 0, 0, 0, 200, // 1 track, 1 size, sizes at 200
+        0, 0, 0, 0, 0, 0, 0, 24, // track 0, values at 24
+    ];
+    assert!(parse_trak(&data).is_err());
+}
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use crate::{R, parsers::*};
+use nom::{
+    number::complete::{be_u8, be_u16, be_u32},
+    bytes::complete::take,
+};
+
+/// The AAT glyph information table
+#[derive(Clone, Debug)]
+pub struct Zapf {
+    // name -> gid
+    names: HashMap<String, u16>,
+}
+impl Zapf {
+    /// The *gid* of the glyph with the given name.
+    ///
+    /// If several glyphs share the name, the lowest *gid* is returned.
+    pub fn gid_for_name(&self, name: &str) -> Option<u16> {
+        self.names.get(name).cloned()
+    }
+}
+
+// the names (kind < 64) of one GlyphInfo record
+fn parse_glyph_info(data: &[u8]) -> R<Vec<String>> {
+    let (i, _group_offset) = be_u32(data)?;
+    let (i, _feature_offset) = be_u32(i)?;
+    let (i, num_unicodes) = be_u16(i)?;
+    let (mut i, _unicodes) = take(2 * num_unicodes as usize)(i)?;
+    let num_kind_names = parse(&mut i, be_u16)?;
+    let mut names = vec![];
+    for _ in 0 .. num_kind_names {
+        let kind = parse(&mut i, be_u8)?;
+        if kind < 64 {
+            let len = parse(&mut i, be_u8)?;
+            let name = parse(&mut i, take(len as usize))?;
+            if let Ok(name) = std::str::from_utf8(name) {
+                names.push(name.to_owned());
+            }
+        } else {
+            let _value = parse(&mut i, be_u16)?;
+        }
+    }
+    Ok((i, names))
+}
+
+pub fn parse_zapf(data: &[u8], num_glyphs: u32) -> R<Zapf> {
+    let (i, _version) = be_u32(data)?;
+    let (mut i, _extra_info) = be_u32(i)?;
+    let mut names = HashMap::new();
+    for gid in 0 .. num_glyphs.min(u16::max_value() as u32 + 1) {
+        let offset = parse(&mut i, be_u32)? as usize;
+        let info = match data.get(offset ..).map(parse_glyph_info) {
+            Some(Ok((_, info))) => info,
+            _ => {
+                warn!("invalid Zapf glyph info for gid {}", gid);
+                continue;
+            }
+        };
+        for name in info {
+            names.entry(name).or_insert(gid as u16);
+        }
+    }
+    Ok((i, Zapf { names }))
+}
+
+#[test]
+fn test_zapf() {
+    let mut data = vec![0, 1, 0, 0,  0, 0, 0, 0,  0, 0, 0, 16,  0, 0, 0, 37];
+    // gid 0: no unicodes, one Adobe name ".notdef"
+    data.extend_from_slice(&[255, 255, 255, 255,  255, 255, 255, 255,  0, 0,  0, 1,  2, 7]);
+    data.extend_from_slice(b".notdef");
+    // gid 1: U+0041, a CID and the universal name "A"
+    data.extend_from_slice(&[255, 255, 255, 255,  255, 255, 255, 255,  0, 1, 0, 0x41,  0, 2,  64, 0, 34,  0, 1, b'A']);
+    let (_, zapf) = parse_zapf(&data, 2).unwrap();
+    assert_eq!(zapf.gid_for_name(".notdef"), Some(0));
+    assert_eq!(zapf.gid_for_name("A"), Some(1));
+    assert_eq!(zapf.gid_for_name("B"), None);
+}
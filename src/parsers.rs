@@ -363,6 +363,13 @@ pub fn count_map<'a, K, V>(parser: impl Fn(&'a [u8]) -> R<'a, (K, V)>, count: us
         Ok((i, map))
     }
 }
+/// `data` starting at `offset`, or an error if the offset points past its end
+pub fn offset_into(data: &[u8], offset: usize) -> R<&[u8]> {
+    match data.get(offset ..) {
+        Some(i) => Ok((i, i)),
+        None => Err(nom::Err::Error(make_error(data, ErrorKind::Eof)))
+    }
+}
 pub fn offset(i: &[u8]) -> R<Offset> {
     Offset::parse(i)
 }
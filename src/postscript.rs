@@ -6,7 +6,7 @@ use tuple::{TupleElements, Map};
 use decorum::R32;
 use indexmap::set::IndexSet;
 use crate::R;
-use crate::parsers::{token, Token, comment, space, hex_string};
+use crate::parsers::{token, Token, comment, space, hex_string, word_sep};
 
 #[cfg(feature="unstable")]
 use slotmap::SlotMap;
@@ -281,6 +281,7 @@ operators!{
     "add"           => Add,
     "array"         => Array,
     "begin"         => Begin,
+    "bind"          => Bind,
     "currentdict"   => CurrentDict,
     "currentfile"   => CurrentFile,
     "cleartomark"   => ClearToMark,
@@ -311,9 +312,12 @@ operators!{
     "mark"          => Mark,
     "mul"           => Mul,
     "noaccess"      => NoAccess,
+    "ND"            => NoAccessDef,
+    "NP"            => NoAccessPut,
     "not"           => Not,
     "pop"           => Pop,
     "put"           => Put,
+    "RD"            => ReadBinary,
     "readonly"      => ReadOnly,
     "readstring"    => ReadString,
     "string"        => String,
@@ -321,7 +325,13 @@ operators!{
     "true"          => True,
     "]"             => EndArray;
     
-    "["             => Mark
+    "["             => Mark,
+    
+    // Type1 fonts usually define these themselves (and their definitions take precedence),
+    // but some foundries rely on them being present.
+    "-|"            => ReadBinary,
+    "|-"            => NoAccessDef,
+    "|"             => NoAccessPut
 }
 
 pub struct Input<'a> {
@@ -650,6 +660,10 @@ impl Vm {
                     item => panic!("begin: unespected item {:?}", self.display(item))
                 }
             }
+            Operator::Bind => {
+                // operators are resolved when executed, so there is nothing to bind.
+                // leave the procedure on the stack.
+            }
             Operator::CurrentDict => {
                 let &key = self.dict_stack.last().expect("no current dictionary");
                 self.push(Item::Dict(key));
@@ -780,6 +794,20 @@ impl Vm {
                     args => panic!("readstring: invalid arguments {:?}", self.display_tuple(args))
                 }
             }
+            Operator::ReadBinary => {
+                // equivalent to `string currentfile exch readstring pop`
+                match self.pop() {
+                    Item::Int(n) if n >= 0 => {
+                        let mut data = vec![0; n as usize];
+                        input.read_to(&mut data);
+                        input.parse(space);
+                        
+                        let key = self.make_string(data);
+                        self.push(Item::String(key));
+                    }
+                    arg => panic!("RD: invalid argument {:?}", self.display(arg))
+                }
+            }
             Operator::Dup => {
                 let v = self.pop();
                 self.push(v.clone());
@@ -928,6 +956,16 @@ impl Vm {
                 }
                 self.push(item);
             }
+            Operator::NoAccessDef => {
+                // `noaccess def`
+                self.exec_operator(Operator::NoAccess, input);
+                self.exec_operator(Operator::Def, input);
+            }
+            Operator::NoAccessPut => {
+                // `noaccess put`
+                self.exec_operator(Operator::NoAccess, input);
+                self.exec_operator(Operator::Put, input);
+            }
             Operator::EndArray => {
                 let start = self.stack.iter()
                     .rposition(|item| *item == Item::Mark)
@@ -954,6 +992,9 @@ impl Vm {
             }
             Operator::Eexec => {
                 match self.pop() {
+                    Item::File if input.iter().all(|&b| word_sep(b)) => {
+                        // nothing left in this block. (in pfb files the encrypted part is the next segment)
+                    }
                     Item::File => {
                         use crate::eexec::Decoder;
                         match input.try_parse(hex_string) {
@@ -0,0 +1,100 @@
+use std::io::{self, Read, Seek, SeekFrom};
+use std::collections::HashMap;
+use crate::{Font, FontError, ParseOptions, TrueTypeFont, OpenTypeFont, try_parse_with_options};
+use crate::opentype::Tables;
+
+fn read_u16(reader: &mut impl Read) -> io::Result<u16> {
+    let mut buf = [0; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_be_bytes(buf))
+}
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+// reads the table directory (the magic has already been consumed) and then the wanted tables
+fn read_tables<R: Read + Seek>(reader: &mut R, options: &ParseOptions) -> Result<Tables<Vec<u8>>, FontError> {
+    let num_tables = read_u16(reader)?;
+    let mut _search_params = [0; 6];
+    reader.read_exact(&mut _search_params)?;
+
+    let mut directory = Vec::with_capacity(num_tables as usize);
+    for _ in 0 .. num_tables {
+        let mut tag = [0; 4];
+        reader.read_exact(&mut tag)?;
+        let _checksum = read_u32(reader)?;
+        let offset = read_u32(reader)?;
+        let len = read_u32(reader)?;
+        if options.wants_table(&tag) {
+            directory.push((tag, offset, len));
+        }
+    }
+
+    // the lengths are checked before anything is allocated for them
+    let stream_len = reader.seek(SeekFrom::End(0))?;
+    if directory.iter().any(|&(_, offset, len)| offset as u64 + len as u64 > stream_len) {
+        return Err(FontError::Truncated);
+    }
+
+    // read in file order, so a forward-only source is seeked as little as possible
+    directory.sort_by_key(|&(_, offset, _)| offset);
+
+    let mut entries = HashMap::with_capacity(directory.len());
+    for (tag, offset, len) in directory {
+        reader.seek(SeekFrom::Start(offset as u64))?;
+        let mut data = vec![0; len as usize];
+        reader.read_exact(&mut data)?;
+        entries.insert(tag, data);
+    }
+    Ok(Tables { entries })
+}
+
+/// Parse a font from a reader.
+///
+/// For sfnt based fonts (TrueType and OpenType) only the table directory and the needed tables are read.
+/// All other formats are read completely and then parsed like `try_parse_with_options` would.
+pub fn parse_reader_with_options<R: Read + Seek>(mut reader: R, options: &ParseOptions) -> Result<Box<dyn Font + Send + Sync + 'static>, FontError> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    info!("font magic: {:?} ({:?})", magic, String::from_utf8_lossy(&magic));
+    match &magic {
+        b"OTTO" | [0,1,0,0] => Ok(Box::new(OpenTypeFont::try_from_tables(read_tables(&mut reader, options)?, options)?) as _),
+        b"true" => Ok(Box::new(TrueTypeFont::try_from_tables(read_tables(&mut reader, options)?, options)?) as _),
+        [0x80, 1, _, _] | b"%!PS" | b"wOFF" | b"wOF2" | b"ttcf" | [1, _, _, _] | [37, 33, _, _] => {
+            reader.seek(SeekFrom::Start(0))?;
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            try_parse_with_options(&data, options)
+        }
+        _ => Err(FontError::UnknownMagic(magic))
+    }
+}
+
+/// Parse a font from a reader, with the default options.
+pub fn parse_reader<R: Read + Seek>(reader: R) -> Result<Box<dyn Font + Send + Sync + 'static>, FontError> {
+    parse_reader_with_options(reader, &ParseOptions::default())
+}
+
+#[test]
+fn test_unknown_magic() {
+    let data = b"nope, not a font";
+    match parse_reader(io::Cursor::new(&data[..])) {
+        Err(FontError::UnknownMagic(magic)) => assert_eq!(&magic, b"nope"),
+        _ => panic!("expected UnknownMagic")
+    }
+    match parse_reader(io::Cursor::new(&b"OTTO\0"[..])) {
+        Err(FontError::Truncated) => {},
+        _ => panic!("expected Truncated")
+    }
+    // a table that claims to be 4 GB long
+    let data = [
+        b'O', b'T', b'T', b'O', 0, 1, 0, 0, 0, 0, 0, 0,
+        b'h', b'e', b'a', b'd', 0, 0, 0, 0, 0, 0, 0, 28, 0xFF, 0xFF, 0xFF, 0xFF,
+    ];
+    match parse_reader(io::Cursor::new(&data[..])) {
+        Err(FontError::Truncated) => {},
+        _ => panic!("expected Truncated")
+    }
+}
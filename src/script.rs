@@ -0,0 +1,94 @@
+use crate::opentype::Tag;
+
+/// A small set of frequent characters for each script, keyed by the OpenType script tag.
+static SAMPLES: &[(&[u8; 4], &str)] = &[
+    (b"latn", "AEINORSTaeinorstlcdmuhgpbfyw"),
+    (b"grek", "ΑΒΓΔΕΟΣΩαβγδεικλμνοπρστω"),
+    (b"cyrl", "АБВДЕИКНОПРСТабвгдеиклмнопрст"),
+    (b"armn", "ԱԲԳԴԵաբգդեոնրս"),
+    (b"hebr", "אבגדהוזחטיכלמנסעפצקרשת"),
+    (b"arab", "ابتثجحخدذرزسشصطعفقكلمنهوي"),
+    (b"syrc", "ܐܒܓܕܗܘܙܚܛܝܟܠܡܢܣ"),
+    (b"thaa", "ހށނރބޅކއވމ"),
+    (b"deva", "अआइउएकखगचजतदनपबमयरलवसह्ािीुे"),
+    (b"beng", "অআইউএকখগচজতদনপবমযরলসহ্ািী"),
+    (b"guru", "ਅਆਇਕਖਗਚਜਤਦਨਪਬਮਰਲਸਹ੍ਾਿੀ"),
+    (b"gujr", "અઆઇકખગચજતદનપબમરલસહ્ાિી"),
+    (b"orya", "ଅଆଇକଖଗଚଜତଦନପବମରଲସହ୍ାିୀ"),
+    (b"taml", "அஆஇஉஎகஙசஞடணதநபமயரலவ்ாி"),
+    (b"telu", "అఆఇకఖగచజతదనపబమరలసహ్ాి"),
+    (b"knda", "ಅಆಇಕಖಗಚಜತದನಪಬಮರಲಸಹ್ಾಿ"),
+    (b"mlym", "അആഇകഖഗചജതദനപബമരലസഹ്ാി"),
+    (b"sinh", "අආඉකගචජතදනපබමයරලවස්ාි"),
+    (b"thai", "กขคงจฉชซดตถทนบปผพมยรลวสหอะาเ"),
+    (b"lao ", "ກຂຄງຈຊດຕຖທນບປຜພມຍຣລວສຫອະາ"),
+    (b"tibt", "ཀཁགངཅཆཇཉཏཐདནཔཕབམ"),
+    (b"mymr", "ကခဂဃငစဆဇညတထဒဓနပဖဗဘမယရလဝသဟအ"),
+    (b"khmr", "កខគឃងចឆជឈញដឋឌឍណតថទធនបផពភមយរលវសហអ"),
+    (b"geor", "აბგდევზთიკლმნოპრსტუ"),
+    (b"ethi", "ሀለሐመሠረሰሸቀበተኀነአከወዐዘየደገጠጰጸፀፈፐ"),
+    (b"hang", "가나다라마바사아자차카타파하한국어글"),
+    (b"kana", "アイウエオカキクケコサシスセソタチツテトナニヌネノ"),
+    (b"hira", "あいうえおかきくけこさしすせそたちつてとなにぬねの"),
+    (b"hani", "一二三人大中国日本年月時分上下的是不了在有"),
+    (b"bopo", "ㄅㄆㄇㄈㄉㄊㄋㄌㄍㄎㄏㄐㄑㄒ"),
+];
+
+/// Unicode blocks (first, last codepoint) and the script they belong to
+static BLOCKS: &[(u32, u32, &[u8; 4])] = &[
+    (0x0041, 0x005A, b"latn"), (0x0061, 0x007A, b"latn"), (0x00C0, 0x024F, b"latn"),
+    (0x0370, 0x03FF, b"grek"), (0x0400, 0x052F, b"cyrl"), (0x0530, 0x058F, b"armn"),
+    (0x0590, 0x05FF, b"hebr"), (0x0600, 0x06FF, b"arab"), (0x0700, 0x074F, b"syrc"),
+    (0x0750, 0x077F, b"arab"), (0x0780, 0x07BF, b"thaa"), (0x08A0, 0x08FF, b"arab"),
+    (0x0900, 0x097F, b"deva"), (0x0980, 0x09FF, b"beng"), (0x0A00, 0x0A7F, b"guru"),
+    (0x0A80, 0x0AFF, b"gujr"), (0x0B00, 0x0B7F, b"orya"), (0x0B80, 0x0BFF, b"taml"),
+    (0x0C00, 0x0C7F, b"telu"), (0x0C80, 0x0CFF, b"knda"), (0x0D00, 0x0D7F, b"mlym"),
+    (0x0D80, 0x0DFF, b"sinh"), (0x0E00, 0x0E7F, b"thai"), (0x0E80, 0x0EFF, b"lao "),
+    (0x0F00, 0x0FFF, b"tibt"), (0x1000, 0x109F, b"mymr"), (0x10A0, 0x10FF, b"geor"),
+    (0x1100, 0x11FF, b"hang"), (0x1200, 0x139F, b"ethi"), (0x1780, 0x17FF, b"khmr"),
+    (0x1E00, 0x1EFF, b"latn"), (0x1F00, 0x1FFF, b"grek"), (0x3040, 0x309F, b"hira"),
+    (0x30A0, 0x30FF, b"kana"), (0x3100, 0x312F, b"bopo"), (0x3130, 0x318F, b"hang"),
+    (0x3400, 0x4DBF, b"hani"), (0x4E00, 0x9FFF, b"hani"), (0xAC00, 0xD7AF, b"hang"),
+    (0xF900, 0xFAFF, b"hani"), (0xFB50, 0xFDFF, b"arab"), (0xFE70, 0xFEFF, b"arab"),
+    (0x20000, 0x2FA1F, b"hani"),
+];
+
+/// The script of the Unicode block `codepoint` is in, if it is one of the known ones.
+pub fn script_of(codepoint: u32) -> Option<Tag> {
+    BLOCKS.iter()
+        .find(|&&(first, last, _)| first <= codepoint && codepoint <= last)
+        .map(|&(_, _, tag)| Tag(*tag))
+}
+
+/// The script with the most codepoints among `codepoints`.
+pub fn dominant_script(codepoints: impl Iterator<Item=u32>) -> Option<Tag> {
+    let mut counts: Vec<(Tag, usize)> = vec![];
+    for script in codepoints.filter_map(script_of) {
+        match counts.iter_mut().find(|(tag, _)| *tag == script) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((script, 1))
+        }
+    }
+    counts.into_iter().rev().max_by_key(|&(_, n)| n).map(|(tag, _)| tag)
+}
+
+/// The representative characters of `script`, or `None` if there is no built-in sample for it.
+pub fn script_sample(script: Tag) -> Option<&'static str> {
+    SAMPLES.iter().find(|&&(tag, _)| *tag == script.0).map(|&(_, sample)| sample)
+}
+
+#[test]
+fn test_script_sample() {
+    assert!(script_sample(Tag(*b"latn")).unwrap().contains('a'));
+    assert!(script_sample(Tag(*b"hani")).unwrap().contains('中'));
+    assert_eq!(script_sample(Tag(*b"zzzz")), None);
+}
+
+#[test]
+fn test_dominant_script() {
+    assert_eq!(script_of('ア' as u32), Some(Tag(*b"kana")));
+    let text = "日本語のテキスト abc";
+    assert_eq!(dominant_script(text.chars().map(|c| c as u32)), Some(Tag(*b"kana")));
+    assert_eq!(dominant_script("漢字かな".chars().map(|c| c as u32)), Some(Tag(*b"hani")));
+    assert_eq!(dominant_script(" 1".chars().map(|c| c as u32)), None);
+}
@@ -0,0 +1,422 @@
+use std::collections::{HashSet, BTreeMap};
+use std::iter::once;
+use crate::{FontError, R};
+use crate::opentype::{parse_tables, parse_head, parse_maxp, parse_loca, parse_hhea, parse_hmtx, cmap::parse_cmap};
+use crate::cff::read_cff;
+use nom::number::complete::be_u16;
+
+// tables that do not refer to glyph ids and are copied unchanged
+const COPIED_TABLES: &[&[u8; 4]] = &[b"OS/2", b"name", b"cvt ", b"fpgm", b"prep", b"gasp"];
+
+/// Write a TrueType or OpenType (CFF) font with only the glyphs `gids` (and `.notdef`) of the font `data`.
+///
+/// Components of composite glyphs (and the base and accent glyphs of CFF accented characters) are kept as well. The glyphs are renumbered in their original order,
+/// and `cmap`, `loca`, `glyf` (or `CFF `), `hmtx`, `hhea`, `maxp` and `head` are rewritten to match. `post` is reduced to
+/// version 3 (without glyph names). Tables that refer to glyph ids in other ways (like `GSUB`, `GPOS` or `kern`)
+/// are dropped. `CFF2` outlines are not supported.
+pub fn subset(data: &[u8], gids: &HashSet<u32>) -> Result<Vec<u8>, FontError> {
+    let tables = parse_tables(data).map_err(|_| FontError::Truncated)?.1;
+    if tables.contains(b"CFF2") {
+        return Err(FontError::UnsupportedTable("CFF2"));
+    }
+    let table = |tag: &[u8; 4]| tables.get(tag).ok_or(FontError::Truncated);
+    let head_data = table(b"head")?;
+    let hhea_data = table(b"hhea")?;
+    let maxp_data = table(b"maxp")?;
+    let head = parse_head(head_data).map_err(|_| FontError::Truncated)?.1;
+    let maxp = parse_maxp(maxp_data).map_err(|_| FontError::Truncated)?.1;
+    let hhea = parse_hhea(hhea_data).map_err(|_| FontError::Truncated)?.1;
+    let hmtx = parse_hmtx(table(b"hmtx")?, &hhea, &maxp).map_err(|_| FontError::Truncated)?.1;
+
+    let mut out_tables: BTreeMap<[u8; 4], Vec<u8>> = BTreeMap::new();
+    let mut new_head = head_data.to_vec();
+    let (magic, old_gids) = match tables.get(b"CFF ") {
+        Some(cff) => {
+            let slot = read_cff(cff).map_err(|_| FontError::Truncated)?.1.try_slot(0)?;
+            let mut old_gids: Vec<u32> = gids.iter().cloned().filter(|&gid| gid < slot.num_glyphs() as u32).chain(once(0)).collect();
+            // the base and accent glyphs of accented characters
+            let charstrings = slot.charstrings()?;
+            let components: Vec<u32> = old_gids.iter()
+                .filter_map(|&gid| charstrings.components(gid))
+                .flat_map(|(base, accent)| once(base).chain(once(accent)))
+                .collect();
+            old_gids.extend(components);
+            old_gids.sort();
+            old_gids.dedup();
+            out_tables.insert(*b"CFF ", slot.subset(&old_gids)?);
+            (*b"OTTO", old_gids)
+        }
+        None => {
+            let loca = parse_loca(table(b"loca")?, &head, &maxp).map_err(|_| FontError::Truncated)?.1;
+            let (old_gids, glyf, loca) = subset_glyf(table(b"glyf")?, &loca, gids)?;
+            out_tables.insert(*b"glyf", glyf);
+            out_tables.insert(*b"loca", loca);
+            // indexToLocFormat: long
+            new_head.get_mut(50 .. 52).ok_or(FontError::Truncated)?.copy_from_slice(&1u16.to_be_bytes());
+            ([0, 1, 0, 0], old_gids)
+        }
+    };
+    let new_gid = |old: u32| old_gids.binary_search(&old).ok().map(|n| n as u16);
+
+    // every glyph gets its own metrics
+    let mut new_hmtx = Vec::with_capacity(4 * old_gids.len());
+    for &gid in &old_gids {
+        let metrics = hmtx.metrics_for_gid(gid as u16);
+        new_hmtx.extend_from_slice(&(metrics.advance as u16).to_be_bytes());
+        new_hmtx.extend_from_slice(&(metrics.lsb as i16).to_be_bytes());
+    }
+    let mut new_hhea = hhea_data.to_vec();
+    new_hhea.get_mut(34 .. 36).ok_or(FontError::Truncated)?.copy_from_slice(&(old_gids.len() as u16).to_be_bytes());
+
+    let mut new_maxp = maxp_data.to_vec();
+    new_maxp.get_mut(4 .. 6).ok_or(FontError::Truncated)?.copy_from_slice(&(old_gids.len() as u16).to_be_bytes());
+
+    // the checksum adjustment is filled in at the end
+    new_head.get_mut(8 .. 12).ok_or(FontError::Truncated)?.copy_from_slice(&[0; 4]);
+
+    if let Some(cmap_data) = tables.get(b"cmap") {
+        let cmap = parse_cmap(cmap_data).map_err(|_| FontError::Truncated)?.1;
+        let mut mapping: Vec<(u32, u16)> = cmap.items()
+            .filter_map(|(cp, gid)| Some((cp, new_gid(gid.0)?)))
+            .filter(|&(_, gid)| gid != 0)
+            .collect();
+        mapping.sort();
+        mapping.dedup_by_key(|&mut (cp, _)| cp);
+        out_tables.insert(*b"cmap", cmap_table(&mapping));
+    }
+    if let Some(post) = tables.get(b"post") {
+        let mut new_post = post.get(.. 32).ok_or(FontError::Truncated)?.to_vec();
+        new_post[0 .. 4].copy_from_slice(&[0, 3, 0, 0]);
+        out_tables.insert(*b"post", new_post);
+    }
+    for &tag in COPIED_TABLES {
+        if let Some(data) = tables.get(tag) {
+            out_tables.insert(*tag, data.to_vec());
+        }
+    }
+    out_tables.insert(*b"hmtx", new_hmtx);
+    out_tables.insert(*b"hhea", new_hhea);
+    out_tables.insert(*b"maxp", new_maxp);
+    out_tables.insert(*b"head", new_head);
+
+    Ok(write_sfnt(&magic, &out_tables))
+}
+
+// the kept glyphs (with the components of composite glyphs), and the new `glyf` and `loca` (long offsets)
+fn subset_glyf(glyf: &[u8], loca: &[u32], gids: &HashSet<u32>) -> Result<(Vec<u32>, Vec<u8>, Vec<u8>), FontError> {
+    let glyph_data = |gid: u32| -> Result<&[u8], FontError> {
+        let start = loca[gid as usize] as usize;
+        let end = loca[gid as usize + 1] as usize;
+        glyf.get(start .. end).ok_or(FontError::Truncated)
+    };
+
+    // the closure over composite glyphs
+    let num_glyphs = match loca.len() {
+        0 => return Err(FontError::Truncated),
+        n => n as u32 - 1
+    };
+    let mut keep: HashSet<u32> = gids.iter().cloned().filter(|&gid| gid < num_glyphs).collect();
+    keep.insert(0);
+    let mut queue: Vec<u32> = keep.iter().cloned().collect();
+    while let Some(gid) = queue.pop() {
+        for (_, component) in components(glyph_data(gid)?).map_err(|_| FontError::Truncated)?.1 {
+            if (component as u32) < num_glyphs && keep.insert(component as u32) {
+                queue.push(component as u32);
+            }
+        }
+    }
+    let mut old_gids: Vec<u32> = keep.into_iter().collect();
+    old_gids.sort();
+    let new_gid = |old: u32| old_gids.binary_search(&old).ok().map(|n| n as u16);
+
+    let mut new_glyf = Vec::new();
+    let mut new_loca = Vec::with_capacity(4 * (old_gids.len() + 1));
+    for &gid in &old_gids {
+        new_loca.extend_from_slice(&(new_glyf.len() as u32).to_be_bytes());
+        let data = glyph_data(gid)?;
+        let start = new_glyf.len();
+        new_glyf.extend_from_slice(data);
+        for (pos, component) in components(data).map_err(|_| FontError::Truncated)?.1 {
+            let component = new_gid(component as u32).unwrap_or(0);
+            new_glyf[start + pos .. start + pos + 2].copy_from_slice(&component.to_be_bytes());
+        }
+        pad(&mut new_glyf);
+    }
+    new_loca.extend_from_slice(&(new_glyf.len() as u32).to_be_bytes());
+    Ok((old_gids, new_glyf, new_loca))
+}
+
+// the component glyph ids of a composite glyph and the position of each in the glyph data
+fn components(data: &[u8]) -> R<Vec<(usize, u16)>> {
+    let mut components = vec![];
+    if data.len() < 10 || data[.. 2] != [0xFF, 0xFF] {
+        return Ok((data, components));
+    }
+    let mut i = &data[10 ..];
+    loop {
+        let (rest, flags) = be_u16(i)?;
+        let (rest, gid) = be_u16(rest)?;
+        components.push((data.len() - i.len() + 2, gid));
+        // arguments, then the scale
+        let mut size = if flags & 1 != 0 { 4 } else { 2 };
+        if flags & 0x08 != 0 {
+            size += 2;
+        } else if flags & 0x40 != 0 {
+            size += 4;
+        } else if flags & 0x80 != 0 {
+            size += 8;
+        }
+        i = rest.get(size ..).unwrap_or(&[]);
+        if flags & 0x20 == 0 {
+            break;
+        }
+    }
+    Ok((i, components))
+}
+
+fn pad(data: &mut Vec<u8>) {
+    while data.len() % 4 != 0 {
+        data.push(0);
+    }
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0; 4];
+        word[.. chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+// (searchRange, entrySelector, rangeShift) for `n` entries of `size` bytes
+fn search_params(n: u16, size: u16) -> (u16, u16, u16) {
+    let mut entry_selector = 0;
+    while 2u32 << entry_selector <= n as u32 {
+        entry_selector += 1;
+    }
+    let search_range = (1u16 << entry_selector) * size;
+    (search_range, entry_selector, (n * size).saturating_sub(search_range))
+}
+
+/// A `cmap` table with a format 4 (BMP) and a format 12 (full Unicode) subtable for the Windows platform.
+/// If the format 4 subtable would not fit into 64 KiB, only the format 12 one is written.
+///
+/// `mapping` must be sorted by codepoint.
+fn cmap_table(mapping: &[(u32, u16)]) -> Vec<u8> {
+    // runs of consecutive codepoints that map to consecutive glyphs: (first codepoint, last codepoint, first gid)
+    let mut runs: Vec<(u32, u32, u16)> = vec![];
+    for &(cp, gid) in mapping {
+        if let Some(run) = runs.last_mut() {
+            if run.1 + 1 == cp && gid as u32 == run.2 as u32 + (cp - run.0) {
+                run.1 = cp;
+                continue;
+            }
+        }
+        runs.push((cp, cp, gid));
+    }
+
+    // format 4. Runs are split at the end of the BMP, and the list ends with the 0xFFFF segment.
+    let mut segments: Vec<(u16, u16, u16)> = runs.iter()
+        .filter(|&&(start, _, _)| start < 0xFFFF)
+        .map(|&(start, end, gid)| (start as u16, end.min(0xFFFE) as u16, gid.wrapping_sub(start as u16)))
+        .collect();
+    segments.push((0xFFFF, 0xFFFF, 1));
+    // the length of a format 4 subtable is 16 bit, too many segments only fit into format 12
+    let format4_len = 16 + 8 * segments.len() as u32;
+    let format4 = match format4_len <= 0xFFFF {
+        true => {
+            let seg_count = segments.len() as u16;
+            let (search_range, entry_selector, range_shift) = search_params(seg_count, 2);
+            let mut format4 = vec![];
+            for &n in &[4, format4_len as u16, 0, 2 * seg_count, search_range, entry_selector, range_shift] {
+                format4.extend_from_slice(&n.to_be_bytes());
+            }
+            for &(_, end, _) in &segments {
+                format4.extend_from_slice(&end.to_be_bytes());
+            }
+            format4.extend_from_slice(&[0, 0]);
+            for &(start, _, _) in &segments {
+                format4.extend_from_slice(&start.to_be_bytes());
+            }
+            for &(_, _, delta) in &segments {
+                format4.extend_from_slice(&delta.to_be_bytes());
+            }
+            format4.extend(std::iter::repeat(0).take(2 * seg_count as usize));
+            Some(format4)
+        }
+        false => None
+    };
+
+    let mut format12 = vec![0, 12, 0, 0];
+    for &n in &[16 + 12 * runs.len() as u32, 0, runs.len() as u32] {
+        format12.extend_from_slice(&n.to_be_bytes());
+    }
+    for &(start, end, gid) in &runs {
+        for &n in &[start, end, gid as u32] {
+            format12.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+
+    match format4 {
+        Some(format4) => {
+            let mut cmap = vec![0, 0, 0, 2];
+            cmap.extend_from_slice(&[0, 3, 0, 1]);
+            cmap.extend_from_slice(&20u32.to_be_bytes());
+            cmap.extend_from_slice(&[0, 3, 0, 10]);
+            cmap.extend_from_slice(&(20 + format4.len() as u32).to_be_bytes());
+            cmap.extend_from_slice(&format4);
+            cmap.extend_from_slice(&format12);
+            cmap
+        }
+        None => {
+            let mut cmap = vec![0, 0, 0, 1];
+            cmap.extend_from_slice(&[0, 3, 0, 10]);
+            cmap.extend_from_slice(&12u32.to_be_bytes());
+            cmap.extend_from_slice(&format12);
+            cmap
+        }
+    }
+}
+
+// a font file with the tables, and the checksum adjustment of `head` set
+fn write_sfnt(magic: &[u8; 4], tables: &BTreeMap<[u8; 4], Vec<u8>>) -> Vec<u8> {
+    let num_tables = tables.len() as u16;
+    let (search_range, entry_selector, range_shift) = search_params(num_tables, 16);
+    let mut data = magic.to_vec();
+    for &n in &[num_tables, search_range, entry_selector, range_shift] {
+        data.extend_from_slice(&n.to_be_bytes());
+    }
+    let mut offset = 12 + 16 * tables.len();
+    let mut head_offset = None;
+    for (tag, table) in tables {
+        if tag == b"head" {
+            head_offset = Some(offset);
+        }
+        data.extend_from_slice(tag);
+        data.extend_from_slice(&checksum(table).to_be_bytes());
+        data.extend_from_slice(&(offset as u32).to_be_bytes());
+        data.extend_from_slice(&(table.len() as u32).to_be_bytes());
+        offset += (table.len() + 3) / 4 * 4;
+    }
+    for table in tables.values() {
+        data.extend_from_slice(table);
+        pad(&mut data);
+    }
+    if let Some(head) = head_offset {
+        let adjustment = 0xB1B0AFBAu32.wrapping_sub(checksum(&data));
+        data[head + 8 .. head + 12].copy_from_slice(&adjustment.to_be_bytes());
+    }
+    data
+}
+
+#[test]
+fn test_subset() {
+    use crate::{Font, GlyphId, opentype::{build_sfnt, OpenTypeFont}};
+
+    let triangle: &[u8] = &[
+        0, 1, 0, 0, 0, 0, 0, 100, 0, 100, // one contour, bbox
+        0, 2, 0, 0, // three points, no instructions
+        1, 1, 1, // on curve, long coordinates
+        0, 0, 0, 100, 0xFF, 0xCE, // x: 0 100 50
+        0, 0, 0, 0, 0, 100, // y: 0 0 100
+        0, 0, 0, // padding
+    ];
+    let composite: &[u8] = &[
+        0xFF, 0xFF, 0, 200, 0, 0, 1, 44, 0, 100, // bbox
+        0, 3, 0, 3, 0, 200, 0, 0, // glyph 3 moved by (200, 0)
+        0, 0, // padding
+    ];
+    let mut glyf = triangle.to_vec();
+    glyf.extend_from_slice(composite);
+    glyf.extend_from_slice(triangle);
+    // .notdef is empty, glyph 1 and 3 are triangles, glyph 2 the moved glyph 3
+    let loca: Vec<u8> = [0u32, 0, 32, 52, 84].iter().flat_map(|n| n.to_be_bytes().to_vec()).collect();
+
+    let mut head = vec![0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0x5F, 0x0F, 0x3C, 0xF5, 0, 0, 3, 232];
+    head.extend_from_slice(&[0; 16]);
+    head.extend_from_slice(&[0, 0, 0, 0, 1, 44, 0, 100]);
+    head.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1, 0, 0]); // long loca
+    let mut hhea = vec![0, 1, 0, 0, 3, 32, 255, 56, 0, 0];
+    hhea.extend_from_slice(&[0; 24]);
+    hhea.extend_from_slice(&[0, 4]);
+    let maxp = [0, 0, 0x50, 0, 0, 4];
+    let hmtx = [0, 250, 0, 0, 0, 100, 0, 0, 1, 44, 0, 200, 0, 150, 0, 0];
+    let cmap = cmap_table(&[('A' as u32, 1), ('B' as u32, 2), ('C' as u32, 3)]);
+    let data = build_sfnt(&[0, 1, 0, 0], &[(b"cmap", &cmap), (b"glyf", &glyf), (b"head", &head), (b"hhea", &hhea), (b"hmtx", &hmtx), (b"loca", &loca), (b"maxp", &maxp)]);
+
+    let original = OpenTypeFont::parse(&data);
+    assert_eq!(original.gid_for_unicode_codepoint('B' as u32), Some(GlyphId(2)));
+
+    let gids = [2].iter().cloned().collect();
+    let subset_data = subset(&data, &gids).unwrap();
+    assert_eq!(checksum(&subset_data), 0xB1B0AFBA);
+    let font = OpenTypeFont::parse(&subset_data);
+    assert_eq!(font.num_glyphs(), 3);
+    assert_eq!(font.gid_for_unicode_codepoint('A' as u32), None);
+    assert_eq!(font.gid_for_unicode_codepoint('B' as u32), Some(GlyphId(1)));
+    assert_eq!(font.gid_for_unicode_codepoint('C' as u32), Some(GlyphId(2)));
+    assert_eq!(font.glyph(GlyphId(1)).unwrap().path.bounds(), original.glyph(GlyphId(2)).unwrap().path.bounds());
+    assert_eq!(font.glyph(GlyphId(1)).unwrap().metrics.advance, 300.);
+    assert_eq!(font.glyph(GlyphId(2)).unwrap().metrics.advance, 150.);
+}
+
+#[test]
+fn test_cmap_table_format12_only() {
+    // every other codepoint, so each one is a segment of its own
+    let mapping: Vec<(u32, u16)> = (0 .. 10000).map(|i| (2 * i, 1 + i as u16)).collect();
+    let cmap = cmap_table(&mapping);
+    assert_eq!(&cmap[.. 12], &[0, 0, 0, 1, 0, 3, 0, 10, 0, 0, 0, 12]);
+    assert_eq!(&cmap[12 .. 14], &[0, 12]);
+    assert_eq!(cmap.len(), 12 + 16 + 12 * 10000);
+}
+
+#[test]
+fn test_subset_cff() {
+    use crate::{Font, GlyphId, opentype::{build_sfnt, OpenTypeFont}};
+    use pathfinder_geometry::{rect::RectF, vector::Vector2F};
+
+    let cff = [
+        1, 0, 4, 1, // header
+        0, 1, 1, 1, 2, b'A', // name index
+        0, 1, 1, 1, 20, // top dict index
+        29, 0, 0, 0, 38, 15, 29, 0, 0, 0, 43, 17, 141, 29, 0, 0, 0, 64, 18, // charset, CharStrings, Private
+        0, 0, // string index
+        0, 0, // global subroutines
+        0, 0, 34, 0, 35, // charset: A B
+        0, 3, 1, 1, 2, 5, 15, // CharStrings index
+        14, // .notdef: endchar
+        32, 10, 14, // -107 callsubr endchar: the square of subroutine 0
+        189, 189, 21, 248, 136, 6, 248, 136, 7, 14, // 50 50 rmoveto 500 hlineto 500 vlineto endchar
+        141, 19, // Private: Subrs right after it
+        0, 1, 1, 1, 14, // local subroutines
+        239, 239, 21, 248, 136, 6, 248, 136, 7, 252, 136, 6, 11,
+    ];
+    let mut head = vec![0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0x5F, 0x0F, 0x3C, 0xF5, 0, 0, 3, 232];
+    head.extend_from_slice(&[0; 16]);
+    head.extend_from_slice(&[0, 0, 0, 0, 3, 232, 3, 232]);
+    head.extend_from_slice(&[0; 10]);
+    let mut hhea = vec![0, 1, 0, 0, 3, 32, 255, 56, 0, 0];
+    hhea.extend_from_slice(&[0; 24]);
+    hhea.extend_from_slice(&[0, 3]);
+    let maxp = [0, 0, 0x50, 0, 0, 3];
+    let hmtx = [0, 250, 0, 0, 2, 88, 0, 100, 1, 244, 0, 50];
+    let cmap = cmap_table(&[('A' as u32, 1), ('B' as u32, 2)]);
+    let data = build_sfnt(b"OTTO", &[(b"CFF ", &cff), (b"cmap", &cmap), (b"head", &head), (b"hhea", &hhea), (b"hmtx", &hmtx), (b"maxp", &maxp)]);
+
+    let gids = [2].iter().cloned().collect();
+    let subset_data = subset(&data, &gids).unwrap();
+    assert_eq!(&subset_data[.. 4], b"OTTO");
+    assert_eq!(checksum(&subset_data), 0xB1B0AFBA);
+    let font = OpenTypeFont::parse(&subset_data);
+    assert_eq!(font.num_glyphs(), 2);
+    assert_eq!(font.gid_for_unicode_codepoint('A' as u32), None);
+    assert_eq!(font.gid_for_unicode_codepoint('B' as u32), Some(GlyphId(1)));
+    let glyph = font.glyph(GlyphId(1)).unwrap();
+    assert_eq!(glyph.path.bounds(), RectF::from_points(Vector2F::new(50., 50.), Vector2F::new(550., 550.)));
+    assert_eq!(glyph.metrics.advance, 500.);
+
+    // the local subroutines are kept
+    let gids = [1].iter().cloned().collect();
+    let font = OpenTypeFont::parse(&subset(&data, &gids).unwrap());
+    assert_eq!(font.glyph(GlyphId(1)).unwrap().path.bounds(), RectF::from_points(Vector2F::new(100., 100.), Vector2F::new(600., 600.)));
+}
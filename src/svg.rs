@@ -1,4 +1,4 @@
-use crate::{R};
+use crate::{R, parsers::offset_into};
 use nom::{
     number::complete::{be_u16, be_u32},
 };
@@ -28,7 +28,7 @@ pub fn parse_svg(data: &[u8]) -> R<SvgTable> {
     let (i, document_list_offset) = be_u32(i)?;
     let (i, _reserved) = be_u32(i)?;
 
-    let (_, svg) = read_document_list(&data[document_list_offset as usize ..])?;
+    let (_, svg) = read_document_list(offset_into(data, document_list_offset as usize)?.1)?;
     Ok((i, svg))
 }
 
@@ -43,13 +43,20 @@ fn read_document_list(input: &[u8]) -> R<SvgTable> {
         let (i, data_len) = be_u32(i)?;
         data = i;
 
-        let svg_data = &input[data_offset as usize .. data_offset as usize + data_len as usize];
+        let svg_data = match input.get(data_offset as usize .. data_offset as usize + data_len as usize) {
+            Some(svg_data) => svg_data,
+            None => {
+                warn!("SVG document of glyphs {} ..= {} is past the end", start_gid, end_gid);
+                continue;
+            }
+        };
         
         // std::fs::write(format!("/tmp/font/{}.svg", start_gid), svg_data);
         let svg = match Svg::from_data(svg_data) {
             Ok(svg) => Arc::new(svg),
             Err(e) => {
-                panic!("SVG error: {:?}", e)
+                warn!("SVG error: {:?}", e);
+                continue;
             }
         };
         for gid in start_gid ..= end_gid {
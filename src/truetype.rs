@@ -1,21 +1,38 @@
 use std::iter;
+use std::borrow::Cow;
+use std::sync::{Arc, RwLock};
+use std::collections::HashMap;
 use std::ops::Deref;
-use crate::{Font, Glyph, R, IResultExt, GlyphId, Name};
+use crate::{Font, Glyph, R, IResultExt, GlyphId, Name, VMetrics, ParseOptions, CaretSlope, GlyphCountReport, LineMetrics, GlyphProps, Axis, FontError, GlyphError, Limit, check_limit, check_glyphs, warn_invalid};
 use crate::parsers::{iterator, parse};
+use crate::glyphname::gid_for_name_via_unicode;
+use crate::geometry::ink_top;
 use pdf_encoding::Encoding;
 use nom::{
     number::complete::{be_u8, be_i8, be_i16, be_u16},
     bytes::complete::take,
-    sequence::tuple
+    sequence::tuple,
+    error::{make_error, ErrorKind},
 };
 use pathfinder_content::outline::{Outline, Contour};
 use pathfinder_geometry::{vector::Vector2F, transform2d::Transform2F, rect::RectF};
 use crate::opentype::{
-    parse_tables, parse_head, parse_maxp, parse_loca,
-    parse_hhea, parse_hmtx, parse_name, Hmtx, Tables,
+    parse_tables, parse_head, parse_maxp, parse_loca, Tag,
+    parse_hhea, parse_hmtx, read_name, Hmtx, Tables, glyph_count_report, check_glyph_count,
+    select_vmetrics, font_weight, font_is_italic,
     cmap::{CMap, parse_cmap},
     kern::{parse_kern},
-    gpos::KernTable,
+    gpos::{GPos, parse_gpos},
+    post::{Post, parse_post},
+    trak::{Trak, parse_trak},
+    os2::{Os2, parse_os2},
+    prop::{Prop, parse_prop},
+    bsln::{Bsln, parse_bsln},
+    fvar::{Fvar, parse_fvar},
+    gvar::{Gvar, parse_gvar},
+    avar::{Avar, parse_avar},
+    hvar::{Hvar, parse_hvar},
+    zapf::{Zapf, parse_zapf},
 };
 use pathfinder_geometry::{transform2d::Matrix2x2F};
 use itertools::Itertools;
@@ -23,75 +40,263 @@ use itertools::Itertools;
 #[derive(Clone)]
 pub enum Shape {
     Simple(Outline),
-    Compound(Vec<(u32, Transform2F)>),
+    Compound(Vec<Component>),
     Empty
 }
 
+/// A glyph placed in a compound glyph
+#[derive(Copy, Clone, Debug)]
+pub struct Component {
+    pub gid: u32,
+    pub transform: Transform2F,
+    /// ROUND_XY_TO_GRID: the offset is rounded to whole units (after variations moved it)
+    pub round_to_grid: bool,
+    /// USE_MY_METRICS: the compound glyph has the metrics of this component
+    pub use_my_metrics: bool,
+}
+impl Shape {
+    /// numberOfContours as in the glyph header: -1 for compound glyphs, 0 for empty ones.
+    pub fn num_contours(&self) -> i16 {
+        match *self {
+            Shape::Simple(ref outline) => outline.contours().len() as i16,
+            Shape::Compound(_) => -1,
+            Shape::Empty => 0
+        }
+    }
+}
+
+/// The glyphs of TrueType outlines.
+///
+/// Those of a `glyf` table are parsed when they are used. WOFF2 fonts with a transformed `glyf` table
+/// and instances of variable fonts have their shapes parsed already.
+#[derive(Clone)]
+pub enum Glyphs {
+    /// the `glyf` table and its `loca` offsets
+    Glyf(Arc<[u8]>, Arc<[u32]>),
+    Shapes(Vec<Shape>),
+}
+impl Glyphs {
+    /// The glyphs of the `glyf` table `data`, or an error if a `loca` entry points outside of it.
+    pub fn from_glyf(loca: &[u32], data: &[u8]) -> Result<Glyphs, FontError> {
+        for (start, end) in loca.iter().cloned().tuple_windows() {
+            if start > end || end as usize > data.len() {
+                return Err(FontError::Truncated);
+            }
+        }
+        Ok(Glyphs::Glyf(data.into(), loca.into()))
+    }
+    /// The number of glyphs
+    pub fn len(&self) -> usize {
+        match *self {
+            Glyphs::Glyf(_, ref loca) => loca.len().saturating_sub(1),
+            Glyphs::Shapes(ref shapes) => shapes.len(),
+        }
+    }
+    /// The shape of glyph `idx`, or `None` if there is no such glyph or it is broken.
+    pub fn shape(&self, idx: u32) -> Option<Cow<Shape>> {
+        warn_invalid(self.try_shape(idx))
+    }
+    /// Like `shape`, but tells a missing glyph from a broken one.
+    pub fn try_shape(&self, idx: u32) -> Result<Cow<Shape>, GlyphError> {
+        match *self {
+            Glyphs::Glyf(..) => {
+                let data = self.glyph_data(idx).ok_or(GlyphError::Missing(GlyphId(idx)))?;
+                match parse_glyph_shape(data) {
+                    Ok((_, shape)) => Ok(Cow::Owned(shape)),
+                    Err(_) => Err(GlyphError::Invalid(GlyphId(idx), "broken glyf entry".into()))
+                }
+            }
+            Glyphs::Shapes(ref shapes) => shapes.get(idx as usize).map(Cow::Borrowed).ok_or(GlyphError::Missing(GlyphId(idx))),
+        }
+    }
+    /// numberOfContours of glyph `idx`, see `Shape::num_contours`. Glyphs of the `glyf` table are not parsed for it.
+    pub fn num_contours(&self, idx: u32) -> Option<i16> {
+        match *self {
+            Glyphs::Glyf(..) => match self.glyph_data(idx)? {
+                &[] => Some(0),
+                &[a, b, ..] => Some(i16::from_be_bytes([a, b])),
+                _ => None
+            }
+            Glyphs::Shapes(ref shapes) => shapes.get(idx as usize).map(|shape| shape.num_contours()),
+        }
+    }
+    // the glyf entry of glyph `idx`
+    fn glyph_data(&self, idx: u32) -> Option<&[u8]> {
+        match *self {
+            Glyphs::Glyf(ref data, ref loca) => {
+                let start = *loca.get(idx as usize)?;
+                let end = *loca.get(idx as usize + 1)?;
+                data.get(start as usize .. end as usize)
+            }
+            Glyphs::Shapes(_) => None
+        }
+    }
+}
+impl From<Vec<Shape>> for Glyphs {
+    fn from(shapes: Vec<Shape>) -> Glyphs {
+        Glyphs::Shapes(shapes)
+    }
+}
+
 #[derive(Clone)]
 pub struct TrueTypeFont {
-    shapes: Vec<Shape>,
+    glyphs: Glyphs,
+    outline_cache: OutlineCache,
     cmap: Option<CMap>,
     hmtx: Hmtx,
     units_per_em: u16,
     bbox: RectF,
-    kern: KernTable,
+    // only the pair adjustments, or the kern table
+    kern: GPos,
+    caret_slope: Option<CaretSlope>,
+    vmetrics: Option<VMetrics>,
+    // macStyle of `head`
+    mac_style: u16,
+    post: Option<Post>,
+    trak: Option<Trak>,
+    os2: Option<Os2>,
+    prop: Option<Prop>,
+    bsln: Option<Bsln>,
+    fvar: Option<Fvar>,
+    variations: Option<Arc<GlyfVariations>>,
+    zapf: Option<Zapf>,
+    glyph_counts: GlyphCountReport,
     name: Name
 }
 
 impl TrueTypeFont {
     pub fn parse(data: &[u8]) -> Self {
-        let tables = parse_tables(data).get();
+        TrueTypeFont::parse_with_options(data, &ParseOptions::default())
+    }
+    pub fn parse_with_options(data: &[u8], options: &ParseOptions) -> Self {
+        let mut tables = parse_tables(data).get();
+        tables.retain_wanted(options);
         TrueTypeFont::parse_glyf(tables)
     }
     pub fn parse_glyf(tables: Tables<impl Deref<Target=[u8]>>) -> Self {
-        let head = parse_head(tables.get(b"head").expect("no head")).get();
-        let maxp = parse_maxp(tables.get(b"maxp").expect("no maxp")).get();
-        let loca = parse_loca(tables.get(b"loca").expect("no loca"), &head, &maxp).get();
-        let hhea = parse_hhea(tables.get(b"hhea").expect("no hhea")).get();
-        let hmtx = parse_hmtx(tables.get(b"hmtx").expect("no hmtx"), &hhea, &maxp).get();
+        TrueTypeFont::try_from_tables(tables, &ParseOptions::default()).unwrap_or_else(|e| panic!("{}", e))
+    }
+    /// Like `parse_glyf`, but drops the tables `options` does not want, enforces its limits
+    /// and returns an error if the outlines or the tables they need are missing or broken.
+    pub fn try_from_tables(mut tables: Tables<impl Deref<Target=[u8]>>, options: &ParseOptions) -> Result<Self, FontError> {
+        tables.retain_wanted(options);
+        check_glyph_count(&tables, options)?;
+        let head = tables.parse_required(b"head", parse_head)?;
+        let maxp = tables.parse_required(b"maxp", parse_maxp)?;
+        let loca = tables.parse_required(b"loca", |i| parse_loca(i, &head, &maxp))?;
+        let hhea = tables.parse_required(b"hhea", parse_hhea)?;
+        let hmtx = tables.parse_required(b"hmtx", |i| parse_hmtx(i, &hhea, &maxp))?;
         
-        let shapes = parse_shapes(&loca, tables.get(b"glyf").unwrap());
+        let glyf = tables.get(b"glyf").ok_or_else(|| FontError::Parse("no glyf table".into()))?;
+        let glyphs = Glyphs::from_glyf(&loca, glyf)?;
+        check_shapes(&glyphs, options)?;
         
-        TrueTypeFont::from_shapes_and_metrics(tables, shapes, hmtx)
+        let font = TrueTypeFont::try_from_shapes_and_metrics(tables, glyphs, hmtx)?;
+        check_glyphs(&font, options)?;
+        Ok(font)
+    }
+    pub fn from_shapes_and_metrics(tables: Tables<impl Deref<Target=[u8]>>, glyphs: impl Into<Glyphs>, hmtx: Hmtx) -> TrueTypeFont {
+        TrueTypeFont::try_from_shapes_and_metrics(tables, glyphs, hmtx).unwrap_or_else(|e| panic!("{}", e))
     }
-    pub fn from_shapes_and_metrics(tables: Tables<impl Deref<Target=[u8]>>, shapes: Vec<Shape>, hmtx: Hmtx) -> TrueTypeFont {
-        let head = parse_head(tables.get(b"head").expect("no head")).get();
-        let cmap = tables.get(b"cmap").map(|data| parse_cmap(data).get());
-        let name = tables.get(b"name").map(|data| parse_name(data).get()).unwrap_or_default();
+    /// Like `from_shapes_and_metrics`, but returns an error if the `head` table is missing or broken.
+    ///
+    /// Other broken tables are skipped.
+    pub fn try_from_shapes_and_metrics(tables: Tables<impl Deref<Target=[u8]>>, glyphs: impl Into<Glyphs>, hmtx: Hmtx) -> Result<TrueTypeFont, FontError> {
+        let glyphs = glyphs.into();
+        let head = tables.parse_required(b"head", parse_head)?;
+        let cmap = tables.parse_optional(b"cmap", parse_cmap);
+        let name = read_name(&tables);
+        let hhea = tables.parse_optional(b"hhea", parse_hhea);
+        let caret_slope = hhea.as_ref().map(|hhea| hhea.caret_slope());
+        let os2 = tables.parse_optional(b"OS/2", parse_os2);
+        let vmetrics = select_vmetrics(hhea.map(|hhea| hhea.into()), os2.as_ref());
+        let zapf = tables.parse_optional(b"Zapf", |data| parse_zapf(data, glyphs.len() as u32));
+        let variations = GlyfVariations::from_tables(&tables, &hmtx).map(Arc::new);
+        // GPOS pair adjustments take precedence over the kern table, which is used if GPOS has none
+        let gpos = match tables.parse_optional(b"maxp", parse_maxp) {
+            Some(maxp) => tables.parse_optional(b"GPOS", |data| parse_gpos(data, &maxp)),
+            None => None
+        };
+        let kern = match gpos {
+            Some(gpos) if !gpos.pair_adjustments.is_empty() => GPos { pair_adjustments: gpos.pair_adjustments, .. GPos::default() },
+            _ => GPos::from_kern(tables.parse_optional(b"kern", parse_kern).unwrap_or_default())
+        };
         
-        TrueTypeFont {
-            shapes,
+        Ok(TrueTypeFont {
+            glyphs,
+            outline_cache: OutlineCache::default(),
             cmap,
             hmtx,
             units_per_em: head.units_per_em,
             bbox: head.bbox(),
-            kern: tables.get(b"kern").map(|data| parse_kern(data).get()).unwrap_or_default(),
+            kern,
+            caret_slope,
+            vmetrics,
+            mac_style: head.mac_style,
+            post: tables.parse_optional(b"post", parse_post),
+            trak: tables.parse_optional(b"trak", parse_trak),
+            os2,
+            prop: tables.parse_optional(b"prop", parse_prop),
+            bsln: tables.parse_optional(b"bsln", parse_bsln),
+            fvar: tables.parse_optional(b"fvar", parse_fvar),
+            variations,
+            zapf,
+            glyph_counts: glyph_count_report(&tables),
             name
+        })
+    }
+    /// The font at a point of the design space, given in user coordinates (like `wght` 700).
+    ///
+    /// Axes that are not given stay at their default. The coordinates are mapped by `avar`, the outlines are moved
+    /// by the `gvar` deltas and the advances by the `HVAR` deltas, or those of the phantom points if there is no `HVAR`.
+    /// Fonts that are not variable are returned unchanged.
+    pub fn instance(&self, coords: &[(Tag, f32)]) -> TrueTypeFont {
+        let mut font = self.clone();
+        if let (Some(fvar), Some(variations)) = (&self.fvar, &self.variations) {
+            let (shapes, hmtx) = variations.instance(&fvar.normalize(coords));
+            font.glyphs = Glyphs::Shapes(shapes);
+            font.outline_cache = OutlineCache::default();
+            font.hmtx = hmtx;
         }
+        font
     }
-    fn get_path(&self, idx: u32) -> Option<Outline> {
-        get_outline(&self.shapes, idx)
+    /// The named instance (from `fvar`) with the given subfamily or PostScript name, like "Bold".
+    pub fn named_instance(&self, name: &str) -> Option<TrueTypeFont> {
+        let coords = self.fvar.as_ref()?.named_instance(&self.name, name)?;
+        Some(self.instance(&coords))
+    }
+    /// Move this font to the given user coordinates, like `instance`.
+    pub fn set_variation(&mut self, coords: &[(Tag, f32)]) {
+        *self = self.instance(coords);
     }
 }
 impl Font for TrueTypeFont {
     fn num_glyphs(&self) -> u32 {
-        self.shapes.len() as u32
+        self.glyphs.len() as u32
     }
     fn font_matrix(&self) -> Transform2F {
         let scale = 1.0 / self.units_per_em as f32;
         Transform2F::from_scale(Vector2F::splat(scale.into()))
     }
     fn glyph(&self, id: GlyphId) -> Option<Glyph> {
-        assert!(id.0 <= u16::max_value() as u32);
+        warn_invalid(self.try_glyph(id))
+    }
+    fn try_glyph(&self, id: GlyphId) -> Result<Glyph, GlyphError> {
         debug!("get gid {:?}", id);
-        let path = self.get_path(id.0)?;
-        let metrics = self.hmtx.metrics_for_gid(id.0 as u16);
+        let path = self.outline_cache.try_outline(&self.glyphs, id.0)?;
+        let metrics = self.hmtx.metrics_for_gid(metrics_glyph(&self.glyphs, id.0) as u16);
         
-        Some(Glyph {
+        Ok(Glyph {
             path,
             metrics
         })
     }
+    fn glyph_count_report(&self) -> GlyphCountReport {
+        self.glyph_counts.clone()
+    }
+    fn num_contours(&self, gid: GlyphId) -> Option<i16> {
+        self.glyphs.num_contours(gid.0)
+    }
     fn gid_for_codepoint(&self, codepoint: u32) -> Option<GlyphId> {
         self.gid_for_unicode_codepoint(codepoint)
     }
@@ -102,14 +307,80 @@ impl Font for TrueTypeFont {
             None => None
         }
     }
+    fn gid_for_name(&self, name: &str) -> Option<GlyphId> {
+        self.post.as_ref().and_then(|post| post.gid_for_name(name))
+            .or_else(|| self.zapf.as_ref().and_then(|zapf| zapf.gid_for_name(name)))
+            .map(|gid| GlyphId(gid as u32))
+            .or_else(|| gid_for_name_via_unicode(self, name))
+    }
     fn encoding(&self) -> Option<Encoding> {
         Some(Encoding::Unicode)
     }
+    fn cmap(&self) -> Option<&CMap> {
+        self.cmap.as_ref()
+    }
     fn bbox(&self) -> Option<RectF> {
         Some(self.bbox)
     }
+    fn caret_slope(&self) -> Option<CaretSlope> {
+        self.caret_slope
+    }
+    fn vmetrics(&self) -> Option<VMetrics> {
+        self.vmetrics
+    }
+    fn weight(&self) -> Option<u16> {
+        font_weight(self.os2.as_ref(), Some(self.mac_style))
+    }
+    fn is_italic(&self) -> bool {
+        font_is_italic(self.os2.as_ref(), Some(self.mac_style))
+    }
+    fn advance_is_explicit(&self, gid: GlyphId) -> bool {
+        self.hmtx.is_explicit(gid.0 as u16)
+    }
+    fn cap_height(&self) -> Option<f32> {
+        match self.os2.as_ref().and_then(|os2| os2.cap_height) {
+            Some(h) if h > 0 => Some(h as f32),
+            _ => ink_top(self, 'H')
+        }
+    }
+    fn x_height(&self) -> Option<f32> {
+        match self.os2.as_ref().and_then(|os2| os2.x_height) {
+            Some(h) if h > 0 => Some(h as f32),
+            _ => ink_top(self, 'x')
+        }
+    }
+    fn underline_metrics(&self) -> Option<LineMetrics> {
+        self.post.as_ref().map(|post| post.underline())
+    }
+    fn strikeout_metrics(&self) -> Option<LineMetrics> {
+        self.os2.as_ref().map(|os2| os2.strikeout())
+    }
+    fn glyph_properties(&self, gid: GlyphId) -> Option<GlyphProps> {
+        self.prop.as_ref().map(|prop| prop.get(gid.0 as u16))
+    }
+    fn aat_baseline(&self, gid: GlyphId) -> Option<f32> {
+        self.bsln.as_ref()?.delta(gid.0 as u16).map(|delta| delta as f32)
+    }
+    fn variation_axes(&self) -> Vec<Axis> {
+        self.fvar.as_ref().map(|fvar| fvar.axes.clone()).unwrap_or_default()
+    }
+    fn num_named_instances(&self) -> usize {
+        self.fvar.as_ref().map(|fvar| fvar.instances.len()).unwrap_or(0)
+    }
+    fn tracking(&self, size: f32) -> Option<f32> {
+        self.trak.as_ref()?.horizontal.as_ref()?.tracking(0.0, size)
+    }
+    fn advance(&self, gid: GlyphId) -> Option<f32> {
+        if gid.0 as usize >= self.glyphs.len() {
+            return None;
+        }
+        Some(self.hmtx.metrics_for_gid(metrics_glyph(&self.glyphs, gid.0) as u16).advance)
+    }
+    fn positioned_advance(&self, gid: GlyphId, _features: &[Tag]) -> f32 {
+        self.advance(gid).unwrap_or(0.0)
+    }
     fn kerning(&self, left: GlyphId, right: GlyphId) -> f32 {
-        self.kern.get(left.0 as u16, right.0 as u16).unwrap_or(0) as f32
+        self.kern.kerning(left.0 as u16, right.0 as u16).unwrap_or(0) as f32
     }
     fn name(&self) -> &Name {
         &self.name
@@ -134,16 +405,6 @@ fn fraction_i16(i: &[u8]) -> R<f32> {
     Ok((i, s as f32 / 16384.0))
 }
 
-pub fn parse_shapes(loca: &[u32], data: &[u8]) -> Vec<Shape> {
-    let mut shapes = Vec::with_capacity(loca.len() - 1);
-    for (i, (start, end)) in loca.iter().cloned().tuple_windows().enumerate() {
-        let slice = &data[start as usize .. end as usize];
-        //debug!("gid {} : data[{} .. {}]", i, start, end);
-        let shape = parse_glyph_shape(slice).get();
-        shapes.push(shape);
-    }
-    shapes
-}
 // the following code is borrowed from stb-truetype and modified heavily
 
 fn parse_glyph_shape(data: &[u8]) -> R<Shape> {
@@ -159,7 +420,7 @@ fn parse_glyph_shape(data: &[u8]) -> R<Shape> {
         0 => Ok((i, Shape::Empty)),
         n if n >= 0 => glyph_shape_positive_contours(i, number_of_contours as usize),
         -1 => compound(i),
-        n => panic!("Contour format {} not supported.", n)
+        _ => Err(nom::Err::Failure(make_error(data, ErrorKind::Verify)))
     }
 }
 
@@ -178,7 +439,13 @@ pub fn compound(mut input: &[u8]) -> R<Shape> {
                 transform.vector = parse(&mut input, vec_i8)?
             }
         } else {
-            panic!("Matching points not supported.");
+            // matching points. The contours do not keep the point numbering, so the component is not moved.
+            if flags & 1 != 0 {
+                parse(&mut input, tuple((be_u16, be_u16)))?;
+            } else {
+                parse(&mut input, tuple((be_u8, be_u8)))?;
+            }
+            warn!("matching points of compound glyphs are not supported");
         };
         if flags & (1 << 3) != 0 {
             // WE_HAVE_A_SCALE
@@ -194,9 +461,22 @@ pub fn compound(mut input: &[u8]) -> R<Shape> {
             let (a, b, c, d) = parse(&mut input, tuple((fraction_i16, fraction_i16, fraction_i16, fraction_i16)))?;
             transform.matrix = Matrix2x2F::row_major(a, b, c, d);
         }
+        if flags & (1 << 11) != 0 && flags & (1 << 12) == 0 {
+            // SCALED_COMPONENT_OFFSET: the offset is transformed as well
+            transform.vector = transform.matrix * transform.vector;
+        }
+        let round_to_grid = flags & (1 << 2) != 0;
+        if round_to_grid {
+            transform.vector = Vector2F::new(transform.vector.x().round(), transform.vector.y().round());
+        }
 
         // Get indexed glyph.
-        parts.push((gidx as u32, transform));
+        parts.push(Component {
+            gid: gidx as u32,
+            transform,
+            round_to_grid,
+            use_my_metrics: flags & (1 << 9) != 0,
+        });
         // More components ?
         if flags & 0x20 == 0 {
             break;
@@ -205,22 +485,240 @@ pub fn compound(mut input: &[u8]) -> R<Shape> {
     Ok((input, Shape::Compound(parts)))
 }
 
-pub fn get_outline(shapes: &[Shape], idx: u32) -> Option<Outline> {
-    match shapes.get(idx as usize)? {
-        &Shape::Simple(ref path) => Some(path.clone()),
-        &Shape::Compound(ref parts) => {
+#[derive(Copy, Clone)]
+enum Visit {
+    New,
+    Active,
+    // (composite depth, number of points)
+    Done(u32, u32),
+}
+fn visit_shape(glyphs: &Glyphs, idx: usize, level: u32, visits: &mut [Visit], options: &ParseOptions) -> Result<(u32, u32), FontError> {
+    match visits[idx] {
+        Visit::Done(depth, points) => return Ok((depth, points)),
+        // a composite that (indirectly) contains itself is infinitely deep
+        Visit::Active => return Err(FontError::LimitExceeded(Limit::CompositeDepth)),
+        Visit::New => {}
+    }
+    check_limit(options.max_composite_depth, level, Limit::CompositeDepth)?;
+    visits[idx] = Visit::Active;
+    let shape = glyphs.try_shape(idx as u32).map_err(|e| FontError::Parse(e.to_string()))?;
+    let (depth, points) = match *shape {
+        Shape::Simple(ref outline) => (0, outline.contours().iter().map(|c| c.len() as u32).sum()),
+        Shape::Compound(ref parts) => {
+            let (mut depth, mut points) = (0, 0u32);
+            for part in parts {
+                if (part.gid as usize) < glyphs.len() {
+                    let (d, p) = visit_shape(glyphs, part.gid as usize, level + 1, visits, options)?;
+                    depth = depth.max(d + 1);
+                    points = points.saturating_add(p);
+                }
+            }
+            (depth, points)
+        }
+        Shape::Empty => (0, 0)
+    };
+    check_limit(options.max_composite_depth, depth, Limit::CompositeDepth)?;
+    check_limit(options.max_points, points, Limit::Points)?;
+    visits[idx] = Visit::Done(depth, points);
+    Ok((depth, points))
+}
+
+/// Check the composite depth and the number of points of every glyph against the limits of `options`.
+///
+/// Without limits no glyph is parsed. Otherwise a broken glyph is an error.
+pub fn check_shapes(glyphs: &Glyphs, options: &ParseOptions) -> Result<(), FontError> {
+    if options.max_composite_depth.is_none() && options.max_points.is_none() {
+        return Ok(());
+    }
+    let mut visits = vec![Visit::New; glyphs.len()];
+    for idx in 0 .. glyphs.len() {
+        visit_shape(glyphs, idx, 0, &mut visits, options)?;
+    }
+    Ok(())
+}
+
+/// The outlines of the glyphs, composed when they are first used.
+///
+/// Clones of a font share the cache.
+#[derive(Clone, Default)]
+pub struct OutlineCache(Arc<RwLock<HashMap<u32, Outline>>>);
+impl OutlineCache {
+    /// Like `get_outline`, but composes each glyph only once.
+    pub fn outline(&self, glyphs: &Glyphs, idx: u32) -> Option<Outline> {
+        warn_invalid(self.try_outline(glyphs, idx))
+    }
+    /// Like `try_get_outline`, but composes each glyph only once.
+    pub fn try_outline(&self, glyphs: &Glyphs, idx: u32) -> Result<Outline, GlyphError> {
+        if let Some(outline) = self.0.read().unwrap().get(&idx) {
+            return Ok(outline.clone());
+        }
+        let outline = try_get_outline(glyphs, idx)?;
+        self.0.write().unwrap().insert(idx, outline.clone());
+        Ok(outline)
+    }
+}
+
+/// The default glyphs of a variable font, and the deltas to move them elsewhere in the design space.
+pub struct GlyfVariations {
+    gvar: Gvar,
+    avar: Option<Avar>,
+    // advance deltas. without it the phantom points of `gvar` are used
+    hvar: Option<Hvar>,
+    glyf: Vec<u8>,
+    loca: Vec<u32>,
+    hmtx: Hmtx,
+}
+impl GlyfVariations {
+    /// `None` unless the font has valid `fvar`, `gvar`, `glyf` and `loca` tables.
+    pub fn from_tables(tables: &Tables<impl Deref<Target=[u8]>>, hmtx: &Hmtx) -> Option<Self> {
+        tables.get(b"fvar")?;
+        let gvar = tables.parse_optional(b"gvar", parse_gvar)?;
+        let head = tables.parse_optional(b"head", parse_head)?;
+        let maxp = tables.parse_optional(b"maxp", parse_maxp)?;
+        let loca = tables.parse_optional(b"loca", |i| parse_loca(i, &head, &maxp))?;
+        let glyf = tables.get(b"glyf")?.to_vec();
+        let avar = tables.parse_optional(b"avar", parse_avar);
+        let hvar = tables.parse_optional(b"HVAR", parse_hvar);
+        Some(GlyfVariations { gvar, avar, hvar, glyf, loca, hmtx: hmtx.clone() })
+    }
+    /// The shapes and horizontal metrics of all glyphs at the normalized `coords` (as given by `Fvar::normalize`).
+    ///
+    /// The `avar` mapping is applied to the coordinates first.
+    pub fn instance(&self, coords: &[f32]) -> (Vec<Shape>, Hmtx) {
+        let mut coords = coords.to_vec();
+        if let Some(ref avar) = self.avar {
+            avar.map(&mut coords);
+        }
+        let (shapes, mut advance_deltas): (Vec<Shape>, Vec<f32>) = self.loca.iter().cloned().tuple_windows().enumerate()
+            .map(|(gid, (start, end))| {
+                let data = self.glyf.get(start as usize .. end as usize).unwrap_or(&[]);
+                self.glyph(gid as u32, data, &coords)
+            }).unzip();
+        if let Some(ref hvar) = self.hvar {
+            for (gid, delta) in advance_deltas.iter_mut().enumerate() {
+                *delta = hvar.advance_delta(gid as u32, &coords);
+            }
+        }
+        (shapes, self.hmtx.with_advance_deltas(&advance_deltas))
+    }
+    // the varied shape of glyph `gid` and the change of its advance
+    fn glyph(&self, gid: u32, data: &[u8], coords: &[f32]) -> (Shape, f32) {
+        // the four phantom points follow the points of the glyph. Only the advance (the distance
+        // of the first two) is used, so their original position does not matter.
+        let phantom = iter::repeat(Vector2F::default()).take(4);
+        let advance_delta = |deltas: &[Vector2F]| {
+            let n = deltas.len() - 4;
+            deltas[n + 1].x() - deltas[n].x()
+        };
+        let shape = match parse_glyph_shape(data) {
+            Ok((_, shape)) => shape,
+            Err(_) => {
+                warn!("invalid glyph {}", gid);
+                Shape::Empty
+            }
+        };
+        match shape {
+            Shape::Simple(_) => {
+                let (end_points, mut points) = simple_glyph_points(data).get();
+                let positions: Vec<_> = points.iter().map(|&(_, p)| p).chain(phantom).collect();
+                let deltas = self.gvar.deltas(gid, coords, &positions, &end_points);
+                for ((_, p), &delta) in points.iter_mut().zip(&deltas) {
+                    *p = *p + delta;
+                }
+                (Shape::Simple(outline_from_points(&end_points, points.into_iter())), advance_delta(&deltas))
+            }
+            // one point per component: its offset
+            Shape::Compound(mut parts) => {
+                let positions: Vec<_> = parts.iter().map(|part| part.transform.vector).chain(phantom).collect();
+                let deltas = self.gvar.deltas(gid, coords, &positions, &[]);
+                for (part, &delta) in parts.iter_mut().zip(&deltas) {
+                    let v = part.transform.vector + delta;
+                    part.transform.vector = match part.round_to_grid {
+                        true => Vector2F::new(v.x().round(), v.y().round()),
+                        false => v
+                    };
+                }
+                (Shape::Compound(parts), advance_delta(&deltas))
+            }
+            Shape::Empty => {
+                let positions: Vec<_> = phantom.collect();
+                let deltas = self.gvar.deltas(gid, coords, &positions, &[]);
+                (Shape::Empty, advance_delta(&deltas))
+            }
+        }
+    }
+}
+
+// components nested deeper are left out
+const MAX_COMPONENT_DEPTH: u32 = 16;
+// components composed into a single glyph at most, counting a repeated component each time
+const MAX_COMPONENTS: u32 = 1 << 16;
+
+/// The outline of glyph `idx`. Components of compound glyphs can be compound glyphs themselves.
+///
+/// Components that contain the glyph they are part of are left out.
+pub fn get_outline(glyphs: &Glyphs, idx: u32) -> Option<Outline> {
+    warn_invalid(try_get_outline(glyphs, idx))
+}
+/// Like `get_outline`, but tells a missing glyph from a broken one.
+///
+/// Missing or broken components are left out, they are reported for their own glyph.
+pub fn try_get_outline(glyphs: &Glyphs, idx: u32) -> Result<Outline, GlyphError> {
+    let mut budget = MAX_COMPONENTS;
+    compose(glyphs, idx, &mut vec![], &mut budget)
+}
+// `active` are the compound glyphs being composed that contain glyph `idx`,
+// `budget` the number of components that may still be composed
+fn compose(glyphs: &Glyphs, idx: u32, active: &mut Vec<u32>, budget: &mut u32) -> Result<Outline, GlyphError> {
+    match *glyphs.try_shape(idx)? {
+        Shape::Simple(ref path) => Ok(path.clone()),
+        Shape::Compound(ref parts) => {
             let mut outline = Outline::new();
-            for &(gid, tr) in parts {
-                if let Some(Shape::Simple(ref path)) = shapes.get(gid as usize) {
-                    let mut path = path.clone();
-                    path.transform(&tr);
-                    outline.push_outline(path);
+            if active.len() < MAX_COMPONENT_DEPTH as usize {
+                active.push(idx);
+                for part in parts {
+                    if active.contains(&part.gid) {
+                        warn!("glyph {} contains itself", part.gid);
+                        continue;
+                    }
+                    if *budget == 0 {
+                        warn!("glyph {} has too many components", idx);
+                        break;
+                    }
+                    *budget -= 1;
+                    if let Some(mut path) = warn_invalid(compose(glyphs, part.gid, active, budget)) {
+                        if path.contours().is_empty() {
+                            continue;
+                        }
+                        path.transform(&part.transform);
+                        outline.push_outline(path);
+                    }
                 }
+                active.pop();
+            }
+            Ok(outline)
+        }
+        Shape::Empty => Ok(Outline::new())
+    }
+}
+
+/// The glyph whose metrics glyph `idx` has: the component marked with USE_MY_METRICS (followed through nested
+/// compound glyphs), or `idx` itself.
+pub fn metrics_glyph(glyphs: &Glyphs, mut idx: u32) -> u32 {
+    for _ in 0 .. MAX_COMPONENT_DEPTH {
+        // only compound glyphs are parsed
+        if glyphs.num_contours(idx) != Some(-1) {
+            break;
+        }
+        match glyphs.shape(idx).as_deref() {
+            Some(Shape::Compound(ref parts)) => match parts.iter().find(|part| part.use_my_metrics) {
+                Some(part) if (part.gid as usize) < glyphs.len() => idx = part.gid,
+                _ => break
             }
-            Some(outline)
+            _ => break
         }
-        &Shape::Empty => Some(Outline::new())
     }
+    idx
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -249,12 +747,31 @@ fn mid(a: Vector2F, b: Vector2F) -> Vector2F {
     (a + b) * 0.5
 }
 fn glyph_shape_positive_contours(i: &[u8], number_of_contours: usize) -> R<Shape> {
+    let (i, (end_points, points)) = glyph_points(i, number_of_contours)?;
+    Ok((i, Shape::Simple(outline_from_points(&end_points, points.into_iter()))))
+}
+
+// the points of a simple glyph, including its header
+fn simple_glyph_points(data: &[u8]) -> R<(Vec<u16>, Vec<(bool, Vector2F)>)> {
+    let (i, number_of_contours) = be_i16(data)?;
+    let (i, _) = take(8usize)(i)?;
+    glyph_points(i, number_of_contours as usize)
+}
+
+// the index of the last point of each contour, and all points with their on-curve flag
+fn glyph_points(i: &[u8], number_of_contours: usize) -> R<(Vec<u16>, Vec<(bool, Vector2F)>)> {
     let (i, point_indices) = take(2 * number_of_contours)(i)?;
     let (i, num_instructions) = be_u16(i)?;
     let (mut i, _instructions) = take(num_instructions)(i)?;
     
+    // the last points of the contours have to be ascending, or the contours would be empty
+    let end_points: Vec<u16> = iterator(point_indices, be_u16).collect();
+    if end_points.windows(2).any(|w| w[0] >= w[1]) {
+        return Err(nom::Err::Failure(make_error(point_indices, ErrorKind::Verify)));
+    }
+    
     // total number of points
-    let n = 1 + be_u16(&point_indices[2 * number_of_contours - 2 ..]).get() as usize;
+    let n = 1 + end_points[number_of_contours - 1] as usize;
 
     let mut flag_data = Vec::with_capacity(n);
 
@@ -287,12 +804,16 @@ fn glyph_shape_positive_contours(i: &[u8], number_of_contours: usize) -> R<Shape
         p.1 = y_coord;
     }
 
-    let mut points = flag_data.iter().map(|&FlagData { flags, p }| 
+    let points = flag_data.iter().map(|&FlagData { flags, p }| 
         (flags & 1 != 0, Vector2F::new(p.0 as f32, p.1 as f32))
-    );
+    ).collect();
+    Ok((i, (end_points, points)))
+}
+
+fn outline_from_points(end_points: &[u16], mut points: impl Iterator<Item=(bool, Vector2F)>) -> Outline {
     let mut start = 0;
     let mut outline = Outline::new();
-    for end in iterator(point_indices, be_u16) {
+    for &end in end_points {
         let n_points = end + 1 - start;
         start += n_points;
         
@@ -300,14 +821,13 @@ fn glyph_shape_positive_contours(i: &[u8], number_of_contours: usize) -> R<Shape
             outline.push_contour(contour);
         }
     }
-    
-    Ok((i, Shape::Simple(outline)))
+    outline
 }
 
 pub fn contour(points: impl Iterator<Item=(bool, Vector2F)>) -> Option<Contour> {
     let mut points = points.peekable();
     
-    let (start_on, p) = points.next().unwrap();
+    let (start_on, p) = points.next()?;
     let start_off = !start_on;
     let (s, sc) = if start_off {
         // if we start off with an off-curve point, then when we need to find a
@@ -374,3 +894,140 @@ pub fn contour(points: impl Iterator<Item=(bool, Vector2F)>) -> Option<Contour>
     contour.close();
     Some(contour)
 }
+
+#[test]
+fn test_outline_cache() {
+    let mut contour = Contour::new();
+    contour.push_endpoint(Vector2F::new(0., 0.));
+    contour.push_endpoint(Vector2F::new(100., 0.));
+    contour.push_endpoint(Vector2F::new(100., 100.));
+    contour.close();
+    let mut square = Outline::new();
+    square.push_contour(contour);
+    let shapes = vec![
+        Shape::Simple(square),
+        Shape::Compound(vec![Component {
+            gid: 0,
+            transform: Transform2F::from_translation(Vector2F::new(50., 0.)),
+            round_to_grid: false,
+            use_my_metrics: false,
+        }]),
+    ];
+    let shapes = Glyphs::from(shapes);
+    let cache = OutlineCache::default();
+    let composed = get_outline(&shapes, 1).unwrap();
+    for _ in 0 .. 2 {
+        assert_eq!(cache.outline(&shapes, 1).unwrap().bounds(), composed.bounds());
+    }
+    assert_eq!(cache.0.read().unwrap().len(), 1);
+    assert_eq!(cache.outline(&shapes, 0).unwrap().bounds(), RectF::new(Vector2F::new(0., 0.), Vector2F::new(100., 100.)));
+    assert_eq!(cache.0.read().unwrap().len(), 2);
+    assert!(cache.outline(&shapes, 2).is_none());
+    assert_eq!(cache.0.read().unwrap().len(), 2);
+}
+
+#[test]
+fn test_nested_compound() {
+    let mut contour = Contour::new();
+    contour.push_endpoint(Vector2F::new(0., 0.));
+    contour.push_endpoint(Vector2F::new(100., 0.));
+    contour.push_endpoint(Vector2F::new(100., 100.));
+    contour.close();
+    let mut square = Outline::new();
+    square.push_contour(contour);
+
+    // glyph 1: glyph 0 at 10, 0 (USE_MY_METRICS, ROUND_XY_TO_GRID) and glyph 2 scaled by 0.5 at 0, 200 (scaled offset)
+    let (_, shape) = compound(&[
+        0x02, 0x26, 0, 0, 10, 0,
+        0x08, 0x0B, 0, 2, 0, 0, 0, 200, 0x20, 0,
+    ]).unwrap();
+    let parts = match shape {
+        Shape::Compound(ref parts) => parts.clone(),
+        _ => panic!("not a compound glyph")
+    };
+    assert!(parts[0].use_my_metrics && parts[0].round_to_grid);
+    assert_eq!(parts[1].transform.vector, Vector2F::new(0., 100.));
+
+    let shapes = vec![
+        Shape::Simple(square),
+        shape,
+        // glyph 2 contains glyph 3, which contains glyph 0
+        Shape::Compound(vec![Component { gid: 3, transform: Transform2F::default(), round_to_grid: false, use_my_metrics: true }]),
+        Shape::Compound(vec![Component { gid: 0, transform: Transform2F::from_translation(Vector2F::new(100., 0.)), round_to_grid: false, use_my_metrics: false }]),
+        // glyph 4 contains itself
+        Shape::Compound(vec![Component { gid: 4, transform: Transform2F::default(), round_to_grid: false, use_my_metrics: true }]),
+    ];
+    let shapes = Glyphs::from(shapes);
+    assert_eq!(get_outline(&shapes, 2).unwrap().bounds(), RectF::new(Vector2F::new(100., 0.), Vector2F::new(100., 100.)));
+    let outline = get_outline(&shapes, 1).unwrap();
+    assert_eq!(outline.contours().len(), 2);
+    assert_eq!(outline.bounds(), RectF::from_points(Vector2F::new(10., 0.), Vector2F::new(110., 150.)));
+    assert_eq!(get_outline(&shapes, 4).unwrap().contours().len(), 0);
+
+    assert_eq!(metrics_glyph(&shapes, 1), 0);
+    assert_eq!(metrics_glyph(&shapes, 2), 3);
+    assert_eq!(metrics_glyph(&shapes, 4), 4);
+}
+
+#[test]
+fn test_lazy_glyf() {
+    let glyf = [
+        // glyph 1: glyph 0 at 5, 0
+        0xFF, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0,
+        0, 0x02, 0, 0, 5, 0,
+        // glyph 2: a simple glyph without its bounding box
+        0, 1,
+    ];
+    let glyphs = Glyphs::from_glyf(&[0, 0, 16, 18], &glyf).unwrap();
+    assert_eq!(glyphs.len(), 3);
+    assert_eq!(glyphs.num_contours(0), Some(0));
+    assert_eq!(glyphs.num_contours(1), Some(-1));
+    assert_eq!(glyphs.num_contours(2), Some(1));
+    assert_eq!(glyphs.num_contours(3), None);
+    match glyphs.shape(1).as_deref() {
+        Some(&Shape::Compound(ref parts)) => assert_eq!(parts[0].gid, 0),
+        _ => panic!("not a compound glyph")
+    }
+    // the broken glyph is only noticed when it is used
+    assert!(glyphs.shape(2).is_none());
+    assert!(matches!(glyphs.try_shape(2), Err(GlyphError::Invalid(GlyphId(2), _))));
+    assert!(matches!(glyphs.try_shape(3), Err(GlyphError::Missing(GlyphId(3)))));
+    assert!(get_outline(&glyphs, 2).is_none());
+
+    assert!(Glyphs::from_glyf(&[0, 0, 20], &glyf).is_err());
+    assert!(Glyphs::from_glyf(&[0, 16, 0], &glyf).is_err());
+}
+
+#[test]
+fn test_compound_cycles() {
+    let mut contour = Contour::new();
+    contour.push_endpoint(Vector2F::new(0., 0.));
+    contour.push_endpoint(Vector2F::new(100., 0.));
+    contour.push_endpoint(Vector2F::new(100., 100.));
+    contour.close();
+    let mut square = Outline::new();
+    square.push_contour(contour);
+    let part = |gid| Component { gid, transform: Transform2F::default(), round_to_grid: false, use_my_metrics: false };
+
+    let mut shapes = vec![
+        Shape::Simple(square),
+        // glyph 1 contains itself four times, and glyph 2
+        Shape::Compound(vec![part(1), part(1), part(1), part(1), part(2)]),
+        // glyph 2 contains glyph 1 four times, and glyph 0
+        Shape::Compound(vec![part(1), part(1), part(1), part(1), part(0)]),
+    ];
+    // glyphs 3 to 14 contain the next one four times, and glyph 14 contains glyph 0 four times: 4^12 squares for glyph 3
+    for gid in 4 ..= 14 {
+        shapes.push(Shape::Compound(vec![part(gid); 4]));
+    }
+    shapes.push(Shape::Compound(vec![part(0); 4]));
+    let glyphs = Glyphs::from(shapes);
+
+    // only the square of glyph 2, which skips glyph 1
+    assert_eq!(get_outline(&glyphs, 1).unwrap().contours().len(), 1);
+    // the copies of glyph 1 are empty, as they only contain glyphs 1 and 2
+    assert_eq!(get_outline(&glyphs, 2).unwrap().contours().len(), 1);
+    // composing stops at the component limit
+    let outline = get_outline(&glyphs, 3).unwrap();
+    assert!(outline.contours().len() > 0 && outline.contours().len() < MAX_COMPONENTS as usize);
+}
@@ -1,14 +1,18 @@
 use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
 use nom::{IResult,
     number::complete::{be_u8, le_u8, be_i32, le_u32},
+    error::{make_error, ErrorKind},
 };
 use tuple::{TupleElements};
 use itertools::Itertools;
 use indexmap::IndexMap;
-use crate::{Font, Glyph, State, v, R, IResultExt, Context, HMetrics, TryIndex, GlyphId, Name, Value};
+use crate::{Font, Glyph, State, v, R, IResultExt, Context, HMetrics, TryIndex, GlyphId, Name, Value, FontError, GlyphError, GlyphHints, MAX_SUBR_DEPTH, warn_invalid};
 use crate::postscript::{Vm, RefItem};
 use crate::eexec::Decoder;
 use crate::parsers::parse;
+use crate::opentype::os2::weight_class_for_name;
+use crate::cff::{STANDARD_STRINGS, STANDARD_ENCODING};
 use pdf_encoding::{glyphname_to_unicode};
 use pathfinder_geometry::{
     transform2d::Transform2F,
@@ -18,29 +22,37 @@ use pathfinder_geometry::{
 
 #[derive(Clone)]
 pub struct Type1Font {
-    glyphs: IndexMap<String, Glyph>, // namee -> glyph
+    charstrings: Arc<Charstrings>,
     codepoints: HashMap<u32, u32>, // codepoint -> glyph id
     unicode_map: HashMap<&'static str, u32>,
     font_matrix: Transform2F,
     bbox: Option<RectF>,
     name: Name,
+    weight: Option<u16>,
+    italic: bool,
 }
 impl Font for Type1Font {
     fn num_glyphs(&self) -> u32 {
-        self.glyphs.len() as u32
+        self.charstrings.glyphs.len() as u32
     }
     fn font_matrix(&self) -> Transform2F {
         self.font_matrix
     }
     fn glyph(&self, gid: GlyphId) -> Option<Glyph> {
-        self.glyphs.get_index(gid.0 as usize).map(|(_, glyph)| glyph.clone())
+        warn_invalid(self.try_glyph(gid))
+    }
+    fn try_glyph(&self, gid: GlyphId) -> Result<Glyph, GlyphError> {
+        self.charstrings.try_get(gid.0).map(|(glyph, _)| glyph)
+    }
+    fn advance(&self, gid: GlyphId) -> Option<f32> {
+        self.charstrings.get(gid.0).map(|(glyph, _)| glyph.metrics.advance)
     }
     fn gid_for_codepoint(&self, codepoint: u32) -> Option<GlyphId> {
         let &index = self.codepoints.get(&codepoint)?;
         Some(GlyphId(index as u32))
     }
     fn gid_for_name(&self, name: &str) -> Option<GlyphId> {
-        self.glyphs.get_full(name).map(|(id, _, _)| GlyphId(id as u32))
+        self.charstrings.glyphs.get_full(name).map(|(id, _, _)| GlyphId(id as u32))
     }
     fn gid_for_unicode_codepoint(&self, codepoint: u32) -> Option<GlyphId> {
         let c = std::char::from_u32(codepoint)?;
@@ -51,9 +63,74 @@ impl Font for Type1Font {
     fn bbox(&self) -> Option<RectF> {
         self.bbox
     }
+    fn glyph_hints(&self, gid: GlyphId) -> Option<GlyphHints> {
+        self.charstrings.get(gid.0).map(|(_, hints)| hints)
+    }
     fn name(&self) -> &Name {
         &self.name
     }
+    fn weight(&self) -> Option<u16> {
+        self.weight
+    }
+    fn is_italic(&self) -> bool {
+        self.italic
+    }
+}
+
+// the decoded charstrings, interpreted when a glyph is first asked for
+struct Charstrings {
+    context: Context<Vec<Vec<u8>>>,
+    glyphs: IndexMap<String, Vec<u8>>, // name -> decoded charstring
+    // gid -> (glyph, hints)
+    cache: RwLock<HashMap<u32, (Glyph, GlyphHints)>>,
+}
+impl Charstrings {
+    // the glyph and hints of `gid`, including the base and accent of an accented character
+    fn get(&self, gid: u32) -> Option<(Glyph, GlyphHints)> {
+        warn_invalid(self.try_get(gid))
+    }
+    fn try_get(&self, gid: u32) -> Result<(Glyph, GlyphHints), GlyphError> {
+        if let Some(glyph) = self.cache.read().unwrap().get(&gid) {
+            return Ok(glyph.clone());
+        }
+        let (mut glyph, hints, seac) = self.run(gid)?;
+        // the base and accent of accented characters are glyphs of the standard encoding
+        if let Some((offset, base, accent)) = seac {
+            for &(code, offset) in [(base, Vector2F::default()), (accent, offset)].iter() {
+                // codes without a glyph in the standard encoding (SID 0) do not refer to .notdef
+                let sid = STANDARD_ENCODING[code as usize];
+                let component = self.glyphs.get_full(STANDARD_STRINGS[sid as usize])
+                    .filter(|_| sid != 0)
+                    .and_then(|(gid, _, _)| warn_invalid(self.run(gid as u32)));
+                match component {
+                    Some((mut component, ..)) => {
+                        component.path.transform(&Transform2F::from_translation(offset));
+                        glyph.path.push_outline(component.path);
+                    }
+                    None => warn!("seac of glyph {}: no glyph for code {}", gid, code)
+                }
+            }
+        }
+        self.cache.write().unwrap().insert(gid, (glyph.clone(), hints.clone()));
+        Ok((glyph, hints))
+    }
+    // the glyph, hints and seac of the charstring alone
+    fn run(&self, gid: u32) -> Result<(Glyph, GlyphHints, Option<(Vector2F, u8, u8)>), GlyphError> {
+        let (name, data) = self.glyphs.get_index(gid as usize).ok_or(GlyphError::Missing(GlyphId(gid)))?;
+        let mut state = State::new();
+        if charstring(data, &self.context, &mut state).is_err() {
+            return Err(GlyphError::Invalid(GlyphId(gid), format!("broken charstring /{}", name)));
+        }
+        let advance = state.char_width.ok_or_else(|| GlyphError::Invalid(GlyphId(gid), format!("charstring /{} has no width", name)))?;
+        let glyph = Glyph {
+            metrics: HMetrics {
+                advance,
+                lsb: state.lsb.unwrap_or_default()
+            },
+            path: state.take_path(),
+        };
+        Ok((glyph, state.hints.clone(), state.seac))
+    }
 }
 
 impl Type1Font {
@@ -73,36 +150,67 @@ impl Type1Font {
         Self::from_vm(vm)
     }
         
-    pub fn from_vm(vm: Vm) -> Self {
-        let (_font_name, font_dict) = vm.fonts().nth(0).unwrap();
+    /// Like `parse_pfa`, but returns an error if the font dictionary is incomplete.
+    pub fn try_parse_pfa(data: &[u8]) -> Result<Self, FontError> {
+        let mut vm = Vm::new();
+        vm.parse_and_exec(data);
+        Self::try_from_vm(vm)
+    }
+    /// Like `parse_pfb`, but returns an error for broken segments or an incomplete font dictionary.
+    pub fn try_parse_pfb(data: &[u8]) -> Result<Self, FontError> {
+        let mut vm = Vm::new();
+        parse_pfb(&mut vm, data).map_err(|_| FontError::Parse("invalid pfb segment".into()))?;
+        Self::try_from_vm(vm)
+    }
+    /// Like `parse_postscript`, but returns an error if the font dictionary is incomplete.
+    pub fn try_parse_postscript(data: &[u8]) -> Result<Self, FontError> {
+        let mut vm = Vm::new();
+        vm.parse_and_exec(data);
+        Self::try_from_vm(vm)
+    }
+
+    /// Like `from_vm`, but returns an error if an entry of the font dictionary it relies on is missing.
+    ///
+    /// Charstrings are only interpreted when their glyph is used, see `ParseOptions::check_glyphs`.
+    pub fn try_from_vm(vm: Vm) -> Result<Self, FontError> {
+        let missing = |key: &str| FontError::Parse(format!("no valid /{}", key));
+        let (_font_name, font_dict) = vm.fonts().nth(0).ok_or_else(|| FontError::Parse("no font defined".into()))?;
         
-        let private_dict = font_dict.get("Private").unwrap()
-            .as_dict().unwrap();
+        let private_dict = font_dict.get("Private").and_then(|i| i.as_dict()).ok_or_else(|| missing("Private"))?;
         let len_iv = private_dict.get("lenIV")
-            .map(|i| i.as_int().unwrap()).unwrap_or(4) as usize;
+            .and_then(|i| i.as_int()).unwrap_or(4) as usize;
         
         debug!("FontDict keys: {:?}", font_dict.iter().map(|(k, _)| k).format(", "));
         debug!("Private keys: {:?}", private_dict.iter().map(|(k, _)| k).format(", "));
         debug!("FontName: {:?}", font_dict.get("FontName"));
         debug!("FontInfo: {:?}", font_dict.get("FontInfo"));
         
-        let postscript_name = font_dict.get("FontName").map(|i| i.as_str().unwrap().into());
-        let full_name = font_dict.get("FontInfo").and_then(|i|
-            i.as_dict().unwrap().get("FullName").map(|i| i.as_str().unwrap().into())
-        );
+        let postscript_name = font_dict.get("FontName").and_then(|i| i.as_str()).map(|s| s.into());
+        let font_info = font_dict.get("FontInfo").and_then(|i| i.as_dict());
+        let info_string = |key: &str| font_info.as_ref()
+            .and_then(|info| info.get(key))
+            .and_then(|i| i.as_str())
+            .map(|s| s.to_owned());
         let name = Name {
-            full_name,
+            full_name: info_string("FullName"),
+            family: info_string("FamilyName"),
             postscript_name,
             .. Name::default()
         };
+        let weight = info_string("Weight").and_then(|name| weight_class_for_name(&name));
+        let italic = font_info.as_ref()
+            .and_then(|info| info.get("ItalicAngle"))
+            .and_then(|i| i.as_f32())
+            .map_or(false, |angle| angle != 0.);
         
-        let char_strings = font_dict.get("CharStrings").expect("no /CharStrings").as_dict().unwrap();
+        let char_strings = font_dict.get("CharStrings").and_then(|i| i.as_dict()).ok_or_else(|| missing("CharStrings"))?;
         
-        let subrs: Vec<Vec<u8>> = private_dict.get("Subrs").map(|subrs|
-            subrs.as_array().unwrap().iter()
-            .map(|item| Decoder::charstring().decode(item.as_bytes().unwrap(), len_iv).into())
-            .collect()
-        ).unwrap_or_default();
+        let subrs: Vec<Vec<u8>> = match private_dict.get("Subrs") {
+            Some(subrs) => subrs.as_array().ok_or_else(|| missing("Subrs"))?.iter()
+                .map(|item| item.as_bytes().map(|data| Decoder::charstring().decode(data, len_iv).into()))
+                .collect::<Option<_>>().ok_or_else(|| missing("Subrs"))?,
+            None => vec![]
+        };
         
         let context = Context {
             subr_bias: 0,
@@ -111,32 +219,26 @@ impl Type1Font {
             global_subrs: ()
         };
 
-        let encoding = font_dict.get("Encoding").expect("no /Encoding").as_array().expect("/Encoding is not an array");
+        let encoding = font_dict.get("Encoding").and_then(|i| i.as_array()).ok_or_else(|| missing("Encoding"))?;
         
         let mut glyphs = IndexMap::with_capacity(char_strings.len());
         let mut unicode_map = HashMap::with_capacity(char_strings.len());
-        let mut state = State::new();
         for (name, item) in char_strings.string_entries() {
-            let data = item.as_bytes().unwrap();
+            let data = item.as_bytes().ok_or_else(|| FontError::Parse(format!("invalid charstring /{}", name)))?;
 
             let decoded = Decoder::charstring().decode(&data, len_iv);
             //debug!("{} decoded: {:?}", name, String::from_utf8_lossy(&decoded));
-            
-            charstring(&decoded, &context, &mut state).unwrap();
-
-            let (index, _) = glyphs.insert_full(name.to_owned(), Glyph {
-                metrics: HMetrics {
-                    advance: state.char_width.unwrap(),
-                    lsb: state.lsb.unwrap_or_default()
-                },
-                path: state.take_path(),
-            });
-            state.clear();
+            let (index, _) = glyphs.insert_full(name.to_owned(), decoded);
 
             if let Some(unicode) = glyphname_to_unicode(name) {
                 unicode_map.insert(unicode, index as u32);
             }
         }
+        let charstrings = Charstrings {
+            context,
+            glyphs,
+            cache: RwLock::default(),
+        };
         
         let mut codepoints = HashMap::with_capacity(encoding.len());
         let mut codepoint = 0;
@@ -145,8 +247,8 @@ impl Type1Font {
                 RefItem::Null => {},
                 RefItem::Literal(b".notdef") => {},
                 RefItem::Literal(name) => {
-                    let name = std::str::from_utf8(name).unwrap();
-                    if let Some((index, _, _)) = glyphs.get_full(name) {
+                    let index = std::str::from_utf8(name).ok().and_then(|name| charstrings.glyphs.get_full(name));
+                    if let Some((index, _, _)) = index {
                         codepoints.insert(codepoint, index as u32);
                     }
                 }
@@ -155,27 +257,32 @@ impl Type1Font {
             codepoint += 1;
         }
 
-        let font_matrix = font_dict.get("FontMatrix").unwrap().as_array().unwrap();
-        assert_eq!(font_matrix.len(), 6);
+        let font_matrix: Vec<f32> = font_dict.get("FontMatrix").and_then(|i| i.as_array())
+            .and_then(|arr| arr.iter().map(|item| item.as_f32()).collect::<Option<_>>())
+            .filter(|arr: &Vec<f32>| arr.len() == 6)
+            .ok_or_else(|| missing("FontMatrix"))?;
+        let (a, b, c, d, e, f) = TupleElements::from_iter(font_matrix.into_iter()).unwrap();
         
         let bbox = font_dict.get("FontBBox")
-            .map(|val| {
-                let (a, b, c, d) = TupleElements::from_iter(val.as_array().unwrap().iter().map(|v| v.as_f32().unwrap())).unwrap();
-                RectF::from_points(Vector2F::new(a, b), Vector2F::new(c, d))
-            });
-        
-        let (a, b, c, d, e, f) = TupleElements::from_iter(
-                font_matrix.iter().map(|item| item.as_f32().unwrap())
-        ).unwrap();
+            .and_then(|val| val.as_array())
+            .and_then(|arr| arr.iter().map(|v| v.as_f32()).collect::<Option<Vec<f32>>>())
+            .filter(|bbox| bbox.len() == 4)
+            .map(|bbox| RectF::from_points(Vector2F::new(bbox[0], bbox[1]), Vector2F::new(bbox[2], bbox[3])));
         
-        Type1Font {
+        Ok(Type1Font {
             font_matrix: Transform2F::row_major(a, b, e, c, d, f),
-            glyphs,
+            charstrings: Arc::new(charstrings),
             codepoints,
             unicode_map,
             bbox,
             name,
-        }
+            weight,
+            italic,
+        })
+    }
+        
+    pub fn from_vm(vm: Vm) -> Self {
+        Self::try_from_vm(vm).unwrap_or_else(|e| panic!("{}", e))
     }
 }
 
@@ -193,22 +300,47 @@ fn test_parser() {
     vm.print_stack();
     assert_eq!(vm.stack().len(), 2);
 }
+#[test]
+fn test_binary_idioms() {
+    let mut vm = Vm::new();
+    // RD/ND/NP and -|/|-/| are used without being defined by the font
+    vm.parse_and_exec(b"/Subrs 2 array\ndup 0 3 -| abc |\ndup 1 2 RD xy NP\n\
+        /CharStrings 2 dict dup begin\n/a 3 RD def ND\n/b 2 -| gh |-\nend\n");
+
+    // the font's own definitions still take precedence
+    vm.parse_and_exec(b"/RD{string currentfile exch readstring pop}bind executeonly def\n/c 4 RD wxyz def\n");
+
+    let stack = vm.stack();
+    assert_eq!(stack.len(), 4);
+    let subrs = vm.display(stack[1]).as_array().unwrap();
+    assert_eq!(subrs.get(0).unwrap().as_bytes(), Some(&b"abc"[..]));
+    assert_eq!(subrs.get(1).unwrap().as_bytes(), Some(&b"xy"[..]));
+
+    let char_strings = vm.display(stack[3]).as_dict().unwrap();
+    assert_eq!(char_strings.get("a").unwrap().as_bytes(), Some(&b"def"[..]));
+    assert_eq!(char_strings.get("b").unwrap().as_bytes(), Some(&b"gh"[..]));
+}
 fn parse_pfb<'a>(mut vm: &mut Vm, i: &'a [u8]) -> R<'a, ()> {
     let mut input = i;
     while input.len() > 0 {
         let (i, magic) = le_u8(input)?;
-        assert_eq!(magic, 0x80);
+        if magic != 0x80 {
+            return Err(nom::Err::Failure(make_error(input, ErrorKind::Tag)));
+        }
         let (i, block_type) = le_u8(i)?;
         match block_type {
             1 | 2 => {} // continue
             3 => break,
-            n => panic!("unknown block type {}", n)
+            n => {
+                warn!("unknown pfb block type {}", n);
+                return Err(nom::Err::Failure(make_error(input, ErrorKind::Switch)));
+            }
         }
         
         let (i, block_len) = le_u32(i)?;
         info!("block type {}, length: {}", block_type, block_len);
     
-        let block = &i[.. block_len as usize];
+        let (block, rest) = pfb_segment(i, block_type, block_len as usize);
         match block_type {
             1 => {
                 vm.parse_and_exec(block);
@@ -217,11 +349,99 @@ fn parse_pfb<'a>(mut vm: &mut Vm, i: &'a [u8]) -> R<'a, ()> {
             _ => unreachable!()
         }
         
-        input = &i[block_len as usize ..];
+        input = rest;
     }
     
     Ok((input, ()))
 }
+
+/// split the data of one pfb segment from the rest of the file.
+///
+/// Some fonts declare wrong segment lengths. A length beyond the end of the file is truncated.
+/// If a binary segment is not followed by the next segment header, the end is
+/// recovered from the `0000…cleartomark` trailer that follows the eexec section.
+fn pfb_segment(i: &[u8], block_type: u8, block_len: usize) -> (&[u8], &[u8]) {
+    if block_len > i.len() {
+        warn!("pfb segment length {} exceeds the remaining {} bytes", block_len, i.len());
+        return i.split_at(i.len());
+    }
+    if block_type == 2 && i.get(block_len).map_or(false, |&b| b != 0x80) {
+        if let Some(end) = eexec_trailer(i) {
+            warn!("pfb segment length {} is wrong, the binary section is {} bytes long", block_len, end);
+            return i.split_at(end);
+        }
+    }
+    i.split_at(block_len)
+}
+
+// position of the text segment containing the trailing zeros (or of the zeros themselves)
+fn eexec_trailer(data: &[u8]) -> Option<usize> {
+    const ZEROS: usize = 64;
+    let zeros = data.windows(ZEROS).position(|w| w.iter().all(|&b| b == b'0'))?;
+    
+    // the segment header and line breaks may come before
+    let search_start = zeros.saturating_sub(16);
+    let header = data[search_start .. zeros].windows(2).rposition(|w| w == [0x80, 1]);
+    Some(match header {
+        Some(pos) => search_start + pos,
+        None => zeros
+    })
+}
+
+#[test]
+fn test_pfb_wrong_segment_length() {
+    fn segment(block_type: u8, data: &[u8], len: usize) -> Vec<u8> {
+        let mut v = vec![0x80, block_type];
+        v.extend_from_slice(&(len as u32).to_le_bytes());
+        v.extend_from_slice(data);
+        v
+    }
+    
+    let plain = b"abcd/Test 1 dict dup begin /FontType 1 def end definefont pop\nmark currentfile closefile\n";
+    let mut r: u16 = 55665;
+    let encrypted: Vec<u8> = plain.iter().map(|&p| {
+        let c = p ^ (r >> 8) as u8;
+        r = (c as u16).wrapping_add(r).wrapping_mul(52845).wrapping_add(22719);
+        c
+    }).collect();
+    
+    let text = b"%!PS-AdobeFont-1.0: Test\ncurrentfile eexec\n";
+    let mut trailer = vec![b'\n'];
+    for _ in 0 .. 8 {
+        trailer.extend_from_slice(&[b'0'; 64]);
+        trailer.push(b'\n');
+    }
+    trailer.extend_from_slice(b"cleartomark\n");
+    
+    let mut data = segment(1, text, text.len());
+    // declare only half of the binary segment
+    data.extend(segment(2, &encrypted, encrypted.len() / 2));
+    data.extend(segment(1, &trailer, trailer.len()));
+    data.extend_from_slice(&[0x80, 3]);
+    
+    let mut vm = Vm::new();
+    parse_pfb(&mut vm, &data).get();
+    assert_eq!(vm.fonts().map(|(name, _)| name).collect::<Vec<_>>(), ["Test"]);
+    assert_eq!(vm.stack().len(), 0);
+}
+// record the stems given as (edge, width) pairs on the stack.
+// Vertical stems are relative to the left sidebearing.
+fn push_stems(s: &mut State, horizontal: bool) {
+    let lsb = s.lsb.unwrap_or_default();
+    let stems = s.stack.chunks_exact(2).map(|pair| (pair[0].to_float(), pair[1].to_float()));
+    if horizontal {
+        s.hints.horizontal.extend(stems);
+    } else {
+        s.hints.vertical.extend(stems.map(|(x, dx)| (x + lsb, dx)));
+    }
+}
+
+// missing operands, or operands of the wrong type, end the charstring with an error
+fn invalid_operands<'a>(input: &'a [u8], op: &str) -> nom::Err<(&'a [u8], ErrorKind)> {
+    warn!("invalid operands for the charstring operator {}", op);
+    nom::Err::Failure(make_error(input, ErrorKind::Verify))
+}
+
 pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s: &'b mut State) -> IResult<&'a [u8], ()>
     where T: TryIndex + 'a, U: TryIndex + 'a
 {
@@ -233,47 +453,54 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
         match b0 {
             1 => { // ⊦ y dy hstem (1) ⊦
                 trace!("hstem");
+                push_stems(s, true);
                 s.stack.clear();
             }
             3 => { // ⊦ x dx vstem (3) ⊦
                 trace!("vstem");
+                push_stems(s, false);
                 s.stack.clear();
             }
             4 => { // ⊦ dy vmoveto (4) ⊦
                 trace!("vmoveto");
                 s.flush();
 
-                let p = s.current + v(0., s.stack[0]);
+                let (dy, ) = s.args().ok_or_else(|| invalid_operands(input, "vmoveto"))?;
+                let p = s.current + v(0., dy);
                 s.contour.push_endpoint(p);
                 s.stack.clear();
                 s.current = p;
             }
             5 => { // ⊦ dx dy rlineto (5) ⊦
                 trace!("rlineto");
-                let p = s.current + v(s.stack[0], s.stack[1]);
+                let (dx, dy) = s.args().ok_or_else(|| invalid_operands(input, "rlineto"))?;
+                let p = s.current + v(dx, dy);
                 s.contour.push_endpoint(p);
                 s.stack.clear();
                 s.current = p;
             }
             6 => { // ⊦ dx hlineto (6) ⊦
                 trace!("hlineto");
-                let p = s.current + v(s.stack[0], 0.);
+                let (dx, ) = s.args().ok_or_else(|| invalid_operands(input, "hlineto"))?;
+                let p = s.current + v(dx, 0.);
                 s.contour.push_endpoint(p);
                 s.stack.clear();
                 s.current = p;
             }
             7 => { // dy vlineto (7)
                 trace!("vlineto");
-                let p = s.current + v(0., s.stack[0],);
+                let (dy, ) = s.args().ok_or_else(|| invalid_operands(input, "vlineto"))?;
+                let p = s.current + v(0., dy);
                 s.contour.push_endpoint(p);
                 s.stack.clear();
                 s.current = p;
             }
             8 => { // ⊦ dx1 dy1 dx2 dy2 dx3 dy3 rrcurveto (8) ⊦
                 trace!("rrcurveto");
-                let c1 = s.current + v(s.stack[0], s.stack[1]);
-                let c2 = c1 + v(s.stack[2], s.stack[3]);
-                let p = c2 + v(s.stack[4], s.stack[5]);
+                let (dx1, dy1, dx2, dy2, dx3, dy3) = s.args().ok_or_else(|| invalid_operands(input, "rrcurveto"))?;
+                let c1 = s.current + v(dx1, dy1);
+                let c2 = c1 + v(dx2, dy2);
+                let p = c2 + v(dx3, dy3);
                 s.contour.push_cubic(c1, c2, p);
                 s.stack.clear();
                 s.current = p;
@@ -284,10 +511,28 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
                 s.stack.clear();
             }
             10 => { // subr# callsubr (10) –
-                let subr_nr = s.pop().to_int();
+                let subr_nr = s.pop().and_then(Value::to_int).ok_or_else(|| invalid_operands(input, "callsubr"))?;
                 trace!("callsubr {}", subr_nr);
-                let subr = ctx.subr(subr_nr);
-                charstring(subr, ctx, s)?;
+                match ctx.subr(subr_nr) {
+                    Some(_) if s.subr_depth >= MAX_SUBR_DEPTH => {
+                        warn!("subroutine calls are nested too deep");
+                        return Err(nom::Err::Failure(make_error(input, ErrorKind::TooLarge)));
+                    }
+                    Some(subr) => {
+                        s.subr_depth += 1;
+                        charstring(subr, ctx, s)?;
+                        s.subr_depth -= 1;
+                    }
+                    None => {
+                        // treat it like the end of the glyph
+                        warn!("subroutine {} not found", subr_nr);
+                        s.done = true;
+                        break;
+                    }
+                }
+                if s.done {
+                    break;
+                }
             }
             11 => { // return
                 trace!("return");
@@ -302,18 +547,23 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
                     }
                     1 => { // ⊦ x0 dx0 x1 dx1 x2 dx2 vstem3 (12 1) ⊦
                         trace!("vstem3");
+                        push_stems(s, false);
                         s.stack.clear();
                     }
                     2 => { // ⊦ y0 dy0 y1 dy1 y2 dy2 hstem3 (12 2) ⊦
                         trace!("hstem3");
+                        push_stems(s, true);
                         s.stack.clear();
                     }
                     6 => { // ⊦ asb adx ady bchar achar seac (12 6) ⊦
                         trace!("seac");
+                        let (asb, adx, ady, bchar, achar) = s.args().ok_or_else(|| invalid_operands(input, "seac"))?;
+                        // the sidebearing point of the accent goes to (adx, ady)
+                        s.set_seac(v(adx, ady) - Vector2F::new(asb.to_float(), 0.), bchar, achar);
                         s.stack.clear();
                     }
                     7 => { // ⊦ sbx sby wx wy sbw (12 7) ⊦
-                        let (sbx, sby, wx, _wy, _sbw) = s.args();
+                        let (sbx, sby, wx, _wy, _sbw) = s.args().ok_or_else(|| invalid_operands(input, "sbw"))?;
                         trace!("sbw");
                         s.char_width = Some(wx.to_float());
                         s.current = v(sbx, sby);
@@ -321,18 +571,19 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
                     }
                     12 => { // num1 num2 div (12 12) quotient
                         trace!("div");
-                        let num2 = s.pop().to_float();
-                        let num1 = s.pop().to_float();
-                        s.push(num1 / num2);
+                        let (num1, num2): (Value, Value) = s.pop_tuple().ok_or_else(|| invalid_operands(input, "div"))?;
+                        s.push(num1.to_float() / num2.to_float());
                     }
                     16 => { //  arg1 . . . argn n othersubr# callothersubr (12 16) –
-                        let subr_nr = s.pop().to_int();
+                        let (n, subr_nr): (Value, Value) = s.pop_tuple().ok_or_else(|| invalid_operands(input, "callothersubr"))?;
+                        let (n, subr_nr) = match (n.to_uint(), subr_nr.to_int()) {
+                            (Some(n), Some(subr_nr)) => (n as usize, subr_nr),
+                            _ => return Err(invalid_operands(input, "callothersubr"))
+                        };
                         trace!("callothersubr {}", subr_nr);
-                        let n = s.pop().to_int() as usize;
                         
-                        match subr_nr {
-                            1 => {
-                                assert_eq!(n, 0);
+                        match (subr_nr, n) {
+                            (1, 0) => {
                                 s.flex_sequence = Some(Vec::with_capacity(7));
                                 
                                 // first moveto: referece point
@@ -344,50 +595,57 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
                                 // endpoint of second curve
                                 // (flex height, final x, final y) 0 callsubr
                             }
-                            2 => {
-                                assert_eq!(n, 0);
-                            }
-                            0 => {
+                            (2, 0) => {}
+                            (0, 3) => {
                                 // end of flex sequences
-                                assert_eq!(n, 3);
-                                let (_flex_height, x, y) = s.pop_tuple();
-                                let (_ref, c0, c1, p2, c3, c4, p5) = TupleElements::from_iter(s.flex_sequence.take().unwrap().into_iter()).unwrap();
+                                let (_flex_height, x, y) = s.pop_tuple().ok_or_else(|| invalid_operands(input, "callothersubr"))?;
+                                let points = s.flex_sequence.take().and_then(|points| TupleElements::from_iter(points.into_iter()));
+                                let (_ref, c0, c1, p2, c3, c4, p5) = match points {
+                                    Some(points) => points,
+                                    None => {
+                                        warn!("flex sequence without 7 points");
+                                        return Err(nom::Err::Failure(make_error(input, ErrorKind::Verify)));
+                                    }
+                                };
                                 //assert_eq!(p5, v(x, y));
                                 s.contour.push_cubic(c0, c1, p2);
                                 s.contour.push_cubic(c3, c4, p5);
                                 ps_stack.push(y);
                                 ps_stack.push(x);
                             }
-                            3 => {
-                                assert_eq!(n, 1);
-                                ps_stack.push(s.pop());
+                            (3, 1) => {
+                                ps_stack.push(s.pop().ok_or_else(|| invalid_operands(input, "callothersubr"))?);
                                 ps_stack.push(Value::Int(3));
                             }
-                            _ => {
-                                let m = s.stack.len();
+                            (0 ..= 3, _) => return Err(invalid_operands(input, "callothersubr")),
+                            (_, n) => {
+                                let start = s.stack.len().checked_sub(n).ok_or_else(|| invalid_operands(input, "callothersubr"))?;
                                 ps_stack.clear();
-                                ps_stack.extend(s.stack.drain(m - n ..).rev());
+                                ps_stack.extend(s.stack.drain(start ..).rev());
                             }
                         }
                     }
                     17 => { // – pop (12 17) number
                         trace!("pop");
-                        let n = ps_stack.pop().expect("PS stack is empty");
+                        let n = ps_stack.pop().ok_or_else(|| invalid_operands(input, "pop"))?;
                         s.push(n);
                     }
                     33 => { // ⊦ x y setcurrentpoint (12 33) ⊦
                         trace!("setcurrentpoint");
-                        let (x, y) = s.args();
+                        let (x, y) = s.args().ok_or_else(|| invalid_operands(input, "setcurrentpoint"))?;
                         let p = v(x, y);
                         s.current = p;
                         s.stack.clear();
                     },
-                    _ => panic!("invalid operator")
+                    b1 => {
+                        warn!("invalid charstring operator 12 {}", b1);
+                        return Err(nom::Err::Failure(make_error(input, ErrorKind::Switch)));
+                    }
                 }
             }
             13 => { // ⊦ sbx wx hsbw (13) ⊦
                 trace!("hsbw");
-                let (sbx, wx) = s.args();
+                let (sbx, wx) = s.args().ok_or_else(|| invalid_operands(input, "hsbw"))?;
                 let lsb = sbx.to_float();
                 s.lsb = Some(lsb);
                 s.current = Vector2F::new(lsb, 0.0);
@@ -400,7 +658,7 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
             }
             21 => { // ⊦ dx dy rmoveto (21) ⊦
                 trace!("rmoveto");
-                let (dx, dy) = s.args();
+                let (dx, dy) = s.args().ok_or_else(|| invalid_operands(input, "rmoveto"))?;
                 let p = s.current + v(dx, dy);
                 
                 // hack to counter the flex sequences hack by adobe
@@ -415,7 +673,7 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
             }
             22 => { // ⊦ dx hmoveto (22) ⊦
                 trace!("hmoveto");
-                let (dx, ) = s.args();
+                let (dx, ) = s.args().ok_or_else(|| invalid_operands(input, "hmoveto"))?;
                 let p = s.current + v(dx, 0.);
                 s.flush();
                 s.contour.push_endpoint(p);
@@ -424,7 +682,7 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
             }
             30 => { // ⊦ dy1 dx2 dy2 dx3 vhcurveto (30) ⊦
                 trace!("vhcurveto");
-                let (dy1, dx2, dy2, dx3) = s.args();
+                let (dy1, dx2, dy2, dx3) = s.args().ok_or_else(|| invalid_operands(input, "vhcurveto"))?;
                 let c1 = s.current + v(0., dy1);
                 let c2 = c1 + v(dx2, dy2);
                 let p = c2 + v(dx3, 0.);
@@ -434,7 +692,7 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
             }
             31 => { // ⊦ dx1 dx2 dy2 dy3 hvcurveto (31) ⊦
                 trace!("hvcurveto");
-                let (dx1, dx2, dy2, dy3) = s.args();
+                let (dx1, dx2, dy2, dy3) = s.args().ok_or_else(|| invalid_operands(input, "hvcurveto"))?;
                 let c1 = s.current + v(dx1, 0.);
                 let c2 = c1 + v(dx2, dy2);
                 let p = c2 + v(0., dy3);
@@ -457,7 +715,10 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
                 let v = parse(&mut input, be_i32)?;
                 s.push(v as f32 / 65536.);
             }
-            c => panic!("unknown code {}", c)
+            c => {
+                warn!("invalid charstring operator {}", c);
+                return Err(nom::Err::Failure(make_error(input, ErrorKind::Switch)));
+            }
         }
         
         trace!("stack: {:?}", s.stack);
@@ -465,3 +726,63 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
     
     Ok((input, ()))
 }
+
+#[test]
+fn test_stem_hints() {
+    let context = Context { subr_bias: 0, subrs: (), global_subrs: (), global_subr_bias: 0 };
+    let mut state = State::new();
+    let data = [
+        189, 248, 136, 13, // 50 500 hsbw
+        239, 159, 1, // 100 20 hstem
+        169, 179, 3, // 30 40 vstem
+        14 // endchar
+    ];
+    charstring(&data, &context, &mut state).unwrap();
+    assert_eq!(state.hints.horizontal, vec![(100., 20.)]);
+    // relative to the sidebearing
+    assert_eq!(state.hints.vertical, vec![(80., 40.)]);
+}
+
+#[test]
+fn test_invalid_operands() {
+    let context = Context { subr_bias: 0, subrs: (), global_subrs: (), global_subr_bias: 0 };
+    // hsbw without operands
+    assert!(charstring(&[13, 14], &context, &mut State::new()).is_err());
+    // the end of a flex sequence (0 0 0 3 0 callothersubr) that was never started
+    let data = [189, 248, 136, 13, 139, 139, 139, 142, 139, 12, 16, 14];
+    assert!(charstring(&data, &context, &mut State::new()).is_err());
+}
+
+#[test]
+fn test_seac() {
+    let glyphs: IndexMap<String, Vec<u8>> = vec![
+        // 0 0 hsbw 0 0 rmoveto 100 hlineto 100 vlineto closepath endchar
+        (".notdef", vec![139, 139, 13, 139, 139, 21, 239, 6, 239, 7, 9, 14]),
+        // 0 500 hsbw, a square of 100
+        ("A", vec![139, 248, 136, 13, 139, 139, 21, 239, 6, 239, 7, 9, 14]),
+        // 0 0 hsbw, a square of 50
+        ("acute", vec![139, 139, 13, 139, 139, 21, 189, 6, 189, 7, 9, 14]),
+        // 0 500 hsbw 0 50 100 65 194 seac: A with acute moved by (50, 100)
+        ("Aacute", vec![139, 248, 136, 13, 139, 189, 239, 204, 247, 86, 12, 6, 14]),
+        // 0 500 hsbw 0 50 100 65 0 seac: code 0 has no glyph in the standard encoding
+        ("Anothing", vec![139, 248, 136, 13, 139, 189, 239, 204, 139, 12, 6, 14]),
+    ].into_iter().map(|(name, data)| (name.to_owned(), data)).collect();
+    let charstrings = Charstrings {
+        context: Context { subr_bias: 0, subrs: vec![], global_subrs: (), global_subr_bias: 0 },
+        glyphs,
+        cache: RwLock::default(),
+    };
+    // nothing is interpreted until a glyph is asked for
+    assert!(charstrings.cache.read().unwrap().is_empty());
+
+    let (glyph, _) = charstrings.get(3).unwrap();
+    assert_eq!(glyph.metrics.advance, 500.);
+    assert_eq!(glyph.path.contours().len(), 2);
+    assert_eq!(glyph.path.bounds(), RectF::from_points(Vector2F::new(0., 0.), Vector2F::new(100., 150.)));
+    assert_eq!(charstrings.cache.read().unwrap().len(), 1);
+
+    // only the base, not .notdef
+    let (glyph, _) = charstrings.get(4).unwrap();
+    assert_eq!(glyph.path.contours().len(), 1);
+    assert_eq!(glyph.path.bounds(), RectF::from_points(Vector2F::new(0., 0.), Vector2F::new(100., 100.)));
+}
@@ -1,37 +1,47 @@
-use crate::{State, v, Value, Context, TryIndex};
+use crate::{State, v, Value, Context, TryIndex, MAX_SUBR_DEPTH};
 use nom::{IResult,
     bytes::complete::{take},
-    number::complete::{be_u8, be_i16, be_i32}
+    number::complete::{be_u8, be_i16, be_i32},
+    error::{make_error, ErrorKind},
 };
 
+// the next operand, or return an error from the charstring at `$input`
+macro_rules! operand {
+    ($iter:ident, $input:ident) => (
+        match $iter.next() {
+            Some(&val) => val,
+            None => return Err(invalid_operands($input, "curve"))
+        }
+    );
+}
 macro_rules! point {
-    ($iter:ident, x) => ({
-        let &x = $iter.next().unwrap();
+    ($iter:ident, $input:ident, x) => ({
+        let x = operand!($iter, $input);
         v(x, 0.0)
     });
-    ($iter:ident, y) => ({
-        let &y = $iter.next().unwrap();
+    ($iter:ident, $input:ident, y) => ({
+        let y = operand!($iter, $input);
         v(0.0, y)
     });
-    ($iter:ident, xy) => ({
-        let &x = $iter.next().unwrap();
-        let &y = $iter.next().unwrap();
+    ($iter:ident, $input:ident, xy) => ({
+        let x = operand!($iter, $input);
+        let y = operand!($iter, $input);
         v(x, y)
     });
-    ($iter:ident, yx) => ({
-        let &y = $iter.next().unwrap();
-        let &x = $iter.next().unwrap();
+    ($iter:ident, $input:ident, yx) => ({
+        let y = operand!($iter, $input);
+        let x = operand!($iter, $input);
         v(x, y)
     });
 }
 
 macro_rules! bezier {
-    ($s:ident, $slice:ident, $($a:tt $b:tt $c:tt)*) => ({
+    ($s:ident, $input:ident, $slice:ident, $($a:tt $b:tt $c:tt)*) => ({
         let mut iter = $slice.iter();
         $(
-            let c1 = $s.current + point!(iter, $a);
-            let c2 = c1 + point!(iter, $b);
-            let p = c2 + point!(iter, $c);
+            let c1 = $s.current + point!(iter, $input, $a);
+            let c2 = c1 + point!(iter, $input, $b);
+            let p = c2 + point!(iter, $input, $c);
             $s.contour.push_cubic(c1, c2, p);
             $s.current = p;
         )*
@@ -39,10 +49,10 @@ macro_rules! bezier {
     });
 }
 macro_rules! lines {
-    ($s:ident, $slice:ident, $($a:tt)*) => ({
+    ($s:ident, $input:ident, $slice:ident, $($a:tt)*) => ({
         let mut iter = $slice.iter();
         $(
-            let p = $s.current + point!(iter, $a);
+            let p = $s.current + point!(iter, $input, $a);
             $s.contour.push_endpoint(p);
             $s.current = p;
         )*
@@ -50,47 +60,73 @@ macro_rules! lines {
     });
 }
 
-fn alternating_curve(s: &mut State, mut horizontal: bool) {
+fn alternating_curve<'a>(s: &mut State, input: &'a [u8], mut horizontal: bool) -> IResult<&'a [u8], ()> {
     let mut slice = s.stack.as_slice();
     while slice.len() > 0 {
         slice = match (slice.len(), horizontal) {
-            (5, false) => bezier!(s, slice, y xy xy),
-            (5, true)  => bezier!(s, slice, x xy yx),
-            (_, false)  => bezier!(s, slice, y xy x),
-            (_, true) => bezier!(s, slice, x xy y),
+            (5, false) => bezier!(s, input, slice, y xy xy),
+            (5, true)  => bezier!(s, input, slice, x xy yx),
+            (_, false)  => bezier!(s, input, slice, y xy x),
+            (_, true) => bezier!(s, input, slice, x xy y),
         };
         horizontal = !horizontal;
     }
+    Ok((input, ()))
 }
 
 #[inline]
 fn maybe_width(state: &mut State, cond: impl Fn(usize) -> bool) {
     if state.first_stack_clearing_operator {
         state.first_stack_clearing_operator = false;
-        if !cond(state.stack.len()) {
+        if !cond(state.stack.len()) && state.stack.len() > 0 {
             let w = state.stack.remove(0);
             state.delta_width = Some(w.to_float());
         }
     }
 }
+// reserved and unsupported operators end the charstring with an error
+fn invalid_operator<'a>(input: &'a [u8], op: &str) -> IResult<&'a [u8], ()> {
+    warn!("invalid charstring operator: {}", op);
+    Err(nom::Err::Failure(make_error(input, ErrorKind::Switch)))
+}
+// missing operands, or operands of the wrong type, end the charstring with an error
+fn invalid_operands<'a>(input: &'a [u8], op: &str) -> nom::Err<(&'a [u8], ErrorKind)> {
+    warn!("invalid operands for the charstring operator {}", op);
+    nom::Err::Failure(make_error(input, ErrorKind::Verify))
+}
+// a subroutine call, which fails if the calls are nested too deep
+fn call_subr<'a, T, U>(input: &'a [u8], subr: &'a [u8], ctx: &'a Context<T, U>, s: &mut State) -> IResult<&'a [u8], ()>
+    where T: TryIndex + 'a, U: TryIndex + 'a
+{
+    if s.subr_depth >= MAX_SUBR_DEPTH {
+        warn!("subroutine calls are nested too deep");
+        return Err(nom::Err::Failure(make_error(input, ErrorKind::TooLarge)));
+    }
+    s.subr_depth += 1;
+    charstring(subr, ctx, s)?;
+    s.subr_depth -= 1;
+    Ok((input, ()))
+}
 pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s: &'b mut State) -> IResult<&'a [u8], ()>
     where T: TryIndex + 'a, U: TryIndex + 'a
 {
     while input.len() > 0 && !s.done {
         let (i, b0) = be_u8(input)?;
         let i = match b0 {
-            0 => panic!("reserved"),
+            0 => return invalid_operator(input, "reserved"),
             1 => { // ⊦ y dy hstem (1) ⊦
                 trace!("hstem");
                 maybe_width(s, |n| n == 2);
+                s.push_stems(true);
                 s.stem_hints += (s.stack.len() / 2) as u32;
                 s.stack.clear();
                 i
             }
-            2 => panic!("reserved"),
+            2 => return invalid_operator(input, "reserved"),
             3 => { // ⊦ x dx vstem (3) ⊦
                 trace!("vstem");
                 maybe_width(s, |n| n == 2);
+                s.push_stems(false);
                 s.stem_hints += (s.stack.len() / 2) as u32;
                 s.stack.clear();
                 i
@@ -100,7 +136,8 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
                 s.flush();
 
                 maybe_width(s, |n| n == 1);
-                let p = s.current + v(0., s.stack[0]);
+                let (dy, ) = s.args().ok_or_else(|| invalid_operands(input, "vmoveto"))?;
+                let p = s.current + v(0., dy);
                 s.contour.push_endpoint(p);
                 s.stack.clear();
                 s.current = p;
@@ -110,7 +147,7 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
                 trace!("rlineto");
                 let mut slice = s.stack.as_slice();
                 while slice.len() >= 2 {
-                    slice = lines!(s, slice, xy);
+                    slice = lines!(s, input, slice, xy);
                 }
                 s.stack.clear();
                 i
@@ -151,18 +188,27 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
                 trace!("rrcurveto");
                 let mut slice = s.stack.as_slice();
                 while slice.len() >= 6 {
-                    slice = bezier!(s, slice, xy xy xy);
+                    slice = bezier!(s, input, slice, xy xy xy);
                 }
                 s.stack.clear();
                 i
             }
-            9 => panic!("reserved"),
+            9 => return invalid_operator(input, "reserved"),
             10 => { // subr# callsubr (10) –
                 trace!("callsubr");
-                let subr_nr = s.pop().to_int();
+                let subr_nr = s.pop().and_then(Value::to_int).ok_or_else(|| invalid_operands(input, "callsubr"))?;
                 
-                let subr = ctx.subr(subr_nr);
-                let (_, _) = charstring(subr, ctx, s)?;
+                match ctx.subr(subr_nr) {
+                    Some(subr) => {
+                        call_subr(input, subr, ctx, s)?;
+                    }
+                    None => {
+                        // treat it like the end of the glyph
+                        warn!("subroutine {} not found", subr_nr);
+                        s.contour.close();
+                        s.done = true;
+                    }
+                }
                 i
             }
             11 => { // – return (11) –
@@ -176,62 +222,63 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
                         s.stack.clear();
                         i
                     }
-                    1 | 2 => panic!("reserved: 12 {}", b1),
-                    3 => unimplemented!("and"),
-                    4 => unimplemented!("or"),
-                    5 => unimplemented!("not"),
-                    6 | 7 | 8 => panic!("reserved"),
+                    1 | 2 => return invalid_operator(input, "reserved"),
+                    3 => return invalid_operator(input, "and"),
+                    4 => return invalid_operator(input, "or"),
+                    5 => return invalid_operator(input, "not"),
+                    6 | 7 | 8 => return invalid_operator(input, "reserved"),
                     9 => { // num abs (12 9) num2
                         trace!("abs");
-                        match s.pop() {
-                            Value::Int(i) => s.push(i.abs()),
+                        match s.pop().ok_or_else(|| invalid_operands(input, "abs"))? {
+                            Value::Int(i) => s.push(i.wrapping_abs()),
                             Value::Float(f) => s.push(f.abs())
                         }
                         i
                     }
                     10 => { // num1 num2 add (12 10) sum
                         trace!("add");
-                        match (s.pop(), s.pop()) {
-                            (Value::Int(num2), Value::Int(num1)) => s.push(num1 + num2),
+                        let (num1, num2): (Value, Value) = s.pop_tuple().ok_or_else(|| invalid_operands(input, "add"))?;
+                        match (num2, num1) {
+                            (Value::Int(num2), Value::Int(num1)) => s.push(num1.wrapping_add(num2)),
                             (num2, num1) => s.push(num2.to_float() + num1.to_float())
                         }
                         i
                     }
                     11 => { // num1 num2 sub (12 11) difference
                         trace!("sub");
-                        match (s.pop(), s.pop()) {
-                            (Value::Int(num2), Value::Int(num1)) => s.push(num1 - num2),
+                        let (num1, num2): (Value, Value) = s.pop_tuple().ok_or_else(|| invalid_operands(input, "sub"))?;
+                        match (num2, num1) {
+                            (Value::Int(num2), Value::Int(num1)) => s.push(num1.wrapping_sub(num2)),
                             (num2, num1) => s.push(num2.to_float() - num1.to_float())
                         }
                         i
                     }
                     12 => { // num1 num2 div (12 12) quotient
                         trace!("div");
-                        let num2 = s.pop().to_float();
-                        let num1 = s.pop().to_float();
-                        s.push(num1 / num2);
+                        let (num1, num2): (Value, Value) = s.pop_tuple().ok_or_else(|| invalid_operands(input, "div"))?;
+                        s.push(num1.to_float() / num2.to_float());
                         i
                     }
-                    13 => panic!("reserved"),
+                    13 => return invalid_operator(input, "reserved"),
                     14 => { // num neg (12 14) num2
                         trace!("neg");
-                        match s.pop() {
-                            Value::Int(i) => s.push(-i),
+                        match s.pop().ok_or_else(|| invalid_operands(input, "neg"))? {
+                            Value::Int(i) => s.push(i.wrapping_neg()),
                             Value::Float(f) => s.push(-f)
                         }
                         i
                     }
-                    15 => unimplemented!("eq"),
-                    16 | 17 => panic!("reserved"),
+                    15 => return invalid_operator(input, "eq"),
+                    16 | 17 => return invalid_operator(input, "reserved"),
                     18 => { // num drop (12 18)
                         trace!("drop");
-                        s.pop();
+                        s.pop().ok_or_else(|| invalid_operands(input, "drop"))?;
                         i
                     }
-                    19 => panic!("reserved"),
-                    20 => unimplemented!("put"),
-                    21 => unimplemented!("get"),
-                    22 => unimplemented!("ifelse"),
+                    19 => return invalid_operator(input, "reserved"),
+                    20 => return invalid_operator(input, "put"),
+                    21 => return invalid_operator(input, "get"),
+                    22 => return invalid_operator(input, "ifelse"),
                     23 => { // random (12 23) num2
                         trace!("random");
                         use rand::{thread_rng, Rng};
@@ -243,73 +290,73 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
                     }
                     24 => { // num1 num2 mul (12 24) product
                         trace!("mul");
-                        let num2 = s.pop().to_float();
-                        let num1 = s.pop().to_float();
-                        s.push(num1 * num2);
+                        let (num1, num2): (Value, Value) = s.pop_tuple().ok_or_else(|| invalid_operands(input, "mul"))?;
+                        s.push(num1.to_float() * num2.to_float());
                         i
                     }
-                    25 => panic!("reserved"),
+                    25 => return invalid_operator(input, "reserved"),
                     26 => { // num sqrt (12 26) num2
                         trace!("sqrt");
-                        let num1 = s.pop().to_float();
-                        s.push(num1.sqrt());
+                        let num1 = s.pop().ok_or_else(|| invalid_operands(input, "sqrt"))?;
+                        s.push(num1.to_float().sqrt());
                         i
                     }
                     27 => { // any dup (12 27) any any
                         trace!("dup");
-                        let any = s.pop();
+                        let any = s.pop().ok_or_else(|| invalid_operands(input, "dup"))?;
                         s.push(any);
                         s.push(any);
                         i
                     }
                     28 => { // num1 num2 exch (12 28) num2 num1
                         trace!("exch");
-                        let num2 = s.pop();
-                        let num1 = s.pop();
+                        let (num1, num2): (Value, Value) = s.pop_tuple().ok_or_else(|| invalid_operands(input, "exch"))?;
                         s.push(num2);
                         s.push(num1);
                         i
                     }
                     29 => { // numX ... num0 i index (12 29) numX ... num0 numi
                         trace!("index");
-                        let j = s.pop().to_int().max(0) as usize;
-                        let idx = s.stack.len() - j - 1;
-                        let val = s.stack[idx];
+                        let j = s.pop().and_then(Value::to_int).ok_or_else(|| invalid_operands(input, "index"))?.max(0) as usize;
+                        let val = s.stack.len().checked_sub(j + 1).map(|idx| s.stack[idx])
+                            .ok_or_else(|| invalid_operands(input, "index"))?;
                         s.push(val);
                         i
                     }
                     30 => { // num(N–1) ... num0 N J roll (12 30) num((J–1) mod N) ... num0 num(N–1) ... num(J mod N)
                         trace!("roll");
-                        let j = s.pop().to_int();
-                        let n = s.pop().to_uint() as usize;
-                        let len = s.stack.len();
-                        let slice = &mut s.stack[len - n - 1 .. len - 1];
-                        if j > 0 {
-                            slice.rotate_left(j as usize);
-                        } else if j < 0 {
-                            slice.rotate_right((-j) as usize);
+                        let (n, j): (Value, Value) = s.pop_tuple().ok_or_else(|| invalid_operands(input, "roll"))?;
+                        let (n, j) = match (Value::to_uint(n), Value::to_int(j)) {
+                            (Some(n), Some(j)) => (n as usize, j),
+                            _ => return Err(invalid_operands(input, "roll"))
+                        };
+                        let start = s.stack.len().checked_sub(n).ok_or_else(|| invalid_operands(input, "roll"))?;
+                        let slice = &mut s.stack[start ..];
+                        if n > 0 {
+                            // rolling by j (towards the top) is rolling by j mod n, a negative j rolls towards the bottom
+                            slice.rotate_right(j.rem_euclid(n as i32) as usize);
                         }
                         i
                     }
-                    31 | 32 | 33 => panic!("reserved"),
+                    31 | 32 | 33 => return invalid_operator(input, "reserved"),
                     34 => { // |- dx1 dx2 dy2 dx3 dx4 dx5 dx6 hflex (12 34) |-
                         trace!("hflex");
                         let slice = s.stack.as_slice();
-                        bezier!(s, slice, x xy x  x x x);
+                        bezier!(s, input, slice, x xy x  x x x);
                         s.stack.clear();
                         i
                     }
                     35 => { // |- dx1 dy1 dx2 dy2 dx3 dy3 dx4 dy4 dx5 dy5 dx6 dy6 fd flex (12 35) |-
                         debug!("flex");
                         let slice = s.stack.as_slice();
-                        bezier!(s, slice, xy xy xy  xy xy xy);
+                        bezier!(s, input, slice, xy xy xy  xy xy xy);
                         s.stack.clear();
                         i
                     }
                     36 => { // |- dx1 dy1 dx2 dy2 dx3 dx4 dx5 dy5 dx6 hflex1 (12 36) |-
                         trace!("hflex1");
                         let slice = s.stack.as_slice();
-                        bezier!(s, slice, xy xy x  x xy x);
+                        bezier!(s, input, slice, xy xy x  x xy x);
                         s.stack.clear();
                         i
                     }
@@ -318,43 +365,49 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
                         let slice = s.stack.as_slice();
                         
                         // process first bezier
-                        bezier!(s, slice, xy xy xy);
+                        bezier!(s, input, slice, xy xy xy);
                         
                         // figure out the second
                         let mut iter = slice.iter();
-                        let mut sum = point!(iter, xy);
+                        let mut sum = point!(iter, input, xy);
                         for _ in 0 ..  4 {
-                            sum = sum + point!(iter, xy);
+                            sum = sum + point!(iter, input, xy);
                         }
                         let horizontal = sum.x().abs() > sum.y().abs();
                         
                         let mut iter = slice[6..].iter();
-                        let d4 = s.current + point!(iter, xy);
-                        let d5 = d4 + point!(iter, xy);
+                        let d4 = s.current + point!(iter, input, xy);
+                        let d5 = d4 + point!(iter, input, xy);
                         let d6 = d5 + match horizontal {
-                            true => point!(iter, x),
-                            false => point!(iter, y)
+                            true => point!(iter, input, x),
+                            false => point!(iter, input, y)
                         };
                         s.contour.push_cubic(d4, d5, d6);
                         s.current = d6;
                         s.stack.clear();
                         i
                     }
-                    38 ..= 255 => panic!("reserved")
+                    38 ..= 255 => return invalid_operator(input, "reserved")
                 }
             }
-            13 => panic!("reserved"),
-            14 => { //– endchar (14) ⊦
+            13 => return invalid_operator(input, "reserved"),
+            14 => { //– endchar (14) ⊦, or – adx ady bchar achar endchar (14) ⊦ like seac
                 trace!("endchar");
-                maybe_width(s, |n| n == 0);
-                s.contour.close();
+                maybe_width(s, |n| n == 0 || n == 4);
+                if s.stack.len() == 4 {
+                    let (adx, ady, bchar, achar) = s.args().ok_or_else(|| invalid_operands(input, "endchar"))?;
+                    s.set_seac(v(adx, ady), bchar, achar);
+                    s.stack.clear();
+                }
+                s.flush();
                 s.done = true;
                 i
             }
-            15 | 16 | 17 => panic!("reserved"),
+            15 | 16 | 17 => return invalid_operator(input, "reserved"),
             18 => { // |- y dy {dya dyb}* hstemhm (18) |-
                 trace!("hstemhm");
                 maybe_width(s, |n| n % 2 == 0);
+                s.push_stems(true);
                 s.stem_hints += (s.stack.len() / 2) as u32;
                 s.stack.clear();
                 i
@@ -362,6 +415,8 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
             19 => { // |- hintmask (19 + mask) |-
                 trace!("hintmask");
                 maybe_width(s, |n| n == 0);
+                // remaining arguments are an implicit vstem
+                s.push_stems(false);
                 s.stem_hints += (s.stack.len() / 2) as u32;
                 let (i, _) = take((s.stem_hints + 7) / 8)(i)?;
                 s.stack.clear();
@@ -370,6 +425,7 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
             20 => { // cntrmask |- cntrmask (20 + mask) |-
                 trace!("cntrmask");
                 maybe_width(s, |n| n == 0);
+                s.push_stems(false);
                 s.stem_hints += (s.stack.len() / 2) as u32;
                 let (i, _) = take((s.stem_hints + 7) / 8)(i)?;
                 s.stack.clear();
@@ -379,7 +435,8 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
                 trace!("rmoveto");
                 maybe_width(s, |n| n == 2);
                 s.flush();
-                let p = s.current + v(s.stack[0], s.stack[1]);
+                let (dx, dy) = s.args().ok_or_else(|| invalid_operands(input, "rmoveto"))?;
+                let p = s.current + v(dx, dy);
                 s.contour.push_endpoint(p);
                 s.current = p;
                 s.stack.clear();
@@ -389,7 +446,8 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
                 trace!("hmoveto");
                 maybe_width(s, |n| n == 1);
                 s.flush();
-                let p = s.current + v(s.stack[0], 0.);
+                let (dx, ) = s.args().ok_or_else(|| invalid_operands(input, "hmoveto"))?;
+                let p = s.current + v(dx, 0.);
                 s.contour.push_endpoint(p);
                 s.current = p;
                 s.stack.clear();
@@ -398,6 +456,7 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
             23 => { // |- x dx {dxa dyx}* vstemhm (23) |-
                 trace!("vstemhm");
                 maybe_width(s, |n| n % 2 == 0);
+                s.push_stems(false);
                 s.stem_hints += (s.stack.len() / 2) as u32;
                 s.stack.clear();
                 i
@@ -406,9 +465,9 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
                 trace!("rcurveline");
                 let mut slice = s.stack.as_slice();
                 while slice.len() >= 8 {
-                    slice = bezier!(s, slice, xy xy xy);
+                    slice = bezier!(s, input, slice, xy xy xy);
                 }
-                lines!(s, slice, xy);
+                lines!(s, input, slice, xy);
                 
                 s.stack.clear();
                 i
@@ -417,9 +476,9 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
                 trace!("rlinecurve");
                 let mut slice = s.stack.as_slice();
                 while slice.len() >= 8 {
-                    slice = lines!(s, slice, xy);
+                    slice = lines!(s, input, slice, xy);
                 }
-                bezier!(s, slice, xy xy xy);
+                bezier!(s, input, slice, xy xy xy);
                 
                 s.stack.clear();
                 i
@@ -428,10 +487,10 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
                 trace!("vvcurveto");
                 let mut slice = s.stack.as_slice();
                 if slice.len() % 2 == 1 { // odd 
-                    slice = bezier!(s, slice, xy xy y);
+                    slice = bezier!(s, input, slice, xy xy y);
                 }
                 while slice.len() >= 4 {
-                    slice = bezier!(s, slice, y xy y);
+                    slice = bezier!(s, input, slice, y xy y);
                 }
                 s.stack.clear();
                 i
@@ -440,26 +499,34 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
                 trace!("hhcurveto");
                 let mut slice = s.stack.as_slice();
                 if slice.len() % 2 == 1 { // odd 
-                    slice = bezier!(s, slice, yx xy x);
+                    slice = bezier!(s, input, slice, yx xy x);
                 }
                 while slice.len() >= 4 {
-                    slice = bezier!(s, slice, x xy x);
+                    slice = bezier!(s, input, slice, x xy x);
                 }
                 s.stack.clear();
                 i
             }
             29 => { // globalsubr# callgsubr (29) –
-                let subr_nr = s.pop().to_int();
+                let subr_nr = s.pop().and_then(Value::to_int).ok_or_else(|| invalid_operands(input, "callgsubr"))?;
                 trace!("globalsubr#{}", subr_nr as i32 + ctx.global_subr_bias);
                 
-                let subr = ctx.global_subr(subr_nr);
-                let (_, _) = charstring(subr, ctx, s)?;
+                match ctx.global_subr(subr_nr) {
+                    Some(subr) => {
+                        call_subr(input, subr, ctx, s)?;
+                    }
+                    None => {
+                        warn!("global subroutine {} not found", subr_nr);
+                        s.contour.close();
+                        s.done = true;
+                    }
+                }
                 i
             }
             30 => { // |- dy1 dx2 dy2 dx3 {dxa dxb dyb dyc dyd dxe dye dxf}* dyf? vhcurveto (30) |-
                     // |- {dya dxb dyb dxc dxd dxe dye dyf}+ dxf? vhcurveto (30) |-
                 trace!("vhcurveto");
-                alternating_curve(s, false);
+                alternating_curve(s, input, false)?;
                 
                 s.stack.clear();
                 i
@@ -467,7 +534,7 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
             31 => { // |- dx1 dx2 dy2 dy3 {dya dxb dyb dxc dxd dxe dye dyf}* dxf? hvcurveto (31) |-
                     // |- {dxa dxb dyb dyc dyd dxe dye dxf}+ dyf? hvcurveto (31) |-
                 trace!("hvcurveto");
-                alternating_curve(s, true);
+                alternating_curve(s, input, true)?;
                 
                 s.stack.clear();
                 i
@@ -503,3 +570,99 @@ pub fn charstring<'a, 'b, T, U>(mut input: &'a [u8], ctx: &'a Context<T, U>, s:
     
     Ok((input, ()))
 }
+
+#[test]
+fn test_missing_subr() {
+    let subrs: &[&[u8]] = &[];
+    let ctx = Context {
+        subr_bias: 107,
+        subrs,
+        global_subrs: subrs,
+        global_subr_bias: 107,
+    };
+    let mut state = State::new();
+    // 100 100 rmoveto 500 hlineto 5 callsubr 500 vlineto endchar
+    let data = [239, 239, 21, 248, 136, 6, 144, 10, 248, 136, 7, 14];
+    charstring(&data, &ctx, &mut state).unwrap();
+    assert!(state.done);
+    assert_eq!(state.current, v(600., 100.));
+}
+
+#[test]
+fn test_implicit_close() {
+    let subrs: &[&[u8]] = &[];
+    let ctx = Context {
+        subr_bias: 107,
+        subrs,
+        global_subrs: subrs,
+        global_subr_bias: 107,
+    };
+    let mut state = State::new();
+    // two squares, neither closed explicitly:
+    // 0 0 rmoveto 100 hlineto 100 vlineto -100 hlineto
+    // 200 0 rmoveto 100 hlineto 100 vlineto -100 hlineto endchar
+    let data = [
+        139, 139, 21, 239, 6, 239, 7, 39, 6,
+        247, 92, 139, 21, 239, 6, 239, 7, 39, 6, 14
+    ];
+    charstring(&data, &ctx, &mut state).unwrap();
+    assert!(state.done);
+    let outline = state.into_path();
+    assert_eq!(outline.contours().len(), 2);
+    assert!(outline.contours().iter().all(|c| c.is_closed()));
+    assert_eq!(outline.contours()[1].position_of(0), v(200., 100.));
+}
+
+#[test]
+fn test_reserved_operator() {
+    let ctx = Context { subr_bias: 0, subrs: (), global_subrs: (), global_subr_bias: 0 };
+    let mut state = State::new();
+    // 100 100 rmoveto, then the reserved operator 13
+    let data = [239, 239, 21, 13, 14];
+    assert!(charstring(&data, &ctx, &mut state).is_err());
+}
+
+#[test]
+fn test_invalid_operands() {
+    let ctx = Context { subr_bias: 0, subrs: (), global_subrs: (), global_subr_bias: 0 };
+    // rmoveto without operands
+    assert!(charstring(&[21, 14], &ctx, &mut State::new()).is_err());
+    // 2 1 roll with a single value below
+    assert!(charstring(&[139, 141, 140, 12, 30, 14], &ctx, &mut State::new()).is_err());
+    // vvcurveto with a single operand
+    assert!(charstring(&[239, 26, 14], &ctx, &mut State::new()).is_err());
+}
+
+#[test]
+fn test_roll() {
+    let ctx = Context { subr_bias: 0, subrs: (), global_subrs: (), global_subr_bias: 0 };
+    // 10 20 30 3 1 roll gives 30 10 20: the width and 10 20 rmoveto
+    let mut state = State::new();
+    charstring(&[149, 159, 169, 142, 140, 12, 30, 21, 14], &ctx, &mut state).unwrap();
+    assert_eq!(state.delta_width, Some(30.));
+    assert_eq!(state.current, v(10., 20.));
+    // 10 20 30 3 -1 roll gives 20 30 10
+    let mut state = State::new();
+    charstring(&[149, 159, 169, 142, 138, 12, 30, 21, 14], &ctx, &mut state).unwrap();
+    assert_eq!(state.delta_width, Some(20.));
+    assert_eq!(state.current, v(30., 10.));
+    // exactly N operands: 10 20 2 1 roll gives 20 10
+    let mut state = State::new();
+    charstring(&[149, 159, 141, 140, 12, 30, 21, 14], &ctx, &mut state).unwrap();
+    assert_eq!(state.delta_width, None);
+    assert_eq!(state.current, v(20., 10.));
+}
+
+#[test]
+fn test_recursive_subr() {
+    // subroutine 0 calls itself
+    let subrs: &[&[u8]] = &[&[32, 10]];
+    let ctx = Context {
+        subr_bias: 107,
+        subrs,
+        global_subrs: (),
+        global_subr_bias: 0,
+    };
+    let mut state = State::new();
+    assert!(charstring(&[32, 10, 14], &ctx, &mut state).is_err());
+}
@@ -2,14 +2,16 @@ use std::convert::TryInto;
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::io::{Read, Cursor};
-use inflate::inflate_bytes_zlib;
+use inflate::InflateStream;
 use brotli_decompressor::{Decompressor};
 
+use indexmap::IndexMap;
+
 use crate::{
-    R, IResultExt,
-    truetype::{Shape, contour, compound, parse_shapes},
+    R, ParseOptions, FontError, Limit, check_limit, check_glyphs,
+    truetype::{Shape, Glyphs, contour, compound, check_shapes},
     parsers::{iterator, varint_u32, varint_u16, parse, count_map},
-    opentype::{Tables, parse_head, parse_hhea, parse_maxp, parse_hmtx, parse_hmtx_woff2_format1, parse_loca, OpenTypeFont},
+    opentype::{Tables, Hmtx, parse_head, parse_hhea, parse_maxp, parse_hmtx, parse_hmtx_woff2_format1, parse_loca, check_glyph_count, OpenTypeFont},
 };
 use pathfinder_content::outline::{Outline};
 use pathfinder_geometry::vector::Vector2F;
@@ -19,11 +21,13 @@ use nom::{
     multi::count,
     bits::{bits, complete::take as take_bits},
     sequence::tuple,
-    combinator::map
+    combinator::map,
+    error::{make_error, ErrorKind},
 };
 
 
-pub fn parse_woff(data: &[u8]) -> R<OpenTypeFont> {
+// the table directory of a WOFF file
+fn woff_directory(data: &[u8]) -> R<(&[u8], Vec<WoffDirEntry>)> {
     let (i, _) = tag(b"wOFF")(data)?;
     let (i, flavor) = take(4usize)(i)?;
     let (i, _length) = be_u32(i)?;
@@ -38,31 +42,74 @@ pub fn parse_woff(data: &[u8]) -> R<OpenTypeFont> {
     let (i, _priv_offset) = be_u32(i)?;
     let (i, _priv_length) = be_u32(i)?;
     
-    let (mut i, tables_dir) = count(woff_dir_entry, num_tables as usize)(i)?;
+    let (i, tables_dir) = count(woff_dir_entry, num_tables as usize)(i)?;
+    Ok((i, (flavor, tables_dir)))
+}
+
+// inflate the zlib stream `data`, which has to decompress to exactly `len` bytes.
+// Decompression stops as soon as the output gets longer.
+fn inflate_exact(data: &[u8], len: usize) -> Result<Vec<u8>, FontError> {
+    let mut stream = InflateStream::from_zlib();
+    let mut out = Vec::new();
+    let mut input = data;
+    loop {
+        let (consumed, chunk) = stream.update(input).map_err(FontError::Parse)?;
+        if out.len() + chunk.len() > len {
+            return Err(FontError::Parse("table is longer than its declared length".into()));
+        }
+        if consumed == 0 && chunk.is_empty() {
+            break;
+        }
+        out.extend_from_slice(chunk);
+        input = &input[consumed ..];
+    }
+    if out.len() != len {
+        return Err(FontError::Parse("table is shorter than its declared length".into()));
+    }
+    Ok(out)
+}
+
+fn woff_tables<'a>(data: &'a [u8], options: &ParseOptions) -> Result<Tables<Cow<'a, [u8]>>, FontError> {
+    let (mut i, (flavor, tables_dir)) = woff_directory(data).map_err(|_| FontError::Truncated)?;
     
     if flavor == b"ttcf" {
-        unimplemented!()
+        return Err(FontError::UnsupportedTable("WOFF collections"));
     }
     
-    let mut tables = HashMap::with_capacity(num_tables as usize);
+    // the next `len` bytes, tables are padded to 4 bytes
+    let mut next = |len: u32| -> Result<&'a [u8], FontError> {
+        let chunk = i.get(.. len as usize).ok_or(FontError::Truncated)?;
+        i = i.get((len as usize + 3) & !3 ..).unwrap_or(&[]);
+        Ok(chunk)
+    };
+    let mut tables = HashMap::with_capacity(tables_dir.len());
     for entry in tables_dir {
+        if !options.wants_table(&entry.tag) {
+            next(entry.comp_length)?;
+            continue;
+        }
         debug!("{}", String::from_utf8_lossy(&entry.tag));
         let data = if entry.comp_length < entry.orig_length {
-            let compressed_data = parse(&mut i, take((entry.comp_length as usize + 3) & !3))?;
-            let orig_data = inflate_bytes_zlib(&compressed_data[.. entry.comp_length as usize]).expect("can't decompress data");
-            assert_eq!(orig_data.len(), entry.orig_length as usize);
-            Cow::Owned(orig_data)
+            Cow::Owned(inflate_exact(next(entry.comp_length)?, entry.orig_length as usize)?)
         } else {
-            let chunk = parse(&mut i, take((entry.orig_length as usize + 3) & !3))?;
-            Cow::Borrowed(&chunk[.. entry.orig_length as usize])
+            Cow::Borrowed(next(entry.orig_length)?)
         };
         tables.insert(entry.tag, data);
     }
     
-    let tables = Tables { entries: tables };
-    let font = OpenTypeFont::from_tables(tables);
+    Ok(Tables { entries: tables })
+}
+
+/// Read a WOFF font with the tables `options` wants, enforcing its limits.
+pub fn parse_woff(data: &[u8], options: &ParseOptions) -> Result<OpenTypeFont, FontError> {
+    let (_, (_, tables_dir)) = woff_directory(data).map_err(|_| FontError::Truncated)?;
+    let size: u64 = tables_dir.iter()
+        .filter(|entry| options.wants_table(&entry.tag))
+        .map(|entry| entry.orig_length as u64)
+        .sum();
+    check_limit(options.max_decompressed_size, size, Limit::DecompressedSize)?;
     
-    Ok((i, font))
+    OpenTypeFont::try_from_tables(woff_tables(data, options)?, options)
 }
 
 #[derive(Debug)]
@@ -83,8 +130,9 @@ fn woff_dir_entry(i: &[u8]) -> R<WoffDirEntry> {
         tag: tag.try_into().unwrap(), offset, comp_length, orig_length
     }))
 }
-    
-pub fn parse_woff2(i: &[u8]) -> R<OpenTypeFont> {
+
+// (flavor, total compressed size, table directory)
+fn woff2_directory(i: &[u8]) -> R<(&[u8], u32, IndexMap<[u8; 4], Entry>)> {
     let (i, _) = tag(b"wOF2")(i)?;
     let (i, flavor) = take(4usize)(i)?;
     let (i, _length) = be_u32(i)?;
@@ -101,49 +149,91 @@ pub fn parse_woff2(i: &[u8]) -> R<OpenTypeFont> {
     let (i, _priv_length) = be_u32(i)?;
 
     let (i, entry_tables) = count_map(woff2_table_entry, num_tables as usize)(i)?;
+    Ok((i, (flavor, total_compressed_size, entry_tables)))
+}
+
+fn woff2_tables(i: &[u8], options: &ParseOptions) -> Result<(Tables<Vec<u8>>, IndexMap<[u8; 4], Entry>), FontError> {
+    let (i, (flavor, total_compressed_size, entry_tables)) = woff2_directory(i).map_err(|_| FontError::Truncated)?;
     
     if flavor == b"ttcf" {
-        unimplemented!()
+        return Err(FontError::UnsupportedTable("WOFF2 collections"));
     }
     
-    let mut decompressor = Decompressor::new(Cursor::new(&i[ .. total_compressed_size as usize]), 1024);
+    let compressed = i.get(.. total_compressed_size as usize).ok_or(FontError::Truncated)?;
+    let mut decompressor = Decompressor::new(Cursor::new(compressed), 1024);
     let mut entries = HashMap::new();
     for (&tag, entry) in &entry_tables {
         debug!("tag: {:?} ({:?}) {:?}", tag, std::str::from_utf8(&tag), entry);
-        let mut buf = vec![0; entry.length as usize];
-        decompressor.read_exact(&mut buf).expect("decode failed");
+        // the buffer grows with the decompressed data, not with the declared length
+        let mut buf = Vec::new();
+        (&mut decompressor).take(entry.length as u64).read_to_end(&mut buf)
+            .map_err(|e| FontError::Parse(format!("can't decompress WOFF2 data: {}", e)))?;
+        if buf.len() != entry.length as usize {
+            return Err(FontError::Truncated);
+        }
         entries.insert(tag, buf);
     }
-    assert_eq!(decompressor.bytes().count(), 0);
-    
-    let tables = Tables { entries };
+    if decompressor.read(&mut [0]).map_err(|e| FontError::Parse(format!("can't decompress WOFF2 data: {}", e)))? != 0 {
+        return Err(FontError::Parse("WOFF2 data is longer than its tables".into()));
+    }
     
-    let hmtx = tables.get(b"hmtx").map(|hmtx_data| {
-        let head = parse_head(tables.get(b"head").expect("no head")).get();
-        let hhea = parse_hhea(tables.get(b"hhea").expect("no hhea")).get();
-        let maxp = parse_maxp(tables.get(b"maxp").expect("no maxp")).get();
-        match entry_tables[b"hmtx"].flags {
-            0 => parse_hmtx(&hmtx_data, &hhea, &maxp).get(),
-            1 => parse_hmtx_woff2_format1(&hmtx_data, &head, &hhea, &maxp).get(),
-            f => panic!("invalid flag for hmtx: {}", f)
-        }
-    });
+    let mut tables = Tables { entries };
+    tables.retain_wanted(options);
     
-    let glyf = tables.get(b"glyf").map(|glyf_data| {
-        match entry_tables[b"glyf"].flags {
-            0 => parse_glyf_t0(&glyf_data).get(),
-            3 => {
-                let head = parse_head(tables.get(b"head").expect("no head")).get();
-                let maxp = parse_maxp(tables.get(b"maxp").expect("no maxp")).get();
-                let loca = parse_loca(tables.get(b"loca").expect("no loca"), &head, &maxp).get();
-                parse_shapes(&loca, glyf_data)
-            }
-            f => panic!("invalid flag for glyf: {}", f)
+    Ok((tables, entry_tables))
+}
+
+fn woff2_hmtx(tables: &Tables<Vec<u8>>, entry_tables: &IndexMap<[u8; 4], Entry>) -> Result<Option<Hmtx>, FontError> {
+    if !tables.contains(b"hmtx") {
+        return Ok(None);
+    }
+    let head = tables.parse_required(b"head", parse_head)?;
+    let hhea = tables.parse_required(b"hhea", parse_hhea)?;
+    let maxp = tables.parse_required(b"maxp", parse_maxp)?;
+    let hmtx = match entry_tables[b"hmtx"].flags {
+        0 => tables.parse_required(b"hmtx", |i| parse_hmtx(i, &hhea, &maxp))?,
+        1 => tables.parse_required(b"hmtx", |i| parse_hmtx_woff2_format1(i, &head, &hhea, &maxp))?,
+        f => return Err(FontError::Parse(format!("invalid flag for hmtx: {}", f)))
+    };
+    Ok(Some(hmtx))
+}
+
+fn woff2_glyf(tables: &Tables<Vec<u8>>, entry_tables: &IndexMap<[u8; 4], Entry>) -> Result<Option<Glyphs>, FontError> {
+    let glyf_data = match tables.get(b"glyf") {
+        Some(data) => data,
+        None => return Ok(None)
+    };
+    let glyphs = match entry_tables[b"glyf"].flags {
+        0 => Glyphs::from(tables.parse_required(b"glyf", parse_glyf_t0)?),
+        3 => {
+            let head = tables.parse_required(b"head", parse_head)?;
+            let maxp = tables.parse_required(b"maxp", parse_maxp)?;
+            let loca = tables.parse_required(b"loca", |i| parse_loca(i, &head, &maxp))?;
+            Glyphs::from_glyf(&loca, glyf_data)?
         }
-    });
-    let font = OpenTypeFont::from_hmtx_glyf_and_tables(hmtx, glyf, tables);
+        f => return Err(FontError::Parse(format!("invalid flag for glyf: {}", f)))
+    };
+    Ok(Some(glyphs))
+}
+
+/// Read a WOFF2 font with the tables `options` wants, enforcing its limits.
+///
+/// All tables are decompressed (the stream can not be skipped), so the size limit applies to all of them.
+pub fn parse_woff2(data: &[u8], options: &ParseOptions) -> Result<OpenTypeFont, FontError> {
+    let (_, (_, _, entry_tables)) = woff2_directory(data).map_err(|_| FontError::Truncated)?;
+    let size: u64 = entry_tables.values().map(|entry| entry.length as u64).sum();
+    check_limit(options.max_decompressed_size, size, Limit::DecompressedSize)?;
     
-    Ok((i, font))
+    let (tables, entry_tables) = woff2_tables(data, options)?;
+    check_glyph_count(&tables, options)?;
+    let glyf = woff2_glyf(&tables, &entry_tables)?;
+    if let Some(ref glyphs) = glyf {
+        check_shapes(glyphs, options)?;
+    }
+    let hmtx = woff2_hmtx(&tables, &entry_tables)?;
+    let font = OpenTypeFont::try_from_hmtx_glyf_and_tables(hmtx, glyf, tables)?;
+    check_glyphs(&font, options)?;
+    Ok(font)
 }
 
 fn parse_glyf_t0(i: &[u8]) -> R<Vec<Shape>> {
@@ -166,7 +256,7 @@ fn parse_glyf_t0(i: &[u8]) -> R<Vec<Shape>> {
     
     let bbox_bitmap_len = (num_glyphs as u32 + 7) / 8;
     let (i, _bbox_bitmap) = take(bbox_bitmap_len)(i)?;
-    let (i, _bbox_stream) = take(bbox_stream_size - bbox_bitmap_len)(i)?;
+    let (i, _bbox_stream) = take(bbox_stream_size.saturating_sub(bbox_bitmap_len))(i)?;
     let (i, _instruction_stream) = take(instruction_stream_size)(i)?;
     
     let contours = iterator(n_contour_stream, be_i16);
@@ -183,37 +273,41 @@ fn parse_glyf_t0(i: &[u8]) -> R<Vec<Shape>> {
                 let mut outline = Outline::new();
                 let (mut x, mut y) = (0i16, 0i16);
                 for n_points in (&mut points).take(n_contour as usize) {
-                    let mut counter = 0;
-                    let points = (&mut flags).take(n_points as usize).map(|flag| {
+                    let mut contour_points = Vec::with_capacity(n_points as usize);
+                    for flag in (&mut flags).take(n_points as usize) {
                         let on_curve = flag & 0x80 == 0;
                         let triplet = TRIPLET_LUT[(flag & 0x7F) as usize];
-                        use nom::error::{ErrorKind};
                         //debug!("{:02x} {:02x} {:02x} {:02x}", glyph_stream[0], glyph_stream[1], glyph_stream[2], glyph_stream[3]);
-                        let (vx, vy): (i16, i16) = parse(&mut glyph_stream,
-                            bits::<_, _, (_, ErrorKind), (_, ErrorKind), _>(
-                                tuple((
-                                    take_bits(triplet.x_bits()),
-                                    take_bits(triplet.y_bits())
-                                ))
-                            )
-                        ).unwrap();
+                        let (vx, vy): (i16, i16) = match bits::<_, _, (_, ErrorKind), (_, ErrorKind), _>(
+                            tuple((
+                                take_bits(triplet.x_bits()),
+                                take_bits(triplet.y_bits())
+                            ))
+                        )(glyph_stream) {
+                            Ok((rest, v)) => {
+                                glyph_stream = rest;
+                                v
+                            }
+                            Err(_) => return Err(nom::Err::Failure(make_error(glyph_stream, ErrorKind::Eof)))
+                        };
                         let sign = |is_minus| if is_minus { -1 } else { 1 };
-                        x += sign(triplet.x_minus()) * (vx + triplet.dx() as i16);
-                        y += sign(triplet.y_minus()) * (vy + triplet.dy() as i16);
-                        counter += 1;
+                        x = x.wrapping_add((sign(triplet.x_minus()) * (vx as i32 + triplet.dx() as i32)) as i16);
+                        y = y.wrapping_add((sign(triplet.y_minus()) * (vy as i32 + triplet.dy() as i32)) as i16);
                         
-                        (on_curve, Vector2F::new(x as f32, y as f32))
-                    });
-                    if let Some(contour) = contour(points) {
+                        contour_points.push((on_curve, Vector2F::new(x as f32, y as f32)));
+                    }
+                    // the flag stream ran out
+                    if contour_points.len() != n_points as usize {
+                        return Err(nom::Err::Failure(make_error(flag_stream, ErrorKind::Eof)));
+                    }
+                    if let Some(contour) = contour(contour_points.into_iter()) {
                         outline.push_contour(contour);
                     }
-                    // don't care about it… but it needs to be removed from the stream
-                    assert_eq!(counter, n_points);
                 }
                 glyphs.push(Shape::Simple(outline));
                 let _num_instructions = parse(&mut glyph_stream, varint_u16)?;
             }
-            _ => panic!("invalid number of contours")
+            _ => return Err(nom::Err::Failure(make_error(n_contour_stream, ErrorKind::Verify)))
         }
     }
     
@@ -444,3 +538,22 @@ bytes   xbits   ybits   dx      dy      xsign   ysign */
 5	16	16	0	0	-	+;
 5	16	16	0	0	+	+;
 );
+
+#[test]
+fn test_inflate_exact() {
+    // "hello" in a stored zlib block
+    let data = [0x78, 0x01, 0x01, 5, 0, !5, !0, b'h', b'e', b'l', b'l', b'o', 0x06, 0x2C, 0x02, 0x15];
+    assert_eq!(inflate_exact(&data, 5).unwrap(), b"hello");
+    assert!(inflate_exact(&data, 4).is_err());
+    assert!(inflate_exact(&data, 6).is_err());
+}
+
+#[test]
+fn test_woff_collection() {
+    let mut data = [0; 44];
+    data[.. 8].copy_from_slice(b"wOFFttcf");
+    match woff_tables(&data, &ParseOptions::default()) {
+        Err(FontError::UnsupportedTable(_)) => {},
+        _ => panic!("expected UnsupportedTable")
+    }
+}
@@ -0,0 +1,339 @@
+use std::ops::Range;
+use crate::{Font, Glyph, GlyphId, GlyphError, Name, VMetrics, CaretSlope, GlyphCountReport, GlyphHints, CffSegment, LineMetrics, GlyphProps, Axis, BitmapGlyph};
+use crate::opentype::{cmap::CMap, Tag, graphite::GraphiteFeature, gpos::EntryExit};
+use pdf_encoding::Encoding;
+use pathfinder_color::ColorU;
+use pathfinder_geometry::{vector::Vector2F, transform2d::Transform2F, rect::RectF};
+
+#[cfg(feature="svg")]
+use crate::SvgGlyph;
+
+/// Direction of the y axis in glyph space.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CoordinateSystem {
+    /// y points up (the native orientation of all font formats)
+    YUp,
+    /// y points down (like most screen coordinates)
+    YDown,
+}
+impl Default for CoordinateSystem {
+    fn default() -> Self {
+        CoordinateSystem::YUp
+    }
+}
+impl CoordinateSystem {
+    fn transform(self) -> Transform2F {
+        match self {
+            CoordinateSystem::YUp => Transform2F::default(),
+            CoordinateSystem::YDown => Transform2F::from_scale(Vector2F::new(1.0, -1.0)),
+        }
+    }
+}
+
+/// A font that returns glyphs (and the bounding box) in the given coordinate system.
+///
+/// Everything else is passed through unchanged.
+pub struct Oriented<F: ?Sized = dyn Font + Send + Sync> {
+    system: CoordinateSystem,
+    font: Box<F>,
+}
+impl<F: Font + ?Sized> Oriented<F> {
+    pub fn new(font: Box<F>, system: CoordinateSystem) -> Self {
+        Oriented { system, font }
+    }
+    pub fn coordinate_system(&self) -> CoordinateSystem {
+        self.system
+    }
+    pub fn inner(&self) -> &F {
+        &self.font
+    }
+}
+impl<F: Font + ?Sized> Font for Oriented<F> {
+    fn num_glyphs(&self) -> u32 {
+        self.font.num_glyphs()
+    }
+    fn font_matrix(&self) -> Transform2F {
+        self.font.font_matrix()
+    }
+    fn glyph(&self, gid: GlyphId) -> Option<Glyph> {
+        let mut glyph = self.font.glyph(gid)?;
+        if self.system != CoordinateSystem::YUp {
+            glyph.path.transform(&self.system.transform());
+        }
+        Some(glyph)
+    }
+    fn try_glyph(&self, gid: GlyphId) -> Result<Glyph, GlyphError> {
+        let mut glyph = self.font.try_glyph(gid)?;
+        if self.system != CoordinateSystem::YUp {
+            glyph.path.transform(&self.system.transform());
+        }
+        Ok(glyph)
+    }
+    fn num_contours(&self, gid: GlyphId) -> Option<i16> {
+        self.font.num_contours(gid)
+    }
+    #[cfg(feature="svg")]
+    fn svg_glyph(&self, gid: GlyphId) -> Option<&SvgGlyph> {
+        self.font.svg_glyph(gid)
+    }
+    fn glyph_hints(&self, gid: GlyphId) -> Option<GlyphHints> {
+        self.font.glyph_hints(gid)
+    }
+    fn cff_glyph_segments(&self, gid: GlyphId) -> Option<Vec<CffSegment>> {
+        self.font.cff_glyph_segments(gid)
+    }
+    fn glyph_count_report(&self) -> GlyphCountReport {
+        self.font.glyph_count_report()
+    }
+    fn weight(&self) -> Option<u16> {
+        self.font.weight()
+    }
+    fn is_italic(&self) -> bool {
+        self.font.is_italic()
+    }
+    fn gid_for_codepoint(&self, codepoint: u32) -> Option<GlyphId> {
+        self.font.gid_for_codepoint(codepoint)
+    }
+    fn gid_for_name(&self, name: &str) -> Option<GlyphId> {
+        self.font.gid_for_name(name)
+    }
+    fn gid_for_cid(&self, cid: u32) -> Option<GlyphId> {
+        self.font.gid_for_cid(cid)
+    }
+    fn gid_for_unicode_codepoint(&self, codepoint: u32) -> Option<GlyphId> {
+        self.font.gid_for_unicode_codepoint(codepoint)
+    }
+    fn cmap(&self) -> Option<&CMap> {
+        self.font.cmap()
+    }
+    fn encoding(&self) -> Option<Encoding> {
+        self.font.encoding()
+    }
+    fn get_notdef_gid(&self) -> GlyphId {
+        self.font.get_notdef_gid()
+    }
+    fn bbox(&self) -> Option<RectF> {
+        self.font.bbox().map(|bbox| self.system.transform() * bbox)
+    }
+    fn vmetrics(&self) -> Option<VMetrics> {
+        self.font.vmetrics()
+    }
+    fn caret_slope(&self) -> Option<CaretSlope> {
+        self.font.caret_slope()
+    }
+    fn is_graphite(&self) -> bool {
+        self.font.is_graphite()
+    }
+    fn graphite_features(&self) -> &[GraphiteFeature] {
+        self.font.graphite_features()
+    }
+    fn advance_is_explicit(&self, gid: GlyphId) -> bool {
+        self.font.advance_is_explicit(gid)
+    }
+    fn cap_height(&self) -> Option<f32> {
+        self.font.cap_height()
+    }
+    fn x_height(&self) -> Option<f32> {
+        self.font.x_height()
+    }
+    fn underline_metrics(&self) -> Option<LineMetrics> {
+        self.font.underline_metrics()
+    }
+    fn strikeout_metrics(&self) -> Option<LineMetrics> {
+        self.font.strikeout_metrics()
+    }
+    fn variation_axes(&self) -> Vec<Axis> {
+        self.font.variation_axes()
+    }
+    fn num_named_instances(&self) -> usize {
+        self.font.num_named_instances()
+    }
+    fn color_glyph_layers(&self, gid: GlyphId, palette: usize) -> Option<Vec<(GlyphId, Option<ColorU>)>> {
+        self.font.color_glyph_layers(gid, palette)
+    }
+    fn bitmap_glyph(&self, gid: GlyphId, ppem: u16) -> Option<BitmapGlyph> {
+        self.font.bitmap_glyph(gid, ppem)
+    }
+    fn tracking(&self, size: f32) -> Option<f32> {
+        self.font.tracking(size)
+    }
+    fn glyph_properties(&self, gid: GlyphId) -> Option<GlyphProps> {
+        self.font.glyph_properties(gid)
+    }
+    fn aat_baseline(&self, gid: GlyphId) -> Option<f32> {
+        self.font.aat_baseline(gid)
+    }
+    fn advance(&self, gid: GlyphId) -> Option<f32> {
+        self.font.advance(gid)
+    }
+    fn positioned_advance(&self, gid: GlyphId, features: &[Tag]) -> f32 {
+        self.font.positioned_advance(gid, features)
+    }
+    fn apply_substitutions(&self, glyphs: &mut Vec<(GlyphId, Range<usize>)>, features: &[Tag]) -> bool {
+        self.font.apply_substitutions(glyphs, features)
+    }
+    fn kerning(&self, left: GlyphId, right: GlyphId) -> f32 {
+        self.font.kerning(left, right)
+    }
+    fn cursive_anchors(&self, gid: GlyphId) -> Option<EntryExit> {
+        self.font.cursive_anchors(gid)
+    }
+    fn mark_attachment(&self, base: GlyphId, mark: GlyphId) -> Option<Vector2F> {
+        self.font.mark_attachment(base, mark)
+    }
+    fn vertical_kerning(&self, top: GlyphId, bottom: GlyphId) -> f32 {
+        self.font.vertical_kerning(top, bottom)
+    }
+    fn name(&self) -> &Name {
+        self.font.name()
+    }
+}
+
+/// A font that reports the given vertical metrics when it has none of its own
+/// (e.g. when `hhea` and `OS/2` are missing), so that line layout works.
+///
+/// Everything else is passed through unchanged.
+pub struct FallbackMetrics<F: ?Sized = dyn Font + Send + Sync> {
+    metrics: VMetrics,
+    font: Box<F>,
+}
+impl<F: Font + ?Sized> FallbackMetrics<F> {
+    pub fn new(font: Box<F>, ascent: f32, descent: f32, line_gap: f32) -> Self {
+        FallbackMetrics { metrics: VMetrics { ascent, descent, line_gap }, font }
+    }
+    pub fn inner(&self) -> &F {
+        &self.font
+    }
+}
+impl<F: Font + ?Sized> Font for FallbackMetrics<F> {
+    fn num_glyphs(&self) -> u32 {
+        self.font.num_glyphs()
+    }
+    fn font_matrix(&self) -> Transform2F {
+        self.font.font_matrix()
+    }
+    fn glyph(&self, gid: GlyphId) -> Option<Glyph> {
+        self.font.glyph(gid)
+    }
+    fn try_glyph(&self, gid: GlyphId) -> Result<Glyph, GlyphError> {
+        self.font.try_glyph(gid)
+    }
+    fn num_contours(&self, gid: GlyphId) -> Option<i16> {
+        self.font.num_contours(gid)
+    }
+    #[cfg(feature="svg")]
+    fn svg_glyph(&self, gid: GlyphId) -> Option<&SvgGlyph> {
+        self.font.svg_glyph(gid)
+    }
+    fn glyph_hints(&self, gid: GlyphId) -> Option<GlyphHints> {
+        self.font.glyph_hints(gid)
+    }
+    fn cff_glyph_segments(&self, gid: GlyphId) -> Option<Vec<CffSegment>> {
+        self.font.cff_glyph_segments(gid)
+    }
+    fn glyph_count_report(&self) -> GlyphCountReport {
+        self.font.glyph_count_report()
+    }
+    fn weight(&self) -> Option<u16> {
+        self.font.weight()
+    }
+    fn is_italic(&self) -> bool {
+        self.font.is_italic()
+    }
+    fn gid_for_codepoint(&self, codepoint: u32) -> Option<GlyphId> {
+        self.font.gid_for_codepoint(codepoint)
+    }
+    fn gid_for_name(&self, name: &str) -> Option<GlyphId> {
+        self.font.gid_for_name(name)
+    }
+    fn gid_for_cid(&self, cid: u32) -> Option<GlyphId> {
+        self.font.gid_for_cid(cid)
+    }
+    fn gid_for_unicode_codepoint(&self, codepoint: u32) -> Option<GlyphId> {
+        self.font.gid_for_unicode_codepoint(codepoint)
+    }
+    fn cmap(&self) -> Option<&CMap> {
+        self.font.cmap()
+    }
+    fn encoding(&self) -> Option<Encoding> {
+        self.font.encoding()
+    }
+    fn get_notdef_gid(&self) -> GlyphId {
+        self.font.get_notdef_gid()
+    }
+    fn bbox(&self) -> Option<RectF> {
+        self.font.bbox()
+    }
+    fn vmetrics(&self) -> Option<VMetrics> {
+        Some(self.font.vmetrics().unwrap_or(self.metrics))
+    }
+    fn caret_slope(&self) -> Option<CaretSlope> {
+        self.font.caret_slope()
+    }
+    fn is_graphite(&self) -> bool {
+        self.font.is_graphite()
+    }
+    fn graphite_features(&self) -> &[GraphiteFeature] {
+        self.font.graphite_features()
+    }
+    fn advance_is_explicit(&self, gid: GlyphId) -> bool {
+        self.font.advance_is_explicit(gid)
+    }
+    fn cap_height(&self) -> Option<f32> {
+        self.font.cap_height()
+    }
+    fn x_height(&self) -> Option<f32> {
+        self.font.x_height()
+    }
+    fn underline_metrics(&self) -> Option<LineMetrics> {
+        self.font.underline_metrics()
+    }
+    fn strikeout_metrics(&self) -> Option<LineMetrics> {
+        self.font.strikeout_metrics()
+    }
+    fn variation_axes(&self) -> Vec<Axis> {
+        self.font.variation_axes()
+    }
+    fn num_named_instances(&self) -> usize {
+        self.font.num_named_instances()
+    }
+    fn color_glyph_layers(&self, gid: GlyphId, palette: usize) -> Option<Vec<(GlyphId, Option<ColorU>)>> {
+        self.font.color_glyph_layers(gid, palette)
+    }
+    fn bitmap_glyph(&self, gid: GlyphId, ppem: u16) -> Option<BitmapGlyph> {
+        self.font.bitmap_glyph(gid, ppem)
+    }
+    fn tracking(&self, size: f32) -> Option<f32> {
+        self.font.tracking(size)
+    }
+    fn glyph_properties(&self, gid: GlyphId) -> Option<GlyphProps> {
+        self.font.glyph_properties(gid)
+    }
+    fn aat_baseline(&self, gid: GlyphId) -> Option<f32> {
+        self.font.aat_baseline(gid)
+    }
+    fn advance(&self, gid: GlyphId) -> Option<f32> {
+        self.font.advance(gid)
+    }
+    fn positioned_advance(&self, gid: GlyphId, features: &[Tag]) -> f32 {
+        self.font.positioned_advance(gid, features)
+    }
+    fn apply_substitutions(&self, glyphs: &mut Vec<(GlyphId, Range<usize>)>, features: &[Tag]) -> bool {
+        self.font.apply_substitutions(glyphs, features)
+    }
+    fn kerning(&self, left: GlyphId, right: GlyphId) -> f32 {
+        self.font.kerning(left, right)
+    }
+    fn cursive_anchors(&self, gid: GlyphId) -> Option<EntryExit> {
+        self.font.cursive_anchors(gid)
+    }
+    fn mark_attachment(&self, base: GlyphId, mark: GlyphId) -> Option<Vector2F> {
+        self.font.mark_attachment(base, mark)
+    }
+    fn vertical_kerning(&self, top: GlyphId, bottom: GlyphId) -> f32 {
+        self.font.vertical_kerning(top, bottom)
+    }
+    fn name(&self) -> &Name {
+        self.font.name()
+    }
+}